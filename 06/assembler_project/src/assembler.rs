@@ -0,0 +1,306 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs::File;
+use std::io::{self, Read};
+use crate::parser::{Parser, SyntaxError};
+use crate::symbol_table::SymbolTable;
+use crate::code_generator;
+
+type MachineCommand = u16;
+
+//Why a .asm file does not assemble, and on which line
+pub struct AssemblyError {
+    pub file_name : String,
+    pub line : usize,
+    pub message : String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file_name, self.line, self.message)
+    }
+}
+
+pub fn read_lines_from_file(file_name : &str) -> io::Result<Vec<String>> {
+    let mut file = File::open(file_name)?;
+
+    let mut raw_string = String::new();
+    file.read_to_string(&mut raw_string)?;
+
+    Ok(raw_string.lines().map(|s| s.trim().to_string()).collect::<Vec<String>>())
+}
+
+pub fn remove_comments_from_lines(lines : &Vec<String>) -> Vec<String> {
+    let mut ret_vec = Vec::new();
+    for line in lines {
+        if line.starts_with("//") || line.is_empty() {
+            continue;
+        }
+        if let Some(comment_index) = line.find("//") {
+            let string : String = String::from(&line[..comment_index]).trim().to_string();
+            ret_vec.push(string);
+        }
+        else {
+            ret_vec.push(line.to_string());
+        }
+    }
+    ret_vec
+}
+
+//Where each ROM word came from: the source file, its lines and the 1-based line of every instruction
+pub struct SourceMap {
+    pub file_name : String,
+    pub lines : Vec<String>,
+    pub line_numbers : Vec<usize>,
+}
+
+fn strip_comment(line : &str) -> &str {
+    match line.find("//") {
+        Some(comment_index) => line[..comment_index].trim(),
+        None => line.trim(),
+    }
+}
+
+//Follows the same classification as the parser: every line that is not a label becomes one instruction
+pub fn source_line_numbers(lines : &[String]) -> Vec<usize> {
+    let mut line_numbers = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let line = strip_comment(line);
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('@') || line.contains('=') || line.contains(';') {
+            line_numbers.push(index + 1);
+        }
+    }
+    line_numbers
+}
+
+fn read_source_lines(file_name : &str) -> Result<Vec<String>, AssemblyError> {
+    read_lines_from_file(file_name).map_err(|e| AssemblyError { file_name : file_name.to_string(), line : 0, message : e.to_string() })
+}
+
+pub fn assemble_file_with_source_map(file_name : &str) -> Result<(Vec<MachineCommand>, SourceMap), AssemblyError> {
+    let lines = read_source_lines(file_name)?;
+    let machine_lines = assemble_source_lines(file_name, &lines)?;
+    let source_map = SourceMap {
+        file_name : file_name.to_string(),
+        line_numbers : source_line_numbers(&lines),
+        lines,
+    };
+    Ok((machine_lines, source_map))
+}
+
+//Translates comment free assembly lines into machine commands; an error gives the index of the line
+pub fn assemble_lines(lines : &Vec<String>) -> Result<Vec<MachineCommand>, SyntaxError> {
+    let mut a = match Parser::new(lines) {
+        Ok(parser) => parser,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut symbol_table = SymbolTable::new(0);
+    symbol_table.pass_1(&mut a)?;
+    //pass_2 drops the labels, so the n-th instruction is the n-th line that is not one
+    let instruction_indices = lines.iter().enumerate()
+        .filter(|(_, line)| !line.starts_with('('))
+        .map(|(index, _)| index)
+        .collect::<Vec<usize>>();
+    let lines = symbol_table.pass_2(lines);
+    match Parser::new(&lines) {
+        Ok(mut a) => code_generator::generate_machine_lines(&mut a)
+            .map_err(|e| SyntaxError { index : instruction_indices[e.index], message : e.message }),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+//Assembles the lines of a .asm file, comments and all; an error names the file and the line
+pub fn assemble_source_lines(file_name : &str, lines : &[String]) -> Result<Vec<MachineCommand>, AssemblyError> {
+    let line_numbers = lines.iter().enumerate()
+        .filter(|(_, line)| !line.starts_with("//") && !line.is_empty())
+        .map(|(index, _)| index + 1)
+        .collect::<Vec<usize>>();
+    assemble_lines(&remove_comments_from_lines(&lines.to_vec()))
+        .map_err(|e| AssemblyError { file_name : file_name.to_string(), line : line_numbers[e.index], message : e.message })
+}
+
+pub fn assemble_file(file_name : &str) -> Result<Vec<MachineCommand>, AssemblyError> {
+    let lines = read_source_lines(file_name)?;
+    assemble_source_lines(file_name, &lines)
+}
+
+//Assembles the text of a .asm file
+pub fn assemble_source(file_name : &str, source : &str) -> Result<Vec<MachineCommand>, AssemblyError> {
+    let lines = source.lines().map(|s| s.trim().to_string()).collect::<Vec<String>>();
+    assemble_source_lines(file_name, &lines)
+}
+
+//Why the instruction literals of a test script do not check out
+pub enum VerifyError {
+    Unreadable(AssemblyError),
+    Mismatches(Vec<AssemblyError>),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Unreadable(e) => write!(f, "{}", e),
+            VerifyError::Mismatches(mismatches) => {
+                let lines = mismatches.iter().map(|e| e.to_string()).collect::<Vec<String>>();
+                write!(f, "{}", lines.join("\n"))
+            },
+        }
+    }
+}
+
+//The "%B<literal> ... // <mnemonic>" lines of a test script: their line numbers, literals and mnemonics
+fn commented_instructions(source : &str) -> Vec<(usize, MachineCommand, String)> {
+    source.lines().enumerate().filter_map(|(index, line)| {
+        let literal = line.split("%B").nth(1)?;
+        let (literal, comment) = literal.split_at(literal.find(|c : char| c != '0' && c != '1')?);
+        let mnemonic = comment.split("//").nth(1)?.trim();
+        let literal = u16::from_str_radix(literal, 2).ok()?;
+        Some((index + 1, literal, mnemonic.to_string()))
+    }).collect()
+}
+
+//Assembles the mnemonic in the comment of every instruction literal of a test script and checks that
+//it is the literal. Returns how many were checked, or every literal that differs. A comment is not
+//assembler source, and the project 5 scripts write a jump in lower case now and then, so the jump
+//is read without regard to case.
+pub fn verify_tst(file_name : &str) -> Result<usize, VerifyError> {
+    let mut source = String::new();
+    File::open(file_name).and_then(|mut file| file.read_to_string(&mut source))
+        .map_err(|e| VerifyError::Unreadable(AssemblyError { file_name : file_name.to_string(), line : 0, message : e.to_string() }))?;
+    let instructions = commented_instructions(&source);
+    let mut mismatches = Vec::new();
+    for (line, literal, mnemonic) in &instructions {
+        let instruction = match mnemonic.find(';') {
+            Some(semicolon) => format!("{}{}", &mnemonic[..semicolon], mnemonic[semicolon..].to_uppercase()),
+            None => mnemonic.clone(),
+        };
+        let message = match assemble_lines(&vec![instruction]).as_deref() {
+            Ok([command]) if command == literal => continue,
+            Ok([command]) => format!("{} assembles to {:016b}, not {:016b}", mnemonic, command, literal),
+            Ok(_) => format!("{} is not an instruction", mnemonic),
+            Err(e) => format!("{} does not assemble: {}", mnemonic, e.message),
+        };
+        mismatches.push(AssemblyError { file_name : file_name.to_string(), line : *line, message });
+    }
+    if !mismatches.is_empty() {
+        return Err(VerifyError::Mismatches(mismatches));
+    }
+    Ok(instructions.len())
+}
+
+//The text of a .hack file: one 16 character line of 0s and 1s per command
+pub fn machine_text(lines : &[MachineCommand]) -> String {
+    lines.iter().map(|line| format!("{:016b}\r\n", line)).collect()
+}
+
+pub fn parse_machine_line(line : &str) -> Option<MachineCommand> {
+    let line = line.trim();
+    if line.len() != 16 || !line.chars().all(|c| c == '0' || c == '1') {
+        return None;
+    }
+    u16::from_str_radix(line, 2).ok()
+}
+
+//A .hack file maps onto itself: each ROM word sits on the next line that is not blank
+pub fn read_machine_lines_with_source_map(file_name : &str) -> io::Result<(Vec<MachineCommand>, SourceMap)> {
+    let machine_lines = read_machine_lines_from_file(file_name)?;
+    let lines = read_lines_from_file(file_name)?;
+    let source_map = SourceMap {
+        file_name : file_name.to_string(),
+        line_numbers : machine_line_numbers(&lines),
+        lines,
+    };
+    Ok((machine_lines, source_map))
+}
+
+//The 1-based line of every command in the lines of a .hack file, which skips blank lines as parse_machine_lines does
+pub fn machine_line_numbers(lines : &[String]) -> Vec<usize> {
+    lines.iter().enumerate().filter(|(_, line)| !line.trim().is_empty()).map(|(index, _)| index + 1).collect()
+}
+
+pub fn read_machine_lines_from_file(file_name : &str) -> io::Result<Vec<MachineCommand>> {
+    let mut source = String::new();
+    File::open(file_name)?.read_to_string(&mut source)?;
+    parse_machine_lines(file_name, &source)
+}
+
+//The commands of the text of a .hack file, which is named in the error for a line that is not one
+pub fn parse_machine_lines(file_name : &str, source : &str) -> io::Result<Vec<MachineCommand>> {
+    let mut machine_lines = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_machine_line(line) {
+            Some(command) => machine_lines.push(command),
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("{}:{}: not a machine instruction", file_name, index + 1)));
+            }
+        }
+    }
+    Ok(machine_lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::assembler::{VerifyError, source_line_numbers, machine_line_numbers, assemble_lines, assemble_source, remove_comments_from_lines, verify_tst};
+    use crate::test_support::PROJECTS;
+
+    #[test]
+    fn source_line_numbers_test() {
+        let source = ["// Adds 2 and 3", "", "@2", "D=A // load", "(LOOP)", "", "@LOOP", "0;JMP"];
+        let lines = source.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(source_line_numbers(&lines), vec![3, 4, 7, 8]);
+        assert_eq!(assemble_lines(&remove_comments_from_lines(&lines)).ok().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn assembly_errors_test() {
+        let error = |source : &str| assemble_source("Bad.asm", source).err().unwrap().to_string();
+        assert_eq!(error("// start\n\n@2\nD=Q\n"), "Bad.asm:4: 'Q' is not a computation");
+        assert_eq!(error("@2\nD=A;JXX\n"), "Bad.asm:2: 'JXX' is not a jump");
+        assert_eq!(error("@2\nX=A\n"), "Bad.asm:2: 'X' is not a destination");
+        assert_eq!(error("@2\nfoo\n@3\n"), "Bad.asm:2: 'foo' is not an instruction or a label");
+        assert_eq!(error("(LOOP\n@LOOP\n0;JMP\n"), "Bad.asm:1: label '(LOOP' is missing its ')'");
+        assert_eq!(error("(END)\n@END\n(END)\n"), "Bad.asm:3: symbol END is already defined");
+        assert_eq!(error("(END)\n@END\n0;JMP\n@32768\n"), "Bad.asm:4: 32768 is out of range: A-instructions load 0 to 32767");
+        assert_eq!(error("@\n"), "Bad.asm:1: '@' is missing its value");
+        assert_eq!(error("@1x\n"), "Bad.asm:1: '1x' is not a number or a symbol");
+        assert_eq!(assemble_source("Good.asm", "@32767\nD=A;JGT // jump\n(END)\n@END\n0;JMP\n").ok().unwrap(),
+                   vec![0x7FFF, 0xEC11, 2, 0xEA87]);
+    }
+
+    #[test]
+    fn machine_line_numbers_test() {
+        let source = ["0000000000000010", "", "1110110000010000", "  ", "", "0000000000000011"];
+        let lines = source.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        assert_eq!(machine_line_numbers(&lines), vec![1, 3, 6]);
+    }
+
+    #[test]
+    fn verify_tst_test() {
+        for name in ["CPU.tst", "CPU-external.tst"].iter() {
+            let path = Path::new(PROJECTS).join("05").join(name).display().to_string();
+            match verify_tst(&path) {
+                Ok(checked) => assert_eq!(checked, 45, "{}", name),
+                Err(e) => panic!("{}", e),
+            }
+        }
+
+        let directory = std::env::temp_dir().join("n2t_verify_tst");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("Wrong.tst");
+        fs::write(&path, "set instruction %B1110001100000101, // D;jlt\nset instruction %B1110110000010000, // D=X\n").unwrap();
+        assert_eq!(verify_tst(&path.display().to_string()).err().unwrap().to_string(),
+                   format!("{0}:1: D;jlt assembles to 1110001100000100, not 1110001100000101\n\
+                            {0}:2: D=X does not assemble: 'X' is not a computation", path.display()));
+        assert!(matches!(verify_tst("Missing.tst"), Err(VerifyError::Unreadable(_))));
+    }
+}
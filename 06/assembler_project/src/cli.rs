@@ -0,0 +1,347 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use crate::vm;
+
+//Exit statuses: 0 when everything worked, FAILED when a check ran and found a problem (a test
+//script's comparison, a verification, a budget), USAGE for a command line that makes no sense and
+//ERROR for input that cannot be read, parsed or compiled and output that cannot be written
+pub const FAILED : i32 = 1;
+pub const USAGE : i32 = 2;
+pub const ERROR : i32 = 3;
+
+#[derive(Debug, PartialEq)]
+pub struct CliError {
+    pub status : i32,
+    pub message : String,
+}
+
+impl CliError {
+    pub fn failed(message : String) -> CliError {
+        CliError { status : FAILED, message }
+    }
+
+    pub fn usage(message : String) -> CliError {
+        CliError { status : USAGE, message }
+    }
+
+    pub fn error(message : String) -> CliError {
+        CliError { status : ERROR, message }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+//For the ? operator on the error types of the assembler, translators and simulators: their messages
+//already say where the problem is
+pub fn input_error<E : fmt::Display>(e : E) -> CliError {
+    CliError::error(format!("Error: {}", e))
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+//Where progress messages go: stderr, so that a command writing its output to stdout with -o - stays
+//usable in a pipe. --quiet drops them and --verbose adds details.
+#[derive(Copy, Clone)]
+pub struct Console {
+    pub verbosity : Verbosity,
+}
+
+impl Console {
+    pub fn info(&self, message : &str) {
+        if self.verbosity != Verbosity::Quiet {
+            eprintln!("{}", message);
+        }
+    }
+
+    pub fn detail(&self, message : &str) {
+        if self.verbosity == Verbosity::Verbose {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+//The arguments after a command's name. Options are taken out by name, each with its value if it has
+//one, and what is left must be the positional arguments; "-" is positional, standing for stdin or stdout.
+pub struct Args {
+    args : Vec<String>,
+}
+
+impl Args {
+    pub fn new(args : &[String]) -> Args {
+        Args { args : args.to_vec() }
+    }
+
+    //The first argument if it is one of a command's actions, taking it out
+    pub fn action(&mut self, actions : &[&str]) -> Option<String> {
+        match self.args.first() {
+            Some(first) if actions.contains(&first.as_str()) => Some(self.args.remove(0)),
+            _ => None,
+        }
+    }
+
+    //Whether any of the names is given, taking it out
+    pub fn flag(&mut self, names : &[&str]) -> bool {
+        let count = self.args.len();
+        self.args.retain(|arg| !names.contains(&arg.as_str()));
+        self.args.len() != count
+    }
+
+    //Whether any of the names is given among the options before the command and any "--", taking it
+    //out. Further on the same name may be another option's value, as in -o -v.
+    pub fn leading_flag(&mut self, names : &[&str]) -> bool {
+        let end = self.args.iter().position(|arg| arg == "--" || arg == "-" || !arg.starts_with('-')).unwrap_or(self.args.len());
+        let count = self.args.len();
+        let mut index = 0;
+        self.args.retain(|arg| {
+            index += 1;
+            index > end || !names.contains(&arg.as_str())
+        });
+        self.args.len() != count
+    }
+
+    //The value after the last of the names given, taking both out
+    pub fn value(&mut self, names : &[&str]) -> Result<Option<String>, CliError> {
+        let mut value = None;
+        while let Some(position) = self.args.iter().position(|arg| names.contains(&arg.as_str())) {
+            if position + 1 >= self.args.len() {
+                return Err(CliError::usage(format!("{} needs a value", self.args[position])));
+            }
+            value = Some(self.args.remove(position + 1));
+            self.args.remove(position);
+        }
+        Ok(value)
+    }
+
+    //Every value given with the name, as with --lib a --lib b
+    pub fn values(&mut self, name : &str) -> Result<Vec<String>, CliError> {
+        let mut values = Vec::new();
+        while let Some(position) = self.args.iter().position(|arg| arg == name) {
+            if position + 1 >= self.args.len() {
+                return Err(CliError::usage(format!("{} needs a value", name)));
+            }
+            values.push(self.args.remove(position + 1));
+            self.args.remove(position);
+        }
+        Ok(values)
+    }
+
+    pub fn number(&mut self, names : &[&str]) -> Result<Option<u64>, CliError> {
+        match self.value(names)? {
+            Some(value) => value.parse::<u64>().map(Some).map_err(|_e| CliError::usage(format!("{} needs a number, not {:?}", names[0], value))),
+            None => Ok(None),
+        }
+    }
+
+    //What is left once the options are taken out: between min and max arguments, none of them an option
+    pub fn positional(self, min : usize, max : usize) -> Result<Vec<String>, CliError> {
+        if let Some(option) = self.args.iter().find(|arg| arg.starts_with('-') && arg.len() > 1) {
+            return Err(CliError::usage(format!("unknown option {}", option)));
+        }
+        if self.args.len() < min {
+            return Err(CliError::usage(String::from("missing argument")));
+        }
+        if self.args.len() > max {
+            return Err(CliError::usage(format!("unexpected argument {}", self.args[max])));
+        }
+        Ok(self.args)
+    }
+}
+
+//A file's text, or stdin's for -
+pub fn read_input(name : &str) -> Result<String, CliError> {
+    let mut text = String::new();
+    if name == "-" {
+        io::stdin().read_to_string(&mut text).map_err(|e| CliError::error(format!("Error reading stdin: {}", e)))?;
+    }
+    else {
+        text = fs::read_to_string(name).map_err(|e| CliError::error(format!("Error reading {:?}: {}", name, e)))?;
+    }
+    Ok(text)
+}
+
+//Writes text to a file, or to stdout for -, and says so unless it went to stdout
+pub fn write_output(name : &str, text : &str, console : Console) -> Result<(), CliError> {
+    if name == "-" {
+        return io::stdout().write_all(text.as_bytes()).map_err(|e| CliError::error(format!("Error writing stdout: {}", e)));
+    }
+    fs::write(name, text).map_err(|e| CliError::error(format!("Error creating file {:?}: {}", name, e)))?;
+    console.info(&format!("Successfully wrote file {}", name));
+    Ok(())
+}
+
+//Where a command's output goes without -o: Foo.asm becomes Foo.hack next to it, a directory Foo
+//becomes Foo/Foo.hack, and stdin's output goes to stdout
+pub fn derived_output(input : &str, extension : &str) -> String {
+    if input == "-" {
+        return String::from("-");
+    }
+    vm::output_path(Path::new(input), extension).display().to_string()
+}
+
+pub const USAGE_TEXT : &str = "\
+Usage: n2t [--quiet | --verbose] <command> [arguments]
+
+Commands:
+    asm      Assemble a .asm file into a .hack file
+    disasm   Disassemble a .hack file into a .asm file
+    run      Run a program on the CPU emulator, or a VM program on the VM emulator
+    test     Run a test script with the simulator it loads
+    vm       Translate .vm files, or measure or check their translation
+    jack     Compile, check, analyze or build Jack programs
+    hdl      Check, simulate, verify, export or measure HDL chips
+
+Options:
+    -h, --help      Show help; n2t <command> --help shows the command's
+    -q, --quiet     Print nothing but results and errors
+    -v, --verbose   Print more about what is done
+    -o <file>       Where to write a command's output; - is stdout
+    -h, -q and -v go before the command; -- ends them. An input file named - is read from stdin.
+
+Exit status: 0 on success, 1 when a test, verification or budget fails,
+2 for a bad command line and 3 for input that cannot be read or translated.
+";
+
+//The help of each command
+pub fn command_help(command : &str) -> Option<&'static str> {
+    let help = match command {
+        "asm" => "\
+Usage: n2t asm <file.asm | -> [-o <file.hack | ->]
+       n2t asm verify <script.tst>...
+Assembles a program; the output is Foo.hack next to Foo.asm unless -o says otherwise.
+verify assembles the mnemonic commented after each %B instruction literal of a test script
+and fails on every literal it does not match.
+",
+        "disasm" => "\
+Usage: n2t disasm <file.hack | -> [-o <file.asm | ->]
+Turns machine code back into assembly; the output is Foo.dis.asm next to Foo.hack unless -o says otherwise.
+",
+        "run" => "\
+Usage: n2t run <program> [options]
+A .hack, .asm or .snap program runs on the CPU emulator, which prints the registers and RAM[0..15]:
+    --cycles <n>     Run n instructions (1000 by default)
+    --debug          Step through the program in the debugger instead
+    --vcd <file>     Write A, D, PC and the RAM words written as a VCD file, for cycles
+    --from <n>       n (0 by default)
+    --to <n>         to n (--cycles by default)
+A .vm file, a directory of .vm files or a Jack program runs on the VM emulator with the builtin OS:
+    --steps <n>      Stop after n VM commands (10000000 by default)
+    --keys <keys>    Type these keys as the program asks for them
+    --jack-os <list> Run these OS classes (a comma separated list, or all) from their Jack code
+",
+        "test" => "\
+Usage: n2t test <script.tst> [options]
+Runs a test script with the simulator for what it loads (.hdl, .vm or a directory, or a .hack or .asm program):
+    --coverage       Print which instructions of a CPU program the script ran
+    --lcov <file>    and write them as an lcov tracefile
+    --vcd <file>     Write the chip a hardware script loads as a VCD file, for clock cycles
+    --from <n>       n (0 by default)
+    --to <n>         to n (the end by default)
+    --keys <keys>    Hold down these keys on the Keyboard part, the next one as each while loop of a
+                     hardware script starts; 05/Memory.tst waits for K, then Y, so it needs --keys KY
+",
+        "vm" => "\
+Usage: n2t vm [translate] <file.vm | dir | -> [-o <file.asm | file.hack | ->] [--optimize] [--bootstrap | --no-bootstrap]
+       n2t vm size <file.vm | dir>...
+       n2t vm check <file.vm | dir> [--steps <n>]
+translate writes Foo.asm (or Dir/Dir.asm); bootstrap code comes with programs that define Sys.init.
+size compares the naive and the optimized translation; check runs the translation against the VM emulator.
+",
+        "jack" => "\
+Usage: n2t jack [compile] <file.jack | dir>
+       n2t jack check <file.jack | dir> [--os <dir>]
+       n2t jack xml <file.jack | dir> [--tokens]
+       n2t jack build <dir> [-o <file.hack>] [--os <dir>] [--keep] [--optimize]
+compile writes Foo.vm next to each Foo.jack; xml writes the parse tree (or FooT.xml tokens);
+build compiles, links the OS and assembles into Dir/Dir.hack.
+",
+        "hdl" => "\
+Usage: n2t hdl <action> <chip.hdl | dir> [--lib <dir>]... [--builtin <Chip,...>] [options]
+Actions:
+    check                     Parse chips and report syntax errors
+    lint                      Report unused, unconnected, mis-sized and shadowing pins
+    eval <pin=value>...       Evaluate a chip and print its outputs
+    run [program] [address=value]... [--cycles <n>]
+                              Clock a computer chip with a program in its ROM32K
+    verify [--samples <n>] [--seed <n>]
+                              Compare chips with the builtin Rust models
+    stats [--budget <file>]   Count Nand gates and measure combinational depth
+    verilog [-o <file.v>] [--rom <file.hack>]
+                              Export a chip as Verilog
+    dot [-o <file.dot>] [--flat]
+                              Draw a chip as a DOT graph
+Parts come from the chip's directory, then each --lib directory, then the builtin chips;
+--builtin takes the named chips from the builtin ones even where an .hdl file exists.
+",
+        _ => return None,
+    };
+    Some(help)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{derived_output, Args, CliError, USAGE};
+
+    fn args(line : &str) -> Args {
+        Args::new(&line.split_whitespace().map(String::from).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn options_test() {
+        let mut arguments = args("Foo.asm -o - --lib a --cycles 7 --lib b --flat");
+        assert!(arguments.flag(&["--flat"]));
+        assert!(!arguments.flag(&["--debug"]));
+        assert_eq!(arguments.value(&["-o", "--output"]).ok().unwrap(), Some(String::from("-")));
+        assert_eq!(arguments.values("--lib").ok().unwrap(), vec!["a", "b"]);
+        assert_eq!(arguments.number(&["--cycles"]).ok().unwrap(), Some(7));
+        assert_eq!(arguments.positional(1, 1).ok().unwrap(), vec!["Foo.asm"]);
+    }
+
+    #[test]
+    fn leading_flags_test() {
+        let mut arguments = args("-q asm Foo.asm -o -v");
+        assert!(arguments.leading_flag(&["-q", "--quiet"]));
+        assert!(!arguments.leading_flag(&["-v", "--verbose"]));
+        assert_eq!(arguments.action(&["asm"]), Some(String::from("asm")));
+        assert_eq!(arguments.value(&["-o"]).ok().unwrap(), Some(String::from("-v")));
+        assert_eq!(arguments.positional(1, 1).ok().unwrap(), vec!["Foo.asm"]);
+
+        let mut arguments = args("-v -- -h");
+        assert!(arguments.leading_flag(&["-v", "--verbose"]));
+        assert!(!arguments.leading_flag(&["-h", "--help"]));
+        assert_eq!(arguments.action(&["--"]), Some(String::from("--")));
+        assert_eq!(arguments.action(&["-h"]), Some(String::from("-h")));
+    }
+
+    fn usage<T>(message : &str) -> Result<T, CliError> {
+        Err(CliError { status : USAGE, message : message.to_string() })
+    }
+
+    #[test]
+    fn usage_errors_test() {
+        assert_eq!(args("Foo.asm -o").value(&["-o"]), usage("-o needs a value"));
+        assert_eq!(args("--cycles many").number(&["--cycles"]), usage("--cycles needs a number, not \"many\""));
+        assert_eq!(args("Foo.asm --fast").positional(1, 1), usage("unknown option --fast"));
+        assert_eq!(args("").positional(1, 1), usage("missing argument"));
+        assert_eq!(args("a b").positional(1, 1), usage("unexpected argument b"));
+        assert_eq!(args("-").positional(1, 1).ok().unwrap(), vec!["-"]);
+    }
+
+    #[test]
+    fn derived_output_test() {
+        assert_eq!(derived_output("prog/Foo.asm", "hack"), "prog/Foo.hack");
+        assert_eq!(derived_output("Foo.hack", "dis.asm"), "Foo.dis.asm");
+        assert_eq!(derived_output("-", "hack"), "-");
+    }
+}
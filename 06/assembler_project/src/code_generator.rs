@@ -4,6 +4,9 @@ use crate::parser::*;
 type MachineCommand = u16;
 
 pub fn dest(dest : String) -> Option<u16> {
+    if !dest.chars().all(|c| c == 'A' || c == 'D' || c == 'M') || dest.chars().any(|c| dest.matches(c).count() > 1) {
+        return None;
+    }
     let mut ret : u16 = 0;
     if dest.contains('M') {
         ret += 1;
@@ -42,14 +45,16 @@ pub fn comp(comp : String) -> Option<u16> {
         "D|A" | "D|M" | "A|D" | "M|D" => Some(0x540),
         _ => None,
     };
-    if comp.contains("M") && ret.is_some(){
-        return Some(ret.unwrap() + 0x1000);
+    if let Some(value) = ret {
+        if comp.contains('M') {
+            return Some(value + 0x1000);
+        }
     }
     ret
 }
 
 pub fn jump(jump : String) -> Option<u16> {
-    return match jump.as_str() {
+    match jump.as_str() {
         "JGT" => Some(1),
         "JEQ" => Some(2),
         "JGE" => Some(3),
@@ -61,35 +66,30 @@ pub fn jump(jump : String) -> Option<u16> {
     }
 }
 
-pub fn generate_machine_lines(parser : &mut Parser) -> Vec<MachineCommand> {
+pub fn generate_machine_lines(parser : &mut Parser) -> Result<Vec<MachineCommand>, SyntaxError> {
     let mut machine_lines = Vec::new();
     loop {
-        let mut command : MachineCommand = 0;
+        let error = |message : String| SyntaxError { index : parser.line_index(), message };
+        let mut command : MachineCommand = 0xE000;
         match parser.command_type() {
             Some(CommandType::A) => {
-                command = parser.symbol().unwrap().parse::<u16>().unwrap();
+                let value = parser.symbol().unwrap();
+                command = match value.parse::<u16>() {
+                    Ok(address) if address < 0x8000 => address,
+                    _ => return Err(error(format!("{} is out of range: A-instructions load 0 to 32767", value))),
+                };
             },
             Some(CommandType::C) => {
                 if let Some(d) = parser.dest() {
-                    if let Some(val) = dest(d) {
-                        command += val;
-                    }
-                }
-                if let Some(c) = parser.comp() {
-                    if let Some(val) = comp(c) {
-                        command += val;
-                    }
+                    command += dest(d.clone()).ok_or_else(|| error(format!("'{}' is not a destination", d)))?;
                 }
+                let c = parser.comp().unwrap_or_default();
+                command += comp(c.clone()).ok_or_else(|| error(format!("'{}' is not a computation", c)))?;
                 if let Some(j) = parser.jump() {
-                    if let Some(val) = jump(j) {
-                        command += val;
-                    }
+                    command += jump(j.clone()).ok_or_else(|| error(format!("'{}' is not a jump", j)))?;
                 }
-                command += 0xE000;
             },
-            _ => {
-                println!("BAD COMMAND");
-            }
+            _ => return Err(error(String::from("not an instruction"))),
         }
 
         machine_lines.push(command);
@@ -97,5 +97,5 @@ pub fn generate_machine_lines(parser : &mut Parser) -> Vec<MachineCommand> {
             break;
         }
     }
-    machine_lines
-}
\ No newline at end of file
+    Ok(machine_lines)
+}
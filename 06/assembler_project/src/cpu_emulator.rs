@@ -0,0 +1,185 @@
+pub mod snapshot;
+pub mod debugger;
+pub mod simulator;
+pub mod coverage;
+pub mod trace;
+
+use coverage::Coverage;
+
+pub const MEMORY_SIZE : usize = 0x8000;
+
+const ADDRESS_MASK : u16 = 0x7FFF;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct MemoryWrite {
+    pub address : u16,
+    pub old_value : u16,
+    pub new_value : u16,
+}
+
+//The complete state of a Hack computer: ROM, RAM (including the memory maps) and registers
+#[derive(Clone, PartialEq, Debug)]
+pub struct Machine {
+    pub rom : Vec<u16>,
+    pub ram : Vec<u16>,
+    pub a : u16,
+    pub d : u16,
+    pub pc : u16,
+    pub cycles : u64,
+    pub coverage : Option<Coverage>,
+}
+
+//Computes the Hack ALU function selected by the six control bits zx nx zy ny f no
+pub fn alu(x : u16, y : u16, control : u16) -> u16 {
+    let mut x = if control & 0x20 > 0 { 0 } else { x };
+    if control & 0x10 > 0 {
+        x = !x;
+    }
+    let mut y = if control & 0x08 > 0 { 0 } else { y };
+    if control & 0x04 > 0 {
+        y = !y;
+    }
+    let out = if control & 0x02 > 0 { x.wrapping_add(y) } else { x & y };
+    if control & 0x01 > 0 {
+        !out
+    }
+    else {
+        out
+    }
+}
+
+//Decides whether the jump bits of a C-instruction select a jump for the given ALU output
+pub fn jump_taken(jump_bits : u16, out : u16) -> bool {
+    let negative = out & 0x8000 > 0;
+    let zero = out == 0;
+    (jump_bits & 4 > 0 && negative) || (jump_bits & 2 > 0 && zero) || (jump_bits & 1 > 0 && !negative && !zero)
+}
+
+impl Machine {
+    pub fn new() -> Machine {
+        Machine {
+            rom : vec![0; MEMORY_SIZE],
+            ram : vec![0; MEMORY_SIZE],
+            a : 0,
+            d : 0,
+            pc : 0,
+            cycles : 0,
+            coverage : None,
+        }
+    }
+
+    pub fn load_program(&mut self, program : &[u16]) {
+        self.rom = vec![0; MEMORY_SIZE];
+        let length = program.len().min(MEMORY_SIZE);
+        self.rom[..length].copy_from_slice(&program[..length]);
+    }
+
+    pub fn read(&self, address : u16) -> u16 {
+        self.ram[(address & ADDRESS_MASK) as usize]
+    }
+
+    pub fn write(&mut self, address : u16, value : u16) {
+        self.ram[(address & ADDRESS_MASK) as usize] = value;
+    }
+
+    pub fn current_instruction(&self) -> u16 {
+        self.rom[(self.pc & ADDRESS_MASK) as usize]
+    }
+
+    //Executes one instruction and returns the memory write it made, if any
+    pub fn step(&mut self) -> Option<MemoryWrite> {
+        let instruction = self.current_instruction();
+        let pc = self.pc & ADDRESS_MASK;
+        self.cycles += 1;
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1) & ADDRESS_MASK;
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(pc, instruction, false);
+            }
+            return None;
+        }
+
+        let y = if instruction & 0x1000 > 0 { self.read(self.a) } else { self.a };
+        let out = alu(self.d, y, (instruction >> 6) & 0x3F);
+        let address = self.a;
+
+        let mut memory_write = None;
+        if instruction & 0x08 > 0 {
+            memory_write = Some(MemoryWrite {
+                address : address & ADDRESS_MASK,
+                old_value : self.read(address),
+                new_value : out,
+            });
+            self.write(address, out);
+        }
+        if instruction & 0x20 > 0 {
+            self.a = out;
+        }
+        if instruction & 0x10 > 0 {
+            self.d = out;
+        }
+
+        let jumped = jump_taken(instruction & 0x07, out);
+        if jumped {
+            self.pc = address & ADDRESS_MASK;
+        }
+        else {
+            self.pc = self.pc.wrapping_add(1) & ADDRESS_MASK;
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(pc, instruction, jumped);
+        }
+        memory_write
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu_emulator::{Machine, alu};
+    use crate::assembler::assemble_lines;
+
+    fn machine_for(source : &[&str]) -> Machine {
+        let lines = source.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let mut machine = Machine::new();
+        machine.load_program(&assemble_lines(&lines).ok().unwrap());
+        machine
+    }
+
+    #[test]
+    fn alu_test() {
+        assert_eq!(alu(5, 3, 0b000010), 8);
+        assert_eq!(alu(5, 3, 0b010011), 2);
+        assert_eq!(alu(5, 3, 0b000111), 0xFFFE);
+        assert_eq!(alu(5, 3, 0b101010), 0);
+        assert_eq!(alu(5, 3, 0b111111), 1);
+        assert_eq!(alu(5, 3, 0b010101), 7);
+    }
+
+    #[test]
+    fn add_program_test() {
+        let mut machine = machine_for(&["@2", "D=A", "@3", "D=D+A", "@0", "M=D"]);
+        (0..6).for_each(|_| { machine.step(); });
+        assert_eq!(machine.ram[0], 5);
+        assert_eq!(machine.pc, 6);
+        assert_eq!(machine.cycles, 6);
+    }
+
+    #[test]
+    fn memory_write_uses_old_a_test() {
+        let mut machine = machine_for(&["@7", "AM=A+1", "@12", "D;JMP"]);
+        machine.step();
+        let write = machine.step().unwrap();
+        assert_eq!(write.address, 7);
+        assert_eq!(write.new_value, 8);
+        assert_eq!(machine.a, 8);
+        (0..2).for_each(|_| { machine.step(); });
+        assert_eq!(machine.pc, 12);
+    }
+}
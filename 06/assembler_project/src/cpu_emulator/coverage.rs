@@ -0,0 +1,181 @@
+use crate::assembler::SourceMap;
+use crate::cpu_emulator::MEMORY_SIZE;
+
+//Execution counts per ROM address, plus taken/not-taken counts for conditional jumps
+#[derive(Clone, PartialEq, Debug)]
+pub struct Coverage {
+    pub hits : Vec<u64>,
+    pub taken : Vec<u64>,
+    pub not_taken : Vec<u64>,
+}
+
+pub fn is_conditional_jump(instruction : u16) -> bool {
+    let jump_bits = instruction & 0x07;
+    instruction & 0x8000 > 0 && jump_bits != 0 && jump_bits != 0x07
+}
+
+struct LineCoverage<'a> {
+    line_number : usize,
+    hits : u64,
+    branch : Option<(u64, u64)>,
+    source : &'a str,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            hits : vec![0; MEMORY_SIZE],
+            taken : vec![0; MEMORY_SIZE],
+            not_taken : vec![0; MEMORY_SIZE],
+        }
+    }
+
+    pub fn record(&mut self, address : u16, instruction : u16, jumped : bool) {
+        let address = address as usize;
+        self.hits[address] += 1;
+        if is_conditional_jump(instruction) {
+            if jumped {
+                self.taken[address] += 1;
+            }
+            else {
+                self.not_taken[address] += 1;
+            }
+        }
+    }
+
+    fn lines<'a>(&self, rom : &[u16], source_map : &'a SourceMap) -> Vec<LineCoverage<'a>> {
+        source_map.line_numbers.iter().enumerate().map(|(address, line_number)| {
+            let branch = if is_conditional_jump(rom[address]) {
+                Some((self.taken[address], self.not_taken[address]))
+            }
+            else {
+                None
+            };
+            LineCoverage {
+                line_number : *line_number,
+                hits : self.hits[address],
+                branch,
+                source : source_map.lines.get(line_number - 1).map(|s| s.as_str()).unwrap_or(""),
+            }
+        }).collect()
+    }
+
+    //An annotated listing of the program followed by instruction and branch totals
+    pub fn text_report(&self, rom : &[u16], source_map : &SourceMap) -> String {
+        let lines = self.lines(rom, source_map);
+        let mut report = format!("Coverage for {}\n", source_map.file_name);
+        let mut branches_covered = 0;
+        for line in &lines {
+            let hits = if line.hits == 0 { String::from("#####") } else { line.hits.to_string() };
+            report.push_str(&format!("{:>9}:{:>6}: {}", hits, line.line_number, line.source));
+            if let Some((taken, not_taken)) = line.branch {
+                report.push_str(&format!("    [taken {}, not taken {}]", taken, not_taken));
+                branches_covered += (taken > 0) as usize + (not_taken > 0) as usize;
+            }
+            report.push('\n');
+        }
+        let executed = lines.iter().filter(|l| l.hits > 0).count();
+        let branches = lines.iter().filter(|l| l.branch.is_some()).count() * 2;
+        report.push_str(&format!("Instructions executed: {} of {} ({})\n", executed, lines.len(), percentage(executed, lines.len())));
+        report.push_str(&format!("Branches covered: {} of {} ({})\n", branches_covered, branches, percentage(branches_covered, branches)));
+        report
+    }
+
+    //The lcov tracefile format read by genhtml and most coverage viewers
+    pub fn lcov_report(&self, rom : &[u16], source_map : &SourceMap) -> String {
+        let lines = self.lines(rom, source_map);
+        let mut report = format!("TN:\nSF:{}\n", source_map.file_name);
+        let mut branches = 0;
+        let mut branches_hit = 0;
+        for line in &lines {
+            if let Some((taken, not_taken)) = line.branch {
+                for (index, count) in [taken, not_taken].iter().enumerate() {
+                    if line.hits == 0 {
+                        report.push_str(&format!("BRDA:{},0,{},-\n", line.line_number, index));
+                    }
+                    else {
+                        report.push_str(&format!("BRDA:{},0,{},{}\n", line.line_number, index, count));
+                    }
+                    branches += 1;
+                    branches_hit += (*count > 0) as usize;
+                }
+            }
+        }
+        report.push_str(&format!("BRF:{}\nBRH:{}\n", branches, branches_hit));
+        for line in &lines {
+            report.push_str(&format!("DA:{},{}\n", line.line_number, line.hits));
+        }
+        let executed = lines.iter().filter(|l| l.hits > 0).count();
+        report.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", lines.len(), executed));
+        report
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+fn percentage(part : usize, whole : usize) -> String {
+    if whole == 0 {
+        return String::from("-");
+    }
+    format!("{:.1}%", part as f64 * 100.0 / whole as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::{SourceMap, source_line_numbers, assemble_lines, remove_comments_from_lines};
+    use crate::cpu_emulator::Machine;
+    use crate::cpu_emulator::coverage::Coverage;
+
+    fn run_countdown() -> (Machine, SourceMap) {
+        let source = ["// counts R0 down to zero", "(LOOP)", "@0", "M=M-1", "D=M", "@LOOP", "D;JGT",
+                      "(END)", "@END", "0;JMP", "@5", "M=1"];
+        let lines = source.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let mut machine = Machine::new();
+        machine.load_program(&assemble_lines(&remove_comments_from_lines(&lines)).ok().unwrap());
+        machine.coverage = Some(Coverage::new());
+        machine.ram[0] = 3;
+        for _ in 0..20 {
+            machine.step();
+        }
+        let source_map = SourceMap {
+            file_name : String::from("Countdown.asm"),
+            line_numbers : source_line_numbers(&lines),
+            lines,
+        };
+        (machine, source_map)
+    }
+
+    #[test]
+    fn hit_and_branch_counts_test() {
+        let (machine, _) = run_countdown();
+        let coverage = machine.coverage.as_ref().unwrap();
+        assert_eq!(coverage.hits[0], 3);
+        assert_eq!(coverage.taken[4], 2);
+        assert_eq!(coverage.not_taken[4], 1);
+        assert_eq!(coverage.hits[7], 0);
+    }
+
+    #[test]
+    fn lcov_report_test() {
+        let (machine, source_map) = run_countdown();
+        let report = machine.coverage.as_ref().unwrap().lcov_report(&machine.rom, &source_map);
+        assert!(report.starts_with("TN:\nSF:Countdown.asm\n"));
+        assert!(report.contains("BRDA:7,0,0,2\nBRDA:7,0,1,1\nBRF:2\nBRH:2\n"));
+        assert!(report.contains("DA:3,3\n"));
+        assert!(report.contains("DA:11,0\n"));
+        assert!(report.ends_with("LF:9\nLH:7\nend_of_record\n"));
+    }
+
+    #[test]
+    fn text_report_test() {
+        let (machine, source_map) = run_countdown();
+        let report = machine.coverage.as_ref().unwrap().text_report(&machine.rom, &source_map);
+        assert!(report.contains("        3:     7: D;JGT    [taken 2, not taken 1]\n"));
+        assert!(report.contains("    #####:    12: M=1\n"));
+        assert!(report.contains("Instructions executed: 7 of 9 (77.8%)\n"));
+    }
+}
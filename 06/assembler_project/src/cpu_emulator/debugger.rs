@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use crate::cpu_emulator::{Machine, MemoryWrite};
+use crate::cpu_emulator::snapshot::{save_snapshot, load_snapshot};
+
+pub const DEFAULT_HISTORY_CAPACITY : usize = 100_000;
+
+//How far run goes without a count when no breakpoint or halt loop stops it
+pub const DEFAULT_RUN_LIMIT : u64 = 10_000_000;
+
+//Everything needed to undo one executed instruction
+#[derive(Copy, Clone, Debug)]
+struct StepDelta {
+    pc : u16,
+    a : u16,
+    d : u16,
+    write : Option<MemoryWrite>,
+}
+
+//Why run stopped, with the steps it took
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RunStop {
+    Breakpoint(u64),
+    Halted(u64),
+    Limit(u64),
+}
+
+pub struct Debugger {
+    pub machine : Machine,
+    pub breakpoints : Vec<u16>,
+    history : VecDeque<StepDelta>,
+    history_capacity : usize,
+}
+
+impl Debugger {
+    pub fn new(machine : Machine, history_capacity : usize) -> Debugger {
+        Debugger {
+            machine,
+            breakpoints : Vec::new(),
+            history : VecDeque::new(),
+            history_capacity,
+        }
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn step(&mut self) {
+        let delta = StepDelta {
+            pc : self.machine.pc,
+            a : self.machine.a,
+            d : self.machine.d,
+            write : None,
+        };
+        let write = self.machine.step();
+        if self.history_capacity == 0 {
+            return;
+        }
+        if self.history.len() == self.history_capacity {
+            self.history.pop_front();
+        }
+        self.history.push_back(StepDelta { write, ..delta });
+    }
+
+    //Undoes the most recent instruction; returns false once the history is exhausted
+    pub fn step_back(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some(delta) => {
+                self.undo(&delta);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn undo(&mut self, delta : &StepDelta) {
+        if let Some(write) = delta.write {
+            self.machine.write(write.address, write.old_value);
+        }
+        self.machine.pc = delta.pc;
+        self.machine.a = delta.a;
+        self.machine.d = delta.d;
+        self.machine.cycles -= 1;
+    }
+
+    //Whether the machine sits in the loop programs end with: an unconditional jump to itself, or
+    //to an @address just before it that loads that address, as in (END) @END 0;JMP
+    pub fn halted(&self) -> bool {
+        let machine = &self.machine;
+        let instruction = machine.current_instruction();
+        if instruction & 0x8000 == 0 || instruction & 0x07 != 0x07 || instruction & 0x20 != 0 {
+            return false;
+        }
+        let target = machine.a & 0x7FFF;
+        target == machine.pc || (target.wrapping_add(1) == machine.pc && machine.rom[target as usize] == target)
+    }
+
+    //Runs until a breakpoint is hit, the program halts or the step budget is used up
+    pub fn run(&mut self, max_steps : u64) -> RunStop {
+        for taken in 0..max_steps {
+            if taken > 0 && self.breakpoints.contains(&self.machine.pc) {
+                return RunStop::Breakpoint(taken);
+            }
+            if self.halted() {
+                return RunStop::Halted(taken);
+            }
+            self.step();
+        }
+        RunStop::Limit(max_steps)
+    }
+
+    //Steps backwards until the instruction that last wrote the address has been undone,
+    //leaving the machine just before that write. Returns false if the write is not in the history.
+    pub fn run_back_to_write(&mut self, address : u16) -> bool {
+        let found = self.history.iter()
+            .rev()
+            .position(|delta| delta.write.map(|w| w.address == address).unwrap_or(false));
+        match found {
+            Some(position) => {
+                for _ in 0..=position {
+                    self.step_back();
+                }
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+fn print_state(debugger : &Debugger, output : &mut dyn Write) {
+    let machine = &debugger.machine;
+    writeln!(output, "pc={} a={} d={} cycles={} instruction={:016b} history={}",
+             machine.pc, machine.a, machine.d as i16, machine.cycles, machine.current_instruction(),
+             debugger.history_len()).ok();
+}
+
+fn parse_number(text : Option<&&str>, default : u64) -> Option<u64> {
+    match text {
+        Some(text) => text.parse::<u64>().ok(),
+        None => Some(default),
+    }
+}
+
+//A line based command loop around the debugger
+pub fn run_repl(debugger : &mut Debugger, input : &mut dyn BufRead, output : &mut dyn Write) {
+    let mut line = String::new();
+    loop {
+        write!(output, "(hack) ").ok();
+        output.flush().ok();
+        line.clear();
+        if input.read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let words = line.split_whitespace().collect::<Vec<&str>>();
+        let command = match words.first() {
+            Some(command) => *command,
+            None => continue,
+        };
+        match command {
+            "step" | "s" => match parse_number(words.get(1), 1) {
+                Some(count) => (0..count).for_each(|_| debugger.step()),
+                None => { writeln!(output, "usage: step [count]").ok(); },
+            },
+            "back" | "b" => match parse_number(words.get(1), 1) {
+                Some(count) => {
+                    for _ in 0..count {
+                        if !debugger.step_back() {
+                            writeln!(output, "history exhausted").ok();
+                            break;
+                        }
+                    }
+                },
+                None => { writeln!(output, "usage: back [count]").ok(); },
+            },
+            "run" | "r" => match parse_number(words.get(1), DEFAULT_RUN_LIMIT) {
+                Some(count) => {
+                    let message = match debugger.run(count) {
+                        RunStop::Breakpoint(taken) => format!("breakpoint at {} after {} steps", debugger.machine.pc, taken),
+                        RunStop::Halted(taken) => format!("halted in the loop at {} after {} steps", debugger.machine.pc, taken),
+                        RunStop::Limit(taken) => format!("stopped after {} steps", taken),
+                    };
+                    writeln!(output, "{}", message).ok();
+                },
+                None => { writeln!(output, "usage: run [count]").ok(); },
+            },
+            "last-write" | "lw" => match words.get(1).and_then(|w| w.parse::<u16>().ok()) {
+                Some(address) => {
+                    if !debugger.run_back_to_write(address) {
+                        writeln!(output, "no write to {} in history", address).ok();
+                    }
+                },
+                None => { writeln!(output, "usage: last-write address").ok(); },
+            },
+            "break" => match words.get(1).and_then(|w| w.parse::<u16>().ok()) {
+                Some(address) => debugger.breakpoints.push(address),
+                None => { writeln!(output, "usage: break address").ok(); },
+            },
+            "ram" => match words.get(1).and_then(|w| w.parse::<u16>().ok()) {
+                Some(address) => {
+                    writeln!(output, "RAM[{}]={}", address, debugger.machine.read(address) as i16).ok();
+                },
+                None => { writeln!(output, "usage: ram address").ok(); },
+            },
+            "save" => match words.get(1) {
+                Some(file_name) => {
+                    if let Err(e) = save_snapshot(&debugger.machine, file_name) {
+                        writeln!(output, "Error saving snapshot {}: {}", file_name, e).ok();
+                    }
+                },
+                None => { writeln!(output, "usage: save file").ok(); },
+            },
+            "restore" => match words.get(1) {
+                Some(file_name) => match load_snapshot(file_name) {
+                    Ok(machine) => *debugger = Debugger::new(machine, debugger.history_capacity),
+                    Err(e) => { writeln!(output, "Error loading snapshot {}: {}", file_name, e).ok(); },
+                },
+                None => { writeln!(output, "usage: restore file").ok(); },
+            },
+            "quit" | "q" => break,
+            _ => {
+                writeln!(output, "commands: step [n], back [n], run [n], last-write addr, break addr, ram addr, save file, restore file, quit").ok();
+                continue;
+            },
+        }
+        print_state(debugger, output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu_emulator::Machine;
+    use crate::cpu_emulator::debugger::{Debugger, RunStop, run_repl};
+    use crate::cpu_emulator::snapshot::{snapshot_to_string, snapshot_from_string};
+    use crate::assembler::assemble_lines;
+
+    fn counting_machine() -> Machine {
+        let source = ["@5", "M=0", "(LOOP)", "@5", "M=M+1", "@20", "M=D", "@LOOP", "0;JMP"];
+        let lines = source.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let mut machine = Machine::new();
+        machine.load_program(&assemble_lines(&lines).ok().unwrap());
+        machine
+    }
+
+    #[test]
+    fn step_back_restores_state_test() {
+        let mut debugger = Debugger::new(counting_machine(), 100);
+        let start = debugger.machine.clone();
+        debugger.run(30);
+        assert_eq!(debugger.machine.ram[5], 5);
+        while debugger.step_back() {}
+        assert_eq!(debugger.machine, start);
+    }
+
+    #[test]
+    fn run_back_to_write_test() {
+        let mut debugger = Debugger::new(counting_machine(), 100);
+        debugger.run(16);
+        assert_eq!(debugger.machine.ram[5], 3);
+        assert!(debugger.run_back_to_write(5));
+        assert_eq!(debugger.machine.ram[5], 2);
+        assert_eq!(debugger.machine.pc, 3);
+        assert!(!debugger.run_back_to_write(1000));
+    }
+
+    #[test]
+    fn run_stops_at_halt_loop_test() {
+        let source = ["@3", "D=A", "@0", "M=D", "(END)", "@END", "0;JMP"];
+        let lines = source.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let mut machine = Machine::new();
+        machine.load_program(&assemble_lines(&lines).ok().unwrap());
+        let mut debugger = Debugger::new(machine.clone(), 100);
+        assert_eq!(debugger.run(1000), RunStop::Halted(5));
+        assert_eq!(debugger.machine.ram[0], 3);
+        let mut output = Vec::new();
+        run_repl(&mut Debugger::new(machine, 100), &mut "run\n".as_bytes(), &mut output);
+        assert!(String::from_utf8(output).unwrap().contains("halted in the loop at 5 after 5 steps"));
+        assert_eq!(Debugger::new(counting_machine(), 100).run(40), RunStop::Limit(40));
+    }
+
+    #[test]
+    fn history_capacity_test() {
+        let mut debugger = Debugger::new(counting_machine(), 4);
+        debugger.run(10);
+        assert_eq!(debugger.history_len(), 4);
+        assert_eq!(debugger.machine.cycles, 10);
+    }
+
+    #[test]
+    fn snapshot_round_trip_test() {
+        let mut debugger = Debugger::new(counting_machine(), 0);
+        debugger.run(23);
+        let text = snapshot_to_string(&debugger.machine);
+        let restored = snapshot_from_string(&text).ok().unwrap();
+        assert_eq!(restored, debugger.machine);
+        assert!(snapshot_from_string("pc 1").is_err());
+    }
+}
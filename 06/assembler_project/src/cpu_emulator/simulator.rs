@@ -0,0 +1,170 @@
+use std::path::Path;
+use crate::assembler::{SourceMap, assemble_file_with_source_map, read_machine_lines_with_source_map};
+use crate::cpu_emulator::Machine;
+use crate::cpu_emulator::coverage::Coverage;
+use crate::cpu_emulator::snapshot::load_snapshot;
+use crate::test_script::{Simulator, Value, split_indexed, resolve_path};
+
+//Runs CPU emulator test scripts (load Prog.hack/.asm/.snap, ticktock, RAM[i], A, D, PC)
+pub struct CpuSimulator {
+    pub machine : Machine,
+    pub source_map : Option<SourceMap>,
+    collect_coverage : bool,
+}
+
+impl CpuSimulator {
+    pub fn new() -> CpuSimulator {
+        CpuSimulator {
+            machine : Machine::new(),
+            source_map : None,
+            collect_coverage : false,
+        }
+    }
+
+    //Every program loaded by the script is run with coverage recording switched on
+    pub fn with_coverage() -> CpuSimulator {
+        CpuSimulator {
+            collect_coverage : true,
+            ..CpuSimulator::new()
+        }
+    }
+
+    pub fn coverage_reports(&self) -> Option<(String, String)> {
+        let coverage = self.machine.coverage.as_ref()?;
+        let source_map = self.source_map.as_ref()?;
+        Some((coverage.text_report(&self.machine.rom, source_map), coverage.lcov_report(&self.machine.rom, source_map)))
+    }
+
+    fn memory_index(index : Option<&str>) -> Result<u16, String> {
+        match index.and_then(|i| i.parse::<u16>().ok()) {
+            Some(index) if index < 0x8000 => Ok(index),
+            _ => Err(String::from("bad memory index")),
+        }
+    }
+}
+
+impl Default for CpuSimulator {
+    fn default() -> CpuSimulator {
+        CpuSimulator::new()
+    }
+}
+
+impl Simulator for CpuSimulator {
+    fn load(&mut self, directory : &Path, file_name : Option<&str>) -> Result<(), String> {
+        let file_name = file_name.ok_or_else(|| String::from("the CPU emulator needs a program to load"))?;
+        let path = resolve_path(directory, file_name);
+        let path_name = path.to_string_lossy().to_string();
+        if file_name.ends_with(".snap") {
+            //A snapshot holds the ROM but not the source lines coverage is reported against
+            if self.collect_coverage {
+                return Err(format!("coverage needs the program's source, which snapshot {} does not have; load the .asm or .hack file", path_name));
+            }
+            self.machine = load_snapshot(&path_name).map_err(|e| format!("Error loading {}: {}", path_name, e))?;
+            self.source_map = None;
+        }
+        else {
+            let program = if file_name.ends_with(".asm") {
+                assemble_file_with_source_map(&path_name).map_err(|e| e.to_string())
+            }
+            else {
+                read_machine_lines_with_source_map(&path_name).map_err(|e| e.to_string())
+            };
+            let (program, source_map) = program.map_err(|e| format!("Error loading {}: {}", path_name, e))?;
+            self.machine = Machine::new();
+            self.machine.load_program(&program);
+            self.source_map = Some(source_map);
+        }
+        if self.collect_coverage {
+            self.machine.coverage = Some(Coverage::new());
+        }
+        Ok(())
+    }
+
+    fn get(&self, variable : &str) -> Result<Value, String> {
+        let value = match split_indexed(variable) {
+            ("A", None) => self.machine.a,
+            ("D", None) => self.machine.d,
+            ("PC", None) => self.machine.pc,
+            ("time", None) => return Ok(Value::Text(self.machine.cycles.to_string())),
+            ("RAM", index) => self.machine.read(CpuSimulator::memory_index(index)?),
+            ("ROM", index) => self.machine.rom[CpuSimulator::memory_index(index)? as usize],
+            _ => return Err(format!("unknown variable '{}'", variable)),
+        };
+        Ok(Value::Number(value))
+    }
+
+    fn set(&mut self, variable : &str, value : u16) -> Result<(), String> {
+        match split_indexed(variable) {
+            ("A", None) => self.machine.a = value,
+            ("D", None) => self.machine.d = value,
+            ("PC", None) => self.machine.pc = value & 0x7FFF,
+            ("RAM", index) => self.machine.write(CpuSimulator::memory_index(index)?, value),
+            ("ROM", index) => self.machine.rom[CpuSimulator::memory_index(index)? as usize] = value,
+            _ => return Err(format!("unknown variable '{}'", variable)),
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, command : &[String]) -> Result<(), String> {
+        match command.iter().map(|s| s.as_str()).collect::<Vec<&str>>().as_slice() {
+            ["ticktock"] => {
+                self.machine.step();
+                Ok(())
+            },
+            _ => Err(format!("unknown command '{}'", command.join(" "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::cpu_emulator::simulator::CpuSimulator;
+    use crate::cpu_emulator::snapshot::save_snapshot;
+    use crate::test_script::{run_script, run_script_file};
+    use crate::test_support::PROJECTS;
+
+    #[test]
+    fn mult_script_test() {
+        let mut simulator = CpuSimulator::new();
+        let result = run_script_file(&format!("{}/04/mult/Mult.tst", PROJECTS), &mut simulator).ok().unwrap();
+        assert!(result.compared);
+        assert!(result.passed());
+    }
+
+    #[test]
+    fn fill_automatic_script_test() {
+        let mut simulator = CpuSimulator::new();
+        let result = run_script_file(&format!("{}/04/fill/FillAutomatic.tst", PROJECTS), &mut simulator).ok().unwrap();
+        assert!(result.passed());
+    }
+
+    #[test]
+    fn mult_coverage_test() {
+        let mut simulator = CpuSimulator::with_coverage();
+        run_script_file(&format!("{}/04/mult/Mult.tst", PROJECTS), &mut simulator).ok().unwrap();
+        let (text, lcov) = simulator.coverage_reports().unwrap();
+        assert!(text.contains("Instructions executed: 39 of 39 (100.0%)"));
+        assert!(text.contains("Branches covered: 4 of 4 (100.0%)"));
+        assert!(lcov.contains("SF:") && lcov.contains("mult.hack"));
+    }
+
+    #[test]
+    fn snapshot_as_starting_state_test() {
+        let directory = std::env::temp_dir().join("hack_snapshot_script_test");
+        fs::create_dir_all(&directory).unwrap();
+        let mut simulator = CpuSimulator::new();
+        simulator.machine.load_program(&[0x0007, 0xEC10, 0x0000, 0xE308]);
+        simulator.machine.ram[1] = 99;
+        simulator.machine.step();
+        save_snapshot(&simulator.machine, &directory.join("Start.snap").to_string_lossy()).ok().unwrap();
+
+        let script = "load Start.snap, output-list time%S1.4.1 A%D1.6.1 RAM[0]%D1.6.1 RAM[1]%D1.6.1;\
+                      repeat 3 { ticktock; } output;";
+        let result = run_script(script, &directory, &mut CpuSimulator::new()).ok().unwrap();
+        assert_eq!(result.output, "| time |   A    | RAM[0] | RAM[1] |\r\n| 4    |      0 |      7 |     99 |\r\n");
+        let (_, message) = run_script(script, &directory, &mut CpuSimulator::with_coverage()).err().unwrap();
+        assert!(message.starts_with("coverage needs the program's source"), "{}", message);
+        fs::remove_dir_all(&directory).ok();
+    }
+}
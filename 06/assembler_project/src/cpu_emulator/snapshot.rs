@@ -0,0 +1,98 @@
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use crate::cpu_emulator::{Machine, MEMORY_SIZE};
+
+const SNAPSHOT_HEADER : &str = "HACK-SNAPSHOT 1";
+
+pub enum SnapshotError {
+    Io(io::Error),
+    BadHeader,
+    BadLine(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::BadHeader => write!(f, "missing \"{}\" header", SNAPSHOT_HEADER),
+            SnapshotError::BadLine(line) => write!(f, "malformed snapshot entry on line {}", line),
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e : io::Error) -> SnapshotError {
+        SnapshotError::Io(e)
+    }
+}
+
+//Serializes the machine state; only non-zero ROM and RAM words are listed
+pub fn snapshot_to_string(machine : &Machine) -> String {
+    let mut text = String::from(SNAPSHOT_HEADER);
+    text.push('\n');
+    text.push_str(&format!("pc {}\na {}\nd {}\ncycles {}\n", machine.pc, machine.a, machine.d, machine.cycles));
+    for (address, word) in machine.rom.iter().enumerate() {
+        if *word != 0 {
+            text.push_str(&format!("rom {} {:016b}\n", address, word));
+        }
+    }
+    for (address, word) in machine.ram.iter().enumerate() {
+        if *word != 0 {
+            text.push_str(&format!("ram {} {}\n", address, word));
+        }
+    }
+    text
+}
+
+fn parse_word(text : &str) -> Option<u16> {
+    if text.len() == 16 && text.chars().all(|c| c == '0' || c == '1') {
+        return u16::from_str_radix(text, 2).ok();
+    }
+    text.parse::<u16>().ok()
+}
+
+fn parse_address(text : &str) -> Option<usize> {
+    text.parse::<usize>().ok().filter(|address| *address < MEMORY_SIZE)
+}
+
+pub fn snapshot_from_string(text : &str) -> Result<Machine, SnapshotError> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == SNAPSHOT_HEADER => (),
+        _ => return Err(SnapshotError::BadHeader),
+    }
+
+    let mut machine = Machine::new();
+    for (index, line) in lines {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+        let bad_line = || SnapshotError::BadLine(index + 1);
+        match fields.as_slice() {
+            [] => (),
+            ["pc", value] => machine.pc = parse_word(value).ok_or_else(bad_line)?,
+            ["a", value] => machine.a = parse_word(value).ok_or_else(bad_line)?,
+            ["d", value] => machine.d = parse_word(value).ok_or_else(bad_line)?,
+            ["cycles", value] => machine.cycles = value.parse::<u64>().map_err(|_| bad_line())?,
+            ["rom", address, value] => {
+                let address = parse_address(address).ok_or_else(bad_line)?;
+                machine.rom[address] = parse_word(value).ok_or_else(bad_line)?;
+            },
+            ["ram", address, value] => {
+                let address = parse_address(address).ok_or_else(bad_line)?;
+                machine.ram[address] = parse_word(value).ok_or_else(bad_line)?;
+            },
+            _ => return Err(bad_line()),
+        }
+    }
+    Ok(machine)
+}
+
+pub fn save_snapshot(machine : &Machine, file_name : &str) -> Result<(), SnapshotError> {
+    fs::write(file_name, snapshot_to_string(machine))?;
+    Ok(())
+}
+
+pub fn load_snapshot(file_name : &str) -> Result<Machine, SnapshotError> {
+    snapshot_from_string(&fs::read_to_string(file_name)?)
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeSet;
+use crate::cpu_emulator::Machine;
+use crate::vcd::Vcd;
+
+//Dumps a run of the machine from cycle from to cycle to as a Value Change Dump: A, D and PC, and in a
+//RAM scope each word the program writes during the run. The dump's time is the cycle count, with the
+//registers at a time being those before that cycle's instruction.
+pub fn trace(machine : &Machine, from : u64, to : u64) -> String {
+    let mut machine = machine.clone();
+    while machine.cycles < from {
+        machine.step();
+    }
+    //A first run finds the words to declare
+    let start = machine.clone();
+    let mut written = BTreeSet::new();
+    while machine.cycles < to {
+        if let Some(write) = machine.step() {
+            written.insert(write.address as usize);
+        }
+    }
+    let mut machine = start;
+    let mut vcd = Vcd::new();
+    vcd.scope("Hack");
+    vcd.variable("A", 16);
+    vcd.variable("D", 16);
+    vcd.variable("PC", 15);
+    vcd.scope("RAM");
+    for address in &written {
+        vcd.variable(&format!("RAM_{}", address), 16);
+    }
+    vcd.upscope();
+    vcd.upscope();
+    loop {
+        let values = [machine.a, machine.d, machine.pc].iter().copied().chain(written.iter().map(|address| machine.ram[*address])).collect::<Vec<u16>>();
+        vcd.sample(machine.cycles, &values);
+        if machine.cycles >= to {
+            return vcd.finish();
+        }
+        machine.step();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembler::assemble_lines;
+    use crate::cpu_emulator::Machine;
+    use crate::cpu_emulator::trace::trace;
+
+    #[test]
+    fn trace_test() {
+        let lines = ["@2", "D=A", "@3", "D=D+A", "@0", "M=D", "@6", "0;JMP"].iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let mut machine = Machine::new();
+        machine.load_program(&assemble_lines(&lines).ok().unwrap());
+        let vcd = trace(&machine, 2, 20);
+        assert!(vcd.contains("$scope module Hack $end\n$var wire 16 ! A [15:0] $end\n$var wire 16 \" D [15:0] $end\n$var wire 15 # PC [14:0] $end\n\
+            $scope module RAM $end\n$var wire 16 $ RAM_0 [15:0] $end\n$upscope $end\n$upscope $end\n"));
+        //The run starts with A=2, D=2 and PC=2, and RAM[0] becomes 5 after the instruction at 5
+        assert!(vcd.contains("$enddefinitions $end\n#2\nb10 !\nb10 \"\nb10 #\nb0 $\n#3\nb11 !\nb11 #\n"));
+        assert!(vcd.contains("#6\nb110 #\nb101 $\n"));
+        assert!(!vcd.contains("#21\n"));
+    }
+}
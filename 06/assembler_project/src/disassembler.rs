@@ -0,0 +1,70 @@
+use crate::code_generator;
+
+type MachineCommand = u16;
+
+//The comp mnemonics the assembler knows, one for each encoding, with D before A or M as the book writes them
+const COMPS : [&str; 28] = [
+    "0", "1", "-1", "D", "A", "!D", "!A", "-D", "-A", "D+1", "A+1", "D-1", "A-1", "D+A", "D-A", "A-D", "D&A", "D|A",
+    "M", "!M", "-M", "M+1", "M-1", "D+M", "D-M", "M-D", "D&M", "D|M",
+];
+
+const DESTS : [&str; 8] = ["", "M", "D", "MD", "A", "AM", "AD", "AMD"];
+
+const JUMPS : [&str; 8] = ["", "JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+//The assembly for one instruction, or a comment for a C-instruction whose ALU bits have no mnemonic
+pub fn disassemble(command : MachineCommand) -> String {
+    if command & 0x8000 == 0 {
+        return format!("@{}", command);
+    }
+    let comp = COMPS.iter().find(|mnemonic| code_generator::comp(mnemonic.to_string()) == Some(command & 0x1FC0));
+    let comp = match comp {
+        Some(comp) => comp,
+        None => return format!("//{:016b} has no mnemonic", command),
+    };
+    let dest = DESTS[((command >> 3) & 7) as usize];
+    let jump = JUMPS[(command & 7) as usize];
+    let mut line = String::new();
+    if !dest.is_empty() {
+        line.push_str(dest);
+        line.push('=');
+    }
+    line.push_str(comp);
+    if !jump.is_empty() {
+        line.push(';');
+        line.push_str(jump);
+    }
+    line
+}
+
+pub fn disassemble_lines(commands : &[MachineCommand]) -> Vec<String> {
+    commands.iter().map(|command| disassemble(*command)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::assembler::{assemble_file, assemble_lines};
+    use crate::disassembler::{disassemble, disassemble_lines};
+    use crate::test_support::PROJECTS;
+
+    #[test]
+    fn instructions_test() {
+        assert_eq!(disassemble(21), "@21");
+        assert_eq!(disassemble(0b1110110000010000), "D=A");
+        assert_eq!(disassemble(0b1111000010001000), "M=D+M");
+        assert_eq!(disassemble(0b1110001100000101), "D;JNE");
+        assert_eq!(disassemble(0b1110101010111111), "AMD=0;JMP");
+        assert_eq!(disassemble(0b1110000110000000), "//1110000110000000 has no mnemonic");
+    }
+
+    //Disassembling the project programs and assembling them again gives the same words
+    #[test]
+    fn round_trip_test() {
+        for name in ["06/add/Add.asm", "06/max/Max.asm", "06/rect/Rect.asm", "06/pong/Pong.asm"].iter() {
+            let program = assemble_file(&Path::new(PROJECTS).join(name).display().to_string()).ok().unwrap();
+            assert!(!program.is_empty());
+            assert_eq!(assemble_lines(&disassemble_lines(&program)).ok().unwrap(), program, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+pub mod lexer;
+pub mod ast;
+pub mod parser;
+pub mod builtin;
+pub mod elaborate;
+pub mod simulator;
+pub mod script;
+pub mod verilog;
+pub mod dot;
+pub mod stats;
+pub mod trace;
+pub mod verify;
+pub mod lint;
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use ast::Chip;
+
+//HDL sources are located the same way Jack sources are
+pub use crate::jack::Span;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct HdlError {
+    pub file_name : String,
+    pub line : usize,
+    pub column : usize,
+    pub message : String,
+}
+
+impl HdlError {
+    pub fn at(file_name : &str, span : Span, message : String) -> HdlError {
+        HdlError { file_name : file_name.to_string(), line : span.line, column : span.column, message }
+    }
+}
+
+impl fmt::Display for HdlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file_name, self.line, self.column, self.message)
+    }
+}
+
+pub fn read_chip(path : &Path) -> Result<Chip, HdlError> {
+    let file_name = path.display().to_string();
+    let source = fs::read_to_string(path).map_err(|e| HdlError { file_name : file_name.clone(), line : 0, column : 0, message : e.to_string() })?;
+    parser::parse_source(&file_name, &source)
+}
+
+//The .hdl files a path names: the file itself, or every .hdl file in a directory, sorted by name
+pub fn hdl_file_paths(path : &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut paths = fs::read_dir(path)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p.extension().map(|e| e == "hdl").unwrap_or(false))
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    Ok(paths)
+}
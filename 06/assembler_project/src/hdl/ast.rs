@@ -0,0 +1,73 @@
+use crate::hdl::Span;
+
+//The typed syntax tree of one chip definition. Every node keeps the span of the source it was parsed from.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Identifier {
+    pub name : String,
+    pub span : Span,
+}
+
+//An IN or OUT pin; a bus when its width is more than 1
+#[derive(Clone, PartialEq, Debug)]
+pub struct PinDeclaration {
+    pub name : Identifier,
+    pub width : u16,
+    pub span : Span,
+}
+
+//The bits a connection uses: pin[3] is 3..3, pin[0..7] is 0..7
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SubBus {
+    pub low : u16,
+    pub high : u16,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct PinReference {
+    pub name : Identifier,
+    //None for the whole pin
+    pub sub_bus : Option<SubBus>,
+    pub span : Span,
+}
+
+//What a part's pin is wired to in the enclosing chip
+#[derive(Clone, PartialEq, Debug)]
+pub enum Wire {
+    Pin(PinReference),
+    Constant(bool, Span),
+}
+
+//One "a=b" inside a part: the part's own pin on the left, the chip's wire on the right
+#[derive(Clone, PartialEq, Debug)]
+pub struct Connection {
+    pub pin : PinReference,
+    pub wire : Wire,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Part {
+    pub chip : Identifier,
+    pub connections : Vec<Connection>,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Chip {
+    pub name : Identifier,
+    pub inputs : Vec<PinDeclaration>,
+    pub outputs : Vec<PinDeclaration>,
+    pub parts : Vec<Part>,
+    //The chip a BUILTIN declaration names, usually the chip itself
+    pub builtin : Option<Identifier>,
+    //The input pins a CLOCKED declaration lists
+    pub clocked : Vec<Identifier>,
+    pub span : Span,
+}
+
+impl Chip {
+    pub fn input(&self, name : &str) -> Option<&PinDeclaration> {
+        self.inputs.iter().find(|pin| pin.name.name == name)
+    }
+}
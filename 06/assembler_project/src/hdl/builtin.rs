@@ -0,0 +1,235 @@
+use crate::cpu_emulator::alu;
+
+//Rust implementations of the standard library chips, used for parts that have no .hdl file
+//in the library directories or that the library is asked to take as builtin.
+//Every pin value is passed as a u16 holding the pin's bits, bit 0 first.
+//
+//Clocked chips keep their state in memory words. At the rising edge of the clock (tick) they read
+//their clocked inputs and decide on a write, which lands at the falling edge (tock); their outputs
+//depend on the memory and on their unclocked inputs only.
+pub struct BuiltinChip {
+    pub name : &'static str,
+    pub inputs : &'static [(&'static str, u16)],
+    pub outputs : &'static [(&'static str, u16)],
+    pub clocked : &'static [&'static str],
+    pub memory : usize,
+    pub eval : fn(&[u16], &mut [u16], &[u16]),
+    pub tick : Option<Tick>,
+}
+
+//Given the inputs and the memory at a tick, the memory word to write at the tock and its value
+pub type Tick = fn(&[u16], &[u16]) -> Option<(usize, u16)>;
+
+fn mux(a : u16, b : u16, sel : u16) -> u16 {
+    if sel & 1 == 0 { a } else { b }
+}
+
+//The state, seen through the one output of a register or DFF
+fn register_eval(_inputs : &[u16], outputs : &mut [u16], memory : &[u16]) {
+    outputs[0] = memory[0];
+}
+
+//in and load
+fn register_tick(inputs : &[u16], _memory : &[u16]) -> Option<(usize, u16)> {
+    if inputs[1] != 0 { Some((0, inputs[0])) } else { None }
+}
+
+//in, load and address, of which only the address reaches the output without the clock
+fn ram_eval(inputs : &[u16], outputs : &mut [u16], memory : &[u16]) {
+    outputs[0] = memory[inputs[2] as usize];
+}
+
+fn ram_tick(inputs : &[u16], _memory : &[u16]) -> Option<(usize, u16)> {
+    if inputs[1] != 0 { Some((inputs[2] as usize, inputs[0])) } else { None }
+}
+
+const UNARY : &[(&str, u16)] = &[("in", 1)];
+const BINARY : &[(&str, u16)] = &[("a", 1), ("b", 1)];
+const UNARY_16 : &[(&str, u16)] = &[("in", 16)];
+const BINARY_16 : &[(&str, u16)] = &[("a", 16), ("b", 16)];
+const OUT : &[(&str, u16)] = &[("out", 1)];
+const OUT_16 : &[(&str, u16)] = &[("out", 16)];
+const SUM : &[(&str, u16)] = &[("sum", 1), ("carry", 1)];
+const REGISTER : &[(&str, u16)] = &[("in", 16), ("load", 1)];
+const CLOCKED : &[&str] = &["in", "load"];
+
+//The fields of a chip without a clock
+const COMBINATIONAL : BuiltinChip = BuiltinChip { name : "", inputs : &[], outputs : &[], clocked : &[], memory : 0, eval : |_, _, _| (), tick : None };
+
+//A RAM of the given address width
+const fn ram(name : &'static str, inputs : &'static [(&'static str, u16)], memory : usize) -> BuiltinChip {
+    BuiltinChip { name, inputs, outputs : OUT_16, clocked : CLOCKED, memory, eval : ram_eval, tick : Some(ram_tick) }
+}
+
+const fn register(name : &'static str) -> BuiltinChip {
+    BuiltinChip { name, inputs : REGISTER, outputs : OUT_16, clocked : CLOCKED, memory : 1, eval : register_eval, tick : Some(register_tick) }
+}
+
+pub const BUILTIN_CHIPS : [BuiltinChip; 35] = [
+    BuiltinChip { name : "Nand", inputs : BINARY, outputs : OUT, eval : |i, o, _| o[0] = !(i[0] & i[1]) & 1, ..COMBINATIONAL },
+    BuiltinChip { name : "Not", inputs : UNARY, outputs : OUT, eval : |i, o, _| o[0] = !i[0] & 1, ..COMBINATIONAL },
+    BuiltinChip { name : "And", inputs : BINARY, outputs : OUT, eval : |i, o, _| o[0] = i[0] & i[1], ..COMBINATIONAL },
+    BuiltinChip { name : "Or", inputs : BINARY, outputs : OUT, eval : |i, o, _| o[0] = i[0] | i[1], ..COMBINATIONAL },
+    BuiltinChip { name : "Xor", inputs : BINARY, outputs : OUT, eval : |i, o, _| o[0] = i[0] ^ i[1], ..COMBINATIONAL },
+    BuiltinChip {
+        name : "Mux", inputs : &[("a", 1), ("b", 1), ("sel", 1)], outputs : OUT,
+        eval : |i, o, _| o[0] = mux(i[0], i[1], i[2]),
+        ..COMBINATIONAL
+    },
+    BuiltinChip {
+        name : "DMux", inputs : &[("in", 1), ("sel", 1)], outputs : &[("a", 1), ("b", 1)],
+        eval : |i, o, _| {
+            o[0] = i[0] & !i[1] & 1;
+            o[1] = i[0] & i[1];
+        },
+        ..COMBINATIONAL
+    },
+    BuiltinChip { name : "Not16", inputs : UNARY_16, outputs : OUT_16, eval : |i, o, _| o[0] = !i[0], ..COMBINATIONAL },
+    BuiltinChip { name : "And16", inputs : BINARY_16, outputs : OUT_16, eval : |i, o, _| o[0] = i[0] & i[1], ..COMBINATIONAL },
+    BuiltinChip { name : "Or16", inputs : BINARY_16, outputs : OUT_16, eval : |i, o, _| o[0] = i[0] | i[1], ..COMBINATIONAL },
+    BuiltinChip {
+        name : "Mux16", inputs : &[("a", 16), ("b", 16), ("sel", 1)], outputs : OUT_16,
+        eval : |i, o, _| o[0] = mux(i[0], i[1], i[2]),
+        ..COMBINATIONAL
+    },
+    BuiltinChip { name : "Or8Way", inputs : &[("in", 8)], outputs : OUT, eval : |i, o, _| o[0] = (i[0] != 0) as u16, ..COMBINATIONAL },
+    BuiltinChip {
+        name : "Mux4Way16", inputs : &[("a", 16), ("b", 16), ("c", 16), ("d", 16), ("sel", 2)], outputs : OUT_16,
+        eval : |i, o, _| o[0] = i[i[4] as usize],
+        ..COMBINATIONAL
+    },
+    BuiltinChip {
+        name : "Mux8Way16",
+        inputs : &[("a", 16), ("b", 16), ("c", 16), ("d", 16), ("e", 16), ("f", 16), ("g", 16), ("h", 16), ("sel", 3)],
+        outputs : OUT_16,
+        eval : |i, o, _| o[0] = i[i[8] as usize],
+        ..COMBINATIONAL
+    },
+    BuiltinChip {
+        name : "DMux4Way", inputs : &[("in", 1), ("sel", 2)], outputs : &[("a", 1), ("b", 1), ("c", 1), ("d", 1)],
+        eval : |i, o, _| {
+            for (k, out) in o.iter_mut().enumerate() {
+                *out = i[0] & (i[1] as usize == k) as u16;
+            }
+        },
+        ..COMBINATIONAL
+    },
+    BuiltinChip {
+        name : "DMux8Way", inputs : &[("in", 1), ("sel", 3)],
+        outputs : &[("a", 1), ("b", 1), ("c", 1), ("d", 1), ("e", 1), ("f", 1), ("g", 1), ("h", 1)],
+        eval : |i, o, _| {
+            for (k, out) in o.iter_mut().enumerate() {
+                *out = i[0] & (i[1] as usize == k) as u16;
+            }
+        },
+        ..COMBINATIONAL
+    },
+    BuiltinChip {
+        name : "HalfAdder", inputs : BINARY, outputs : SUM,
+        eval : |i, o, _| {
+            o[0] = i[0] ^ i[1];
+            o[1] = i[0] & i[1];
+        },
+        ..COMBINATIONAL
+    },
+    BuiltinChip {
+        name : "FullAdder", inputs : &[("a", 1), ("b", 1), ("c", 1)], outputs : SUM,
+        eval : |i, o, _| {
+            let total = i[0] + i[1] + i[2];
+            o[0] = total & 1;
+            o[1] = total >> 1;
+        },
+        ..COMBINATIONAL
+    },
+    BuiltinChip { name : "Add16", inputs : BINARY_16, outputs : OUT_16, eval : |i, o, _| o[0] = i[0].wrapping_add(i[1]), ..COMBINATIONAL },
+    BuiltinChip { name : "Inc16", inputs : UNARY_16, outputs : OUT_16, eval : |i, o, _| o[0] = i[0].wrapping_add(1), ..COMBINATIONAL },
+    BuiltinChip {
+        name : "ALU",
+        inputs : &[("x", 16), ("y", 16), ("zx", 1), ("nx", 1), ("zy", 1), ("ny", 1), ("f", 1), ("no", 1)],
+        outputs : &[("out", 16), ("zr", 1), ("ng", 1)],
+        eval : |i, o, _| {
+            o[0] = alu(i[0], i[1], i[2..].iter().fold(0, |control, bit| (control << 1) | bit));
+            o[1] = (o[0] == 0) as u16;
+            o[2] = o[0] >> 15;
+        },
+        ..COMBINATIONAL
+    },
+    BuiltinChip {
+        name : "DFF", inputs : UNARY, outputs : OUT, clocked : &["in"], memory : 1,
+        eval : register_eval, tick : Some(|i, _| Some((0, i[0]))),
+    },
+    BuiltinChip {
+        name : "Bit", inputs : &[("in", 1), ("load", 1)], outputs : OUT, clocked : CLOCKED, memory : 1,
+        eval : register_eval, tick : Some(register_tick),
+    },
+    register("Register"),
+    register("ARegister"),
+    register("DRegister"),
+    BuiltinChip {
+        name : "PC", inputs : &[("in", 16), ("load", 1), ("inc", 1), ("reset", 1)], outputs : OUT_16,
+        clocked : &["in", "load", "inc", "reset"], memory : 1, eval : register_eval,
+        tick : Some(|i, m| Some((0, if i[3] != 0 { 0 } else if i[1] != 0 { i[0] } else if i[2] != 0 { m[0].wrapping_add(1) } else { m[0] }))),
+    },
+    ram("RAM8", &[("in", 16), ("load", 1), ("address", 3)], 8),
+    ram("RAM64", &[("in", 16), ("load", 1), ("address", 6)], 64),
+    ram("RAM512", &[("in", 16), ("load", 1), ("address", 9)], 512),
+    ram("RAM4K", &[("in", 16), ("load", 1), ("address", 12)], 4096),
+    ram("RAM16K", &[("in", 16), ("load", 1), ("address", 14)], 16384),
+    //The screen memory map: 256 rows of 32 words
+    ram("Screen", &[("in", 16), ("load", 1), ("address", 13)], 8192),
+    //The code of the key held down, which the simulation sets from outside
+    BuiltinChip { name : "Keyboard", outputs : OUT_16, memory : 1, eval : register_eval, ..COMBINATIONAL },
+    //Loaded from a .hack file by the simulation
+    BuiltinChip {
+        name : "ROM32K", inputs : &[("address", 15)], outputs : OUT_16, memory : 32768,
+        eval : |i, o, m| o[0] = m[i[0] as usize], ..COMBINATIONAL
+    },
+];
+
+pub fn builtin_chip(name : &str) -> Option<&'static BuiltinChip> {
+    BUILTIN_CHIPS.iter().find(|chip| chip.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hdl::builtin::builtin_chip;
+
+    fn eval(name : &str, inputs : &[u16]) -> Vec<u16> {
+        let chip = builtin_chip(name).unwrap();
+        let mut outputs = vec![0; chip.outputs.len()];
+        (chip.eval)(inputs, &mut outputs, &[]);
+        outputs
+    }
+
+    #[test]
+    fn builtin_chips_test() {
+        assert_eq!(eval("Nand", &[1, 1]), vec![0]);
+        assert_eq!(eval("Nand", &[0, 1]), vec![1]);
+        assert_eq!(eval("DMux4Way", &[1, 2]), vec![0, 0, 1, 0]);
+        assert_eq!(eval("Mux8Way16", &[1, 2, 3, 4, 5, 6, 7, 8, 6]), vec![7]);
+        assert_eq!(eval("FullAdder", &[1, 1, 1]), vec![1, 1]);
+        //x-y with x=17 and y=3: zx=0 nx=1 zy=0 ny=0 f=1 no=1
+        assert_eq!(eval("ALU", &[17, 3, 0, 1, 0, 0, 1, 1]), vec![14, 0, 0]);
+        assert_eq!(eval("ALU", &[17, 17, 0, 1, 0, 0, 1, 1]), vec![0, 1, 0]);
+        assert!(builtin_chip("CPU").is_none());
+    }
+
+    #[test]
+    fn clocked_chips_test() {
+        let tick = |name : &str, inputs : &[u16], memory : &[u16]| (builtin_chip(name).unwrap().tick.unwrap())(inputs, memory);
+        assert_eq!(tick("DFF", &[1], &[0]), Some((0, 1)));
+        assert_eq!(tick("Register", &[77, 0], &[5]), None);
+        assert_eq!(tick("ARegister", &[77, 1], &[5]), Some((0, 77)));
+        //in load inc reset: reset wins over load, load over inc
+        assert_eq!(tick("PC", &[9, 1, 1, 1], &[5]), Some((0, 0)));
+        assert_eq!(tick("PC", &[9, 1, 1, 0], &[5]), Some((0, 9)));
+        assert_eq!(tick("PC", &[9, 0, 1, 0], &[0xffff]), Some((0, 0)));
+        assert_eq!(tick("RAM16K", &[4, 1, 16383], &[]), Some((16383, 4)));
+        let ram = builtin_chip("RAM8").unwrap();
+        let mut outputs = [0];
+        (ram.eval)(&[0, 0, 3], &mut outputs, &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(outputs, [3]);
+        assert_eq!((ram.clocked, ram.memory), (&["in", "load"][..], 8));
+        assert!(builtin_chip("Keyboard").unwrap().tick.is_none());
+    }
+}
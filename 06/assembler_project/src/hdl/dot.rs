@@ -0,0 +1,255 @@
+use std::collections::{HashMap, HashSet};
+use crate::hdl::HdlError;
+use crate::hdl::ast::{Chip, SubBus, Wire};
+use crate::hdl::elaborate::{elaborate, ComponentKind, Definition, Library, Netlist, FALSE, TRUE};
+use crate::hdl::stats::gate_count;
+
+//Draws chips as Graphviz DOT graphs: one level of a chip, with its parts as nodes and the wires
+//between them as edges, or the whole net list flattened to Nand gates and builtin chips.
+
+const HEADER : [&str; 3] = [
+    "    rankdir=LR;",
+    "    node [fontname=\"Helvetica\", fontsize=11];",
+    "    edge [fontname=\"Helvetica\", fontsize=9];",
+];
+
+//"out[0..7]", "in[3]" or "a": a pin as a connection names it
+fn slice(name : &str, sub_bus : Option<SubBus>) -> String {
+    match sub_bus {
+        Some(s) if s.low == s.high => format!("{}[{}]", name, s.low),
+        Some(s) => format!("{}[{}..{}]", name, s.low, s.high),
+        None => name.to_string(),
+    }
+}
+
+//A wire's name with its width in the "/16" bus notation of schematics
+fn labelled(name : &str, width : u16) -> String {
+    if width == 1 { name.to_string() } else { format!("{} /{}", name, width) }
+}
+
+fn overlap(a : Option<SubBus>, b : Option<SubBus>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.low <= b.high && b.low <= a.high,
+        _ => true,
+    }
+}
+
+//Where a wire of the chip gets its value: an input pin's node, or a part's node and output pin
+struct Driver {
+    node : String,
+    pin : Option<String>,
+    //The bits of the wire it drives, all of them for None
+    sub_bus : Option<SubBus>,
+}
+
+fn pin_width(pins : &[(&str, bool, u16)], name : &str, sub_bus : Option<SubBus>) -> u16 {
+    match sub_bus {
+        Some(s) => s.high - s.low + 1,
+        None => pins.iter().find(|(n, _, _)| *n == name).map(|(_, _, width)| *width).unwrap_or(1),
+    }
+}
+
+fn chip_lines(library : &mut Library, chip : &Chip) -> Result<Vec<String>, HdlError> {
+    let mut lines = Vec::new();
+    for pin in &chip.inputs {
+        lines.push(format!("    \"in.{}\" [label=\"{}\", shape=cds];", pin.name.name, labelled(&pin.name.name, pin.width)));
+    }
+    for pin in &chip.outputs {
+        lines.push(format!("    \"out.{}\" [label=\"{}\", shape=cds];", pin.name.name, labelled(&pin.name.name, pin.width)));
+    }
+    let mut parts = Vec::new();
+    for (index, part) in chip.parts.iter().enumerate() {
+        lines.push(format!("    part{} [label=\"{}\", shape=box];", index, part.chip.name));
+        let definition = library.resolve(&part.chip.name)?.unwrap();
+        parts.push(definition.pins().iter().map(|(name, input, width)| (name.to_string(), *input, *width)).collect::<Vec<(String, bool, u16)>>());
+    }
+    let mut drivers : HashMap<&str, Vec<Driver>> = HashMap::new();
+    for pin in &chip.inputs {
+        drivers.entry(&pin.name.name).or_default().push(Driver { node : format!("\"in.{}\"", pin.name.name), pin : None, sub_bus : None });
+    }
+    for (index, (part, pins)) in chip.parts.iter().zip(parts.iter()).enumerate() {
+        for connection in &part.connections {
+            let output = pins.iter().any(|(name, input, _)| *name == connection.pin.name.name && !input);
+            if let (true, Wire::Pin(reference)) = (output, &connection.wire) {
+                drivers.entry(&reference.name.name).or_default().push(Driver {
+                    node : format!("part{}", index),
+                    pin : Some(slice(&connection.pin.name.name, connection.pin.sub_bus)),
+                    sub_bus : reference.sub_bus,
+                });
+            }
+        }
+    }
+    let edge = |from : &Driver, to : &str, label : String, head : Option<String>| {
+        let mut attributes = vec![format!("label=\"{}\"", label)];
+        attributes.extend(from.pin.iter().map(|pin| format!("taillabel=\"{}\"", pin)));
+        attributes.extend(head.map(|pin| format!("headlabel=\"{}\"", pin)));
+        format!("    {} -> {} [{}];", from.node, to, attributes.join(", "))
+    };
+    let mut constants = Vec::new();
+    let mut edges = Vec::new();
+    for (index, (part, pins)) in chip.parts.iter().zip(parts.iter()).enumerate() {
+        let pins = pins.iter().map(|(name, input, width)| (name.as_str(), *input, *width)).collect::<Vec<(&str, bool, u16)>>();
+        for connection in &part.connections {
+            if !pins.iter().any(|(name, input, _)| *name == connection.pin.name.name && *input) {
+                continue;
+            }
+            let head = slice(&connection.pin.name.name, connection.pin.sub_bus);
+            let width = pin_width(&pins, &connection.pin.name.name, connection.pin.sub_bus);
+            match &connection.wire {
+                Wire::Constant(value, _) => {
+                    let constant = Driver { node : value.to_string(), pin : None, sub_bus : None };
+                    if !constants.contains(value) {
+                        constants.push(*value);
+                    }
+                    edges.push(edge(&constant, &format!("part{}", index), labelled(&value.to_string(), width), Some(head)));
+                },
+                Wire::Pin(reference) => {
+                    for driver in drivers.get(reference.name.name.as_str()).into_iter().flatten() {
+                        if overlap(driver.sub_bus, reference.sub_bus) {
+                            let label = labelled(&slice(&reference.name.name, reference.sub_bus), width);
+                            edges.push(edge(driver, &format!("part{}", index), label, Some(head.clone())));
+                        }
+                    }
+                },
+            }
+        }
+    }
+    for pin in &chip.outputs {
+        for driver in drivers.get(pin.name.name.as_str()).into_iter().flatten() {
+            let width = driver.sub_bus.map(|s| s.high - s.low + 1).unwrap_or(pin.width);
+            edges.push(edge(driver, &format!("\"out.{}\"", pin.name.name), labelled(&slice(&pin.name.name, driver.sub_bus), width), None));
+        }
+    }
+    for value in constants {
+        lines.push(format!("    {} [shape=plaintext];", value));
+    }
+    lines.extend(edges);
+    Ok(lines)
+}
+
+//One level of a chip: its pins and parts as nodes, and an edge for every use of a wire, labelled
+//with the wire and its width and, at its ends, the part pins it connects
+pub fn chip_graph(library : &mut Library, name : &str) -> Result<String, HdlError> {
+    let netlist = elaborate(library, name)?;
+    let mut lines = vec![format!("digraph \"{}\" {{", name)];
+    lines.extend(HEADER.iter().map(|line| line.to_string()));
+    match library.resolve(name)? {
+        Some(Definition::Hdl(chip, _)) if chip.builtin.is_none() => lines.extend(chip_lines(library, &chip)?),
+        //A builtin chip has nothing inside to show
+        _ => return Ok(flat_graph(&netlist)),
+    }
+    lines.push(String::from("}"));
+    Ok(format!("{}\n", lines.join("\n")))
+}
+
+//The whole chip flattened: a node for every Nand gate and builtin chip and an edge wherever one
+//drives another, with the top chip's pins at the ends
+pub fn flat_graph(netlist : &Netlist) -> String {
+    let top = &netlist.instances[0];
+    let mut lines = vec![format!("digraph \"{}\" {{", top.chip)];
+    lines.extend(HEADER.iter().map(|line| line.to_string()));
+    lines.push(format!("    label=\"{}: {}\";", top.chip, gate_count(&netlist.components)));
+    lines.push(String::from("    labelloc=t;"));
+    let mut drivers = vec![None; netlist.nets];
+    drivers[FALSE] = Some(String::from("false"));
+    drivers[TRUE] = Some(String::from("true"));
+    for (name, nets) in &top.pins[..netlist.inputs] {
+        lines.push(format!("    \"in.{}\" [label=\"{}\", shape=cds];", name, labelled(name, nets.len() as u16)));
+        for net in nets {
+            drivers[*net] = Some(format!("\"in.{}\"", name));
+        }
+    }
+    for (index, component) in netlist.components.iter().enumerate() {
+        let tooltip = netlist.path(component.instance);
+        match &component.kind {
+            ComponentKind::Nand { .. } => lines.push(format!("    c{} [label=\"\", shape=invtriangle, width=0.25, height=0.25, tooltip=\"{}\"];", index, tooltip)),
+            ComponentKind::Builtin { chip, .. } => lines.push(format!("    c{} [label=\"{}\", shape=box, tooltip=\"{}\"];", index, chip.name, tooltip)),
+        }
+        for net in component.output_nets() {
+            drivers[net] = Some(format!("c{}", index));
+        }
+    }
+    let mut edges = Vec::new();
+    let mut seen = HashSet::new();
+    let mut used_constants = Vec::new();
+    let mut connect = |nets : &[usize], to : String, edges : &mut Vec<String>| {
+        for net in nets {
+            if let Some(driver) = &drivers[*net] {
+                if *net <= TRUE && !used_constants.contains(net) {
+                    used_constants.push(*net);
+                }
+                if seen.insert((driver.clone(), to.clone())) {
+                    edges.push(format!("    {} -> {};", driver, to));
+                }
+            }
+        }
+    };
+    for (index, component) in netlist.components.iter().enumerate() {
+        let inputs = match &component.kind {
+            ComponentKind::Nand { a, b, .. } => vec![*a, *b],
+            ComponentKind::Builtin { inputs, .. } => inputs.iter().flatten().copied().collect(),
+        };
+        connect(&inputs, format!("c{}", index), &mut edges);
+    }
+    for (name, nets) in &top.pins[netlist.inputs..netlist.inputs + netlist.outputs] {
+        lines.push(format!("    \"out.{}\" [label=\"{}\", shape=cds];", name, labelled(name, nets.len() as u16)));
+        connect(nets, format!("\"out.{}\"", name), &mut edges);
+    }
+    used_constants.sort_unstable();
+    for net in used_constants {
+        lines.push(format!("    {} [shape=plaintext];", if net == TRUE { "true" } else { "false" }));
+    }
+    lines.extend(edges);
+    lines.push(String::from("}"));
+    format!("{}\n", lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use crate::hdl::dot::{chip_graph, flat_graph};
+    use crate::hdl::stats::gate_count;
+    use crate::hdl::elaborate::{elaborate, Library};
+    use crate::test_support::PROJECTS;
+
+    fn project(path : &str) -> PathBuf {
+        Path::new(PROJECTS).join(path)
+    }
+
+    #[test]
+    fn alu_graph_test() {
+        let (mut library, name) = Library::for_chip(&project("02/ALU.hdl"), &[], &[]);
+        let graph = chip_graph(&mut library, &name).ok().unwrap();
+        assert!(graph.starts_with("digraph \"ALU\" {\n    rankdir=LR;\n"));
+        assert_eq!(graph.matches("shape=box").count(), 13);
+        assert!(graph.contains("    \"in.x\" [label=\"x /16\", shape=cds];\n"));
+        assert!(graph.contains("    \"in.zx\" -> part1 [label=\"zx\", headlabel=\"sel[0]\"];\n"));
+        //The wide output split into bytes, each byte into its own Or8Way
+        assert!(graph.contains("    part8 -> part9 [label=\"outlow /8\", taillabel=\"out[0..7]\", headlabel=\"in\"];\n"));
+        assert!(graph.contains("    part8 -> \"out.ng\" [label=\"ng\", taillabel=\"out[15]\"];\n"));
+        assert!(graph.contains("    false [shape=plaintext];\n    true [shape=plaintext];\n"));
+        assert!(graph.contains("    true -> part1 [label=\"true /16\", headlabel=\"d\"];\n"));
+        assert!(graph.ends_with("}\n"));
+    }
+
+    #[test]
+    fn flat_graph_test() {
+        let mut library = Library::new(vec![project("01")]);
+        let xor = elaborate(&mut library, "Xor").ok().unwrap();
+        let nands = xor.components.len();
+        assert_eq!(gate_count(&xor.components), format!("{} Nand gates", nands));
+        let graph = flat_graph(&xor);
+        assert!(graph.contains(&format!("    label=\"Xor: {} Nand gates\";\n", nands)));
+        assert_eq!(graph.matches("shape=invtriangle").count(), nands);
+        assert!(graph.contains("tooltip=\"Xor/Not/Nand\""));
+        assert!(graph.contains("    \"in.a\" -> c0;\n"));
+        let mut library = Library::new(vec![project("03/a"), project("01")]);
+        let bit = elaborate(&mut library, "Bit").ok().unwrap();
+        assert!(gate_count(&bit.components).ends_with(" Nand gates, 1 DFF"));
+        //The DFF feeds back into the Mux in front of it
+        let graph = flat_graph(&bit);
+        let dff = bit.components.len() - 1;
+        assert!(graph.contains(&format!("    c{} [label=\"DFF\", shape=box, tooltip=\"Bit/DFF\"];\n", dff)));
+        assert!(graph.contains(&format!("    c{} -> \"out.out\";\n", dff)));
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::hdl::{read_chip, HdlError, Span};
+use crate::hdl::ast::{Chip, PinReference, Wire};
+use crate::hdl::builtin::{builtin_chip, BuiltinChip};
+
+//Net 0 always reads false and net 1 always reads true
+pub const FALSE : usize = 0;
+pub const TRUE : usize = 1;
+
+#[derive(Clone)]
+pub enum Definition {
+    //A chip read from an .hdl file, with the file's name for error messages
+    Hdl(Rc<Chip>, Rc<String>),
+    Builtin(&'static BuiltinChip),
+}
+
+impl Definition {
+    //Every pin with whether it is an input and its width, inputs first
+    pub fn pins(&self) -> Vec<(&str, bool, u16)> {
+        match self {
+            Definition::Hdl(chip, _) => chip.inputs.iter().map(|pin| (pin.name.name.as_str(), true, pin.width))
+                .chain(chip.outputs.iter().map(|pin| (pin.name.name.as_str(), false, pin.width)))
+                .collect(),
+            Definition::Builtin(chip) => chip.inputs.iter().map(|(name, width)| (*name, true, *width))
+                .chain(chip.outputs.iter().map(|(name, width)| (*name, false, *width)))
+                .collect(),
+        }
+    }
+}
+
+//Where the parts of a chip come from: the first library directory holding Name.hdl, or else the
+//builtin chip of that name. Nand is always builtin.
+pub struct Library {
+    directories : Vec<PathBuf>,
+    builtin : Vec<String>,
+    chips : HashMap<String, Definition>,
+}
+
+impl Library {
+    pub fn new(directories : Vec<PathBuf>) -> Library {
+        Library { directories, builtin : Vec::new(), chips : HashMap::new() }
+    }
+
+    //The library for Dir/Name.hdl, which looks in Dir before the other directories, and the chip's name
+    pub fn for_chip(path : &Path, directories : &[PathBuf], builtin : &[String]) -> (Library, String) {
+        let directory = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        let mut library = Library::new(std::iter::once(directory).chain(directories.iter().cloned()).collect());
+        library.use_builtin(builtin);
+        let name = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        (library, name)
+    }
+
+    //Takes these chips from the builtin implementations even where an .hdl file exists
+    pub fn use_builtin(&mut self, names : &[String]) {
+        self.builtin.extend(names.iter().cloned());
+        self.chips.clear();
+    }
+
+    pub fn resolve(&mut self, name : &str) -> Result<Option<Definition>, HdlError> {
+        if let Some(definition) = self.chips.get(name) {
+            return Ok(Some(definition.clone()));
+        }
+        let builtin = builtin_chip(name);
+        let prefer_builtin = name == "Nand" || self.builtin.iter().any(|n| n == name);
+        let path = self.directories.iter().map(|d| d.join(format!("{}.hdl", name))).find(|p| p.is_file());
+        let definition = match path {
+            Some(path) if builtin.is_none() || !prefer_builtin => {
+                let file_name = path.display().to_string();
+                let chip = read_chip(&path)?;
+                if chip.name.name != name {
+                    return Err(HdlError::at(&file_name, chip.name.span, format!("{} defines chip {} instead of {}", file_name, chip.name.name, name)));
+                }
+                Definition::Hdl(Rc::new(chip), Rc::new(file_name))
+            },
+            _ => match builtin {
+                Some(chip) => Definition::Builtin(chip),
+                None => return Ok(None),
+            },
+        };
+        self.chips.insert(name.to_string(), definition.clone());
+        Ok(Some(definition))
+    }
+}
+
+pub enum ComponentKind {
+    Nand { a : usize, b : usize, out : usize },
+    //One net list per pin, in the order the chip declares its pins
+    Builtin { chip : &'static BuiltinChip, inputs : Vec<Vec<usize>>, outputs : Vec<Vec<usize>> },
+}
+
+pub struct Component {
+    pub kind : ComponentKind,
+    //The chip instance the component implements or belongs to
+    pub instance : usize,
+}
+
+impl Component {
+    //The inputs the outputs follow without waiting for the clock
+    pub fn combinational_input_nets(&self) -> Vec<usize> {
+        match &self.kind {
+            ComponentKind::Nand { a, b, .. } => vec![*a, *b],
+            ComponentKind::Builtin { chip, inputs, .. } => chip.inputs.iter().zip(inputs.iter())
+                .filter(|((name, _), _)| !chip.clocked.contains(name))
+                .flat_map(|(_, nets)| nets.iter().copied())
+                .collect(),
+        }
+    }
+
+    pub fn output_nets(&self) -> Vec<usize> {
+        match &self.kind {
+            ComponentKind::Nand { out, .. } => vec![*out],
+            ComponentKind::Builtin { outputs, .. } => outputs.iter().flatten().copied().collect(),
+        }
+    }
+}
+
+//One chip in the part hierarchy, with the nets of its pins (inputs, outputs, then internal pins)
+//and the part declaration it came from, which for the top chip is the chip itself
+pub struct Instance {
+    pub chip : String,
+    pub parent : Option<usize>,
+    pub pins : Vec<(String, Vec<usize>)>,
+    pub file_name : Rc<String>,
+    pub span : Span,
+}
+
+impl Instance {
+    pub fn pin(&self, name : &str) -> Option<&Vec<usize>> {
+        self.pins.iter().find(|(n, _)| n == name).map(|(_, nets)| nets)
+    }
+}
+
+//A chip flattened to Nand gates and builtin chips wired by single-bit nets. Instance 0 is the top chip.
+pub struct Netlist {
+    pub nets : usize,
+    pub components : Vec<Component>,
+    pub instances : Vec<Instance>,
+    //How many of the top chip's first pins are inputs, and how many of the ones after them outputs
+    pub inputs : usize,
+    pub outputs : usize,
+}
+
+impl Netlist {
+    //"ALU/Mux4Way16/Mux16": the chip names from the top down to an instance
+    pub fn path(&self, instance : usize) -> String {
+        let mut names = vec![self.instances[instance].chip.as_str()];
+        let mut parent = self.instances[instance].parent;
+        while let Some(index) = parent {
+            names.push(&self.instances[index].chip);
+            parent = self.instances[index].parent;
+        }
+        names.reverse();
+        names.join("/")
+    }
+}
+
+pub fn bits(width : usize) -> String {
+    if width == 1 { String::from("1 bit") } else { format!("{} bits", width) }
+}
+
+//Follows a net to the one that is actually driven
+fn root(aliases : &[Option<usize>], mut net : usize) -> usize {
+    while let Some(next) = aliases[net] {
+        net = next;
+    }
+    net
+}
+
+pub fn describe(reference : &PinReference) -> String {
+    match reference.sub_bus {
+        Some(sub_bus) if sub_bus.low == sub_bus.high => format!("{}[{}]", reference.name.name, sub_bus.low),
+        Some(sub_bus) => format!("{}[{}..{}]", reference.name.name, sub_bus.low, sub_bus.high),
+        None => reference.name.name.clone(),
+    }
+}
+
+struct Elaborator<'a> {
+    library : &'a mut Library,
+    //The net each net was connected to, for nets that stand for another chip's output
+    aliases : Vec<Option<usize>>,
+    components : Vec<Component>,
+    instances : Vec<Instance>,
+    chips : Vec<String>,
+}
+
+impl<'a> Elaborator<'a> {
+    fn fresh(&mut self, width : u16) -> Vec<usize> {
+        let start = self.aliases.len();
+        self.aliases.resize(start + width as usize, None);
+        (start..start + width as usize).collect()
+    }
+
+    //The bits of a pin a reference selects, checked against the pin's width
+    fn select(file_name : &str, reference : &PinReference, width : usize) -> Result<(usize, usize), HdlError> {
+        match reference.sub_bus {
+            Some(sub_bus) if sub_bus.high as usize >= width => Err(HdlError::at(file_name, reference.span,
+                format!("sub-bus {} is out of range for {} of {}", describe(reference), reference.name.name, bits(width)))),
+            Some(sub_bus) => Ok((sub_bus.low as usize, sub_bus.high as usize)),
+            None => Ok((0, width - 1)),
+        }
+    }
+
+    fn instantiate(&mut self, definition : Definition, bindings : &HashMap<String, Vec<usize>>, parent : Option<usize>, file_name : Rc<String>, span : Span) -> Result<(), HdlError> {
+        let (chip, chip_file) = match definition {
+            Definition::Hdl(chip, chip_file) => match &chip.builtin {
+                Some(name) => match builtin_chip(&name.name) {
+                    Some(builtin) => return self.instantiate(Definition::Builtin(builtin), bindings, parent, file_name, span),
+                    None => return Err(HdlError::at(&chip_file, name.span, format!("there is no builtin chip {}", name.name))),
+                },
+                None => (chip.clone(), chip_file),
+            },
+            Definition::Builtin(builtin) => {
+                let instance = self.instances.len();
+                let nets = |pins : &[(&str, u16)]| pins.iter().map(|(name, _)| bindings[*name].clone()).collect::<Vec<Vec<usize>>>();
+                let kind = if builtin.name == "Nand" {
+                    ComponentKind::Nand { a : bindings["a"][0], b : bindings["b"][0], out : bindings["out"][0] }
+                }
+                else {
+                    ComponentKind::Builtin { chip : builtin, inputs : nets(builtin.inputs), outputs : nets(builtin.outputs) }
+                };
+                let pins = definition.pins().iter().map(|(name, _, _)| (name.to_string(), bindings[*name].clone())).collect();
+                self.instances.push(Instance { chip : builtin.name.to_string(), parent, pins, file_name, span });
+                self.components.push(Component { kind, instance });
+                return Ok(());
+            },
+        };
+        if self.chips.contains(&chip.name.name) {
+            return Err(HdlError::at(&file_name, span, format!("chip {} contains itself", chip.name.name)));
+        }
+        let instance = self.instances.len();
+        let pins = chip.inputs.iter().chain(chip.outputs.iter())
+            .map(|pin| (pin.name.name.clone(), bindings[&pin.name.name].clone()))
+            .collect();
+        self.instances.push(Instance { chip : chip.name.name.clone(), parent, pins, file_name, span });
+        self.chips.push(chip.name.name.clone());
+        self.instantiate_parts(&chip, &chip_file, instance)?;
+        self.chips.pop();
+        Ok(())
+    }
+
+    fn instantiate_parts(&mut self, chip : &Chip, file_name : &Rc<String>, instance : usize) -> Result<(), HdlError> {
+        let error = |span : Span, message : String| HdlError::at(file_name, span, message);
+        let mut definitions = Vec::new();
+        for part in &chip.parts {
+            match self.library.resolve(&part.chip.name)? {
+                Some(definition) => definitions.push(definition),
+                None => return Err(error(part.chip.span, format!("unknown chip {}", part.chip.name))),
+            }
+        }
+        //Internal pins come into being where a part output drives them, as wide as the bits driving them
+        let mut scope = self.instances[instance].pins.clone();
+        for (part, definition) in chip.parts.iter().zip(definitions.iter()) {
+            for connection in &part.connections {
+                let (input, width) = match definition.pins().iter().find(|(name, _, _)| *name == connection.pin.name.name) {
+                    Some((_, input, width)) => (*input, *width as usize),
+                    None => return Err(error(connection.pin.span, format!("{} has no pin {}", part.chip.name, connection.pin.name.name))),
+                };
+                let (low, high) = Elaborator::select(file_name, &connection.pin, width)?;
+                let reference = match &connection.wire {
+                    _ if input => continue,
+                    Wire::Constant(_, span) => return Err(error(*span, format!("output {} of {} cannot be connected to a constant", connection.pin.name.name, part.chip.name))),
+                    Wire::Pin(reference) => reference,
+                };
+                let name = &reference.name.name;
+                if chip.input(name).is_some() {
+                    return Err(error(reference.span, format!("input pin {} cannot be driven by a part", name)));
+                }
+                if scope.iter().any(|(n, _)| n == name) {
+                    continue;
+                }
+                if reference.sub_bus.is_some() {
+                    return Err(error(reference.span, format!("internal pin {} cannot take a sub-bus", name)));
+                }
+                let nets = self.fresh((high - low + 1) as u16);
+                scope.push((name.clone(), nets));
+            }
+        }
+        let lookup = |reference : &PinReference| -> Result<Vec<usize>, HdlError> {
+            match scope.iter().find(|(name, _)| *name == reference.name.name) {
+                Some((_, nets)) => {
+                    let (low, high) = Elaborator::select(file_name, reference, nets.len())?;
+                    Ok(nets[low..=high].to_vec())
+                },
+                None => Err(error(reference.span, format!("{} is not a pin of {} or an output of any of its parts", reference.name.name, chip.name.name))),
+            }
+        };
+        for (part, definition) in chip.parts.iter().zip(definitions) {
+            let pins = definition.pins().iter().map(|(name, input, width)| (name.to_string(), *input, *width)).collect::<Vec<(String, bool, u16)>>();
+            let mut inputs : HashMap<String, Vec<Option<usize>>> = HashMap::new();
+            let mut bindings = HashMap::new();
+            for (name, input, width) in &pins {
+                if !input {
+                    bindings.insert(name.clone(), self.fresh(*width));
+                }
+            }
+            for connection in &part.connections {
+                let name = &connection.pin.name.name;
+                let (_, input, width) = pins.iter().find(|(n, _, _)| n == name).unwrap();
+                let (low, high) = Elaborator::select(file_name, &connection.pin, *width as usize)?;
+                let wire = match &connection.wire {
+                    Wire::Constant(value, _) => vec![if *value { TRUE } else { FALSE }; high - low + 1],
+                    Wire::Pin(reference) => lookup(reference)?,
+                };
+                if wire.len() != high - low + 1 {
+                    let wire_name = match &connection.wire {
+                        Wire::Pin(reference) => describe(reference),
+                        Wire::Constant(..) => String::new(),
+                    };
+                    return Err(error(connection.span, format!("{} of {} is {} wide, but {} is {} wide",
+                        describe(&connection.pin), part.chip.name, bits(high - low + 1), wire_name, bits(wire.len()))));
+                }
+                if *input {
+                    let connected = inputs.entry(name.clone()).or_insert_with(|| vec![None; *width as usize]);
+                    for (bit, net) in (low..=high).zip(wire) {
+                        if connected[bit].replace(net).is_some() {
+                            return Err(error(connection.pin.span, format!("{} of {} is connected twice", describe(&connection.pin), part.chip.name)));
+                        }
+                    }
+                }
+                else if let Wire::Pin(reference) = &connection.wire {
+                    for (target, source) in wire.into_iter().zip(bindings[name][low..=high].to_vec()) {
+                        if self.aliases[target].replace(source).is_some() {
+                            return Err(error(connection.span, format!("{} has more than one driver", describe(reference))));
+                        }
+                    }
+                }
+            }
+            for (name, input, _) in &pins {
+                if *input {
+                    match inputs.remove(name) {
+                        Some(connected) => {
+                            bindings.insert(name.clone(), connected.into_iter().map(|net| net.unwrap_or(FALSE)).collect());
+                        },
+                        None => return Err(error(part.chip.span, format!("input {} of {} is not connected", name, part.chip.name))),
+                    }
+                }
+            }
+            self.instantiate(definition, &bindings, Some(instance), file_name.clone(), part.span)?;
+        }
+        self.instances[instance].pins = scope;
+        Ok(())
+    }
+}
+
+//Flattens a chip from the library into a net list, checking every connection on the way
+pub fn elaborate(library : &mut Library, name : &str) -> Result<Netlist, HdlError> {
+    let definition = match library.resolve(name)? {
+        Some(definition) => definition,
+        None => return Err(HdlError { file_name : format!("{}.hdl", name), line : 0, column : 0, message : format!("unknown chip {}", name) }),
+    };
+    let (file_name, span) = match &definition {
+        Definition::Hdl(chip, file_name) => (file_name.clone(), chip.span),
+        Definition::Builtin(_) => (Rc::new(format!("{}.hdl", name)), Span::default()),
+    };
+    let mut elaborator = Elaborator { library, aliases : vec![None, None], components : Vec::new(), instances : Vec::new(), chips : Vec::new() };
+    let pins = definition.pins().iter().map(|(name, input, width)| (name.to_string(), *input, *width)).collect::<Vec<(String, bool, u16)>>();
+    let mut bindings = HashMap::new();
+    for (name, _, width) in &pins {
+        let nets = elaborator.fresh(*width);
+        bindings.insert(name.clone(), nets);
+    }
+    elaborator.instantiate(definition, &bindings, None, file_name, span)?;
+    let Elaborator { aliases, mut components, mut instances, .. } = elaborator;
+    let root = |net : &mut usize| *net = root(&aliases, *net);
+    for component in &mut components {
+        match &mut component.kind {
+            ComponentKind::Nand { a, b, out } => {
+                root(a);
+                root(b);
+                root(out);
+            },
+            ComponentKind::Builtin { inputs, outputs, .. } => inputs.iter_mut().chain(outputs.iter_mut()).flatten().for_each(root),
+        }
+    }
+    for instance in &mut instances {
+        instance.pins.iter_mut().flat_map(|(_, nets)| nets.iter_mut()).for_each(root);
+    }
+    Ok(Netlist { nets : aliases.len(), components, instances, inputs : pins.iter().filter(|(_, input, _)| *input).count(), outputs : pins.iter().filter(|(_, input, _)| !*input).count() })
+}
@@ -0,0 +1,209 @@
+use crate::hdl::{HdlError, Span};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Keyword {
+    Chip,
+    In,
+    Out,
+    Parts,
+    Builtin,
+    Clocked,
+    True,
+    False,
+}
+
+const KEYWORDS : [(&str, Keyword); 8] = [
+    ("CHIP", Keyword::Chip),
+    ("IN", Keyword::In),
+    ("OUT", Keyword::Out),
+    ("PARTS", Keyword::Parts),
+    ("BUILTIN", Keyword::Builtin),
+    ("CLOCKED", Keyword::Clocked),
+    ("true", Keyword::True),
+    ("false", Keyword::False),
+];
+
+const SYMBOLS : &str = "{}()[];,=:";
+
+impl Keyword {
+    pub fn from_name(name : &str) -> Option<Keyword> {
+        KEYWORDS.iter().find(|(n, _)| *n == name).map(|(_, k)| *k)
+    }
+
+    pub fn name(self) -> &'static str {
+        KEYWORDS.iter().find(|(_, k)| *k == self).map(|(n, _)| *n).unwrap()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Symbol(char),
+    //The ".." of a sub-bus such as out[0..7]
+    Range,
+    Number(u16),
+    Identifier(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Token {
+    pub kind : TokenKind,
+    pub span : Span,
+}
+
+struct Lexer<'a> {
+    file_name : &'a str,
+    source : &'a str,
+    offset : usize,
+    line : usize,
+    column : usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.source[self.offset..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.source[self.offset..].chars().nth(1)
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        }
+        else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn here(&self) -> Span {
+        Span { start : self.offset, end : self.offset, line : self.line, column : self.column }
+    }
+
+    fn error(&self, span : Span, message : &str) -> HdlError {
+        HdlError::at(self.file_name, span, message.to_string())
+    }
+
+    //Skips white space, // comments and /* */ or /** */ comments
+    fn skip_trivia(&mut self) -> Result<(), HdlError> {
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.advance();
+                },
+                (Some('/'), Some('/')) => {
+                    while let Some(c) = self.advance() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                },
+                (Some('/'), Some('*')) => {
+                    let start = self.here();
+                    self.advance();
+                    self.advance();
+                    loop {
+                        match self.advance() {
+                            Some('*') if self.peek() == Some('/') => {
+                                self.advance();
+                                break;
+                            },
+                            Some(_) => (),
+                            None => return Err(self.error(start, "unterminated comment")),
+                        }
+                    }
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn take_while(&mut self, predicate : impl Fn(char) -> bool) -> &'a str {
+        let start = self.offset;
+        while let Some(c) = self.peek() {
+            if !predicate(c) {
+                break;
+            }
+            self.advance();
+        }
+        &self.source[start..self.offset]
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, HdlError> {
+        self.skip_trivia()?;
+        let mut span = self.here();
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let kind = if c.is_ascii_digit() {
+            let digits = self.take_while(|c| c.is_ascii_digit());
+            match digits.parse::<u16>() {
+                Ok(value) => TokenKind::Number(value),
+                _ => return Err(self.error(span, &format!("number {} is too large", digits))),
+            }
+        }
+        else if c.is_ascii_alphabetic() || c == '_' {
+            let word = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            match Keyword::from_name(word) {
+                Some(keyword) => TokenKind::Keyword(keyword),
+                None => TokenKind::Identifier(word.to_string()),
+            }
+        }
+        else if c == '.' && self.peek_second() == Some('.') {
+            self.advance();
+            self.advance();
+            TokenKind::Range
+        }
+        else if SYMBOLS.contains(c) {
+            self.advance();
+            TokenKind::Symbol(c)
+        }
+        else {
+            return Err(self.error(span, &format!("unexpected character '{}'", c)));
+        };
+        span.end = self.offset;
+        Ok(Some(Token { kind, span }))
+    }
+}
+
+pub fn tokenize(file_name : &str, source : &str) -> Result<Vec<Token>, HdlError> {
+    let mut lexer = Lexer { file_name, source, offset : 0, line : 1, column : 1 };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hdl::lexer::{tokenize, Keyword, TokenKind};
+
+    #[test]
+    fn token_kinds_and_spans_test() {
+        let source = "/** doc */ CHIP And {\n  // comment\n  PARTS: Nand(a=a, b=true, out[0..7]=x);\n}";
+        let tokens = tokenize("And.hdl", source).ok().unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::Keyword(Keyword::Chip));
+        assert_eq!((tokens[0].span.line, tokens[0].span.column), (1, 12));
+        assert_eq!(tokens[3].kind, TokenKind::Keyword(Keyword::Parts));
+        assert_eq!((tokens[3].span.line, tokens[3].span.column), (3, 3));
+        assert_eq!(tokens[13].kind, TokenKind::Keyword(Keyword::True));
+        assert_eq!(tokens[16].kind, TokenKind::Symbol('['));
+        assert_eq!(tokens[18].kind, TokenKind::Range);
+        assert_eq!(&source[tokens[18].span.start..tokens[18].span.end], "..");
+        assert_eq!(tokens[19].kind, TokenKind::Number(7));
+    }
+
+    #[test]
+    fn lexical_errors_test() {
+        assert_eq!(tokenize("A.hdl", "CHIP A {\n  IN a[99999];").err().unwrap().to_string(), "A.hdl:2:8: number 99999 is too large");
+        assert_eq!(tokenize("A.hdl", "/* never closed").err().unwrap().to_string(), "A.hdl:1:1: unterminated comment");
+        assert_eq!(tokenize("A.hdl", "out[0.7]").err().unwrap().to_string(), "A.hdl:1:6: unexpected character '.'");
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use crate::hdl::{HdlError, Span};
+use crate::hdl::ast::{Chip, PinDeclaration, PinReference, Wire};
+use crate::hdl::elaborate::{bits, describe, Definition, Library};
+
+//Static checks over a chip's syntax tree for mistakes that elaboration either rejects one at a time
+//or lets through silently: internal pins that nothing reads, part inputs left unconnected (which
+//read false), connections between pins of different widths, pins with more than one driver, output
+//pins nothing drives, sub-buses out of range, and internal pins that shadow the chip's own pins. The parts'
+//pins come from the library. Each warning is located like an error.
+pub fn lint(chip : &Chip, file_name : &str, library : &mut Library) -> Result<Vec<HdlError>, HdlError> {
+    let mut warnings = Vec::new();
+    let mut warn = |span : Span, message : String| warnings.push(HdlError::at(file_name, span, message));
+    if chip.builtin.is_some() {
+        return Ok(Vec::new());
+    }
+    let ports = chip.inputs.iter().chain(chip.outputs.iter()).collect::<Vec<&PinDeclaration>>();
+    let port = |name : &str| ports.iter().find(|pin| pin.name.name == name).copied();
+    let mut definitions = Vec::new();
+    for part in &chip.parts {
+        let definition = library.resolve(&part.chip.name)?;
+        if definition.is_none() {
+            warn(part.chip.span, format!("unknown chip {}", part.chip.name));
+        }
+        definitions.push(definition.map(|d| Definition::pins(&d).iter().map(|(n, input, width)| (n.to_string(), *input, *width as usize)).collect::<Vec<(String, bool, usize)>>()));
+    }
+    //Sub-buses, on the part's side of a connection and on the chip's where the wire is one of its pins
+    for (part, pins) in chip.parts.iter().zip(definitions.iter()) {
+        for connection in &part.connections {
+            let part_pin = pins.as_ref().and_then(|pins| pins.iter().find(|(name, _, _)| *name == connection.pin.name.name));
+            if let Some((_, _, width)) = part_pin {
+                check_range(&connection.pin, *width, &part.chip.name, &mut warn);
+            }
+            if let Wire::Pin(reference) = &connection.wire {
+                if let Some(pin) = port(&reference.name.name) {
+                    check_range(reference, pin.width as usize, &chip.name.name, &mut warn);
+                }
+            }
+        }
+    }
+    //What the parts' outputs drive: bits of the chip's outputs, and internal pins with their widths,
+    //where they are first driven and whether anything reads them
+    let mut driven = chip.outputs.iter().map(|pin| (pin.name.name.as_str(), vec![false; pin.width as usize])).collect::<HashMap<&str, Vec<bool>>>();
+    let mut internals : Vec<(&str, usize, Span, bool)> = Vec::new();
+    for (part, pins) in chip.parts.iter().zip(definitions.iter()) {
+        let pins = match pins {
+            Some(pins) => pins,
+            None => continue,
+        };
+        for connection in &part.connections {
+            let (low, high) = match pins.iter().find(|(name, _, _)| *name == connection.pin.name.name) {
+                Some((_, false, width)) => match selection(&connection.pin, *width) {
+                    Some(bits) => bits,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            let reference = match &connection.wire {
+                Wire::Pin(reference) => reference,
+                Wire::Constant(..) => continue,
+            };
+            let name = reference.name.name.as_str();
+            if chip.input(name).is_some() {
+                warn(reference.span, format!("{} of {} drives {}, which would shadow the input pin of that name", describe(&connection.pin), part.chip.name, name));
+            }
+            else if let Some(bits) = driven.get_mut(name) {
+                let width = bits.len();
+                if let Some((first, last)) = selection(reference, width) {
+                    if last - first != high - low {
+                        warn(connection.span, mismatch(&connection.pin, &part.chip.name, high - low + 1, reference, last - first + 1));
+                    }
+                    if bits[first..=last].iter().any(|bit| *bit) {
+                        warn(reference.span, format!("{} has more than one driver", describe(reference)));
+                    }
+                    bits[first..=last].iter_mut().for_each(|bit| *bit = true);
+                }
+            }
+            else if reference.sub_bus.is_some() {
+                warn(reference.span, format!("internal pin {} cannot take a sub-bus", name));
+            }
+            else if internals.iter().any(|(internal, _, _, _)| *internal == name) {
+                warn(reference.span, format!("{} has more than one driver", name));
+            }
+            else {
+                if let Some(pin) = ports.iter().find(|pin| pin.name.name.eq_ignore_ascii_case(name)) {
+                    warn(reference.span, format!("internal pin {} shadows the pin {} but for case", name, pin.name.name));
+                }
+                internals.push((name, high - low + 1, reference.span, false));
+            }
+        }
+    }
+    //What the parts' inputs read, and which of their bits nothing is connected to
+    for (part, pins) in chip.parts.iter().zip(definitions.iter()) {
+        let pins = match pins {
+            Some(pins) => pins,
+            None => continue,
+        };
+        let mut connected = pins.iter().filter(|(_, input, _)| *input).map(|(name, _, width)| (name.as_str(), vec![false; *width])).collect::<HashMap<&str, Vec<bool>>>();
+        for connection in &part.connections {
+            let (input, width) = match pins.iter().find(|(name, _, _)| *name == connection.pin.name.name) {
+                Some((_, input, width)) => (*input, *width),
+                None => {
+                    warn(connection.pin.span, format!("{} has no pin {}", part.chip.name, connection.pin.name.name));
+                    continue;
+                },
+            };
+            let (low, high) = match selection(&connection.pin, width) {
+                Some(bits) if input => bits,
+                _ => continue,
+            };
+            connected.get_mut(connection.pin.name.name.as_str()).unwrap()[low..=high].iter_mut().for_each(|bit| *bit = true);
+            let reference = match &connection.wire {
+                Wire::Pin(reference) => reference,
+                Wire::Constant(..) => continue,
+            };
+            let name = reference.name.name.as_str();
+            let wire_width = if let Some(pin) = port(name) {
+                selection(reference, pin.width as usize).map(|(first, last)| last - first + 1)
+            }
+            else if let Some(internal) = internals.iter_mut().find(|(internal, _, _, _)| *internal == name) {
+                internal.3 = true;
+                if reference.sub_bus.is_some() {
+                    warn(reference.span, format!("internal pin {} cannot take a sub-bus", name));
+                    None
+                }
+                else {
+                    Some(internal.1)
+                }
+            }
+            else {
+                warn(reference.span, format!("{} is not a pin of {} or an output of any of its parts", name, chip.name.name));
+                None
+            };
+            if let Some(wire_width) = wire_width.filter(|wire_width| *wire_width != high - low + 1) {
+                warn(connection.span, mismatch(&connection.pin, &part.chip.name, high - low + 1, reference, wire_width));
+            }
+        }
+        for (name, _, _) in pins.iter().filter(|(_, input, _)| *input) {
+            let bits = &connected[name.as_str()];
+            if bits.iter().all(|bit| !*bit) {
+                warn(part.chip.span, format!("input {} of {} is not connected and reads false", name, part.chip.name));
+            }
+            else if bits.iter().any(|bit| !*bit) {
+                warn(part.chip.span, format!("bits {} of input {} of {} are not connected and read false", unset(bits), name, part.chip.name));
+            }
+        }
+    }
+    for (name, _, span, read) in &internals {
+        if !read {
+            warn(*span, format!("internal pin {} is never read", name));
+        }
+    }
+    for pin in &chip.outputs {
+        let bits = &driven[pin.name.name.as_str()];
+        if bits.iter().all(|bit| !*bit) {
+            warn(pin.name.span, format!("output {} is never driven", pin.name.name));
+        }
+        else if bits.iter().any(|bit| !*bit) {
+            warn(pin.name.span, format!("bits {} of output {} are never driven", unset(bits), pin.name.name));
+        }
+    }
+    Ok(sorted(warnings))
+}
+
+fn sorted(mut warnings : Vec<HdlError>) -> Vec<HdlError> {
+    warnings.sort_by_key(|warning| (warning.line, warning.column));
+    warnings
+}
+
+//The bits a reference selects of a pin, unless they are out of range
+fn selection(reference : &PinReference, width : usize) -> Option<(usize, usize)> {
+    match reference.sub_bus {
+        Some(sub_bus) if sub_bus.high as usize >= width => None,
+        Some(sub_bus) => Some((sub_bus.low as usize, sub_bus.high as usize)),
+        None => Some((0, width - 1)),
+    }
+}
+
+fn check_range(reference : &PinReference, width : usize, chip : &str, warn : &mut impl FnMut(Span, String)) {
+    if selection(reference, width).is_none() {
+        warn(reference.span, format!("sub-bus {} is out of range for {} of {}, which is {}", describe(reference), reference.name.name, chip, bits(width)));
+    }
+}
+
+fn mismatch(pin : &PinReference, part : &str, width : usize, wire : &PinReference, wire_width : usize) -> String {
+    format!("{} of {} is {} wide, but {} is {} wide", describe(pin), part, bits(width), describe(wire), bits(wire_width))
+}
+
+//"3, 8..15": the bits that are false
+fn unset(bits : &[bool]) -> String {
+    let mut ranges = Vec::new();
+    let mut bit = 0;
+    while bit < bits.len() {
+        if bits[bit] {
+            bit += 1;
+            continue;
+        }
+        let start = bit;
+        while bit < bits.len() && !bits[bit] {
+            bit += 1;
+        }
+        ranges.push(if bit - 1 == start { start.to_string() } else { format!("{}..{}", start, bit - 1) });
+    }
+    ranges.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::hdl::elaborate::Library;
+    use crate::hdl::lint::lint;
+    use crate::hdl::parser::parse_source;
+    use crate::hdl::read_chip;
+    use crate::test_support::PROJECTS;
+
+    fn warnings(source : &str) -> Vec<String> {
+        let chip = parse_source("Chip.hdl", source).unwrap_or_else(|e| panic!("{}", e));
+        let mut library = Library::new(Vec::new());
+        lint(&chip, "Chip.hdl", &mut library).ok().unwrap().iter().map(|warning| warning.to_string()).collect()
+    }
+
+    //The projects' chips are clean but for the carries out of the top bit that the adders drop
+    #[test]
+    fn project_chips_test() {
+        let mut found = Vec::new();
+        for directory in ["01", "02", "03/a", "03/b", "05"].iter() {
+            for path in crate::hdl::hdl_file_paths(&Path::new(PROJECTS).join(directory)).unwrap() {
+                let chip = read_chip(&path).ok().unwrap();
+                let (mut library, _) = Library::for_chip(&path, &[], &[]);
+                let warnings = lint(&chip, &chip.name.name, &mut library).ok().unwrap();
+                found.extend(warnings.iter().map(|warning| warning.to_string()));
+            }
+        }
+        assert_eq!(found, vec!["Add16:31:63: internal pin carry16 is never read", "Inc16:31:51: internal pin c16 is never read"]);
+    }
+
+    #[test]
+    fn checks_test() {
+        let source = "CHIP Chip {\n\
+            \x20   IN a, b[16], c;\n\
+            \x20   OUT out[16], zr, ng;\n\
+            \x20   PARTS:\n\
+            \x20   Not(in=b, out=notb);\n\
+            \x20   Not16(in=b, out=out, out=unused);\n\
+            \x20   Mux16(a=b, b[0..7]=b[0..7], sel=b[16], out=out);\n\
+            \x20   And(a=a, out=c);\n\
+            \x20   Or(a=notb, b=a, out=Zr);\n\
+            \x20   Or(a=Zr, b=x, out=zr[0..1]);\n\
+            }";
+        assert_eq!(warnings(source), vec![
+            "Chip.hdl:3:18: output zr is never driven",
+            "Chip.hdl:3:22: output ng is never driven",
+            "Chip.hdl:5:9: in of Not is 1 bit wide, but b is 16 bits wide",
+            "Chip.hdl:6:30: internal pin unused is never read",
+            "Chip.hdl:7:5: bits 8..15 of input b of Mux16 are not connected and read false",
+            "Chip.hdl:7:37: sub-bus b[16] is out of range for b of Chip, which is 16 bits",
+            "Chip.hdl:7:48: out has more than one driver",
+            "Chip.hdl:8:5: input b of And is not connected and reads false",
+            "Chip.hdl:8:18: out of And drives c, which would shadow the input pin of that name",
+            "Chip.hdl:9:25: internal pin Zr shadows the pin zr but for case",
+            "Chip.hdl:10:16: x is not a pin of Chip or an output of any of its parts",
+            "Chip.hdl:10:23: sub-bus zr[0..1] is out of range for zr of Chip, which is 1 bit",
+        ]);
+        let source = "CHIP Chip {\n\
+            \x20   IN a, in[4];\n\
+            \x20   OUT out[4], b;\n\
+            \x20   PARTS:\n\
+            \x20   Not(in=a, out=x, output=y);\n\
+            \x20   Not(in=x, out=x);\n\
+            \x20   Not(in=x, out=out[1]);\n\
+            \x20   Not(in=in[3], out=out[3]);\n\
+            }";
+        assert_eq!(warnings(source), vec![
+            "Chip.hdl:3:9: bits 0, 2 of output out are never driven",
+            "Chip.hdl:3:17: output b is never driven",
+            "Chip.hdl:5:22: Not has no pin output",
+            "Chip.hdl:6:19: x has more than one driver",
+        ]);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::hdl::{HdlError, Span};
+use crate::hdl::ast::*;
+use crate::hdl::lexer::{Keyword, Token, TokenKind, tokenize};
+
+//A recursive descent parser for the nand2tetris HDL:
+//  CHIP name { [IN pins;] [OUT pins;] (PARTS: part* | BUILTIN name; [CLOCKED names;]) }
+//where a part is name(pin=wire, ...); and both sides of a connection may take a sub-bus.
+struct Parser<'a> {
+    file_name : &'a str,
+    tokens : &'a [Token],
+    position : usize,
+}
+
+fn describe(kind : &TokenKind) -> String {
+    match kind {
+        TokenKind::Keyword(keyword) => format!("'{}'", keyword.name()),
+        TokenKind::Symbol(symbol) => format!("'{}'", symbol),
+        TokenKind::Range => String::from("'..'"),
+        TokenKind::Number(value) => format!("number {}", value),
+        TokenKind::Identifier(name) => format!("identifier '{}'", name),
+    }
+}
+
+//"'a'", "'a' or 'b'", "'a', 'b' or 'c'"
+fn expected_list(expected : &[&str]) -> String {
+    match expected.split_last() {
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a TokenKind> {
+        self.tokens.get(self.position).map(|t| &t.kind)
+    }
+
+    fn is_symbol(&self, symbol : char) -> bool {
+        self.peek() == Some(&TokenKind::Symbol(symbol))
+    }
+
+    fn is_keyword(&self, keyword : Keyword) -> bool {
+        self.peek() == Some(&TokenKind::Keyword(keyword))
+    }
+
+    //The span of the next token, or an empty span just past the last one at the end of the file
+    fn current_span(&self) -> Span {
+        match self.tokens.get(self.position) {
+            Some(token) => token.span,
+            None => {
+                let mut span = self.tokens.last().map(|t| t.span).unwrap_or_default();
+                span.column += span.end - span.start;
+                span.start = span.end;
+                span
+            },
+        }
+    }
+
+    //From the start of a node to the end of the last token consumed
+    fn span_from(&self, start : Span) -> Span {
+        let end = self.tokens[..self.position].last().map(|t| t.span.end).unwrap_or(start.end);
+        Span { end, ..start }
+    }
+
+    fn error(&self, expected : &[&str]) -> HdlError {
+        let found = match self.peek() {
+            Some(kind) => describe(kind),
+            None => String::from("end of file"),
+        };
+        HdlError::at(self.file_name, self.current_span(), format!("expected {}, found {}", expected_list(expected), found))
+    }
+
+    fn advance(&mut self) -> &'a Token {
+        let token = &self.tokens[self.position];
+        self.position += 1;
+        token
+    }
+
+    fn expect_symbol(&mut self, symbol : char) -> Result<(), HdlError> {
+        if !self.is_symbol(symbol) {
+            return Err(self.error(&[&format!("'{}'", symbol)]));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_keyword(&mut self, keyword : Keyword) -> Result<(), HdlError> {
+        if !self.is_keyword(keyword) {
+            return Err(self.error(&[&format!("'{}'", keyword.name())]));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_identifier(&mut self) -> Result<Identifier, HdlError> {
+        match self.peek() {
+            Some(TokenKind::Identifier(name)) => {
+                let span = self.advance().span;
+                Ok(Identifier { name : name.clone(), span })
+            },
+            _ => Err(self.error(&["identifier"])),
+        }
+    }
+
+    fn expect_number(&mut self) -> Result<u16, HdlError> {
+        match self.peek() {
+            Some(TokenKind::Number(value)) => {
+                self.advance();
+                Ok(*value)
+            },
+            _ => Err(self.error(&["number"])),
+        }
+    }
+
+    fn parse_chip(&mut self) -> Result<Chip, HdlError> {
+        let start = self.current_span();
+        self.expect_keyword(Keyword::Chip)?;
+        let name = self.expect_identifier()?;
+        self.expect_symbol('{')?;
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        if self.is_keyword(Keyword::In) {
+            self.advance();
+            inputs = self.parse_pin_declarations()?;
+        }
+        if self.is_keyword(Keyword::Out) {
+            self.advance();
+            outputs = self.parse_pin_declarations()?;
+        }
+        let mut parts = Vec::new();
+        let mut builtin = None;
+        let mut clocked = Vec::new();
+        match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Parts)) => {
+                self.advance();
+                self.expect_symbol(':')?;
+                while !self.is_symbol('}') && self.peek().is_some() {
+                    parts.push(self.parse_part()?);
+                }
+            },
+            Some(TokenKind::Keyword(Keyword::Builtin)) => {
+                self.advance();
+                builtin = Some(self.expect_identifier()?);
+                self.expect_symbol(';')?;
+                if self.is_keyword(Keyword::Clocked) {
+                    self.advance();
+                    clocked = self.parse_names()?;
+                }
+            },
+            _ => {
+                let mut expected = vec!["'PARTS'", "'BUILTIN'"];
+                if outputs.is_empty() {
+                    expected.insert(0, "'OUT'");
+                    if inputs.is_empty() {
+                        expected.insert(0, "'IN'");
+                    }
+                }
+                return Err(self.error(&expected));
+            },
+        }
+        self.expect_symbol('}')?;
+        if self.peek().is_some() {
+            return Err(self.error(&["end of file"]));
+        }
+        let chip = Chip { name, inputs, outputs, parts, builtin, clocked, span : self.span_from(start) };
+        self.check_declarations(&chip)?;
+        Ok(chip)
+    }
+
+    //name, name[16], ... ;
+    fn parse_pin_declarations(&mut self) -> Result<Vec<PinDeclaration>, HdlError> {
+        let mut pins = Vec::new();
+        loop {
+            let start = self.current_span();
+            let name = self.expect_identifier()?;
+            let mut width = 1;
+            if self.is_symbol('[') {
+                self.advance();
+                let width_span = self.current_span();
+                width = self.expect_number()?;
+                if width == 0 || width > 16 {
+                    return Err(HdlError::at(self.file_name, width_span, format!("bus width {} is not between 1 and 16", width)));
+                }
+                self.expect_symbol(']')?;
+            }
+            pins.push(PinDeclaration { name, width, span : self.span_from(start) });
+            if !self.is_symbol(',') {
+                break;
+            }
+            self.advance();
+        }
+        self.expect_symbol(';')?;
+        Ok(pins)
+    }
+
+    //name, name, ... ;
+    fn parse_names(&mut self) -> Result<Vec<Identifier>, HdlError> {
+        let mut names = vec![self.expect_identifier()?];
+        while self.is_symbol(',') {
+            self.advance();
+            names.push(self.expect_identifier()?);
+        }
+        self.expect_symbol(';')?;
+        Ok(names)
+    }
+
+    fn parse_part(&mut self) -> Result<Part, HdlError> {
+        let start = self.current_span();
+        let chip = self.expect_identifier()?;
+        self.expect_symbol('(')?;
+        let mut connections = Vec::new();
+        loop {
+            let start = self.current_span();
+            let pin = self.parse_pin_reference()?;
+            self.expect_symbol('=')?;
+            let wire = match self.peek() {
+                Some(TokenKind::Keyword(Keyword::True)) => Wire::Constant(true, self.advance().span),
+                Some(TokenKind::Keyword(Keyword::False)) => Wire::Constant(false, self.advance().span),
+                Some(TokenKind::Identifier(_)) => Wire::Pin(self.parse_pin_reference()?),
+                _ => return Err(self.error(&["identifier", "'true'", "'false'"])),
+            };
+            connections.push(Connection { pin, wire, span : self.span_from(start) });
+            match self.peek() {
+                Some(TokenKind::Symbol(',')) => {
+                    self.advance();
+                },
+                Some(TokenKind::Symbol(')')) => break,
+                _ => return Err(self.error(&["','", "')'"])),
+            }
+        }
+        self.expect_symbol(')')?;
+        self.expect_symbol(';')?;
+        Ok(Part { chip, connections, span : self.span_from(start) })
+    }
+
+    //name, name[i] or name[i..j]
+    fn parse_pin_reference(&mut self) -> Result<PinReference, HdlError> {
+        let start = self.current_span();
+        let name = self.expect_identifier()?;
+        let mut sub_bus = None;
+        if self.is_symbol('[') {
+            self.advance();
+            let low = self.expect_number()?;
+            let mut high = low;
+            match self.peek() {
+                Some(TokenKind::Range) => {
+                    self.advance();
+                    high = self.expect_number()?;
+                },
+                Some(TokenKind::Symbol(']')) => (),
+                _ => return Err(self.error(&["'..'", "']'"])),
+            }
+            self.expect_symbol(']')?;
+            if high < low {
+                return Err(HdlError::at(self.file_name, self.span_from(start), format!("sub-bus {}[{}..{}] runs backwards", name.name, low, high)));
+            }
+            sub_bus = Some(SubBus { low, high });
+        }
+        Ok(PinReference { name, sub_bus, span : self.span_from(start) })
+    }
+
+    //Pin names must be unique and CLOCKED may only list inputs
+    fn check_declarations(&self, chip : &Chip) -> Result<(), HdlError> {
+        let pins = chip.inputs.iter().chain(chip.outputs.iter()).collect::<Vec<&PinDeclaration>>();
+        for (i, pin) in pins.iter().enumerate() {
+            if pins[..i].iter().any(|other| other.name.name == pin.name.name) {
+                return Err(HdlError::at(self.file_name, pin.name.span, format!("pin {} is declared twice", pin.name.name)));
+            }
+        }
+        for name in &chip.clocked {
+            if chip.input(&name.name).is_none() {
+                return Err(HdlError::at(self.file_name, name.span, format!("{} is not an input pin", name.name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_tokens(file_name : &str, tokens : &[Token]) -> Result<Chip, HdlError> {
+    let mut parser = Parser { file_name, tokens, position : 0 };
+    parser.parse_chip()
+}
+
+pub fn parse_source(file_name : &str, source : &str) -> Result<Chip, HdlError> {
+    parse_tokens(file_name, &tokenize(file_name, source)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::hdl::{hdl_file_paths, read_chip};
+    use crate::hdl::ast::*;
+    use crate::hdl::parser::parse_source;
+    use crate::test_support::PROJECTS;
+
+    #[test]
+    fn alu_tree_test() {
+        let chip = read_chip(&Path::new(PROJECTS).join("02/ALU.hdl")).ok().unwrap();
+        assert_eq!(chip.name.name, "ALU");
+        assert_eq!(chip.inputs.iter().map(|p| (p.name.name.as_str(), p.width)).collect::<Vec<(&str, u16)>>(),
+                   vec![("x", 16), ("y", 16), ("zx", 1), ("nx", 1), ("zy", 1), ("ny", 1), ("f", 1), ("no", 1)]);
+        assert_eq!((chip.outputs[0].name.name.as_str(), chip.outputs[0].width), ("out", 16));
+        assert_eq!(chip.parts.len(), 13);
+        //Mux4Way16 (a=x, b=false, c=notx, d=true, sel[0]=zx, sel[1]=nx, out=finalx);
+        let mux = &chip.parts[1];
+        assert_eq!(mux.chip.name, "Mux4Way16");
+        assert!(matches!(mux.connections[1].wire, Wire::Constant(false, _)));
+        assert!(matches!(mux.connections[3].wire, Wire::Constant(true, _)));
+        assert_eq!(mux.connections[5].pin.sub_bus, Some(SubBus { low : 1, high : 1 }));
+        //The connections of the last Mux4Way16 run over several lines
+        let outputs = &chip.parts[8].connections[6..];
+        assert_eq!(outputs.iter().map(|c| c.pin.sub_bus).collect::<Vec<Option<SubBus>>>(),
+                   vec![None, Some(SubBus { low : 15, high : 15 }), Some(SubBus { low : 0, high : 7 }), Some(SubBus { low : 8, high : 15 })]);
+        assert_eq!((outputs[3].span.line, outputs[3].span.column), (58, 83));
+        assert!(chip.builtin.is_none() && chip.clocked.is_empty());
+    }
+
+    #[test]
+    fn builtin_chip_test() {
+        let chip = parse_source("DFF.hdl", "CHIP DFF {\n    IN in;\n    OUT out;\n    BUILTIN DFF;\n    CLOCKED in;\n}").ok().unwrap();
+        assert_eq!(chip.builtin.map(|b| b.name), Some(String::from("DFF")));
+        assert_eq!(chip.clocked.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>(), vec!["in"]);
+        assert!(chip.parts.is_empty());
+    }
+
+    #[test]
+    fn every_project_chip_parses_test() {
+        let mut parsed = 0;
+        for directory in ["01", "02", "03/a", "03/b", "05"].iter() {
+            for path in hdl_file_paths(&Path::new(PROJECTS).join(directory)).unwrap() {
+                let chip = read_chip(&path).unwrap_or_else(|e| panic!("{}", e));
+                assert_eq!(chip.name.name, path.file_stem().unwrap().to_string_lossy());
+                parsed += 1;
+            }
+        }
+        assert_eq!(parsed, 31);
+    }
+
+    #[test]
+    fn syntax_errors_test() {
+        let error = |source : &str| parse_source("A.hdl", source).err().unwrap().to_string();
+        assert_eq!(error("CHIP A {\n    IN a, b\n    OUT out;\n}"), "A.hdl:3:5: expected ';', found 'OUT'");
+        assert_eq!(error("CHIP A {\n    IN a;\n    OUT out;\n    PARTS:\n    Nand(a=a, b=a out=out);\n}"),
+                   "A.hdl:5:19: expected ',' or ')', found identifier 'out'");
+        assert_eq!(error("CHIP A {\n    IN a;\n    OUT out;\n    PARTS:\n    Not(in=a, out=);\n}"),
+                   "A.hdl:5:19: expected identifier, 'true' or 'false', found ')'");
+        assert_eq!(error("CHIP A {\n    IN a;\n    Nand(a=a);\n}"), "A.hdl:3:5: expected 'OUT', 'PARTS' or 'BUILTIN', found identifier 'Nand'");
+        assert_eq!(error("CHIP A {\n    IN a[16];\n    OUT out;\n    PARTS:\n    Or8Way(in=a[7..0], out=out);\n}"),
+                   "A.hdl:5:15: sub-bus a[7..0] runs backwards");
+        assert_eq!(error("CHIP A {\n    IN a[17];"), "A.hdl:2:10: bus width 17 is not between 1 and 16");
+        assert_eq!(error("CHIP A {\n    IN a, a;\n    OUT out;\n    PARTS:\n}"), "A.hdl:2:11: pin a is declared twice");
+        assert_eq!(error("CHIP A {\n    IN a;\n    OUT out;\n    BUILTIN A;\n    CLOCKED out;\n}"), "A.hdl:5:13: out is not an input pin");
+        assert_eq!(error("CHIP A {\n    IN a;\n    OUT out;\n    PARTS:\n    Not(in=a, out=out);\n"),
+                   "A.hdl:5:24: expected '}', found end of file");
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use crate::assembler::read_machine_lines_from_file;
+use crate::hdl::simulator::HdlSimulator;
+use crate::hdl::trace::Trace;
+use crate::test_script::{Simulator, Value, split_indexed, resolve_path};
+
+//Runs hardware simulator test scripts (load Chip.hdl, tick, tock, eval, pins, Part[] and Part[i]).
+//Parts that are not in the chip's directory come from the builtin chips.
+pub struct HardwareSimulator {
+    pub chip : Option<HdlSimulator>,
+    //Where the script is, for the programs it loads into ROM32K
+    directory : PathBuf,
+    time : u64,
+    //Between a tick and its tock, which the time column shows as "3+"
+    ticked : bool,
+    //The clock cycles to dump, and the dump of the loaded chip
+    trace_range : Option<(u64, Option<u64>)>,
+    pub trace : Option<Trace>,
+    //The keys to hold down on the Keyboard part, the next one as each while loop starts
+    keys : VecDeque<u16>,
+}
+
+impl HardwareSimulator {
+    pub fn new() -> HardwareSimulator {
+        HardwareSimulator {
+            chip : None,
+            directory : PathBuf::new(),
+            time : 0,
+            ticked : false,
+            trace_range : None,
+            trace : None,
+            keys : VecDeque::new(),
+        }
+    }
+
+    //Memory.tst loops until the keyboard shows 'K' and then until it shows 'Y', so it runs with KY
+    pub fn hold_keys(&mut self, keys : &[u16]) {
+        self.keys = keys.iter().copied().collect();
+    }
+
+    //A simulator that dumps the chip a script loads, from clock cycle from to clock cycle to
+    pub fn with_trace(from : u64, to : Option<u64>) -> HardwareSimulator {
+        HardwareSimulator { trace_range : Some((from, to)), ..HardwareSimulator::new() }
+    }
+
+    fn sample(&mut self) {
+        if let (Some(trace), Some(chip)) = (self.trace.as_mut(), self.chip.as_ref()) {
+            trace.sample(chip, self.time, self.ticked);
+        }
+    }
+
+    fn chip(&self) -> Result<&HdlSimulator, String> {
+        self.chip.as_ref().ok_or_else(|| String::from("no chip is loaded"))
+    }
+
+    fn chip_mut(&mut self) -> Result<&mut HdlSimulator, String> {
+        self.chip.as_mut().ok_or_else(|| String::from("no chip is loaded"))
+    }
+
+    //The word of a part's memory that Part[] (the only word) or Part[i] names
+    fn memory_address(chip : &HdlSimulator, part : &str, index : &str) -> Result<usize, String> {
+        let size = chip.memory(part).map(|memory| memory.len()).ok_or_else(|| format!("the chip has no part {} with memory", part))?;
+        let address = if index.is_empty() { 0 } else { index.parse::<usize>().map_err(|_e| format!("bad index '{}'", index))? };
+        if address >= size {
+            return Err(format!("{}[{}] is out of range", part, index));
+        }
+        Ok(address)
+    }
+
+    fn load_memory(&mut self, part : &str, file_name : &str) -> Result<(), String> {
+        let path = resolve_path(&self.directory, file_name).display().to_string();
+        let program = read_machine_lines_from_file(&path).map_err(|e| format!("Error loading {}: {}", file_name, e))?;
+        let chip = self.chip_mut()?;
+        let memory = chip.memory_mut(part).ok_or_else(|| format!("the chip has no part {} with memory", part))?;
+        if program.len() > memory.len() {
+            return Err(format!("{} does not fit in {}", file_name, part));
+        }
+        memory[..program.len()].copy_from_slice(&program);
+        chip.eval();
+        Ok(())
+    }
+}
+
+impl Default for HardwareSimulator {
+    fn default() -> HardwareSimulator {
+        HardwareSimulator::new()
+    }
+}
+
+impl Simulator for HardwareSimulator {
+    fn load(&mut self, directory : &Path, file_name : Option<&str>) -> Result<(), String> {
+        let file_name = file_name.ok_or_else(|| String::from("the hardware simulator needs a chip to load"))?;
+        let chip = HdlSimulator::load(&resolve_path(directory, file_name), &[], &[]).map_err(|e| format!("Error loading {}", e))?;
+        self.trace = self.trace_range.map(|(from, to)| Trace::new(&chip.netlist, from, to));
+        self.chip = Some(chip);
+        self.directory = directory.to_path_buf();
+        self.time = 0;
+        self.ticked = false;
+        self.sample();
+        Ok(())
+    }
+
+    fn get(&self, variable : &str) -> Result<Value, String> {
+        if variable == "time" {
+            return Ok(Value::Text(format!("{}{}", self.time, if self.ticked { "+" } else { "" })));
+        }
+        let chip = self.chip()?;
+        match split_indexed(variable) {
+            (part, Some(index)) => {
+                let address = HardwareSimulator::memory_address(chip, part, index)?;
+                Ok(Value::Number(chip.latched(part, address).unwrap()))
+            },
+            (pin, None) => chip.get(pin).map(Value::Number),
+        }
+    }
+
+    fn set(&mut self, variable : &str, value : u16) -> Result<(), String> {
+        let chip = self.chip_mut()?;
+        match split_indexed(variable) {
+            (part, Some(index)) => {
+                let address = HardwareSimulator::memory_address(chip, part, index)?;
+                chip.memory_mut(part).unwrap()[address] = value;
+                Ok(())
+            },
+            (pin, None) => chip.set(pin, value),
+        }
+    }
+
+    fn execute(&mut self, command : &[String]) -> Result<(), String> {
+        match command.iter().map(|s| s.as_str()).collect::<Vec<&str>>().as_slice() {
+            ["eval"] => self.chip_mut()?.eval(),
+            ["tick"] => {
+                self.chip_mut()?.tick();
+                self.ticked = true;
+            },
+            ["tock"] => {
+                self.chip_mut()?.tock();
+                self.time += 1;
+                self.ticked = false;
+            },
+            [part, "load", file_name] => self.load_memory(part, file_name)?,
+            _ => return Err(format!("unknown command '{}'", command.join(" "))),
+        }
+        self.sample();
+        Ok(())
+    }
+
+    fn start_waiting(&mut self) {
+        if let Some(keyboard) = self.chip.as_mut().and_then(|chip| chip.memory_mut("Keyboard")) {
+            if let Some(key) = self.keys.pop_front() {
+                keyboard[0] = key;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::hdl::script::HardwareSimulator;
+    use crate::test_script::{loaded_program, run_script_file};
+    use crate::test_support::PROJECTS;
+
+    //Every script in projects 1 to 5 that loads a chip, with the project's own chips as parts
+    #[test]
+    fn project_scripts_test() {
+        let mut scripts = Vec::new();
+        for directory in ["01", "02", "03/a", "03/b", "05"].iter() {
+            for entry in fs::read_dir(Path::new(PROJECTS).join(directory)).unwrap().flatten() {
+                let path = entry.path();
+                if path.extension().map(|e| e == "tst").unwrap_or(false) {
+                    let source = fs::read_to_string(&path).unwrap();
+                    if let Ok(Some(Some(name))) = loaded_program(&source) {
+                        if name.ends_with(".hdl") {
+                            scripts.push(path);
+                        }
+                    }
+                }
+            }
+        }
+        assert_eq!(scripts.len(), 38);
+        for script in &scripts {
+            let mut simulator = HardwareSimulator::new();
+            if script.ends_with("Memory.tst") {
+                simulator.hold_keys(&['K' as u16, 'Y' as u16]);
+            }
+            let result = run_script_file(&script.display().to_string(), &mut simulator).unwrap_or_else(|e| panic!("{}", e));
+            assert!(result.compared);
+            assert!(result.passed(), "{} fails at line {:?}", script.display(), result.comparison_failure);
+        }
+    }
+
+    #[test]
+    fn loop_without_its_key_test() {
+        let mut simulator = HardwareSimulator::new();
+        simulator.hold_keys(&['K' as u16]);
+        let error = run_script_file(&format!("{}/05/Memory.tst", PROJECTS), &mut simulator).err().unwrap();
+        assert_eq!((error.line, error.message.as_str()), (158, "the loop is still waiting after 100000 rounds"));
+    }
+
+    #[test]
+    fn time_and_memory_test() {
+        let directory = std::env::temp_dir().join("n2t_hardware_script");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("Counter.hdl"), "CHIP Counter {\n    IN load;\n    OUT out[16];\n    PARTS:\n    PC(in=false, load=load, inc=true, reset=false, out=out);\n}").unwrap();
+        fs::write(directory.join("Counter.tst"), "load Counter.hdl,\noutput-list time%S1.4.1 out%D1.6.1 PC[]%D1.6.1;\ntick, output;\ntock, output;\nset PC[] 40, eval, output;\nset load 1, tick, tock, output;\n").unwrap();
+        //PC[] shows the count the PC took in at the tick, before out does at the tock
+        let mut simulator = HardwareSimulator::new();
+        let result = run_script_file(&directory.join("Counter.tst").display().to_string(), &mut simulator).ok().unwrap();
+        assert_eq!(result.output.replace("\r\n", "\n"), "| time |  out   |  PC[]  |\n| 0+   |      0 |      1 |\n| 1    |      1 |      1 |\n| 1    |     40 |     40 |\n| 2    |      0 |      0 |\n");
+    }
+
+    #[test]
+    fn trace_test() {
+        let directory = std::env::temp_dir().join("n2t_hardware_trace");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("Counter.hdl"), "CHIP Counter {\n    IN load;\n    OUT out[16];\n    PARTS:\n    PC(in=false, load=load, inc=true, reset=false, out=out);\n}").unwrap();
+        fs::write(directory.join("Counter.tst"), "load Counter.hdl;\nrepeat 5 {\n    tick, tock;\n}\n").unwrap();
+        //Cycles 2 and 3: the tock that starts each and the tick in it
+        let mut simulator = HardwareSimulator::with_trace(2, Some(3));
+        run_script_file(&directory.join("Counter.tst").display().to_string(), &mut simulator).ok().unwrap();
+        let vcd = simulator.trace.unwrap().finish();
+        assert!(vcd.contains("$scope module Counter $end\n$var wire 1 ! clk $end\n$var wire 1 \" load $end\n$var wire 16 # out [15:0] $end\n$scope module PC_0 $end\n"));
+        assert!(vcd.contains("$enddefinitions $end\n#0\n0!\n0\"\nb10 #\n"));
+        assert!(vcd.contains("#1\n1!\n#2\n0!\nb11 #\nb11 (\n#3\n1!\n"));
+        assert!(!vcd.contains("#4\n"));
+    }
+}
@@ -0,0 +1,408 @@
+use std::path::{Path, PathBuf};
+use crate::hdl::HdlError;
+use crate::hdl::elaborate::{elaborate, ComponentKind, Library, Netlist, TRUE};
+
+//Evaluates a net list one component at a time, each after every component that drives its inputs.
+//Clocked components keep their memory here, along with the write each one decided on at the last tick.
+pub struct HdlSimulator {
+    pub netlist : Netlist,
+    values : Vec<bool>,
+    order : Vec<usize>,
+    memories : Vec<Vec<u16>>,
+    writes : Vec<Option<(usize, u16)>>,
+}
+
+fn read(values : &[bool], nets : &[usize]) -> u16 {
+    nets.iter().enumerate().fold(0, |word, (bit, net)| word | ((values[*net] as u16) << bit))
+}
+
+fn write(values : &mut [bool], nets : &[usize], word : u16) {
+    for (bit, net) in nets.iter().enumerate() {
+        values[*net] = word & (1 << bit) != 0;
+    }
+}
+
+//Orders the components so that each comes after the ones driving its unclocked inputs. A component left
+//over once nothing more can be ordered lies on or behind a combinational loop; walking back from
+//it through unordered drivers ends up going round the loop, which names one of its chips.
+pub fn topological_order(netlist : &Netlist) -> Result<Vec<usize>, HdlError> {
+    let components = &netlist.components;
+    let mut drivers = vec![None; netlist.nets];
+    for (index, component) in components.iter().enumerate() {
+        for net in component.output_nets() {
+            drivers[net] = Some(index);
+        }
+    }
+    let inputs = components.iter()
+        .map(|component| component.combinational_input_nets().iter().filter_map(|net| drivers[*net]).collect::<Vec<usize>>())
+        .collect::<Vec<Vec<usize>>>();
+    let mut waiting = inputs.iter().map(|drivers| drivers.len()).collect::<Vec<usize>>();
+    let mut dependents = vec![Vec::new(); components.len()];
+    for (index, drivers) in inputs.iter().enumerate() {
+        for driver in drivers {
+            dependents[*driver].push(index);
+        }
+    }
+    let mut order = (0..components.len()).filter(|index| waiting[*index] == 0).collect::<Vec<usize>>();
+    let mut next = 0;
+    while next < order.len() {
+        for dependent in &dependents[order[next]] {
+            waiting[*dependent] -= 1;
+            if waiting[*dependent] == 0 {
+                order.push(*dependent);
+            }
+        }
+        next += 1;
+    }
+    if let Some(mut stuck) = (0..components.len()).find(|index| waiting[*index] > 0) {
+        for _ in 0..components.len() {
+            stuck = *inputs[stuck].iter().find(|driver| waiting[**driver] > 0).unwrap();
+        }
+        let instance = &netlist.instances[components[stuck].instance];
+        return Err(HdlError::at(&instance.file_name, instance.span, format!("combinational loop through {}", netlist.path(components[stuck].instance))));
+    }
+    Ok(order)
+}
+
+impl HdlSimulator {
+    pub fn new(netlist : Netlist) -> Result<HdlSimulator, HdlError> {
+        let order = topological_order(&netlist)?;
+        let mut values = vec![false; netlist.nets];
+        values[TRUE] = true;
+        let memories = netlist.components.iter().map(|component| match &component.kind {
+            ComponentKind::Builtin { chip, .. } => vec![0; chip.memory],
+            ComponentKind::Nand { .. } => Vec::new(),
+        }).collect();
+        let writes = vec![None; netlist.components.len()];
+        let mut simulator = HdlSimulator { netlist, values, order, memories, writes };
+        simulator.eval();
+        Ok(simulator)
+    }
+
+    //Loads Dir/Name.hdl with Dir and then the other directories as its library
+    pub fn load(path : &Path, directories : &[PathBuf], builtin : &[String]) -> Result<HdlSimulator, HdlError> {
+        let (mut library, name) = Library::for_chip(path, directories, builtin);
+        HdlSimulator::new(elaborate(&mut library, &name)?)
+    }
+
+    //The nets of one of the top chip's pins, internal pins included
+    fn pin(&self, name : &str) -> Result<&Vec<usize>, String> {
+        self.netlist.instances[0].pin(name).ok_or_else(|| format!("{} has no pin {}", self.netlist.instances[0].chip, name))
+    }
+
+    pub fn is_input(&self, name : &str) -> bool {
+        self.netlist.instances[0].pins[..self.netlist.inputs].iter().any(|(n, _)| n == name)
+    }
+
+    pub fn set(&mut self, name : &str, value : u16) -> Result<(), String> {
+        if !self.is_input(name) {
+            return Err(format!("{} is not an input pin of {}", name, self.netlist.instances[0].chip));
+        }
+        let nets = self.pin(name)?.clone();
+        write(&mut self.values, &nets, value);
+        Ok(())
+    }
+
+    pub fn get(&self, name : &str) -> Result<u16, String> {
+        Ok(read(&self.values, self.pin(name)?))
+    }
+
+    //The word some nets carry, bit 0 first, for pins of the parts as well as the top chip's
+    pub fn read_nets(&self, nets : &[usize]) -> u16 {
+        read(&self.values, nets)
+    }
+
+    //The memory of the first builtin chip of this name anywhere in the part hierarchy, e.g. the
+    //RAM16K inside Memory inside Computer
+    fn memory_index(&self, chip : &str) -> Option<usize> {
+        self.netlist.components.iter().position(|component| match &component.kind {
+            ComponentKind::Builtin { chip : builtin, .. } => builtin.name == chip && builtin.memory > 0,
+            ComponentKind::Nand { .. } => false,
+        })
+    }
+
+    pub fn memory(&self, chip : &str) -> Option<&[u16]> {
+        self.memory_index(chip).map(|index| &self.memories[index][..])
+    }
+
+    pub fn memory_mut(&mut self, chip : &str) -> Option<&mut [u16]> {
+        self.memory_index(chip).map(move |index| &mut self.memories[index][..])
+    }
+
+    //A word of a chip's memory as it will be after the next tock: between a tick and its tock this is
+    //the value the chip took in at the tick, which is what the test scripts show for Register[] and RAM[i]
+    pub fn latched(&self, chip : &str, address : usize) -> Option<u16> {
+        let index = self.memory_index(chip)?;
+        match self.writes[index] {
+            Some((written, value)) if written == address => Some(value),
+            _ => self.memories[index].get(address).copied(),
+        }
+    }
+
+    //The rising edge of the clock: every clocked chip reads its inputs and decides what to store
+    pub fn tick(&mut self) {
+        self.eval();
+        for (index, component) in self.netlist.components.iter().enumerate() {
+            if let ComponentKind::Builtin { chip, inputs, .. } = &component.kind {
+                if let Some(tick) = chip.tick {
+                    let words = inputs.iter().map(|nets| read(&self.values, nets)).collect::<Vec<u16>>();
+                    self.writes[index] = tick(&words, &self.memories[index]);
+                }
+            }
+        }
+        self.eval();
+    }
+
+    //The falling edge: the stored values appear on the outputs
+    pub fn tock(&mut self) {
+        for (index, write) in self.writes.iter_mut().enumerate() {
+            if let Some((address, value)) = write.take() {
+                self.memories[index][address] = value;
+            }
+        }
+        self.eval();
+    }
+
+    pub fn eval(&mut self) {
+        let mut inputs = Vec::new();
+        let mut outputs = Vec::new();
+        for index in &self.order {
+            match &self.netlist.components[*index].kind {
+                ComponentKind::Nand { a, b, out } => self.values[*out] = !(self.values[*a] && self.values[*b]),
+                ComponentKind::Builtin { chip, inputs : input_nets, outputs : output_nets } => {
+                    inputs.clear();
+                    inputs.extend(input_nets.iter().map(|nets| read(&self.values, nets)));
+                    outputs.clear();
+                    outputs.resize(output_nets.len(), 0);
+                    (chip.eval)(&inputs, &mut outputs, &self.memories[*index]);
+                    for (nets, word) in output_nets.iter().zip(outputs.iter()) {
+                        write(&mut self.values, nets, *word);
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use crate::assembler::assemble_file;
+    use crate::hdl::elaborate::ComponentKind;
+    use crate::hdl::simulator::HdlSimulator;
+    use crate::test_support::PROJECTS;
+
+    fn project(directory : &str) -> PathBuf {
+        Path::new(PROJECTS).join(directory)
+    }
+
+    //Loads a chip from 01 or 02 with all of 01 and 02 as its library, so that nothing is builtin but Nand
+    fn load_gates(path : &str) -> HdlSimulator {
+        HdlSimulator::load(&project(path), &[project("01"), project("02")], &[]).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    fn only_nands(simulator : &HdlSimulator) -> bool {
+        simulator.netlist.components.iter().all(|c| matches!(c.kind, ComponentKind::Nand { .. }))
+    }
+
+    fn eval(simulator : &mut HdlSimulator, inputs : &[(&str, u16)]) {
+        for (name, value) in inputs {
+            simulator.set(name, *value).unwrap();
+        }
+        simulator.eval();
+    }
+
+    //A model of the Hack ALU to check the gate-level one against
+    fn alu(x : u16, y : u16, control : u16) -> u16 {
+        let bit = |i : u16| control & (1 << (5 - i)) != 0;
+        let x = if bit(0) { 0 } else { x };
+        let x = if bit(1) { !x } else { x };
+        let y = if bit(2) { 0 } else { y };
+        let y = if bit(3) { !y } else { y };
+        let out = if bit(4) { x.wrapping_add(y) } else { x & y };
+        if bit(5) { !out } else { out }
+    }
+
+    #[test]
+    fn project_1_gates_test() {
+        let mut xor = load_gates("01/Xor.hdl");
+        assert!(only_nands(&xor));
+        for (a, b) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter() {
+            eval(&mut xor, &[("a", *a), ("b", *b)]);
+            assert_eq!(xor.get("out").unwrap(), a ^ b);
+        }
+        let mut mux = load_gates("01/Mux8Way16.hdl");
+        let values = [0x1234, 0xfedc, 7, 0x8000, 1, 2, 3, 0xffff];
+        for (name, value) in ["a", "b", "c", "d", "e", "f", "g", "h"].iter().zip(values.iter()) {
+            mux.set(name, *value).unwrap();
+        }
+        for sel in 0..8 {
+            eval(&mut mux, &[("sel", sel)]);
+            assert_eq!(mux.get("out").unwrap(), values[sel as usize]);
+        }
+        let mut dmux = load_gates("01/DMux8Way.hdl");
+        eval(&mut dmux, &[("in", 1), ("sel", 5)]);
+        let outputs = ["a", "b", "c", "d", "e", "f", "g", "h"].iter().map(|n| dmux.get(n).unwrap()).collect::<Vec<u16>>();
+        assert_eq!(outputs, vec![0, 0, 0, 0, 0, 1, 0, 0]);
+        let mut or8 = load_gates("01/Or8Way.hdl");
+        eval(&mut or8, &[("in", 0x40)]);
+        assert_eq!(or8.get("out").unwrap(), 1);
+        eval(&mut or8, &[("in", 0)]);
+        assert_eq!(or8.get("out").unwrap(), 0);
+    }
+
+    #[test]
+    fn alu_test() {
+        let mut gates = load_gates("02/ALU.hdl");
+        assert!(only_nands(&gates));
+        //Without the library its parts come from the builtin chips
+        let mut builtin = HdlSimulator::load(&project("02/ALU.hdl"), &[], &[]).ok().unwrap();
+        assert!(!only_nands(&builtin));
+        let names = ["zx", "nx", "zy", "ny", "f", "no"];
+        let pairs = [(0, 0), (17, 3), (3, 17), (0xffff, 1), (0x8000, 0x7fff), (0x1234, 0xabcd)];
+        for control in 0..64 {
+            for (x, y) in pairs.iter() {
+                for simulator in [&mut gates, &mut builtin].iter_mut() {
+                    simulator.set("x", *x).unwrap();
+                    simulator.set("y", *y).unwrap();
+                    for (i, name) in names.iter().enumerate() {
+                        simulator.set(name, (control >> (5 - i)) & 1).unwrap();
+                    }
+                    simulator.eval();
+                    let out = alu(*x, *y, control);
+                    assert_eq!(simulator.get("out").unwrap(), out, "control {:06b} x {} y {}", control, x, y);
+                    assert_eq!(simulator.get("zr").unwrap(), (out == 0) as u16);
+                    assert_eq!(simulator.get("ng").unwrap(), out >> 15);
+                }
+            }
+        }
+        //Internal pins can be read as well
+        assert_eq!(gates.get("outlow").unwrap(), gates.get("out").unwrap() & 0xff);
+        assert!(gates.set("out", 1).is_err());
+    }
+
+    #[test]
+    fn project_2_adders_test() {
+        let mut add = load_gates("02/Add16.hdl");
+        let mut inc = load_gates("02/Inc16.hdl");
+        for (a, b) in [(0, 0), (1, 0xffff), (12345, 54321), (0x7fff, 1)].iter() {
+            eval(&mut add, &[("a", *a), ("b", *b)]);
+            assert_eq!(add.get("out").unwrap(), a.wrapping_add(*b));
+            eval(&mut inc, &[("in", *a)]);
+            assert_eq!(inc.get("out").unwrap(), a.wrapping_add(1));
+        }
+    }
+
+    fn cycle(simulator : &mut HdlSimulator, inputs : &[(&str, u16)]) {
+        for (name, value) in inputs {
+            simulator.set(name, *value).unwrap();
+        }
+        simulator.tick();
+        simulator.tock();
+    }
+
+    #[test]
+    fn bit_and_pc_test() {
+        //Bit feeds its DFF back into its Mux, which is not a combinational loop
+        let mut bit = HdlSimulator::load(&project("03/a/Bit.hdl"), &[], &[]).ok().unwrap();
+        eval(&mut bit, &[("in", 1), ("load", 1)]);
+        bit.tick();
+        assert_eq!(bit.get("out").unwrap(), 0);
+        bit.tock();
+        assert_eq!(bit.get("out").unwrap(), 1);
+        cycle(&mut bit, &[("in", 0), ("load", 0)]);
+        assert_eq!(bit.get("out").unwrap(), 1);
+        let mut pc = HdlSimulator::load(&project("03/a/PC.hdl"), &[project("01"), project("02")], &[]).ok().unwrap();
+        let mut model = HdlSimulator::load(&project("03/a/PC.hdl"), &[], &[String::from("PC")]).ok().unwrap();
+        let steps = [(0, 0, 1, 0), (0, 0, 1, 0), (1234, 1, 1, 0), (0, 0, 0, 0), (0, 0, 1, 0), (0, 1, 1, 1), (0xfffe, 1, 0, 0), (0, 0, 1, 0), (0, 0, 1, 0)];
+        for (input, load, inc, reset) in steps.iter() {
+            for simulator in [&mut pc, &mut model].iter_mut() {
+                cycle(simulator, &[("in", *input), ("load", *load), ("inc", *inc), ("reset", *reset)]);
+            }
+            assert_eq!(pc.get("out").unwrap(), model.get("out").unwrap());
+        }
+        assert_eq!(pc.get("out").unwrap(), 0);
+    }
+
+    #[test]
+    fn ram64_test() {
+        //RAM64 from RAM8, Register and Bit in 03/a, down to DFFs and Nand gates
+        let mut ram = HdlSimulator::load(&project("03/a/RAM64.hdl"), &[project("01"), project("02")], &[]).ok().unwrap();
+        assert!(ram.netlist.components.iter().all(|c| match &c.kind {
+            ComponentKind::Builtin { chip, .. } => chip.name == "DFF",
+            ComponentKind::Nand { .. } => true,
+        }));
+        for address in 0..64 {
+            cycle(&mut ram, &[("in", address * 3 + 1), ("load", 1), ("address", address)]);
+        }
+        eval(&mut ram, &[("load", 0), ("in", 0)]);
+        for address in (0..64).rev() {
+            eval(&mut ram, &[("address", address)]);
+            assert_eq!(ram.get("out").unwrap(), address * 3 + 1);
+        }
+        //A write shows after the tock, not the tick
+        eval(&mut ram, &[("in", 999), ("load", 1), ("address", 9)]);
+        ram.tick();
+        assert_eq!(ram.get("out").unwrap(), 28);
+        ram.tock();
+        assert_eq!(ram.get("out").unwrap(), 999);
+    }
+
+    #[test]
+    fn computer_runs_assembled_programs_test() {
+        let mut computer = HdlSimulator::load(&project("05/Computer.hdl"), &[], &[]).ok().unwrap();
+        let program = assemble_file(&project("06/max/Max.asm").display().to_string()).ok().unwrap();
+        computer.memory_mut("ROM32K").unwrap()[..program.len()].copy_from_slice(&program);
+        for (x, y) in [(3, 5), (23456, 12345)].iter() {
+            cycle(&mut computer, &[("reset", 1)]);
+            computer.set("reset", 0).unwrap();
+            computer.memory_mut("RAM16K").unwrap()[..2].copy_from_slice(&[*x, *y]);
+            for _ in 0..20 {
+                cycle(&mut computer, &[]);
+            }
+            assert_eq!(computer.memory("RAM16K").unwrap()[2], *x.max(y));
+        }
+        //Rect draws through the Screen part of Memory
+        let program = assemble_file(&project("06/rect/Rect.asm").display().to_string()).ok().unwrap();
+        computer.memory_mut("ROM32K").unwrap()[..program.len()].copy_from_slice(&program);
+        cycle(&mut computer, &[("reset", 1)]);
+        computer.set("reset", 0).unwrap();
+        computer.memory_mut("RAM16K").unwrap()[0] = 4;
+        for _ in 0..200 {
+            cycle(&mut computer, &[]);
+        }
+        let screen = computer.memory("Screen").unwrap();
+        assert_eq!(screen.iter().enumerate().filter(|(_, word)| **word != 0).map(|(address, _)| address).collect::<Vec<usize>>(), vec![0, 32, 64, 96]);
+        assert_eq!(screen[0], 0xffff);
+    }
+
+    fn elaboration_error(name : &str, source : &str) -> String {
+        let directory = std::env::temp_dir().join("n2t_hdl_errors");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(format!("{}.hdl", name));
+        fs::write(&path, source).unwrap();
+        let message = HdlSimulator::load(&path, &[], &[]).err().unwrap().to_string();
+        message.replace(&directory.display().to_string(), "")
+    }
+
+    #[test]
+    fn elaboration_errors_test() {
+        assert_eq!(elaboration_error("Loop", "CHIP Loop {\n    IN a;\n    OUT out;\n    PARTS:\n    Not(in=x, out=y);\n    And(a=a, b=y, out=x, out=out);\n}"),
+                   "/Loop.hdl:5:5: combinational loop through Loop/Not");
+        assert_eq!(elaboration_error("Open", "CHIP Open {\n    IN a;\n    OUT out;\n    PARTS:\n    And(a=a, out=out);\n}"),
+                   "/Open.hdl:5:5: input b of And is not connected");
+        assert_eq!(elaboration_error("Wide", "CHIP Wide {\n    IN a[16];\n    OUT out;\n    PARTS:\n    Not(in=a, out=out);\n}"),
+                   "/Wide.hdl:5:9: in of Not is 1 bit wide, but a is 16 bits wide");
+        assert_eq!(elaboration_error("Narrow", "CHIP Narrow {\n    IN a[8];\n    OUT out[16];\n    PARTS:\n    Not16(in[0..7]=a, in[8..15]=a[1..8], out=out);\n}"),
+                   "/Narrow.hdl:5:33: sub-bus a[1..8] is out of range for a of 8 bits");
+        assert_eq!(elaboration_error("Twice", "CHIP Twice {\n    IN a;\n    OUT out;\n    PARTS:\n    Not(in=a, out=out);\n    Not(in=a, out=out);\n}"),
+                   "/Twice.hdl:6:15: out has more than one driver");
+        assert_eq!(elaboration_error("Stray", "CHIP Stray {\n    IN a;\n    OUT out;\n    PARTS:\n    Not(in=b, out=out);\n}"),
+                   "/Stray.hdl:5:12: b is not a pin of Stray or an output of any of its parts");
+        assert_eq!(elaboration_error("Unknown", "CHIP Unknown {\n    IN a;\n    OUT out;\n    PARTS:\n    Nor(a=a, b=a, out=out);\n}"),
+                   "/Unknown.hdl:5:5: unknown chip Nor");
+        assert_eq!(elaboration_error("Inner", "CHIP Inner {\n    IN a;\n    OUT out;\n    PARTS:\n    Not(in=a, out=a);\n}"),
+                   "/Inner.hdl:5:19: input pin a cannot be driven by a part");
+    }
+}
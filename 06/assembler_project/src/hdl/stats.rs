@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Formatter;
+use crate::hdl::HdlError;
+use crate::hdl::elaborate::{Component, ComponentKind, Netlist, FALSE, TRUE};
+use crate::hdl::simulator::topological_order;
+
+//What an elaborated chip costs: its Nand gates, overall and per kind of part, and the longest chain
+//of Nand gates a signal goes through between the clock edges.
+
+//What the parts of a chip of one kind are made of together
+pub struct PartType {
+    pub chip : String,
+    pub parts : usize,
+    pub contents : String,
+}
+
+pub struct Stats {
+    pub chip : String,
+    pub nands : usize,
+    //"11 Nand gates" or "4 Nand gates, 1 DFF"
+    pub contents : String,
+    pub parts : Vec<PartType>,
+    //The most Nand gates between an input pin or a clocked chip's output and an output pin or a clocked chip's input
+    pub depth : usize,
+    //The nets along one such path, each by the highest pin in the hierarchy that carries it
+    pub critical_path : Vec<String>,
+}
+
+//"432 Nand gates" or "4 Nand gates, 1 DFF": what some components are
+pub fn gate_count<'a>(components : impl IntoIterator<Item = &'a Component>) -> String {
+    let mut builtin = BTreeMap::new();
+    let mut nands = 0;
+    for component in components {
+        match &component.kind {
+            ComponentKind::Nand { .. } => nands += 1,
+            ComponentKind::Builtin { chip, .. } => *builtin.entry(chip.name).or_insert(0) += 1,
+        }
+    }
+    let mut counts = vec![format!("{} Nand gate{}", nands, if nands == 1 { "" } else { "s" })];
+    counts.extend(builtin.iter().map(|(name, count)| format!("{} {}", count, name)));
+    counts.join(", ")
+}
+
+//"ALU/Mux16.out[3]" for every net, taking the pin nearest the top chip that carries it
+fn net_names(netlist : &Netlist) -> Vec<String> {
+    let mut names = vec![None; netlist.nets];
+    names[FALSE] = Some(String::from("false"));
+    names[TRUE] = Some(String::from("true"));
+    for (index, instance) in netlist.instances.iter().enumerate() {
+        let path = netlist.path(index);
+        for (pin, nets) in &instance.pins {
+            for (bit, net) in nets.iter().enumerate() {
+                if names[*net].is_none() {
+                    names[*net] = Some(if nets.len() == 1 { format!("{}.{}", path, pin) } else { format!("{}.{}[{}]", path, pin, bit) });
+                }
+            }
+        }
+    }
+    names.into_iter().map(|name| name.unwrap_or_default()).collect()
+}
+
+//The part of the top chip that an instance is or lies inside
+fn top_part(netlist : &Netlist, mut instance : usize) -> usize {
+    while let Some(parent) = netlist.instances[instance].parent {
+        if parent == 0 {
+            break;
+        }
+        instance = parent;
+    }
+    instance
+}
+
+pub fn analyze(netlist : &Netlist) -> Result<Stats, HdlError> {
+    let mut part_types : Vec<(String, Vec<usize>, Vec<&Component>)> = Vec::new();
+    for component in &netlist.components {
+        let part = top_part(netlist, component.instance);
+        let chip = &netlist.instances[part].chip;
+        let position = match part_types.iter().position(|(name, _, _)| name == chip) {
+            Some(position) => position,
+            None => {
+                part_types.push((chip.clone(), Vec::new(), Vec::new()));
+                part_types.len() - 1
+            },
+        };
+        let (_, parts, components) = &mut part_types[position];
+        if !parts.contains(&part) {
+            parts.push(part);
+        }
+        components.push(component);
+    }
+    //Each net's depth and the input of its driver that the depth comes through
+    let mut depths = vec![0; netlist.nets];
+    let mut previous = vec![None; netlist.nets];
+    for index in topological_order(netlist)? {
+        let component = &netlist.components[index];
+        let from = component.combinational_input_nets().into_iter().fold(None, |deepest : Option<usize>, net| match deepest {
+            Some(deepest) if depths[deepest] >= depths[net] => Some(deepest),
+            _ => Some(net),
+        });
+        let step = matches!(component.kind, ComponentKind::Nand { .. }) as usize;
+        for net in component.output_nets() {
+            if let Some(from) = from {
+                depths[net] = depths[from] + step;
+                previous[net] = Some(from);
+            }
+            else {
+                depths[net] = 0;
+            }
+        }
+    }
+    let top = &netlist.instances[0];
+    let clocked_inputs = netlist.components.iter().flat_map(|component| match &component.kind {
+        ComponentKind::Builtin { chip, inputs, .. } => chip.inputs.iter().zip(inputs.iter())
+            .filter(|((name, _), _)| chip.clocked.contains(name))
+            .flat_map(|(_, nets)| nets.iter().copied())
+            .collect(),
+        ComponentKind::Nand { .. } => Vec::new(),
+    });
+    let ends = top.pins[netlist.inputs..netlist.inputs + netlist.outputs].iter().flat_map(|(_, nets)| nets.iter().copied()).chain(clocked_inputs);
+    let end = ends.fold(None, |deepest : Option<usize>, net| match deepest {
+        Some(deepest) if depths[deepest] >= depths[net] => Some(deepest),
+        _ => Some(net),
+    });
+    let mut critical_path = Vec::new();
+    let names = net_names(netlist);
+    let mut net = end;
+    while let Some(current) = net {
+        critical_path.push(names[current].clone());
+        net = previous[current];
+    }
+    critical_path.reverse();
+    Ok(Stats {
+        chip : top.chip.clone(),
+        nands : netlist.components.iter().filter(|c| matches!(c.kind, ComponentKind::Nand { .. })).count(),
+        contents : gate_count(&netlist.components),
+        parts : part_types.into_iter().map(|(chip, parts, components)| PartType { chip, parts : parts.len(), contents : gate_count(components) }).collect(),
+        depth : end.map(|net| depths[net]).unwrap_or(0),
+        critical_path,
+    })
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {}", self.chip, self.contents)?;
+        let width = self.parts.iter().map(|part| part.chip.len()).max().unwrap_or(0);
+        for part in &self.parts {
+            let parts = format!("{} part{}", part.parts, if part.parts == 1 { "" } else { "s" });
+            writeln!(f, "  {:<width$}  {:>9}  {}", part.chip, parts, part.contents, width = width)?;
+        }
+        writeln!(f, "Depth: {}", self.depth)?;
+        writeln!(f, "Critical path:")?;
+        for name in &self.critical_path {
+            writeln!(f, "  {}", name)?;
+        }
+        Ok(())
+    }
+}
+
+//The most a chip may cost, read from lines like "ALU nand=700 depth=40"; // starts a comment
+pub struct Budget {
+    pub chip : String,
+    pub nands : Option<usize>,
+    pub depth : Option<usize>,
+}
+
+impl Budget {
+    //What is over budget, if anything
+    pub fn excesses(&self, stats : &Stats) -> Vec<String> {
+        let mut excesses = Vec::new();
+        if let Some(nands) = self.nands.filter(|nands| stats.nands > *nands) {
+            excesses.push(format!("{} has {} Nand gates, over its budget of {}", stats.chip, stats.nands, nands));
+        }
+        if let Some(depth) = self.depth.filter(|depth| stats.depth > *depth) {
+            excesses.push(format!("{} has depth {}, over its budget of {}", stats.chip, stats.depth, depth));
+        }
+        excesses
+    }
+}
+
+pub fn parse_budgets(file_name : &str, source : &str) -> Result<Vec<Budget>, HdlError> {
+    let mut budgets = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let chip = match words.next() {
+            Some(chip) => chip,
+            None => continue,
+        };
+        let mut budget = Budget { chip : chip.to_string(), nands : None, depth : None };
+        for word in words {
+            let error = |message : String| HdlError { file_name : file_name.to_string(), line : index + 1, column : line.find(word).unwrap_or(0) + 1, message };
+            let (key, value) = word.split_once('=').ok_or_else(|| error(format!("expected nand=N or depth=N, found {}", word)))?;
+            let value = value.parse::<usize>().map_err(|_e| error(format!("bad number {}", value)))?;
+            match key {
+                "nand" => budget.nands = Some(value),
+                "depth" => budget.depth = Some(value),
+                _ => return Err(error(format!("unknown budget {}", key))),
+            }
+        }
+        budgets.push(budget);
+    }
+    Ok(budgets)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use crate::hdl::elaborate::{elaborate, Library};
+    use crate::hdl::stats::{analyze, parse_budgets, Stats};
+    use crate::test_support::PROJECTS;
+
+    fn project(path : &str) -> PathBuf {
+        Path::new(PROJECTS).join(path)
+    }
+
+    fn stats(directories : &[&str], name : &str) -> Stats {
+        let mut library = Library::new(directories.iter().map(|d| project(d)).collect());
+        analyze(&elaborate(&mut library, name).ok().unwrap()).ok().unwrap()
+    }
+
+    #[test]
+    fn xor_test() {
+        //Not is one Nand gate, And two and Or five; the deepest path runs through Not, And and Or, 1 + 2 + 4 = 7 deep
+        let xor = stats(&["01"], "Xor");
+        assert_eq!(xor.nands, 11);
+        assert_eq!(xor.depth, 7);
+        assert_eq!(xor.to_string(), "Xor: 11 Nand gates\n  Not    2 parts  2 Nand gates\n  And    2 parts  4 Nand gates\n  Or      1 part  5 Nand gates\nDepth: 7\nCritical path:\n  \
+            Xor.b\n  Xor.notb\n  Xor/And.nandab\n  Xor.aAndNotb\n  Xor/Or.nota\n  Xor/Or/And.nandab\n  Xor/Or.notbAndnota\n  Xor.out\n");
+    }
+
+    #[test]
+    fn clocked_test() {
+        //From the DFF in Bit round through the Mux back into it
+        let bit = stats(&["03/a", "01"], "Bit");
+        assert_eq!(bit.contents, format!("{} Nand gates, 1 DFF", bit.nands));
+        assert_eq!(bit.critical_path.last().unwrap(), "Bit.dffin");
+        assert_eq!(bit.depth, stats(&["01"], "Mux").depth);
+        //The builtin registers of the CPU start and end paths without adding to them
+        let cpu = stats(&["05", "01", "02"], "CPU");
+        assert!(cpu.parts.iter().any(|part| part.chip == "ALU" && part.contents.ends_with("Nand gates")));
+        assert!(cpu.parts.iter().any(|part| part.chip == "PC" && part.contents == "0 Nand gates, 1 PC"));
+        assert!(cpu.critical_path[0].starts_with("CPU.instruction") || cpu.critical_path[0].starts_with("CPU/"));
+        assert!(cpu.depth > stats(&["02", "01"], "ALU").depth);
+    }
+
+    #[test]
+    fn budget_test() {
+        let budgets = parse_budgets("budget.txt", "// Project 1\nXor nand=10 depth=7\n\nAnd nand=2 // fine\n").ok().unwrap();
+        assert_eq!(budgets.len(), 2);
+        let xor = stats(&["01"], "Xor");
+        assert_eq!(budgets[0].excesses(&xor), vec!["Xor has 11 Nand gates, over its budget of 10"]);
+        assert!(budgets[1].excesses(&stats(&["01"], "And")).is_empty());
+        assert_eq!(parse_budgets("budget.txt", "Xor nand=10\nOr gates=3\n").err().unwrap().to_string(), "budget.txt:2:4: unknown budget gates");
+        assert_eq!(parse_budgets("budget.txt", "Xor nand=ten").err().unwrap().to_string(), "budget.txt:1:5: bad number ten");
+    }
+}
@@ -0,0 +1,101 @@
+use crate::hdl::elaborate::Netlist;
+use crate::hdl::simulator::HdlSimulator;
+use crate::vcd::Vcd;
+
+//Records the pins of a chip and of every part inside it, internal pins included, as a Value Change
+//Dump. Each part is a scope named Chip_i after its place among its parent's parts, as in the Verilog
+//export, and the top scope has a clk that is high from a tick to its tock. Every load, eval, tick and
+//tock in the selected clock cycles takes one step of the dump's time.
+pub struct Trace {
+    vcd : Vcd,
+    //The nets of every variable after clk, in the order they were declared
+    pins : Vec<Vec<usize>>,
+    from : u64,
+    to : Option<u64>,
+    step : u64,
+}
+
+impl Trace {
+    pub fn new(netlist : &Netlist, from : u64, to : Option<u64>) -> Trace {
+        let mut children = vec![Vec::new(); netlist.instances.len()];
+        for (index, instance) in netlist.instances.iter().enumerate() {
+            if let Some(parent) = instance.parent {
+                children[parent].push(index);
+            }
+        }
+        let mut trace = Trace { vcd : Vcd::new(), pins : Vec::new(), from, to, step : 0 };
+        trace.vcd.scope(&netlist.instances[0].chip);
+        trace.vcd.variable("clk", 1);
+        trace.declare(netlist, &children, 0);
+        trace.vcd.upscope();
+        trace
+    }
+
+    fn declare(&mut self, netlist : &Netlist, children : &[Vec<usize>], instance : usize) {
+        for (pin, nets) in &netlist.instances[instance].pins {
+            self.vcd.variable(pin, nets.len());
+            self.pins.push(nets.clone());
+        }
+        for (position, child) in children[instance].iter().enumerate() {
+            self.vcd.scope(&format!("{}_{}", netlist.instances[*child].chip, position));
+            self.declare(netlist, children, *child);
+            self.vcd.upscope();
+        }
+    }
+
+    //Takes the chip's values at a time of the test script, if the time is in the range
+    pub fn sample(&mut self, simulator : &HdlSimulator, time : u64, ticked : bool) {
+        if time < self.from || self.to.map(|to| time > to).unwrap_or(false) {
+            return;
+        }
+        let values = std::iter::once(ticked as u16).chain(self.pins.iter().map(|nets| simulator.read_nets(nets))).collect::<Vec<u16>>();
+        self.vcd.sample(self.step, &values);
+        self.step += 1;
+    }
+
+    pub fn finish(&self) -> String {
+        self.vcd.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::hdl::simulator::HdlSimulator;
+    use crate::hdl::trace::Trace;
+    use crate::test_support::PROJECTS;
+
+    #[test]
+    fn scopes_test() {
+        let mut simulator = HdlSimulator::load(&Path::new(PROJECTS).join("01/Xor.hdl"), &[], &[]).ok().unwrap();
+        let mut trace = Trace::new(&simulator.netlist, 0, None);
+        trace.sample(&simulator, 0, false);
+        simulator.set("a", 1).unwrap();
+        simulator.eval();
+        trace.sample(&simulator, 0, false);
+        let vcd = trace.finish();
+        //Xor's own pins, then its parts', each part in a scope of its own
+        assert!(vcd.contains("$scope module Xor $end\n$var wire 1 ! clk $end\n$var wire 1 \" a $end\n$var wire 1 # b $end\n$var wire 1 $ out $end\n$var wire 1 % nota $end\n"));
+        assert!(vcd.contains("$scope module Not_0 $end\n"));
+        assert!(vcd.contains("$scope module And_2 $end\n"));
+        assert_eq!(vcd.matches("$scope").count(), vcd.matches("$upscope").count());
+        assert_eq!(vcd.matches("$scope").count(), simulator.netlist.instances.len());
+        //Setting a turns out on, which the Or part sees too
+        let last = &vcd[vcd.rfind("#1\n").unwrap()..];
+        assert!(last.contains("\n1\"\n") && last.contains("\n1$\n"));
+    }
+
+    #[test]
+    fn range_test() {
+        let mut simulator = HdlSimulator::load(&Path::new(PROJECTS).join("03/a/Bit.hdl"), &[], &[]).ok().unwrap();
+        let mut trace = Trace::new(&simulator.netlist, 2, Some(3));
+        for time in 0..6 {
+            simulator.set("in", (time % 2) as u16).unwrap();
+            simulator.eval();
+            trace.sample(&simulator, time, false);
+        }
+        //Only times 2 and 3 are dumped, as the first two steps
+        let vcd = trace.finish();
+        assert!(vcd.contains("#0\n") && vcd.contains("#1\n") && !vcd.contains("#2\n"));
+    }
+}
@@ -0,0 +1,171 @@
+use std::fmt;
+use std::fmt::Formatter;
+use crate::hdl::builtin::builtin_chip;
+use crate::hdl::simulator::HdlSimulator;
+
+//Checks a combinational chip against the Rust implementation of the builtin chip of the same name:
+//truth tables for the gates of project 1, arithmetic for Add16, Inc16 and the ALU. Chips with at
+//most EXHAUSTIVE_BITS input bits are tried on every input; wider ones on random inputs, each input
+//pin often taking an edge value (0, 1, all ones, only the top bit) where carries and zr/ng go wrong.
+const EXHAUSTIVE_BITS : usize = 16;
+
+//The first inputs the chip and the model disagree on, and what each output is in both
+pub struct Mismatch {
+    pub inputs : Vec<(String, u16, usize)>,
+    //Name, the model's value and the chip's
+    pub outputs : Vec<(String, u16, u16)>,
+}
+
+pub struct Verification {
+    pub chip : String,
+    pub cases : u64,
+    pub exhaustive : bool,
+    pub mismatch : Option<Mismatch>,
+}
+
+const ZERO_SEED_STATE : u32 = 0x9E37_79B9;
+
+//A xorshift generator, so that a seed always gives the same cases
+fn random(seed : &mut u32) -> u16 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    (*seed >> 8) as u16
+}
+
+fn mask(width : usize) -> u16 {
+    if width >= 16 { 0xFFFF } else { (1 << width) - 1 }
+}
+
+fn random_input(seed : &mut u32, width : usize) -> u16 {
+    let value = match random(seed) % 8 {
+        0 => 0,
+        1 => 0xFFFF,
+        2 => 1,
+        3 => 1 << (width - 1),
+        _ => random(seed),
+    };
+    value & mask(width)
+}
+
+//A value as a test script sets it: 16-bit pins signed, since scripts write -1 rather than 65535
+fn script_value(value : u16, width : usize) -> String {
+    if width == 16 { (value as i16).to_string() } else { value.to_string() }
+}
+
+//Runs the chip and the model on one set of inputs and returns the outputs they disagree on
+fn compare(simulator : &mut HdlSimulator, inputs : &[(String, u16, usize)]) -> Result<Vec<(String, u16, u16)>, String> {
+    let model = builtin_chip(&simulator.netlist.instances[0].chip).unwrap();
+    let words = inputs.iter().map(|(_, value, _)| *value).collect::<Vec<u16>>();
+    let mut expected = vec![0; model.outputs.len()];
+    (model.eval)(&words, &mut expected, &[]);
+    for (name, value, _) in inputs {
+        simulator.set(name, *value)?;
+    }
+    simulator.eval();
+    let mut differences = Vec::new();
+    for ((name, _), expected) in model.outputs.iter().zip(expected) {
+        let actual = simulator.get(name)?;
+        if actual != expected {
+            differences.push((name.to_string(), expected, actual));
+        }
+    }
+    Ok(differences)
+}
+
+//Tries a chip on every input when it has few input bits, otherwise on samples random ones.
+//xorshift never leaves a state of 0, so a seed of 0 starts from another fixed state.
+pub fn verify(simulator : &mut HdlSimulator, samples : u64, seed : u32) -> Result<Verification, String> {
+    let mut seed = if seed == 0 { ZERO_SEED_STATE } else { seed };
+    let netlist = &simulator.netlist;
+    let chip = netlist.instances[0].chip.clone();
+    let model = match builtin_chip(&chip) {
+        Some(model) if model.memory == 0 && model.name != "Nand" => model,
+        Some(_) => return Err(format!("{} has state, so it has no reference model to verify against", chip)),
+        None => return Err(format!("there is no reference model for {}", chip)),
+    };
+    let pins = netlist.instances[0].pins[..netlist.inputs + netlist.outputs].iter()
+        .map(|(name, nets)| (name.as_str(), nets.len() as u16))
+        .collect::<Vec<(&str, u16)>>();
+    if pins[..netlist.inputs] != *model.inputs || pins[netlist.inputs..] != *model.outputs {
+        return Err(format!("the pins of {} are not those of the builtin {}", chip, chip));
+    }
+    let widths = model.inputs.iter().map(|(name, width)| (name.to_string(), *width as usize)).collect::<Vec<(String, usize)>>();
+    let bits = widths.iter().map(|(_, width)| width).sum::<usize>();
+    let exhaustive = bits <= EXHAUSTIVE_BITS;
+    let cases = if exhaustive { 1 << bits } else { samples };
+    for case in 0..cases {
+        let mut shift = 0;
+        let inputs = widths.iter().map(|(name, width)| {
+            let value = if exhaustive { (case >> shift) as u16 & mask(*width) } else { random_input(&mut seed, *width) };
+            shift += width;
+            (name.clone(), value, *width)
+        }).collect::<Vec<(String, u16, usize)>>();
+        let outputs = compare(simulator, &inputs)?;
+        if !outputs.is_empty() {
+            return Ok(Verification { chip, cases : case + 1, exhaustive, mismatch : Some(Mismatch { inputs, outputs }) });
+        }
+    }
+    Ok(Verification { chip, cases, exhaustive, mismatch : None })
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.mismatch {
+            None if self.exhaustive => writeln!(f, "{}: all {} inputs match the builtin {}", self.chip, self.cases, self.chip),
+            None => writeln!(f, "{}: {} random inputs match the builtin {}", self.chip, self.cases, self.chip),
+            Some(mismatch) => {
+                writeln!(f, "{} differs from the builtin {} on input {}:", self.chip, self.chip, self.cases)?;
+                let sets = mismatch.inputs.iter().map(|(name, value, width)| format!("set {} {}", name, script_value(*value, *width)));
+                writeln!(f, "{}, eval, output;", sets.collect::<Vec<String>>().join(", "))?;
+                for (name, expected, actual) in &mismatch.outputs {
+                    writeln!(f, "//{} is {} where it should be {}", name, actual, expected)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::hdl::simulator::HdlSimulator;
+    use crate::hdl::verify::verify;
+    use crate::test_support::PROJECTS;
+
+    fn load(path : &Path) -> HdlSimulator {
+        HdlSimulator::load(path, &[Path::new(PROJECTS).join("01"), Path::new(PROJECTS).join("02")], &[]).ok().unwrap()
+    }
+
+    #[test]
+    fn project_chips_test() {
+        for (path, exhaustive) in [("01/Xor.hdl", true), ("01/DMux8Way.hdl", true), ("01/Mux16.hdl", false), ("02/Inc16.hdl", true), ("02/ALU.hdl", false)].iter() {
+            let result = verify(&mut load(&Path::new(PROJECTS).join(path)), 200, 1).ok().unwrap();
+            assert!(result.mismatch.is_none(), "{}", result);
+            assert_eq!(result.exhaustive, *exhaustive);
+        }
+        let result = verify(&mut load(&Path::new(PROJECTS).join("01/Mux.hdl")), 200, 1).ok().unwrap();
+        assert_eq!(result.to_string(), "Mux: all 8 inputs match the builtin Mux\n");
+        assert_eq!(verify(&mut load(&Path::new(PROJECTS).join("03/a/Bit.hdl")), 200, 1).err().unwrap(), "Bit has state, so it has no reference model to verify against");
+    }
+
+    #[test]
+    fn counterexample_test() {
+        let directory = std::env::temp_dir().join("n2t_hdl_verify");
+        fs::create_dir_all(directory.join("xor")).unwrap();
+        fs::create_dir_all(directory.join("alu")).unwrap();
+        //An Xor that is really an Or, and an ALU whose zr only looks at the low byte, which ALU.tst misses
+        fs::write(directory.join("xor/Xor.hdl"), "CHIP Xor {\n    IN a, b;\n    OUT out;\n    PARTS:\n    Or(a=a, b=b, out=out);\n}").unwrap();
+        let source = fs::read_to_string(Path::new(PROJECTS).join("02/ALU.hdl")).unwrap();
+        fs::write(directory.join("alu/ALU.hdl"), source.replace("Or8Way (in=outhigh", "Or8Way (in=false")).unwrap();
+        let xor = verify(&mut load(&directory.join("xor/Xor.hdl")), 200, 1).ok().unwrap();
+        assert_eq!(xor.to_string(), "Xor differs from the builtin Xor on input 4:\nset a 1, set b 1, eval, output;\n//out is 1 where it should be 0\n");
+        for seed in [1, 0].iter() {
+            let alu = verify(&mut load(&directory.join("alu/ALU.hdl")), 1000, *seed).ok().unwrap();
+            let mismatch = alu.mismatch.unwrap_or_else(|| panic!("the broken zr went unnoticed with seed {}", seed));
+            assert_eq!(mismatch.outputs.iter().map(|(name, _, _)| name.as_str()).collect::<Vec<&str>>(), vec!["zr"]);
+        }
+    }
+}
@@ -0,0 +1,885 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::hdl::HdlError;
+use crate::hdl::ast::{Chip, Connection, SubBus, Wire};
+use crate::hdl::builtin::{builtin_chip, BuiltinChip};
+use crate::hdl::elaborate::{elaborate, Definition, Library};
+
+//Writes a chip and every chip it is made of as Verilog modules with the same pin names and widths.
+//Chips with an .hdl file become structural modules instantiating their parts, the builtin chips
+//behavioral ones. Modules holding a clocked chip, however deep, take a clk input first.
+
+//Verilog keywords an HDL name could clash with, and the clock input. Such names get an underscore.
+const RESERVED : &[&str] = &[
+    "always", "and", "assign", "begin", "buf", "case", "clk", "default", "else", "end", "endcase", "endmodule", "for",
+    "function", "if", "initial", "inout", "input", "integer", "module", "nand", "negedge", "nor", "not", "or", "output",
+    "parameter", "posedge", "reg", "repeat", "supply0", "supply1", "tri", "wait", "while", "wire", "xnor", "xor",
+];
+
+fn identifier(name : &str) -> String {
+    if RESERVED.contains(&name) { format!("{}_", name) } else { name.to_string() }
+}
+
+//"[15:0] " for a bus, nothing for a single bit
+fn range(width : u16) -> String {
+    if width == 1 { String::new() } else { format!("[{}:0] ", width - 1) }
+}
+
+fn constant(width : u16, value : bool) -> String {
+    format!("{}'h{:x}", width, if value { (1u32 << width) - 1 } else { 0 })
+}
+
+//The bits a sub-bus of a pin of this width selects, all of them without one
+fn bounds(sub_bus : Option<SubBus>, width : u16) -> (u16, u16) {
+    sub_bus.map(|s| (s.low, s.high)).unwrap_or((0, width - 1))
+}
+
+//A wire of the given width, or the part of it a sub-bus selects
+fn select(name : &str, width : u16, sub_bus : Option<SubBus>) -> String {
+    match bounds(sub_bus, width) {
+        (low, high) if low == 0 && high == width - 1 => name.to_string(),
+        (low, high) if low == high => format!("{}[{}]", name, low),
+        (low, high) => format!("{}[{}:{}]", name, high, low),
+    }
+}
+
+fn header(name : &str, clocked : bool, pins : &[(&str, bool, u16)]) -> Vec<String> {
+    let ports = clocked.then(|| String::from("input clk")).into_iter()
+        .chain(pins.iter().map(|(pin, input, width)| format!("{} {}{}", if *input { "input" } else { "output" }, range(*width), identifier(pin))))
+        .collect::<Vec<String>>();
+    let mut lines = vec![format!("module {} (", name)];
+    lines.push(format!("    {}", ports.join(",\n    ")));
+    lines.push(String::from(");"));
+    lines
+}
+
+//The body of a builtin chip's module
+fn behaviour(chip : &BuiltinChip, rom_file : Option<&str>) -> Vec<String> {
+    let lines : &[&str] = match chip.name {
+        "Nand" => &["assign out = ~(a & b);"],
+        "Not" | "Not16" => &["assign out = ~in;"],
+        "And" | "And16" => &["assign out = a & b;"],
+        "Or" | "Or16" => &["assign out = a | b;"],
+        "Xor" => &["assign out = a ^ b;"],
+        "Mux" | "Mux16" => &["assign out = sel ? b : a;"],
+        "DMux" => &["assign a = sel ? 1'h0 : in;", "assign b = sel ? in : 1'h0;"],
+        "Or8Way" => &["assign out = in != 8'h0;"],
+        "Mux4Way16" => &["assign out = sel[1] ? (sel[0] ? d : c) : (sel[0] ? b : a);"],
+        "Mux8Way16" => &["assign out = sel[2] ? (sel[1] ? (sel[0] ? h : g) : (sel[0] ? f : e)) : (sel[1] ? (sel[0] ? d : c) : (sel[0] ? b : a));"],
+        "DMux4Way" | "DMux8Way" => {
+            let select_width = chip.inputs[1].1;
+            return chip.outputs.iter().enumerate()
+                .map(|(index, (name, _))| format!("assign {} = sel == {}'h{:x} ? in : 1'h0;", name, select_width, index))
+                .collect();
+        },
+        "HalfAdder" => &["assign sum = a ^ b;", "assign carry = a & b;"],
+        "FullAdder" => &["assign sum = a ^ b ^ c;", "assign carry = (a & b) | (c & (a ^ b));"],
+        "Add16" => &["assign out = a + b;"],
+        "Inc16" => &["assign out = in + 16'h1;"],
+        "ALU" => &[
+            "wire [15:0] zeroedx;", "wire [15:0] zeroedy;", "wire [15:0] px;", "wire [15:0] py;", "wire [15:0] result;", "",
+            "assign zeroedx = zx ? 16'h0 : x;", "assign px = nx ? ~zeroedx : zeroedx;",
+            "assign zeroedy = zy ? 16'h0 : y;", "assign py = ny ? ~zeroedy : zeroedy;",
+            "assign result = f ? px + py : px & py;", "assign out = no ? ~result : result;",
+            "assign zr = out == 16'h0;", "assign ng = out[15];",
+        ],
+        "DFF" => &["reg state;", "", "always @(posedge clk) state <= in;", "assign out = state;"],
+        "PC" => &["reg [15:0] state;", "", "always @(posedge clk) state <= reset ? 16'h0 : load ? in : inc ? state + 16'h1 : state;", "assign out = state;"],
+        "Keyboard" => &["// Drive this from a keyboard controller: the code of the key held down, or 0", "assign out = 16'h0;"],
+        "ROM32K" => {
+            let mut lines = vec![String::from("reg [15:0] memory [0:32767];"), String::new()];
+            lines.extend(rom_file.map(|file| format!("initial $readmemb(\"{}\", memory);", file)));
+            lines.push(String::from("assign out = memory[address];"));
+            return lines;
+        },
+        //Bit and the registers
+        _ if chip.memory == 1 => return vec![
+            format!("reg {}state;", range(chip.outputs[0].1)), String::new(),
+            String::from("always @(posedge clk) if (load) state <= in;"), String::from("assign out = state;"),
+        ],
+        //The RAMs and the screen
+        _ => return vec![
+            format!("reg [15:0] memory [0:{}];", chip.memory - 1), String::new(),
+            String::from("always @(posedge clk) if (load) memory[address] <= in;"), String::from("assign out = memory[address];"),
+        ],
+    };
+    lines.iter().map(|line| line.to_string()).collect()
+}
+
+struct Exporter<'a> {
+    library : &'a mut Library,
+    //The chips in the order they are first met going down from the top chip, by the name parts use
+    chips : Vec<(String, Definition)>,
+    clocked : HashMap<String, bool>,
+}
+
+impl<'a> Exporter<'a> {
+    //The definition a part name stands for, with BUILTIN chips replaced by the builtin
+    fn definition(&mut self, name : &str) -> Result<Definition, HdlError> {
+        let definition = self.library.resolve(name)?.ok_or_else(|| HdlError { file_name : format!("{}.hdl", name), line : 0, column : 0, message : format!("unknown chip {}", name) })?;
+        Ok(match &definition {
+            Definition::Hdl(chip, file_name) => match &chip.builtin {
+                Some(builtin) => Definition::Builtin(builtin_chip(&builtin.name).ok_or_else(|| HdlError::at(file_name, builtin.span, format!("there is no builtin chip {}", builtin.name)))?),
+                None => definition,
+            },
+            Definition::Builtin(_) => definition,
+        })
+    }
+
+    //Adds a chip and its parts, returning whether it holds a clocked chip
+    fn collect(&mut self, name : &str) -> Result<bool, HdlError> {
+        if let Some(clocked) = self.clocked.get(name) {
+            return Ok(*clocked);
+        }
+        let definition = self.definition(name)?;
+        self.chips.push((name.to_string(), definition.clone()));
+        let clocked = match &definition {
+            Definition::Builtin(chip) => chip.tick.is_some(),
+            Definition::Hdl(chip, _) => {
+                let mut clocked = false;
+                for part in &chip.parts {
+                    clocked |= self.collect(&part.chip.name)?;
+                }
+                clocked
+            },
+        };
+        self.clocked.insert(name.to_string(), clocked);
+        Ok(clocked)
+    }
+
+    fn pins(&self, name : &str) -> Vec<(&str, bool, u16)> {
+        self.chips.iter().find(|(n, _)| n == name).map(|(_, definition)| definition.pins()).unwrap_or_default()
+    }
+
+    fn structure(&self, chip : &Chip) -> Vec<String> {
+        let mut widths = chip.inputs.iter().chain(chip.outputs.iter()).map(|pin| (pin.name.name.as_str(), pin.width)).collect::<HashMap<&str, u16>>();
+        let mut wires = Vec::new();
+        //Internal pins are as wide as the part outputs driving them
+        for part in &chip.parts {
+            let pins = self.pins(&part.chip.name);
+            for connection in &part.connections {
+                let pin = pins.iter().find(|(name, _, _)| *name == connection.pin.name.name);
+                if let (Some((_, false, width)), Wire::Pin(reference)) = (pin, &connection.wire) {
+                    let name = reference.name.name.as_str();
+                    if !widths.contains_key(name) {
+                        let (low, high) = bounds(connection.pin.sub_bus, *width);
+                        widths.insert(name, high - low + 1);
+                        wires.push(format!("    wire {}{};", range(high - low + 1), identifier(name)));
+                    }
+                }
+            }
+        }
+        let wire = |wire : &Wire, width : u16| match wire {
+            Wire::Constant(value, _) => constant(width, *value),
+            Wire::Pin(reference) => select(&identifier(&reference.name.name), widths[reference.name.name.as_str()], reference.sub_bus),
+        };
+        let mut instances = Vec::new();
+        let mut assignments = Vec::new();
+        for (index, part) in chip.parts.iter().enumerate() {
+            let instance = format!("{}_{}", part.chip.name, index);
+            let mut ports = Vec::new();
+            for (pin, input, width) in self.pins(&part.chip.name) {
+                let connections = part.connections.iter().filter(|c| c.pin.name.name == pin).collect::<Vec<&Connection>>();
+                let whole = |connection : &Connection| bounds(connection.pin.sub_bus, width) == (0, width - 1);
+                if connections.is_empty() {
+                    continue;
+                }
+                else if input {
+                    //The connected sub-buses from the top bit down, with the bits between them false
+                    let mut pieces = connections.iter().map(|c| (bounds(c.pin.sub_bus, width), &c.wire)).collect::<Vec<((u16, u16), &Wire)>>();
+                    pieces.sort_by_key(|((low, _), _)| std::cmp::Reverse(*low));
+                    let mut concatenation = Vec::new();
+                    let mut next = width;
+                    for ((low, high), connected) in pieces {
+                        if high + 1 < next {
+                            concatenation.push(constant(next - high - 1, false));
+                        }
+                        concatenation.push(wire(connected, high - low + 1));
+                        next = low;
+                    }
+                    if next > 0 {
+                        concatenation.push(constant(next, false));
+                    }
+                    let value = if concatenation.len() == 1 { concatenation.remove(0) } else { format!("{{{}}}", concatenation.join(", ")) };
+                    ports.push(format!(".{}({})", identifier(pin), value));
+                }
+                else if connections.len() == 1 && whole(connections[0]) {
+                    ports.push(format!(".{}({})", identifier(pin), wire(&connections[0].wire, width)));
+                }
+                else {
+                    //An output feeding several wires, or feeding them in parts, goes through a wire of its own
+                    let output = format!("{}_{}", instance, pin);
+                    wires.push(format!("    wire {}{};", range(width), output));
+                    ports.push(format!(".{}({})", identifier(pin), output));
+                    for connection in connections {
+                        let (low, high) = bounds(connection.pin.sub_bus, width);
+                        assignments.push(format!("    assign {} = {};", wire(&connection.wire, high - low + 1), select(&output, width, connection.pin.sub_bus)));
+                    }
+                }
+            }
+            if self.clocked[&part.chip.name] {
+                ports.insert(0, String::from(".clk(clk)"));
+            }
+            instances.push(format!("    {} {} ({});", part.chip.name, instance, ports.join(", ")));
+        }
+        let mut lines = wires;
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.extend(instances);
+        lines.extend(assignments);
+        lines
+    }
+
+    fn module(&self, name : &str, definition : &Definition, rom_file : Option<&str>) -> Vec<String> {
+        let mut lines = header(name, self.clocked[name], &definition.pins());
+        match definition {
+            Definition::Hdl(chip, _) => lines.extend(self.structure(chip)),
+            Definition::Builtin(chip) => lines.extend(behaviour(chip, rom_file).into_iter().map(|line| if line.is_empty() { line } else { format!("    {}", line) })),
+        }
+        lines.push(String::from("endmodule"));
+        lines
+    }
+}
+
+//The Verilog for a chip from the library, the top module first and then its parts in the order
+//they are first used. A ROM32K part is initialized from the .hack file named, if any.
+pub fn export_verilog(library : &mut Library, name : &str, rom_file : Option<&str>) -> Result<String, HdlError> {
+    elaborate(library, name)?;
+    let mut exporter = Exporter { library, chips : Vec::new(), clocked : HashMap::new() };
+    exporter.collect(name)?;
+    let modules = exporter.chips.iter()
+        .map(|(name, definition)| exporter.module(name, definition, rom_file).join("\n"))
+        .collect::<Vec<String>>();
+    Ok(format!("{}\n", modules.join("\n\n")))
+}
+
+//Exports Dir/Name.hdl with Dir and then the other directories as its library
+pub fn export_file(path : &Path, directories : &[PathBuf], builtin : &[String], rom_file : Option<&str>) -> Result<String, HdlError> {
+    let (mut library, name) = Library::for_chip(path, directories, builtin);
+    export_verilog(&mut library, &name, rom_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use crate::assembler::{assemble_file, machine_text};
+    use crate::hdl::simulator::HdlSimulator;
+    use crate::hdl::verilog::{export_file, identifier};
+    use crate::test_support::PROJECTS;
+
+    //Just enough of a Verilog simulator to run what the exporter writes, so that the modules can be
+    //checked against the HDL simulator without a Verilog tool at hand
+
+    #[derive(Clone, PartialEq, Debug)]
+    enum Token {
+        Word(String),
+        //The value and the width, 32 bits for an unsized number
+        Number(u64, u32),
+        Text(String),
+        Symbol(String),
+    }
+
+    fn tokenize(source : &str) -> Vec<Token> {
+        let chars = source.chars().collect::<Vec<char>>();
+        let mut tokens = Vec::new();
+        let mut index = 0;
+        while index < chars.len() {
+            let c = chars[index];
+            let pair = chars[index..].iter().take(2).collect::<String>();
+            let start = index;
+            if c.is_whitespace() {
+                index += 1;
+            }
+            else if pair == "//" {
+                while index < chars.len() && chars[index] != '\n' {
+                    index += 1;
+                }
+            }
+            else if c == '"' {
+                index += 1 + chars[index + 1..].iter().position(|c| *c == '"').unwrap() + 1;
+                tokens.push(Token::Text(chars[start + 1..index - 1].iter().collect()));
+            }
+            else if c.is_ascii_digit() {
+                while index < chars.len() && (chars[index].is_ascii_alphanumeric() || chars[index] == '\'') {
+                    index += 1;
+                }
+                let text = chars[start..index].iter().collect::<String>();
+                tokens.push(match text.split_once('\'') {
+                    Some((width, value)) => {
+                        let radix = match &value[..1] { "h" => 16, "b" => 2, _ => 10 };
+                        Token::Number(u64::from_str_radix(&value[1..], radix).unwrap(), width.parse().unwrap())
+                    },
+                    None => Token::Number(text.parse().unwrap(), 32),
+                });
+            }
+            else if c.is_alphabetic() || c == '_' || c == '$' {
+                while index < chars.len() && (chars[index].is_alphanumeric() || chars[index] == '_' || chars[index] == '$') {
+                    index += 1;
+                }
+                tokens.push(Token::Word(chars[start..index].iter().collect()));
+            }
+            else if ["<=", "==", "!="].contains(&pair.as_str()) {
+                tokens.push(Token::Symbol(pair));
+                index += 2;
+            }
+            else {
+                tokens.push(Token::Symbol(c.to_string()));
+                index += 1;
+            }
+        }
+        tokens
+    }
+
+    #[derive(Clone, Debug)]
+    enum Expression {
+        Signal(String),
+        //name[high:low]
+        Bits(String, u32, u32),
+        //A word of a memory, or a bit of a signal
+        Index(String, Box<Expression>),
+        Number(u64, u32),
+        Not(Box<Expression>),
+        Binary(String, Box<Expression>, Box<Expression>),
+        Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+        Concatenation(Vec<Expression>),
+    }
+
+    enum Item {
+        Assign(Expression, Expression),
+        //always @(posedge clk), with its condition if any
+        Always(Option<Expression>, Expression, Expression),
+        Instance(String, Vec<(String, Expression)>),
+        ReadMemory(String, String),
+    }
+
+    struct Module {
+        name : String,
+        //With whether each is an input
+        ports : Vec<(String, bool)>,
+        widths : HashMap<String, u32>,
+        memories : HashMap<String, usize>,
+        items : Vec<Item>,
+    }
+
+    const LEVELS : [&[&str]; 5] = [&["|"], &["^"], &["&"], &["==", "!="], &["+"]];
+
+    struct Parser {
+        tokens : Vec<Token>,
+        next : usize,
+    }
+
+    impl Parser {
+        fn take(&mut self) -> Token {
+            self.next += 1;
+            self.tokens[self.next - 1].clone()
+        }
+
+        fn symbol(&mut self, symbol : &str) -> bool {
+            let found = self.tokens.get(self.next) == Some(&Token::Symbol(symbol.to_string()));
+            self.next += found as usize;
+            found
+        }
+
+        fn expect(&mut self, symbol : &str) {
+            assert!(self.symbol(symbol), "expected {} but found {:?}", symbol, self.tokens.get(self.next));
+        }
+
+        fn word(&mut self) -> String {
+            match self.take() {
+                Token::Word(word) => word,
+                token => panic!("expected a name but found {:?}", token),
+            }
+        }
+
+        fn number(&mut self) -> u32 {
+            match self.take() {
+                Token::Number(value, _) => value as u32,
+                token => panic!("expected a number but found {:?}", token),
+            }
+        }
+
+        //The width a [high:0] range declares
+        fn width(&mut self) -> u32 {
+            if !self.symbol("[") {
+                return 1;
+            }
+            let high = self.number();
+            self.expect(":");
+            let low = self.number();
+            self.expect("]");
+            high - low + 1
+        }
+
+        fn expression(&mut self) -> Expression {
+            let condition = self.binary(0);
+            if !self.symbol("?") {
+                return condition;
+            }
+            let then = self.expression();
+            self.expect(":");
+            Expression::Conditional(Box::new(condition), Box::new(then), Box::new(self.expression()))
+        }
+
+        fn binary(&mut self, level : usize) -> Expression {
+            if level == LEVELS.len() {
+                return self.unary();
+            }
+            let mut left = self.binary(level + 1);
+            while let Some(Token::Symbol(operator)) = self.tokens.get(self.next).cloned() {
+                if !LEVELS[level].contains(&operator.as_str()) {
+                    break;
+                }
+                self.next += 1;
+                left = Expression::Binary(operator, Box::new(left), Box::new(self.binary(level + 1)));
+            }
+            left
+        }
+
+        fn unary(&mut self) -> Expression {
+            if self.symbol("~") {
+                return Expression::Not(Box::new(self.unary()));
+            }
+            if self.symbol("(") {
+                let expression = self.expression();
+                self.expect(")");
+                return expression;
+            }
+            if self.symbol("{") {
+                let mut parts = vec![self.expression()];
+                while self.symbol(",") {
+                    parts.push(self.expression());
+                }
+                self.expect("}");
+                return Expression::Concatenation(parts);
+            }
+            match self.take() {
+                Token::Number(value, width) => Expression::Number(value, width),
+                Token::Word(name) if self.symbol("[") => {
+                    let index = self.expression();
+                    let expression = match index {
+                        Expression::Number(high, _) if self.symbol(":") => Expression::Bits(name, high as u32, self.number()),
+                        Expression::Number(bit, _) => Expression::Bits(name, bit as u32, bit as u32),
+                        index => Expression::Index(name, Box::new(index)),
+                    };
+                    self.expect("]");
+                    expression
+                },
+                Token::Word(name) => Expression::Signal(name),
+                token => panic!("expected an expression but found {:?}", token),
+            }
+        }
+
+        fn module(&mut self) -> Module {
+            assert_eq!(self.word(), "module");
+            let mut module = Module { name : self.word(), ports : Vec::new(), widths : HashMap::new(), memories : HashMap::new(), items : Vec::new() };
+            self.expect("(");
+            loop {
+                let input = self.word() == "input";
+                let width = self.width();
+                let name = self.word();
+                module.widths.insert(name.clone(), width);
+                module.ports.push((name, input));
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+            self.expect(")");
+            self.expect(";");
+            loop {
+                match self.word().as_str() {
+                    "endmodule" => return module,
+                    "wire" => {
+                        let width = self.width();
+                        module.widths.insert(self.word(), width);
+                    },
+                    "reg" => {
+                        let width = self.width();
+                        let name = self.word();
+                        if self.symbol("[") {
+                            self.number();
+                            self.expect(":");
+                            module.memories.insert(name.clone(), self.number() as usize + 1);
+                            self.expect("]");
+                        }
+                        module.widths.insert(name, width);
+                    },
+                    "assign" => {
+                        let target = self.expression();
+                        self.expect("=");
+                        module.items.push(Item::Assign(target, self.expression()));
+                    },
+                    "always" => {
+                        self.expect("@");
+                        self.expect("(");
+                        assert_eq!((self.word(), self.word()), (String::from("posedge"), String::from("clk")));
+                        self.expect(")");
+                        let condition = if self.tokens[self.next] == Token::Word(String::from("if")) {
+                            self.next += 1;
+                            Some(self.unary())
+                        }
+                        else {
+                            None
+                        };
+                        let target = self.expression();
+                        self.expect("<=");
+                        module.items.push(Item::Always(condition, target, self.expression()));
+                    },
+                    "initial" => {
+                        assert_eq!(self.word(), "$readmemb");
+                        self.expect("(");
+                        let file = match self.take() {
+                            Token::Text(file) => file,
+                            token => panic!("expected a file name but found {:?}", token),
+                        };
+                        self.expect(",");
+                        module.items.push(Item::ReadMemory(file, self.word()));
+                        self.expect(")");
+                    },
+                    chip => {
+                        let chip = chip.to_string();
+                        self.word();
+                        self.expect("(");
+                        let mut ports = Vec::new();
+                        while self.symbol(".") {
+                            let port = self.word();
+                            self.expect("(");
+                            ports.push((port, self.expression()));
+                            self.expect(")");
+                            self.symbol(",");
+                        }
+                        self.expect(")");
+                        module.items.push(Item::Instance(chip, ports));
+                    },
+                }
+                self.expect(";");
+            }
+        }
+    }
+
+    //One instance of a module with the values of its signals and memories
+    struct Scope {
+        module : usize,
+        values : HashMap<String, u64>,
+        memories : HashMap<String, Vec<u64>>,
+        children : Vec<Scope>,
+        //What the always blocks decided at the clock edge: the signal or memory, the address and the value
+        writes : Vec<(String, Option<usize>, u64)>,
+    }
+
+    fn mask(width : u32) -> u64 {
+        (1 << width) - 1
+    }
+
+    struct Design {
+        modules : Vec<Module>,
+    }
+
+    impl Design {
+        fn parse(source : &str) -> Design {
+            let mut parser = Parser { tokens : tokenize(source), next : 0 };
+            let mut modules = Vec::new();
+            while parser.next < parser.tokens.len() {
+                modules.push(parser.module());
+            }
+            Design { modules }
+        }
+
+        //An instance of the first module, which is the top one
+        fn instantiate(&self, module : usize) -> Scope {
+            let memories = self.modules[module].memories.iter().map(|(name, size)| (name.clone(), vec![0; *size])).collect();
+            let mut scope = Scope { module, values : HashMap::new(), memories, children : Vec::new(), writes : Vec::new() };
+            for item in &self.modules[module].items {
+                match item {
+                    Item::Instance(chip, _) => scope.children.push(self.instantiate(self.modules.iter().position(|m| m.name == *chip).unwrap())),
+                    Item::ReadMemory(file, memory) => {
+                        let words = fs::read_to_string(file).unwrap().lines().map(|line| u64::from_str_radix(line.trim(), 2).unwrap()).collect::<Vec<u64>>();
+                        scope.memories.get_mut(memory).unwrap()[..words.len()].copy_from_slice(&words);
+                    },
+                    _ => (),
+                }
+            }
+            scope
+        }
+
+        fn eval(&self, scope : &Scope, expression : &Expression) -> (u64, u32) {
+            let widths = &self.modules[scope.module].widths;
+            let value = |name : &str| scope.values.get(name).copied().unwrap_or(0);
+            match expression {
+                Expression::Signal(name) => (value(name), widths[name]),
+                Expression::Bits(name, high, low) => ((value(name) >> low) & mask(high - low + 1), high - low + 1),
+                Expression::Index(name, index) => {
+                    let index = self.eval(scope, index).0 as usize;
+                    match scope.memories.get(name) {
+                        Some(memory) => (memory[index], widths[name]),
+                        None => ((value(name) >> index) & 1, 1),
+                    }
+                },
+                Expression::Number(value, width) => (*value, *width),
+                Expression::Not(operand) => {
+                    let (value, width) = self.eval(scope, operand);
+                    (!value & mask(width), width)
+                },
+                Expression::Binary(operator, left, right) => {
+                    let ((x, left_width), (y, right_width)) = (self.eval(scope, left), self.eval(scope, right));
+                    let width = left_width.max(right_width);
+                    match operator.as_str() {
+                        "|" => (x | y, width),
+                        "^" => (x ^ y, width),
+                        "&" => (x & y, width),
+                        "+" => ((x + y) & mask(width), width),
+                        "==" => ((x == y) as u64, 1),
+                        _ => ((x != y) as u64, 1),
+                    }
+                },
+                Expression::Conditional(condition, then, otherwise) => {
+                    let ((x, then_width), (y, otherwise_width)) = (self.eval(scope, then), self.eval(scope, otherwise));
+                    (if self.eval(scope, condition).0 != 0 { x } else { y }, then_width.max(otherwise_width))
+                },
+                Expression::Concatenation(parts) => parts.iter().fold((0, 0), |(value, width), part| {
+                    let (part, part_width) = self.eval(scope, part);
+                    ((value << part_width) | part, width + part_width)
+                }),
+            }
+        }
+
+        //Stores a value in a signal or some of its bits, returning whether it changed
+        fn store(&self, scope : &mut Scope, target : &Expression, value : u64) -> bool {
+            let widths = &self.modules[scope.module].widths;
+            let (name, value) = match target {
+                Expression::Signal(name) => (name, value & mask(widths[name])),
+                Expression::Bits(name, high, low) => {
+                    let field = mask(high - low + 1) << low;
+                    (name, (scope.values.get(name).copied().unwrap_or(0) & !field) | ((value << low) & field))
+                },
+                _ => panic!("cannot assign to {:?}", target),
+            };
+            scope.values.insert(name.clone(), value) != Some(value)
+        }
+
+        fn pass(&self, scope : &mut Scope) -> bool {
+            let mut changed = false;
+            let mut child = 0;
+            for item in &self.modules[scope.module].items {
+                match item {
+                    Item::Assign(target, value) => {
+                        let (value, _) = self.eval(scope, value);
+                        changed |= self.store(scope, target, value);
+                    },
+                    Item::Instance(_, ports) => {
+                        let inputs = self.modules[scope.children[child].module].ports.iter().filter(|(_, input)| *input).map(|(name, _)| name.clone()).collect::<Vec<String>>();
+                        for (port, expression) in ports.iter().filter(|(port, _)| inputs.contains(port)) {
+                            let (value, _) = self.eval(scope, expression);
+                            changed |= self.store(&mut scope.children[child], &Expression::Signal(port.clone()), value);
+                        }
+                        changed |= self.pass(&mut scope.children[child]);
+                        for (port, expression) in ports.iter().filter(|(port, _)| !inputs.contains(port)) {
+                            let value = scope.children[child].values.get(port).copied().unwrap_or(0);
+                            changed |= self.store(scope, expression, value);
+                        }
+                        child += 1;
+                    },
+                    _ => (),
+                }
+            }
+            changed
+        }
+
+        fn settle(&self, scope : &mut Scope) {
+            for _ in 0..1000 {
+                if !self.pass(scope) {
+                    return;
+                }
+            }
+            panic!("the design does not settle");
+        }
+
+        fn sample(&self, scope : &mut Scope) {
+            for item in &self.modules[scope.module].items {
+                if let Item::Always(condition, target, value) = item {
+                    if condition.as_ref().map(|c| self.eval(scope, c).0 != 0).unwrap_or(true) {
+                        let (value, _) = self.eval(scope, value);
+                        let write = match target {
+                            Expression::Signal(name) => (name.clone(), None, value),
+                            Expression::Index(name, address) => (name.clone(), Some(self.eval(scope, address).0 as usize), value),
+                            _ => panic!("cannot assign to {:?}", target),
+                        };
+                        scope.writes.push(write);
+                    }
+                }
+            }
+            for child in &mut scope.children {
+                self.sample(child);
+            }
+        }
+
+        fn commit(&self, scope : &mut Scope) {
+            for (name, address, value) in std::mem::take(&mut scope.writes) {
+                match address {
+                    Some(address) => scope.memories.get_mut(&name).unwrap()[address] = value,
+                    None => {
+                        self.store(scope, &Expression::Signal(name), value);
+                    },
+                }
+            }
+            for child in &mut scope.children {
+                self.commit(child);
+            }
+        }
+
+        //A rising clock edge, after which everything settles again
+        fn clock(&self, scope : &mut Scope) {
+            self.settle(scope);
+            self.sample(scope);
+            self.commit(scope);
+            self.settle(scope);
+        }
+
+        fn memory<'s>(&self, scope : &'s mut Scope, module : &str) -> Option<&'s mut Vec<u64>> {
+            if self.modules[scope.module].name == module {
+                return scope.memories.get_mut("memory");
+            }
+            scope.children.iter_mut().find_map(|child| self.memory(child, module))
+        }
+    }
+
+    fn project(path : &str) -> PathBuf {
+        Path::new(PROJECTS).join(path)
+    }
+
+    //A xorshift generator, so that the samples are the same on every run
+    fn random(seed : &mut u32) -> u16 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 17;
+        *seed ^= *seed << 5;
+        (*seed >> 8) as u16
+    }
+
+    //The exported chip and the HDL simulator side by side, to be driven with the same inputs
+    fn both(path : &Path, directories : &[PathBuf], rom_file : Option<&str>) -> (Design, Scope, HdlSimulator) {
+        let design = Design::parse(&export_file(path, directories, &[], rom_file).ok().unwrap());
+        let scope = design.instantiate(0);
+        (design, scope, HdlSimulator::load(path, directories, &[]).ok().unwrap())
+    }
+
+    fn set(design : &Design, scope : &mut Scope, simulator : &mut HdlSimulator, inputs : &[(&str, u16)]) {
+        for (name, value) in inputs {
+            design.store(scope, &Expression::Signal(identifier(name)), *value as u64);
+            simulator.set(name, *value).unwrap();
+        }
+        design.settle(scope);
+        simulator.eval();
+    }
+
+    fn assert_outputs(scope : &Scope, simulator : &HdlSimulator, outputs : &[&str]) {
+        for output in outputs {
+            assert_eq!(scope.values[*output], simulator.get(output).unwrap() as u64, "{}", output);
+        }
+    }
+
+    #[test]
+    fn computer_structure_test() {
+        let path = project("05/Computer.hdl");
+        let verilog = export_file(&path, &[], &[], Some("Max.hack")).ok().unwrap();
+        assert_eq!(verilog, export_file(&path, &[], &[], Some("Max.hack")).ok().unwrap());
+        let design = Design::parse(&verilog);
+        let names = design.modules.iter().map(|m| m.name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["Computer", "ROM32K", "Memory", "DMux", "RAM16K", "Screen", "Keyboard", "Mux4Way16",
+                               "CPU", "Mux16", "Not", "Or", "ARegister", "And", "DRegister", "ALU", "PC"]);
+        assert_eq!(design.modules.iter().map(|m| m.ports.len()).sum::<usize>(), 75);
+        let cpu = &design.modules[8];
+        assert_eq!(cpu.ports.iter().map(|(name, _)| name.as_str()).collect::<Vec<&str>>(),
+                   vec!["clk", "inM", "instruction", "reset", "outM", "writeM", "addressM", "pc"]);
+        assert_eq!(cpu.ports.iter().map(|(name, _)| cpu.widths[name]).collect::<Vec<u32>>(), vec![1, 16, 16, 1, 16, 1, 15, 15]);
+        assert_eq!(cpu.items.iter().filter(|item| matches!(item, Item::Instance(..))).count(), 19);
+        assert!(verilog.contains("    initial $readmemb(\"Max.hack\", memory);\n"));
+        assert!(verilog.contains("    assign addressM = ARegister_3_out[14:0];\n"));
+    }
+
+    #[test]
+    fn wiring_test() {
+        //Part inputs put together from sub-buses and constants, an output feeding several wires, and a
+        //pin named after a Verilog keyword
+        let directory = std::env::temp_dir().join("n2t_verilog_wiring");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("Wiring.hdl");
+        fs::write(&path, "CHIP Wiring {\n    IN a[8], or;\n    OUT out[16], low, high[2];\n    PARTS:\n    Not16(in[0..7]=a, in[8]=or, in[12..15]=true, out=out, out[0]=low, out[14..15]=high);\n}").unwrap();
+        let (design, mut scope, mut simulator) = both(&path, &[], None);
+        let verilog = export_file(&path, &[], &[], None).ok().unwrap();
+        assert!(verilog.contains("    input or_,\n"));
+        assert!(verilog.contains(".in({4'hf, 3'h0, or_, a})"));
+        for (a, or) in [(0, 0), (0x5a, 1), (0xff, 0), (0x81, 1)].iter() {
+            set(&design, &mut scope, &mut simulator, &[("a", *a), ("or", *or)]);
+            assert_outputs(&scope, &simulator, &["out", "low", "high"]);
+        }
+    }
+
+    #[test]
+    fn alu_equivalence_test() {
+        //Down to Nand gates through the chips of projects 1 and 2
+        let (design, mut scope, mut simulator) = both(&project("02/ALU.hdl"), &[project("01")], None);
+        assert!(design.modules.iter().any(|m| m.name == "Nand"));
+        let mut seed = 2463534242;
+        for _ in 0..50 {
+            let control = random(&mut seed);
+            let mut inputs = vec![("x", random(&mut seed)), ("y", random(&mut seed))];
+            inputs.extend(["zx", "nx", "zy", "ny", "f", "no"].iter().enumerate().map(|(i, name)| (*name, (control >> i) & 1)));
+            set(&design, &mut scope, &mut simulator, &inputs);
+            assert_outputs(&scope, &simulator, &["out", "zr", "ng"]);
+        }
+    }
+
+    #[test]
+    fn cpu_and_pc_equivalence_test() {
+        let mut seed = 88172645;
+        let (design, mut scope, mut simulator) = both(&project("05/CPU.hdl"), &[], None);
+        for cycle in 0..300 {
+            let inputs = [("inM", random(&mut seed)), ("instruction", random(&mut seed)), ("reset", (cycle % 50 == 0) as u16)];
+            set(&design, &mut scope, &mut simulator, &inputs);
+            assert_outputs(&scope, &simulator, &["outM", "writeM", "addressM", "pc"]);
+            design.clock(&mut scope);
+            simulator.tick();
+            simulator.tock();
+            assert_outputs(&scope, &simulator, &["outM", "writeM", "addressM", "pc"]);
+        }
+        let (design, mut scope, mut simulator) = both(&project("03/a/PC.hdl"), &[project("01"), project("02")], None);
+        for _ in 0..40 {
+            let bits = random(&mut seed);
+            let inputs = [("in", random(&mut seed)), ("load", (bits & 7 == 0) as u16), ("inc", bits >> 15), ("reset", (bits & 0x1f0 == 0) as u16)];
+            set(&design, &mut scope, &mut simulator, &inputs);
+            design.clock(&mut scope);
+            simulator.tick();
+            simulator.tock();
+            assert_outputs(&scope, &simulator, &["out"]);
+        }
+    }
+
+    #[test]
+    fn computer_runs_rom_file_test() {
+        let directory = std::env::temp_dir().join("n2t_verilog_rom");
+        fs::create_dir_all(&directory).unwrap();
+        let hack_file = directory.join("Max.hack");
+        let program = assemble_file(&project("06/max/Max.asm").display().to_string()).ok().unwrap();
+        fs::write(&hack_file, machine_text(&program)).unwrap();
+        let (design, mut scope, mut simulator) = both(&project("05/Computer.hdl"), &[], Some(&hack_file.display().to_string()));
+        simulator.memory_mut("ROM32K").unwrap()[..program.len()].copy_from_slice(&program);
+        for (x, y) in [(3, 5), (23456, 12345)].iter() {
+            set(&design, &mut scope, &mut simulator, &[("reset", 1)]);
+            design.clock(&mut scope);
+            simulator.tick();
+            simulator.tock();
+            set(&design, &mut scope, &mut simulator, &[("reset", 0)]);
+            design.memory(&mut scope, "RAM16K").unwrap()[..2].copy_from_slice(&[*x as u64, *y as u64]);
+            simulator.memory_mut("RAM16K").unwrap()[..2].copy_from_slice(&[*x, *y]);
+            for _ in 0..20 {
+                design.clock(&mut scope);
+                simulator.tick();
+                simulator.tock();
+            }
+            assert_eq!(design.memory(&mut scope, "RAM16K").unwrap()[2], *x.max(y) as u64);
+            assert_eq!(simulator.memory("RAM16K").unwrap()[2], *x.max(y));
+        }
+    }
+}
@@ -0,0 +1,57 @@
+pub mod lexer;
+pub mod ast;
+pub mod parser;
+pub mod xml;
+pub mod symbols;
+pub mod codegen;
+pub mod checker;
+pub mod os;
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//Where a piece of Jack source sits: byte offsets for slicing plus the 1-based line and column of its start
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct Span {
+    pub start : usize,
+    pub end : usize,
+    pub line : usize,
+    pub column : usize,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct JackError {
+    pub file_name : String,
+    pub line : usize,
+    pub column : usize,
+    pub message : String,
+}
+
+impl JackError {
+    pub fn at(file_name : &str, span : Span, message : String) -> JackError {
+        JackError { file_name : file_name.to_string(), line : span.line, column : span.column, message }
+    }
+}
+
+impl fmt::Display for JackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file_name, self.line, self.column, self.message)
+    }
+}
+
+//The .jack files a path names: the file itself, or every .jack file in a directory, sorted by name
+pub fn jack_file_paths(path : &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut paths = fs::read_dir(path)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p.extension().map(|e| e == "jack").unwrap_or(false))
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    Ok(paths)
+}
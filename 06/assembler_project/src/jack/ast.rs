@@ -0,0 +1,188 @@
+use crate::jack::Span;
+
+//The typed syntax tree of one Jack class. Every node keeps the span of the source it was parsed from.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Identifier {
+    pub name : String,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Type {
+    Int,
+    Char,
+    Boolean,
+    Class(String),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ClassVarKind {
+    Static,
+    Field,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Class {
+    pub name : Identifier,
+    pub variables : Vec<ClassVarDec>,
+    pub subroutines : Vec<SubroutineDec>,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ClassVarDec {
+    pub kind : ClassVarKind,
+    pub var_type : Type,
+    pub names : Vec<Identifier>,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Parameter {
+    pub var_type : Type,
+    pub name : Identifier,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct VarDec {
+    pub var_type : Type,
+    pub names : Vec<Identifier>,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SubroutineDec {
+    pub kind : SubroutineKind,
+    //None for void
+    pub return_type : Option<Type>,
+    pub name : Identifier,
+    pub parameters : Vec<Parameter>,
+    pub locals : Vec<VarDec>,
+    pub statements : Vec<Statement>,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Statement {
+    pub kind : StatementKind,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum StatementKind {
+    Let(Identifier, Option<Box<Expression>>, Expression),
+    If(Expression, Vec<Statement>, Option<Vec<Statement>>),
+    While(Expression, Vec<Statement>),
+    Do(SubroutineCall),
+    Return(Option<Expression>),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    And,
+    Or,
+    Less,
+    Greater,
+    Equal,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum KeywordConstant {
+    True,
+    False,
+    Null,
+    This,
+}
+
+//Jack has no operator precedence: terms are combined strictly left to right
+#[derive(Clone, PartialEq, Debug)]
+pub struct Expression {
+    pub first : Term,
+    pub rest : Vec<(BinaryOperator, Term)>,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Term {
+    pub kind : TermKind,
+    pub span : Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TermKind {
+    IntegerConstant(u16),
+    StringConstant(String),
+    KeywordConstant(KeywordConstant),
+    Variable(Identifier),
+    ArrayElement(Identifier, Box<Expression>),
+    Call(SubroutineCall),
+    Parenthesized(Box<Expression>),
+    Unary(UnaryOperator, Box<Term>),
+}
+
+//f(...), Class.f(...) or variable.f(...); the receiver is whatever precedes the dot
+#[derive(Clone, PartialEq, Debug)]
+pub struct SubroutineCall {
+    pub receiver : Option<Identifier>,
+    pub name : Identifier,
+    pub arguments : Vec<Expression>,
+    pub span : Span,
+}
+
+impl BinaryOperator {
+    pub fn from_symbol(symbol : char) -> Option<BinaryOperator> {
+        match symbol {
+            '+' => Some(BinaryOperator::Add),
+            '-' => Some(BinaryOperator::Subtract),
+            '*' => Some(BinaryOperator::Multiply),
+            '/' => Some(BinaryOperator::Divide),
+            '&' => Some(BinaryOperator::And),
+            '|' => Some(BinaryOperator::Or),
+            '<' => Some(BinaryOperator::Less),
+            '>' => Some(BinaryOperator::Greater),
+            '=' => Some(BinaryOperator::Equal),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(self) -> char {
+        match self {
+            BinaryOperator::Add => '+',
+            BinaryOperator::Subtract => '-',
+            BinaryOperator::Multiply => '*',
+            BinaryOperator::Divide => '/',
+            BinaryOperator::And => '&',
+            BinaryOperator::Or => '|',
+            BinaryOperator::Less => '<',
+            BinaryOperator::Greater => '>',
+            BinaryOperator::Equal => '=',
+        }
+    }
+}
+
+impl UnaryOperator {
+    pub fn symbol(self) -> char {
+        match self {
+            UnaryOperator::Negate => '-',
+            UnaryOperator::Not => '~',
+        }
+    }
+}
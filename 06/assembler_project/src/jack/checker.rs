@@ -0,0 +1,362 @@
+use std::collections::HashMap;
+use crate::jack::{JackError, Span};
+use crate::jack::ast::*;
+use crate::jack::symbols::{SymbolTable, SymbolKind};
+
+//What a call site needs to know about a subroutine
+#[derive(Clone, PartialEq, Debug)]
+pub struct Signature {
+    pub kind : SubroutineKind,
+    pub parameters : usize,
+    pub returns_value : bool,
+}
+
+//Every class a program can refer to, with the signatures of its subroutines
+pub struct ClassIndex {
+    classes : HashMap<String, HashMap<String, Signature>>,
+}
+
+impl ClassIndex {
+    pub fn new() -> ClassIndex {
+        ClassIndex { classes : HashMap::new() }
+    }
+
+    //A class added later replaces an earlier one of the same name, so programs can override OS classes
+    pub fn add(&mut self, class : &Class) {
+        let subroutines = class.subroutines.iter().map(|subroutine| {
+            let signature = Signature {
+                kind : subroutine.kind,
+                parameters : subroutine.parameters.len(),
+                returns_value : subroutine.return_type.is_some(),
+            };
+            (subroutine.name.name.clone(), signature)
+        }).collect();
+        self.classes.insert(class.name.name.clone(), subroutines);
+    }
+
+    fn contains(&self, class_name : &str) -> bool {
+        self.classes.contains_key(class_name)
+    }
+
+    fn signature(&self, class_name : &str, name : &str) -> Option<&Signature> {
+        self.classes.get(class_name).and_then(|subroutines| subroutines.get(name))
+    }
+}
+
+impl Default for ClassIndex {
+    fn default() -> ClassIndex {
+        ClassIndex::new()
+    }
+}
+
+//Whether a statement list always ends in a return
+fn always_returns(statements : &[Statement]) -> bool {
+    match statements.last().map(|s| &s.kind) {
+        Some(StatementKind::Return(_)) => true,
+        Some(StatementKind::If(_, then_branch, Some(else_branch))) => always_returns(then_branch) && always_returns(else_branch),
+        _ => false,
+    }
+}
+
+struct Checker<'a> {
+    file_name : &'a str,
+    class_name : &'a str,
+    index : &'a ClassIndex,
+    symbols : SymbolTable,
+    subroutine : Option<&'a SubroutineDec>,
+    errors : Vec<JackError>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, span : Span, message : String) {
+        self.errors.push(JackError::at(self.file_name, span, message));
+    }
+
+    fn in_function(&self) -> bool {
+        self.subroutine.map(|s| s.kind == SubroutineKind::Function).unwrap_or(false)
+    }
+
+    fn subroutine_name(&self) -> String {
+        format!("{}.{}", self.class_name, self.subroutine.map(|s| s.name.name.as_str()).unwrap_or(""))
+    }
+
+    fn check_type(&mut self, var_type : &Type, span : Span) {
+        if let Type::Class(name) = var_type {
+            if !self.index.contains(name) {
+                self.error(span, format!("unknown class '{}'", name));
+            }
+        }
+    }
+
+    fn check_variable(&mut self, name : &Identifier) {
+        match self.symbols.lookup(&name.name).map(|s| s.kind) {
+            None => self.error(name.span, format!("undeclared variable '{}'", name.name)),
+            Some(SymbolKind::Field) if self.in_function() => {
+                self.error(name.span, format!("field '{}' cannot be used in function {}", name.name, self.subroutine_name()));
+            },
+            Some(_) => (),
+        }
+    }
+
+    fn check_class(&mut self, class : &'a Class) {
+        for variable in &class.variables {
+            self.check_type(&variable.var_type, variable.span);
+            let kind = if variable.kind == ClassVarKind::Static { SymbolKind::Static } else { SymbolKind::Field };
+            for name in &variable.names {
+                self.symbols.define(&name.name, &variable.var_type, kind);
+            }
+        }
+        for subroutine in &class.subroutines {
+            self.check_subroutine(subroutine);
+        }
+    }
+
+    fn check_subroutine(&mut self, subroutine : &'a SubroutineDec) {
+        self.subroutine = Some(subroutine);
+        self.symbols.start_subroutine();
+        if let Some(return_type) = &subroutine.return_type {
+            self.check_type(return_type, subroutine.name.span);
+        }
+        for parameter in &subroutine.parameters {
+            self.check_type(&parameter.var_type, parameter.name.span);
+            self.symbols.define(&parameter.name.name, &parameter.var_type, SymbolKind::Argument);
+        }
+        for local in &subroutine.locals {
+            self.check_type(&local.var_type, local.span);
+            for name in &local.names {
+                self.symbols.define(&name.name, &local.var_type, SymbolKind::Local);
+            }
+        }
+        self.check_statements(&subroutine.statements);
+        if subroutine.return_type.is_some() && !always_returns(&subroutine.statements) {
+            let message = format!("{} can reach its end without returning a value", self.subroutine_name());
+            self.error(subroutine.name.span, message);
+        }
+    }
+
+    fn check_statements(&mut self, statements : &[Statement]) {
+        for statement in statements {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, statement : &Statement) {
+        match &statement.kind {
+            StatementKind::Let(target, element, value) => {
+                self.check_variable(target);
+                if let Some(element) = element {
+                    self.check_expression(element);
+                }
+                self.check_expression(value);
+            },
+            StatementKind::If(condition, then_branch, else_branch) => {
+                self.check_expression(condition);
+                self.check_statements(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.check_statements(else_branch);
+                }
+            },
+            StatementKind::While(condition, body) => {
+                self.check_expression(condition);
+                self.check_statements(body);
+            },
+            StatementKind::Do(call) => self.check_call(call),
+            StatementKind::Return(value) => {
+                let returns_value = self.subroutine.map(|s| s.return_type.is_some()).unwrap_or(false);
+                match value {
+                    Some(value) => {
+                        if !returns_value {
+                            let message = format!("void subroutine {} cannot return a value", self.subroutine_name());
+                            self.error(statement.span, message);
+                        }
+                        self.check_expression(value);
+                    },
+                    None if returns_value => {
+                        let message = format!("{} must return a value", self.subroutine_name());
+                        self.error(statement.span, message);
+                    },
+                    None => (),
+                }
+            },
+        }
+    }
+
+    fn check_expression(&mut self, expression : &Expression) {
+        self.check_term(&expression.first);
+        for (_, term) in &expression.rest {
+            self.check_term(term);
+        }
+    }
+
+    fn check_term(&mut self, term : &Term) {
+        match &term.kind {
+            TermKind::IntegerConstant(_) | TermKind::StringConstant(_) => (),
+            TermKind::KeywordConstant(KeywordConstant::This) if self.in_function() => {
+                let message = format!("'this' cannot be used in function {}", self.subroutine_name());
+                self.error(term.span, message);
+            },
+            TermKind::KeywordConstant(_) => (),
+            TermKind::Variable(name) => self.check_variable(name),
+            TermKind::ArrayElement(name, element) => {
+                self.check_variable(name);
+                self.check_expression(element);
+            },
+            TermKind::Call(call) => self.check_call(call),
+            TermKind::Parenthesized(expression) => self.check_expression(expression),
+            TermKind::Unary(_, operand) => self.check_term(operand),
+        }
+    }
+
+    fn check_call(&mut self, call : &SubroutineCall) {
+        for argument in &call.arguments {
+            self.check_expression(argument);
+        }
+        //The class the subroutine belongs to, and whether the call supplies an object
+        let (class_name, on_object) = match &call.receiver {
+            None => (self.class_name.to_string(), !self.in_function()),
+            Some(receiver) => match self.symbols.lookup(&receiver.name).map(|s| s.var_type.clone()) {
+                Some(Type::Class(class_name)) => {
+                    self.check_variable(receiver);
+                    (class_name, true)
+                },
+                Some(_) => {
+                    self.error(receiver.span, format!("'{}' is not an object, so it has no methods", receiver.name));
+                    return;
+                },
+                None if self.index.contains(&receiver.name) => (receiver.name.clone(), false),
+                None if receiver.name.starts_with(|c : char| c.is_ascii_uppercase()) => {
+                    self.error(receiver.span, format!("unknown class '{}'", receiver.name));
+                    return;
+                },
+                None => {
+                    self.error(receiver.span, format!("undeclared variable '{}'", receiver.name));
+                    return;
+                },
+            },
+        };
+        if !self.index.contains(&class_name) {
+            //Reported where the variable's type was declared
+            return;
+        }
+        let full_name = format!("{}.{}", class_name, call.name.name);
+        let signature = match self.index.signature(&class_name, &call.name.name) {
+            Some(signature) => signature.clone(),
+            None => {
+                self.error(call.name.span, format!("undeclared subroutine '{}'", full_name));
+                return;
+            },
+        };
+        match (signature.kind, call.receiver.is_some(), on_object) {
+            (SubroutineKind::Method, false, false) => {
+                let message = format!("method {} cannot be called from function {}", full_name, self.subroutine_name());
+                self.error(call.span, message);
+            },
+            (SubroutineKind::Method, true, false) => {
+                self.error(call.span, format!("method {} must be called on an object, not on its class", full_name));
+            },
+            (SubroutineKind::Function, true, true) | (SubroutineKind::Constructor, true, true) => {
+                let kind = if signature.kind == SubroutineKind::Function { "function" } else { "constructor" };
+                self.error(call.span, format!("{} is a {} and cannot be called on an object", full_name, kind));
+            },
+            _ => (),
+        }
+        if signature.parameters != call.arguments.len() {
+            self.error(call.span, format!("{} expects {} argument{} but is given {}", full_name, signature.parameters,
+                                          if signature.parameters == 1 { "" } else { "s" }, call.arguments.len()));
+        }
+    }
+}
+
+//Checks one class against the index of every class the program can use; returns all problems found
+pub fn check_class(file_name : &str, class : &Class, index : &ClassIndex) -> Vec<JackError> {
+    let mut checker = Checker {
+        file_name,
+        class_name : &class.name.name,
+        index,
+        symbols : SymbolTable::new(),
+        subroutine : None,
+        errors : Vec::new(),
+    };
+    checker.check_class(class);
+    checker.errors
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::jack::jack_file_paths;
+    use crate::jack::checker::{check_class, ClassIndex};
+    use crate::jack::parser::parse_source;
+    use crate::test_support::PROJECTS;
+
+    const LIBRARY : &str = "class Output { function void printInt(int i) { return; } }";
+
+    fn check(source : &str) -> Vec<String> {
+        let mut index = ClassIndex::new();
+        let library = parse_source("Output.jack", LIBRARY).ok().unwrap();
+        let class = parse_source("Main.jack", source).ok().unwrap();
+        index.add(&library);
+        index.add(&class);
+        check_class("Main.jack", &class, &index).iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn semantic_errors_test() {
+        let errors = check("class Main {\n\
+                            field int x;\n\
+                            function void main() { var Point p; let y = 1; let x = 2; do Output.printInt(); do run(); return; }\n\
+                            function int f(int a) { if (a) { return a; } }\n\
+                            method void run() { do Output.print(1); do Main.run(); do main(); return this; }\n\
+                            function Main g() { do Sys.halt(); do q.run(); return this; }\n\
+                            }");
+        assert_eq!(errors, vec![
+            "Main.jack:3:24: unknown class 'Point'",
+            "Main.jack:3:41: undeclared variable 'y'",
+            "Main.jack:3:52: field 'x' cannot be used in function Main.main",
+            "Main.jack:3:62: Output.printInt expects 1 argument but is given 0",
+            "Main.jack:3:84: method Main.run cannot be called from function Main.main",
+            "Main.jack:4:14: Main.f can reach its end without returning a value",
+            "Main.jack:5:31: undeclared subroutine 'Output.print'",
+            "Main.jack:5:44: method Main.run must be called on an object, not on its class",
+            "Main.jack:5:67: void subroutine Main.run cannot return a value",
+            "Main.jack:6:24: unknown class 'Sys'",
+            "Main.jack:6:39: undeclared variable 'q'",
+            "Main.jack:6:55: 'this' cannot be used in function Main.g",
+        ]);
+    }
+
+    #[test]
+    fn method_and_function_calls_test() {
+        let errors = check("class Main {\n\
+                            function Main new() { return Main.make(); }\n\
+                            function Main make() { var Main m; do m.new(); do m.run(1); return m; }\n\
+                            method int run() { do run(); return 1; }\n\
+                            }");
+        assert_eq!(errors, vec![
+            "Main.jack:3:39: Main.new is a function and cannot be called on an object",
+            "Main.jack:3:51: Main.run expects 0 arguments but is given 1",
+        ]);
+    }
+
+    #[test]
+    fn project_11_programs_are_clean_test() {
+        let parse_directory = |directory : &Path| jack_file_paths(directory).unwrap().iter().map(|path| {
+            let file_name = path.display().to_string();
+            let class = parse_source(&file_name, &fs::read_to_string(path).unwrap()).ok().unwrap();
+            (file_name, class)
+        }).collect::<Vec<_>>();
+        let os = parse_directory(&Path::new(PROJECTS).join("12"));
+        for program in ["Seven", "ConvertToBin", "Square", "Average", "Pong", "ComplexArrays"].iter() {
+            let classes = parse_directory(&Path::new(PROJECTS).join("11").join(program));
+            let mut index = ClassIndex::new();
+            for (_, class) in os.iter().chain(classes.iter()) {
+                index.add(class);
+            }
+            for (file_name, class) in &classes {
+                let errors = check_class(file_name, class, &index);
+                assert!(errors.is_empty(), "{}", errors.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("\n"));
+            }
+        }
+    }
+}
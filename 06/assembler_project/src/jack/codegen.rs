@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use crate::jack::{JackError, Span};
+use crate::jack::ast::*;
+use crate::jack::symbols::{SymbolTable, SymbolKind};
+use crate::vm::VmFile;
+use crate::vm::parser::{VmCommand, VmLine, Segment, ArithmeticCommand};
+
+//Compiles one class to VM commands following the standard Jack calling conventions: methods get
+//the object as argument 0, constructors allocate their fields with Memory.alloc, `that` is used
+//for array access and strings are built with String.new and String.appendChar.
+struct CodeGenerator<'a> {
+    file_name : &'a str,
+    class_name : String,
+    //Whether each subroutine of this class is a method, to resolve unqualified calls
+    methods : HashMap<String, bool>,
+    symbols : SymbolTable,
+    if_count : usize,
+    while_count : usize,
+    commands : Vec<VmCommand>,
+}
+
+impl<'a> CodeGenerator<'a> {
+    fn emit(&mut self, command : VmCommand) {
+        self.commands.push(command);
+    }
+
+    fn push(&mut self, segment : Segment, index : u16) {
+        self.emit(VmCommand::Push(segment, index));
+    }
+
+    fn pop(&mut self, segment : Segment, index : u16) {
+        self.emit(VmCommand::Pop(segment, index));
+    }
+
+    fn arithmetic(&mut self, command : ArithmeticCommand) {
+        self.emit(VmCommand::Arithmetic(command));
+    }
+
+    fn call(&mut self, name : &str, arguments : usize) {
+        self.emit(VmCommand::Call(name.to_string(), arguments as u16));
+    }
+
+    fn error(&self, span : Span, message : String) -> JackError {
+        JackError::at(self.file_name, span, message)
+    }
+
+    fn variable(&self, name : &Identifier) -> Result<(Segment, u16), JackError> {
+        match self.symbols.lookup(&name.name) {
+            Some(symbol) => Ok((symbol.kind.segment(), symbol.index)),
+            None => Err(self.error(name.span, format!("undeclared variable '{}'", name.name))),
+        }
+    }
+
+    fn compile_class(&mut self, class : &Class) -> Result<(), JackError> {
+        for variable in &class.variables {
+            let kind = if variable.kind == ClassVarKind::Static { SymbolKind::Static } else { SymbolKind::Field };
+            for name in &variable.names {
+                self.symbols.define(&name.name, &variable.var_type, kind);
+            }
+        }
+        for subroutine in &class.subroutines {
+            self.methods.insert(subroutine.name.name.clone(), subroutine.kind == SubroutineKind::Method);
+        }
+        for subroutine in &class.subroutines {
+            self.compile_subroutine(subroutine)?;
+        }
+        Ok(())
+    }
+
+    fn compile_subroutine(&mut self, subroutine : &SubroutineDec) -> Result<(), JackError> {
+        self.symbols.start_subroutine();
+        self.if_count = 0;
+        self.while_count = 0;
+        if subroutine.kind == SubroutineKind::Method {
+            self.symbols.define("this", &Type::Class(self.class_name.clone()), SymbolKind::Argument);
+        }
+        for parameter in &subroutine.parameters {
+            self.symbols.define(&parameter.name.name, &parameter.var_type, SymbolKind::Argument);
+        }
+        for local in &subroutine.locals {
+            for name in &local.names {
+                self.symbols.define(&name.name, &local.var_type, SymbolKind::Local);
+            }
+        }
+        let name = format!("{}.{}", self.class_name, subroutine.name.name);
+        self.emit(VmCommand::Function(name, self.symbols.var_count(SymbolKind::Local)));
+        match subroutine.kind {
+            SubroutineKind::Constructor => {
+                self.push(Segment::Constant, self.symbols.var_count(SymbolKind::Field));
+                self.call("Memory.alloc", 1);
+                self.pop(Segment::Pointer, 0);
+            },
+            SubroutineKind::Method => {
+                self.push(Segment::Argument, 0);
+                self.pop(Segment::Pointer, 0);
+            },
+            SubroutineKind::Function => (),
+        }
+        self.compile_statements(&subroutine.statements)
+    }
+
+    fn compile_statements(&mut self, statements : &[Statement]) -> Result<(), JackError> {
+        for statement in statements {
+            self.compile_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn compile_statement(&mut self, statement : &Statement) -> Result<(), JackError> {
+        match &statement.kind {
+            StatementKind::Let(target, None, value) => {
+                let (segment, index) = self.variable(target)?;
+                self.compile_expression(value)?;
+                self.pop(segment, index);
+            },
+            StatementKind::Let(target, Some(element), value) => {
+                //The value is computed before `that` is set, since it may index arrays itself
+                let (segment, index) = self.variable(target)?;
+                self.push(segment, index);
+                self.compile_expression(element)?;
+                self.arithmetic(ArithmeticCommand::Add);
+                self.compile_expression(value)?;
+                self.pop(Segment::Temp, 0);
+                self.pop(Segment::Pointer, 1);
+                self.push(Segment::Temp, 0);
+                self.pop(Segment::That, 0);
+            },
+            StatementKind::If(condition, then_branch, else_branch) => {
+                let else_label = format!("IF_ELSE{}", self.if_count);
+                let end_label = format!("IF_END{}", self.if_count);
+                self.if_count += 1;
+                self.compile_expression(condition)?;
+                self.arithmetic(ArithmeticCommand::Not);
+                self.emit(VmCommand::IfGoto(else_label.clone()));
+                self.compile_statements(then_branch)?;
+                match else_branch {
+                    Some(else_branch) => {
+                        self.emit(VmCommand::Goto(end_label.clone()));
+                        self.emit(VmCommand::Label(else_label));
+                        self.compile_statements(else_branch)?;
+                        self.emit(VmCommand::Label(end_label));
+                    },
+                    None => self.emit(VmCommand::Label(else_label)),
+                }
+            },
+            StatementKind::While(condition, body) => {
+                let loop_label = format!("WHILE_EXP{}", self.while_count);
+                let end_label = format!("WHILE_END{}", self.while_count);
+                self.while_count += 1;
+                self.emit(VmCommand::Label(loop_label.clone()));
+                self.compile_expression(condition)?;
+                self.arithmetic(ArithmeticCommand::Not);
+                self.emit(VmCommand::IfGoto(end_label.clone()));
+                self.compile_statements(body)?;
+                self.emit(VmCommand::Goto(loop_label));
+                self.emit(VmCommand::Label(end_label));
+            },
+            StatementKind::Do(call) => {
+                self.compile_call(call)?;
+                self.pop(Segment::Temp, 0);
+            },
+            StatementKind::Return(value) => {
+                match value {
+                    Some(value) => self.compile_expression(value)?,
+                    None => self.push(Segment::Constant, 0),
+                }
+                self.emit(VmCommand::Return);
+            },
+        }
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expression : &Expression) -> Result<(), JackError> {
+        self.compile_term(&expression.first)?;
+        for (operator, term) in &expression.rest {
+            self.compile_term(term)?;
+            match operator {
+                BinaryOperator::Add => self.arithmetic(ArithmeticCommand::Add),
+                BinaryOperator::Subtract => self.arithmetic(ArithmeticCommand::Sub),
+                BinaryOperator::Multiply => self.call("Math.multiply", 2),
+                BinaryOperator::Divide => self.call("Math.divide", 2),
+                BinaryOperator::And => self.arithmetic(ArithmeticCommand::And),
+                BinaryOperator::Or => self.arithmetic(ArithmeticCommand::Or),
+                BinaryOperator::Less => self.arithmetic(ArithmeticCommand::Lt),
+                BinaryOperator::Greater => self.arithmetic(ArithmeticCommand::Gt),
+                BinaryOperator::Equal => self.arithmetic(ArithmeticCommand::Eq),
+            }
+        }
+        Ok(())
+    }
+
+    fn compile_term(&mut self, term : &Term) -> Result<(), JackError> {
+        match &term.kind {
+            TermKind::IntegerConstant(value) => self.push(Segment::Constant, *value),
+            TermKind::StringConstant(text) => {
+                self.push(Segment::Constant, text.chars().count() as u16);
+                self.call("String.new", 1);
+                for c in text.chars() {
+                    self.push(Segment::Constant, c as u16);
+                    self.call("String.appendChar", 2);
+                }
+            },
+            TermKind::KeywordConstant(KeywordConstant::True) => {
+                self.push(Segment::Constant, 0);
+                self.arithmetic(ArithmeticCommand::Not);
+            },
+            TermKind::KeywordConstant(KeywordConstant::False) | TermKind::KeywordConstant(KeywordConstant::Null) => {
+                self.push(Segment::Constant, 0);
+            },
+            TermKind::KeywordConstant(KeywordConstant::This) => self.push(Segment::Pointer, 0),
+            TermKind::Variable(name) => {
+                let (segment, index) = self.variable(name)?;
+                self.push(segment, index);
+            },
+            TermKind::ArrayElement(name, element) => {
+                let (segment, index) = self.variable(name)?;
+                self.push(segment, index);
+                self.compile_expression(element)?;
+                self.arithmetic(ArithmeticCommand::Add);
+                self.pop(Segment::Pointer, 1);
+                self.push(Segment::That, 0);
+            },
+            TermKind::Call(call) => self.compile_call(call)?,
+            TermKind::Parenthesized(expression) => self.compile_expression(expression)?,
+            TermKind::Unary(operator, operand) => {
+                self.compile_term(operand)?;
+                self.arithmetic(if *operator == UnaryOperator::Negate { ArithmeticCommand::Neg } else { ArithmeticCommand::Not });
+            },
+        }
+        Ok(())
+    }
+
+    //f() calls a subroutine of this class, on `this` unless it is a function or constructor;
+    //v.f() calls a method of v's class on v, and C.f() calls a function of class C
+    fn compile_call(&mut self, call : &SubroutineCall) -> Result<(), JackError> {
+        let (class_name, has_receiver) = match &call.receiver {
+            None => {
+                let is_method = self.methods.get(&call.name.name).copied().unwrap_or(true);
+                if is_method {
+                    self.push(Segment::Pointer, 0);
+                }
+                (self.class_name.clone(), is_method)
+            },
+            Some(receiver) => match self.symbols.lookup(&receiver.name).cloned() {
+                Some(symbol) => {
+                    let class_name = match &symbol.var_type {
+                        Type::Class(class_name) => class_name.clone(),
+                        _ => return Err(self.error(receiver.span, format!("'{}' is not an object, so it has no methods", receiver.name))),
+                    };
+                    self.push(symbol.kind.segment(), symbol.index);
+                    (class_name, true)
+                },
+                None => (receiver.name.clone(), false),
+            },
+        };
+        for argument in &call.arguments {
+            self.compile_expression(argument)?;
+        }
+        let arguments = call.arguments.len() + if has_receiver { 1 } else { 0 };
+        self.call(&format!("{}.{}", class_name, call.name.name), arguments);
+        Ok(())
+    }
+}
+
+pub fn compile_class(file_name : &str, class : &Class) -> Result<Vec<VmCommand>, JackError> {
+    let mut generator = CodeGenerator {
+        file_name,
+        class_name : class.name.name.clone(),
+        methods : HashMap::new(),
+        symbols : SymbolTable::new(),
+        if_count : 0,
+        while_count : 0,
+        commands : Vec::new(),
+    };
+    generator.compile_class(class)?;
+    Ok(generator.commands)
+}
+
+//Compiles a class into the VmFile the translator and the VM emulator would read from its .vm file
+pub fn compile_vm_file(file_name : &str, class : &Class) -> Result<VmFile, JackError> {
+    let commands = compile_class(file_name, class)?;
+    Ok(VmFile {
+        name : class.name.name.clone(),
+        commands : commands.into_iter().enumerate().map(|(index, command)| VmLine { line : index + 1, command }).collect(),
+    })
+}
+
+pub fn vm_text(file : &VmFile) -> String {
+    let mut text = String::new();
+    for line in &file.commands {
+        text.push_str(&line.command.to_string());
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jack::jack_file_paths;
+    use crate::jack::JackError;
+    use crate::jack::codegen::{compile_vm_file, vm_text};
+    use crate::jack::os::screen_text;
+    use crate::jack::parser;
+    use crate::pipeline::load_vm_program;
+    use crate::vm::VmFile;
+    use crate::vm::emulator::{VmMachine, link};
+    use crate::vm::headless::{run_headless, Outcome};
+    use crate::vm::parser::parse_source;
+    use crate::test_support::PROJECTS;
+
+    fn compile_source(file_name : &str, source : &str) -> Result<VmFile, JackError> {
+        compile_vm_file(file_name, &parser::parse_source(file_name, source)?)
+    }
+
+    fn compile(source : &str) -> String {
+        match compile_source("Test.jack", source) {
+            Ok(file) => vm_text(&file),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn objects_and_methods_test() {
+        let vm = compile("class Point {\n  field int x, y;\n  static int count;\n\
+                          constructor Point new(int ax) { let x = ax; let count = count + 1; return this; }\n\
+                          method int getX() { return x; }\n\
+                          method int twice() { return getX() + p(); }\n\
+                          function int p() { var Point q; let q = Point.new(2); return q.getX(); }\n}");
+        assert!(vm.starts_with("function Point.new 0\npush constant 2\ncall Memory.alloc 1\npop pointer 0\n\
+                                push argument 0\npop this 0\npush static 0\npush constant 1\nadd\npop static 0\n\
+                                push pointer 0\nreturn\n"));
+        assert!(vm.contains("function Point.getX 0\npush argument 0\npop pointer 0\npush this 0\nreturn\n"));
+        assert!(vm.contains("push pointer 0\ncall Point.getX 1\ncall Point.p 0\nadd\nreturn\n"));
+        assert!(vm.contains("function Point.p 1\npush constant 2\ncall Point.new 1\npop local 0\npush local 0\ncall Point.getX 1\n"));
+    }
+
+    #[test]
+    fn arrays_strings_and_flow_test() {
+        let vm = compile("class Main {\n  function void main() {\n    var Array a; var String s;\n\
+                          let a[1] = a[0];\n    let s = \"Hi\";\n\
+                          while (~(a[0] = 0)) { if (a[0] > 2) { let a[0] = a[0] - 1; } else { let a[0] = 0; } }\n\
+                          return;\n  }\n}");
+        assert!(vm.contains("push local 0\npush constant 1\nadd\npush local 0\npush constant 0\nadd\npop pointer 1\npush that 0\n\
+                             pop temp 0\npop pointer 1\npush temp 0\npop that 0\n"));
+        assert!(vm.contains("push constant 2\ncall String.new 1\npush constant 72\ncall String.appendChar 2\n\
+                             push constant 105\ncall String.appendChar 2\npop local 1\n"));
+        assert!(vm.contains("label WHILE_EXP0\n"));
+        assert!(vm.contains("eq\nnot\nnot\nif-goto WHILE_END0\n"));
+        assert!(vm.contains("gt\nnot\nif-goto IF_ELSE0\n"));
+        assert!(vm.contains("goto IF_END0\nlabel IF_ELSE0\n"));
+        assert!(vm.ends_with("goto WHILE_EXP0\nlabel WHILE_END0\npush constant 0\nreturn\n"));
+        assert_eq!(compile_source("Test.jack", "class A { function void f() { let y = 1; return; } }").err().unwrap().to_string(),
+                   "Test.jack:1:35: undeclared variable 'y'");
+    }
+
+    #[test]
+    fn project_11_programs_compile_test() {
+        for program in ["Seven", "ConvertToBin", "Square", "Average", "Pong", "ComplexArrays"].iter() {
+            let directory = std::path::Path::new(PROJECTS).join("11").join(program);
+            for path in jack_file_paths(&directory).unwrap() {
+                let file_name = path.display().to_string();
+                let file = match compile_source(&file_name, &std::fs::read_to_string(&path).unwrap()) {
+                    Ok(file) => file,
+                    Err(e) => panic!("{}", e),
+                };
+                assert_eq!(parse_source(&file_name, &vm_text(&file)).ok().unwrap(), file.commands);
+            }
+        }
+    }
+
+    //Seven on the Jack OS the toolchain ships, as n2t run does it: it prints 1 + (2 * 3)
+    #[test]
+    fn seven_end_to_end_test() {
+        let (files, os_files) = load_vm_program(&std::path::Path::new(PROJECTS).join("11/Seven")).ok().unwrap();
+        let program = files.iter().chain(os_files.iter()).cloned().collect::<Vec<VmFile>>();
+        let mut machine = VmMachine::new(link(&program).ok().unwrap());
+        machine.bootstrap().ok().unwrap();
+        assert_eq!(run_headless(&mut machine, &[], 10_000_000).ok().unwrap(), Outcome::Halted);
+        assert_eq!(screen_text(&machine.ram), vec!["7"]);
+    }
+}
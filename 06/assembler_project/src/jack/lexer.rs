@@ -0,0 +1,305 @@
+use crate::jack::{JackError, Span};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Keyword {
+    Class,
+    Constructor,
+    Function,
+    Method,
+    Field,
+    Static,
+    Var,
+    Int,
+    Char,
+    Boolean,
+    Void,
+    True,
+    False,
+    Null,
+    This,
+    Let,
+    Do,
+    If,
+    Else,
+    While,
+    Return,
+}
+
+const KEYWORDS : [(&str, Keyword); 21] = [
+    ("class", Keyword::Class),
+    ("constructor", Keyword::Constructor),
+    ("function", Keyword::Function),
+    ("method", Keyword::Method),
+    ("field", Keyword::Field),
+    ("static", Keyword::Static),
+    ("var", Keyword::Var),
+    ("int", Keyword::Int),
+    ("char", Keyword::Char),
+    ("boolean", Keyword::Boolean),
+    ("void", Keyword::Void),
+    ("true", Keyword::True),
+    ("false", Keyword::False),
+    ("null", Keyword::Null),
+    ("this", Keyword::This),
+    ("let", Keyword::Let),
+    ("do", Keyword::Do),
+    ("if", Keyword::If),
+    ("else", Keyword::Else),
+    ("while", Keyword::While),
+    ("return", Keyword::Return),
+];
+
+const SYMBOLS : &str = "{}()[].,;+-*/&|<>=~";
+
+impl Keyword {
+    pub fn from_name(name : &str) -> Option<Keyword> {
+        KEYWORDS.iter().find(|(n, _)| *n == name).map(|(_, k)| *k)
+    }
+
+    pub fn name(self) -> &'static str {
+        KEYWORDS.iter().find(|(_, k)| *k == self).map(|(n, _)| *n).unwrap()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Symbol(char),
+    IntegerConstant(u16),
+    StringConstant(String),
+    Identifier(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Token {
+    pub kind : TokenKind,
+    pub span : Span,
+}
+
+impl TokenKind {
+    //The element name the nand2tetris XML formats use for this kind of token
+    pub fn xml_tag(&self) -> &'static str {
+        match self {
+            TokenKind::Keyword(_) => "keyword",
+            TokenKind::Symbol(_) => "symbol",
+            TokenKind::IntegerConstant(_) => "integerConstant",
+            TokenKind::StringConstant(_) => "stringConstant",
+            TokenKind::Identifier(_) => "identifier",
+        }
+    }
+
+    pub fn text(&self) -> String {
+        match self {
+            TokenKind::Keyword(keyword) => keyword.name().to_string(),
+            TokenKind::Symbol(symbol) => symbol.to_string(),
+            TokenKind::IntegerConstant(value) => value.to_string(),
+            TokenKind::StringConstant(text) => text.clone(),
+            TokenKind::Identifier(name) => name.clone(),
+        }
+    }
+
+    //One XML element, e.g. "<symbol> &lt; </symbol>"
+    pub fn xml_element(&self) -> String {
+        format!("<{0}> {1} </{0}>", self.xml_tag(), escape_xml(&self.text()))
+    }
+}
+
+pub fn escape_xml(text : &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+struct Lexer<'a> {
+    file_name : &'a str,
+    source : &'a str,
+    offset : usize,
+    line : usize,
+    column : usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.source[self.offset..].chars().next()
+    }
+
+    fn peek_second(&self) -> Option<char> {
+        self.source[self.offset..].chars().nth(1)
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        }
+        else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn here(&self) -> Span {
+        Span { start : self.offset, end : self.offset, line : self.line, column : self.column }
+    }
+
+    fn error(&self, span : Span, message : &str) -> JackError {
+        JackError::at(self.file_name, span, message.to_string())
+    }
+
+    //Skips white space and all three comment forms
+    fn skip_trivia(&mut self) -> Result<(), JackError> {
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.advance();
+                },
+                (Some('/'), Some('/')) => {
+                    while let Some(c) = self.advance() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                },
+                (Some('/'), Some('*')) => {
+                    let start = self.here();
+                    self.advance();
+                    self.advance();
+                    loop {
+                        match self.advance() {
+                            Some('*') if self.peek() == Some('/') => {
+                                self.advance();
+                                break;
+                            },
+                            Some(_) => (),
+                            None => return Err(self.error(start, "unterminated comment")),
+                        }
+                    }
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn take_while(&mut self, predicate : impl Fn(char) -> bool) -> &'a str {
+        let start = self.offset;
+        while let Some(c) = self.peek() {
+            if !predicate(c) {
+                break;
+            }
+            self.advance();
+        }
+        &self.source[start..self.offset]
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, JackError> {
+        self.skip_trivia()?;
+        let mut span = self.here();
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let kind = if c.is_ascii_digit() {
+            let digits = self.take_while(|c| c.is_ascii_digit());
+            match digits.parse::<u16>() {
+                Ok(value) if value <= 32767 => TokenKind::IntegerConstant(value),
+                _ => return Err(self.error(span, &format!("integer constant {} is larger than 32767", digits))),
+            }
+        }
+        else if c.is_ascii_alphabetic() || c == '_' {
+            let word = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            match Keyword::from_name(word) {
+                Some(keyword) => TokenKind::Keyword(keyword),
+                None => TokenKind::Identifier(word.to_string()),
+            }
+        }
+        else if c == '"' {
+            self.advance();
+            let text = self.take_while(|c| c != '"' && c != '\n');
+            if self.advance() != Some('"') {
+                return Err(self.error(span, "unterminated string constant"));
+            }
+            TokenKind::StringConstant(text.to_string())
+        }
+        else if SYMBOLS.contains(c) {
+            self.advance();
+            TokenKind::Symbol(c)
+        }
+        else {
+            return Err(self.error(span, &format!("unexpected character '{}'", c)));
+        };
+        span.end = self.offset;
+        Ok(Some(Token { kind, span }))
+    }
+}
+
+pub fn tokenize(file_name : &str, source : &str) -> Result<Vec<Token>, JackError> {
+    let mut lexer = Lexer { file_name, source, offset : 0, line : 1, column : 1 };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+//The project 10 token file format: one element per line inside <tokens>
+pub fn tokens_xml(tokens : &[Token]) -> String {
+    let mut xml = String::from("<tokens>\n");
+    for token in tokens {
+        xml.push_str(&token.kind.xml_element());
+        xml.push('\n');
+    }
+    xml.push_str("</tokens>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::jack::lexer::{tokenize, tokens_xml, Keyword, TokenKind};
+    use crate::test_support::PROJECTS;
+
+    #[test]
+    fn token_kinds_and_spans_test() {
+        let source = "/** doc */ class Main {\n  // comment\n  let x = \"a < b\"; /* inline */ do f(32767);\n}";
+        let tokens = tokenize("Main.jack", source).ok().unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::Keyword(Keyword::Class));
+        assert_eq!((tokens[0].span.line, tokens[0].span.column), (1, 12));
+        assert_eq!(tokens[3].kind, TokenKind::Keyword(Keyword::Let));
+        assert_eq!((tokens[3].span.line, tokens[3].span.column), (3, 3));
+        assert_eq!(tokens[6].kind, TokenKind::StringConstant(String::from("a < b")));
+        assert_eq!(&source[tokens[6].span.start..tokens[6].span.end], "\"a < b\"");
+        assert_eq!(tokens[6].kind.xml_element(), "<stringConstant> a &lt; b </stringConstant>");
+        assert_eq!(tokens[11].kind, TokenKind::IntegerConstant(32767));
+    }
+
+    #[test]
+    fn lexical_errors_test() {
+        let error = tokenize("Main.jack", "class Main {\n  let x = 32768;").err().unwrap();
+        assert_eq!(error.to_string(), "Main.jack:2:11: integer constant 32768 is larger than 32767");
+        assert_eq!(tokenize("A.jack", "let s = \"abc\n\";").err().unwrap().to_string(), "A.jack:1:9: unterminated string constant");
+        assert_eq!(tokenize("A.jack", "/* never closed").err().unwrap().to_string(), "A.jack:1:1: unterminated comment");
+        assert_eq!(tokenize("A.jack", "x = #;").err().unwrap().to_string(), "A.jack:1:5: unexpected character '#'");
+    }
+
+    #[test]
+    fn project_10_token_files_test() {
+        let mut checked = 0;
+        for directory in ["ArrayTest", "ExpressionLessSquare", "Square"].iter() {
+            let directory = Path::new(PROJECTS).join("10").join(directory);
+            for entry in fs::read_dir(&directory).unwrap().flatten() {
+                let path = entry.path();
+                if path.extension().map(|e| e != "jack").unwrap_or(true) {
+                    continue;
+                }
+                let source = fs::read_to_string(&path).unwrap();
+                let tokens = tokenize(&path.display().to_string(), &source).ok().unwrap();
+                let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                let expected = fs::read_to_string(directory.join(format!("{}T.xml", name))).unwrap();
+                assert_eq!(tokens_xml(&tokens), expected, "{}", path.display());
+                checked += 1;
+            }
+        }
+        assert_eq!(checked, 7);
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use crate::jack::JackError;
+use crate::jack::ast::{Class, StatementKind, TermKind};
+use crate::jack::parser::parse_source;
+
+//The Jack OS that ships with the toolchain. Programs are linked against it unless they define
+//a class of the same name themselves or the build names another OS directory.
+const SOURCES : [(&str, &str); 8] = [
+    ("Array", include_str!("os/Array.jack")),
+    ("Keyboard", include_str!("os/Keyboard.jack")),
+    ("Math", include_str!("os/Math.jack")),
+    ("Memory", include_str!("os/Memory.jack")),
+    ("Output", include_str!("os/Output.jack")),
+    ("Screen", include_str!("os/Screen.jack")),
+    ("String", include_str!("os/String.jack")),
+    ("Sys", include_str!("os/Sys.jack")),
+];
+
+pub const SCREEN : usize = 0x4000;
+pub const KEYBOARD : usize = 0x6000;
+const TEXT_ROWS : usize = 23;
+const TEXT_COLUMNS : usize = 64;
+pub const GLYPH_HEIGHT : usize = 11;
+
+//The parsed OS classes with the file names their errors are reported against
+pub fn os_classes() -> Result<Vec<(String, Class)>, JackError> {
+    SOURCES.iter().map(|(name, source)| {
+        let file_name = format!("os/{}.jack", name);
+        let class = parse_source(&file_name, source)?;
+        Ok((file_name, class))
+    }).collect()
+}
+
+//The rows of each character's bitmap, read from the Output.create calls in Output.initMap.
+//Bit i of a row is the i-th pixel from the left; character 0 is the black square printed for
+//characters without a glyph.
+pub fn char_maps() -> HashMap<u16, [u16; GLYPH_HEIGHT]> {
+    let output = parse_source("os/Output.jack", SOURCES[4].1).expect("the bundled Output.jack parses");
+    let init_map = output.subroutines.iter().find(|s| s.name.name == "initMap").expect("the bundled Output.jack has initMap");
+    let mut maps = HashMap::new();
+    for statement in &init_map.statements {
+        if let StatementKind::Do(call) = &statement.kind {
+            let numbers = call.arguments.iter().filter_map(|argument| match argument.first.kind {
+                TermKind::IntegerConstant(value) => Some(value),
+                _ => None,
+            }).collect::<Vec<u16>>();
+            if call.name.name == "create" && numbers.len() == GLYPH_HEIGHT + 1 {
+                let mut rows = [0; GLYPH_HEIGHT];
+                rows.copy_from_slice(&numbers[1..]);
+                maps.insert(numbers[0], rows);
+            }
+        }
+    }
+    maps
+}
+
+//Which character each bitmap shows
+fn font() -> HashMap<[u16; GLYPH_HEIGHT], char> {
+    char_maps().into_iter()
+        .map(|(code, rows)| (rows, if code == 0 { '\u{25a0}' } else { code as u8 as char }))
+        .collect()
+}
+
+//Reads back the text the OS printed: every 8x11 cell of the screen is matched against the font.
+//Cells that hold no glyph read as U+FFFD; trailing blanks are dropped.
+pub fn screen_text(ram : &[u16]) -> Vec<String> {
+    let glyphs = font();
+    let mut lines = (0..TEXT_ROWS).map(|row| {
+        let line = (0..TEXT_COLUMNS).map(|column| {
+            let mut rows = [0; GLYPH_HEIGHT];
+            for (i, bits) in rows.iter_mut().enumerate() {
+                let word = ram[SCREEN + (row * GLYPH_HEIGHT + i) * 32 + column / 2];
+                *bits = if column % 2 == 0 { word & 0xFF } else { word >> 8 };
+            }
+            glyphs.get(&rows).copied().unwrap_or('\u{fffd}')
+        }).collect::<String>();
+        line.trim_end().to_string()
+    }).collect::<Vec<String>>();
+    while lines.last().map(|line| line.is_empty()).unwrap_or(false) {
+        lines.pop();
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jack::checker::{check_class, ClassIndex};
+    use crate::jack::os::{font, os_classes, screen_text, SCREEN};
+    use crate::jack::parser::parse_source;
+
+    #[test]
+    fn built_in_os_is_clean_test() {
+        let main = parse_source("Main.jack", "class Main { function void main() { return; } }").ok().unwrap();
+        let classes = os_classes().ok().unwrap();
+        let mut index = ClassIndex::new();
+        index.add(&main);
+        for (_, class) in &classes {
+            index.add(class);
+        }
+        for (file_name, class) in &classes {
+            let errors = check_class(file_name, class, &index);
+            assert!(errors.is_empty(), "{}", errors.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("\n"));
+        }
+    }
+
+    #[test]
+    fn screen_text_test() {
+        //Every printable character has a glyph of its own
+        assert_eq!(font().len(), 96);
+        let mut ram = vec![0; 0x8000];
+        //'H' in column 0 and 'i' in column 1 of row 1
+        let h = [51, 51, 51, 51, 63, 51, 51, 51, 51, 0, 0];
+        let i = [12, 12, 0, 14, 12, 12, 12, 12, 30, 0, 0];
+        for row in 0..11 {
+            ram[SCREEN + (11 + row) * 32] = h[row] | (i[row] << 8);
+        }
+        assert_eq!(screen_text(&ram), vec![String::new(), String::from("Hi")]);
+        //A stray pixel pattern in the bottom right cell
+        ram[SCREEN + 22 * 11 * 32 + 31] = 0xFF00;
+        let lines = screen_text(&ram);
+        assert_eq!(lines.len(), 23);
+        assert_eq!(lines[22], format!("{}\u{fffd}", " ".repeat(63)));
+    }
+}
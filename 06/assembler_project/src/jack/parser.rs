@@ -0,0 +1,454 @@
+use crate::jack::{JackError, Span};
+use crate::jack::ast::*;
+use crate::jack::lexer::{Keyword, Token, TokenKind, tokenize};
+
+//A recursive descent parser following the Jack grammar; one token of lookahead everywhere
+//except terms, which need two to tell a variable from an array element or a call.
+struct Parser<'a> {
+    file_name : &'a str,
+    tokens : &'a [Token],
+    position : usize,
+}
+
+fn describe(kind : &TokenKind) -> String {
+    match kind {
+        TokenKind::Keyword(keyword) => format!("'{}'", keyword.name()),
+        TokenKind::Symbol(symbol) => format!("'{}'", symbol),
+        TokenKind::IntegerConstant(value) => format!("integer constant {}", value),
+        TokenKind::StringConstant(text) => format!("string constant \"{}\"", text),
+        TokenKind::Identifier(name) => format!("identifier '{}'", name),
+    }
+}
+
+//"'a'", "'a' or 'b'", "'a', 'b' or 'c'"
+fn expected_list(expected : &[&str]) -> String {
+    match expected.split_last() {
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+const TYPES : [&str; 4] = ["'int'", "'char'", "'boolean'", "identifier"];
+const STATEMENTS : [&str; 5] = ["'let'", "'if'", "'while'", "'do'", "'return'"];
+const TERMS : [&str; 10] = ["integer constant", "string constant", "'true'", "'false'", "'null'", "'this'", "identifier", "'('", "'-'", "'~'"];
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a TokenKind> {
+        self.tokens.get(self.position).map(|t| &t.kind)
+    }
+
+    fn peek_second(&self) -> Option<&'a TokenKind> {
+        self.tokens.get(self.position + 1).map(|t| &t.kind)
+    }
+
+    fn is_symbol(&self, symbol : char) -> bool {
+        self.peek() == Some(&TokenKind::Symbol(symbol))
+    }
+
+    fn is_keyword(&self, keyword : Keyword) -> bool {
+        self.peek() == Some(&TokenKind::Keyword(keyword))
+    }
+
+    //The span of the next token, or an empty span just past the last one at the end of the file
+    fn current_span(&self) -> Span {
+        match self.tokens.get(self.position) {
+            Some(token) => token.span,
+            None => {
+                let mut span = self.tokens.last().map(|t| t.span).unwrap_or_default();
+                span.column += span.end - span.start;
+                span.start = span.end;
+                span
+            },
+        }
+    }
+
+    //From the start of a node to the end of the last token consumed
+    fn span_from(&self, start : Span) -> Span {
+        let end = self.tokens[..self.position].last().map(|t| t.span.end).unwrap_or(start.end);
+        Span { end, ..start }
+    }
+
+    fn error(&self, expected : &[&str]) -> JackError {
+        let found = match self.peek() {
+            Some(kind) => describe(kind),
+            None => String::from("end of file"),
+        };
+        JackError::at(self.file_name, self.current_span(), format!("expected {}, found {}", expected_list(expected), found))
+    }
+
+    fn advance(&mut self) -> &'a Token {
+        let token = &self.tokens[self.position];
+        self.position += 1;
+        token
+    }
+
+    fn expect_symbol(&mut self, symbol : char) -> Result<(), JackError> {
+        if !self.is_symbol(symbol) {
+            return Err(self.error(&[&format!("'{}'", symbol)]));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_keyword(&mut self, keyword : Keyword) -> Result<(), JackError> {
+        if !self.is_keyword(keyword) {
+            return Err(self.error(&[&format!("'{}'", keyword.name())]));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_identifier(&mut self) -> Result<Identifier, JackError> {
+        match self.peek() {
+            Some(TokenKind::Identifier(name)) => {
+                let span = self.advance().span;
+                Ok(Identifier { name : name.clone(), span })
+            },
+            _ => Err(self.error(&["identifier"])),
+        }
+    }
+
+    fn parse_type(&mut self, extra : Option<&str>) -> Result<Option<Type>, JackError> {
+        let var_type = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Int)) => Some(Type::Int),
+            Some(TokenKind::Keyword(Keyword::Char)) => Some(Type::Char),
+            Some(TokenKind::Keyword(Keyword::Boolean)) => Some(Type::Boolean),
+            Some(TokenKind::Keyword(Keyword::Void)) if extra.is_some() => None,
+            Some(TokenKind::Identifier(name)) => Some(Type::Class(name.clone())),
+            _ => {
+                let mut expected = TYPES.to_vec();
+                expected.extend(extra);
+                return Err(self.error(&expected));
+            },
+        };
+        self.advance();
+        Ok(var_type)
+    }
+
+    fn parse_var_type(&mut self) -> Result<Type, JackError> {
+        Ok(self.parse_type(None)?.unwrap())
+    }
+
+    //name (',' name)* ';'
+    fn parse_names(&mut self) -> Result<Vec<Identifier>, JackError> {
+        let mut names = vec![self.expect_identifier()?];
+        while self.is_symbol(',') {
+            self.advance();
+            names.push(self.expect_identifier()?);
+        }
+        self.expect_symbol(';')?;
+        Ok(names)
+    }
+
+    fn parse_class(&mut self) -> Result<Class, JackError> {
+        let start = self.current_span();
+        self.expect_keyword(Keyword::Class)?;
+        let name = self.expect_identifier()?;
+        self.expect_symbol('{')?;
+        let mut variables = Vec::new();
+        while let Some(TokenKind::Keyword(Keyword::Static)) | Some(TokenKind::Keyword(Keyword::Field)) = self.peek() {
+            variables.push(self.parse_class_var_dec()?);
+        }
+        let mut subroutines = Vec::new();
+        loop {
+            match self.peek() {
+                Some(TokenKind::Keyword(Keyword::Constructor)) | Some(TokenKind::Keyword(Keyword::Function)) |
+                Some(TokenKind::Keyword(Keyword::Method)) => subroutines.push(self.parse_subroutine()?),
+                Some(TokenKind::Symbol('}')) => break,
+                _ => {
+                    let mut expected = vec!["'constructor'", "'function'", "'method'", "'}'"];
+                    if subroutines.is_empty() {
+                        expected.splice(0..0, vec!["'static'", "'field'"]);
+                    }
+                    return Err(self.error(&expected));
+                },
+            }
+        }
+        self.expect_symbol('}')?;
+        if self.peek().is_some() {
+            return Err(self.error(&["end of file"]));
+        }
+        Ok(Class { name, variables, subroutines, span : self.span_from(start) })
+    }
+
+    fn parse_class_var_dec(&mut self) -> Result<ClassVarDec, JackError> {
+        let start = self.current_span();
+        let kind = if self.is_keyword(Keyword::Static) { ClassVarKind::Static } else { ClassVarKind::Field };
+        self.advance();
+        let var_type = self.parse_var_type()?;
+        let names = self.parse_names()?;
+        Ok(ClassVarDec { kind, var_type, names, span : self.span_from(start) })
+    }
+
+    fn parse_subroutine(&mut self) -> Result<SubroutineDec, JackError> {
+        let start = self.current_span();
+        let kind = match self.advance().kind {
+            TokenKind::Keyword(Keyword::Constructor) => SubroutineKind::Constructor,
+            TokenKind::Keyword(Keyword::Function) => SubroutineKind::Function,
+            _ => SubroutineKind::Method,
+        };
+        let return_type = self.parse_type(Some("'void'"))?;
+        let name = self.expect_identifier()?;
+        self.expect_symbol('(')?;
+        let mut parameters = Vec::new();
+        if !self.is_symbol(')') {
+            loop {
+                let var_type = self.parse_var_type()?;
+                parameters.push(Parameter { var_type, name : self.expect_identifier()? });
+                if !self.is_symbol(',') {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.expect_symbol(')')?;
+        self.expect_symbol('{')?;
+        let mut locals = Vec::new();
+        while self.is_keyword(Keyword::Var) {
+            let local_start = self.current_span();
+            self.advance();
+            let var_type = self.parse_var_type()?;
+            let names = self.parse_names()?;
+            locals.push(VarDec { var_type, names, span : self.span_from(local_start) });
+        }
+        let statements = self.parse_statements()?;
+        self.expect_symbol('}')?;
+        Ok(SubroutineDec { kind, return_type, name, parameters, locals, statements, span : self.span_from(start) })
+    }
+
+    fn parse_statements(&mut self) -> Result<Vec<Statement>, JackError> {
+        let mut statements = Vec::new();
+        while !self.is_symbol('}') {
+            statements.push(self.parse_statement()?);
+        }
+        Ok(statements)
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Statement>, JackError> {
+        self.expect_symbol('{')?;
+        let statements = self.parse_statements()?;
+        self.expect_symbol('}')?;
+        Ok(statements)
+    }
+
+    fn parse_condition(&mut self) -> Result<Expression, JackError> {
+        self.expect_symbol('(')?;
+        let condition = self.parse_expression()?;
+        self.expect_symbol(')')?;
+        Ok(condition)
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, JackError> {
+        let start = self.current_span();
+        let kind = match self.peek() {
+            Some(TokenKind::Keyword(Keyword::Let)) => {
+                self.advance();
+                let target = self.expect_identifier()?;
+                let index = if self.is_symbol('[') {
+                    self.advance();
+                    let index = self.parse_expression()?;
+                    self.expect_symbol(']')?;
+                    Some(Box::new(index))
+                }
+                else {
+                    None
+                };
+                if !self.is_symbol('=') {
+                    return Err(self.error(if index.is_none() { &["'['", "'='"] } else { &["'='"] }));
+                }
+                self.advance();
+                let value = self.parse_expression()?;
+                self.expect_symbol(';')?;
+                StatementKind::Let(target, index, value)
+            },
+            Some(TokenKind::Keyword(Keyword::If)) => {
+                self.advance();
+                let condition = self.parse_condition()?;
+                let then_branch = self.parse_block()?;
+                let else_branch = if self.is_keyword(Keyword::Else) {
+                    self.advance();
+                    Some(self.parse_block()?)
+                }
+                else {
+                    None
+                };
+                StatementKind::If(condition, then_branch, else_branch)
+            },
+            Some(TokenKind::Keyword(Keyword::While)) => {
+                self.advance();
+                let condition = self.parse_condition()?;
+                StatementKind::While(condition, self.parse_block()?)
+            },
+            Some(TokenKind::Keyword(Keyword::Do)) => {
+                self.advance();
+                let name = self.expect_identifier()?;
+                let call = self.parse_call(name)?;
+                self.expect_symbol(';')?;
+                StatementKind::Do(call)
+            },
+            Some(TokenKind::Keyword(Keyword::Return)) => {
+                self.advance();
+                let value = if self.is_symbol(';') { None } else { Some(self.parse_expression()?) };
+                self.expect_symbol(';')?;
+                StatementKind::Return(value)
+            },
+            _ => {
+                let mut expected = STATEMENTS.to_vec();
+                expected.push("'}'");
+                return Err(self.error(&expected));
+            },
+        };
+        Ok(Statement { kind, span : self.span_from(start) })
+    }
+
+    //The rest of a call whose first identifier has been read
+    fn parse_call(&mut self, first : Identifier) -> Result<SubroutineCall, JackError> {
+        let start = first.span;
+        let (receiver, name) = if self.is_symbol('.') {
+            self.advance();
+            (Some(first), self.expect_identifier()?)
+        }
+        else if self.is_symbol('(') {
+            (None, first)
+        }
+        else {
+            return Err(self.error(&["'.'", "'('"]));
+        };
+        self.expect_symbol('(')?;
+        let mut arguments = Vec::new();
+        if !self.is_symbol(')') {
+            arguments.push(self.parse_expression()?);
+            while self.is_symbol(',') {
+                self.advance();
+                arguments.push(self.parse_expression()?);
+            }
+        }
+        self.expect_symbol(')')?;
+        Ok(SubroutineCall { receiver, name, arguments, span : self.span_from(start) })
+    }
+
+    fn parse_expression(&mut self) -> Result<Expression, JackError> {
+        let start = self.current_span();
+        let first = self.parse_term()?;
+        let mut rest = Vec::new();
+        while let Some(TokenKind::Symbol(symbol)) = self.peek() {
+            let operator = match BinaryOperator::from_symbol(*symbol) {
+                Some(operator) => operator,
+                None => break,
+            };
+            self.advance();
+            rest.push((operator, self.parse_term()?));
+        }
+        Ok(Expression { first, rest, span : self.span_from(start) })
+    }
+
+    fn parse_term(&mut self) -> Result<Term, JackError> {
+        let start = self.current_span();
+        let kind = match self.peek() {
+            Some(TokenKind::IntegerConstant(value)) => {
+                self.advance();
+                TermKind::IntegerConstant(*value)
+            },
+            Some(TokenKind::StringConstant(text)) => {
+                self.advance();
+                TermKind::StringConstant(text.clone())
+            },
+            Some(TokenKind::Keyword(keyword)) => {
+                let constant = match keyword {
+                    Keyword::True => KeywordConstant::True,
+                    Keyword::False => KeywordConstant::False,
+                    Keyword::Null => KeywordConstant::Null,
+                    Keyword::This => KeywordConstant::This,
+                    _ => return Err(self.error(&TERMS)),
+                };
+                self.advance();
+                TermKind::KeywordConstant(constant)
+            },
+            Some(TokenKind::Symbol('(')) => {
+                self.advance();
+                let expression = self.parse_expression()?;
+                self.expect_symbol(')')?;
+                TermKind::Parenthesized(Box::new(expression))
+            },
+            Some(TokenKind::Symbol(symbol)) if *symbol == '-' || *symbol == '~' => {
+                let operator = if *symbol == '-' { UnaryOperator::Negate } else { UnaryOperator::Not };
+                self.advance();
+                TermKind::Unary(operator, Box::new(self.parse_term()?))
+            },
+            Some(TokenKind::Identifier(_)) => {
+                match self.peek_second() {
+                    Some(TokenKind::Symbol('[')) => {
+                        let name = self.expect_identifier()?;
+                        self.advance();
+                        let index = self.parse_expression()?;
+                        self.expect_symbol(']')?;
+                        TermKind::ArrayElement(name, Box::new(index))
+                    },
+                    Some(TokenKind::Symbol('(')) | Some(TokenKind::Symbol('.')) => {
+                        let name = self.expect_identifier()?;
+                        TermKind::Call(self.parse_call(name)?)
+                    },
+                    _ => TermKind::Variable(self.expect_identifier()?),
+                }
+            },
+            _ => return Err(self.error(&TERMS)),
+        };
+        Ok(Term { kind, span : self.span_from(start) })
+    }
+}
+
+pub fn parse_tokens(file_name : &str, tokens : &[Token]) -> Result<Class, JackError> {
+    let mut parser = Parser { file_name, tokens, position : 0 };
+    parser.parse_class()
+}
+
+pub fn parse_source(file_name : &str, source : &str) -> Result<Class, JackError> {
+    parse_tokens(file_name, &tokenize(file_name, source)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jack::ast::*;
+    use crate::jack::parser::parse_source;
+
+    #[test]
+    fn expression_tree_test() {
+        let class = parse_source("Main.jack", "class Main {\n  function int f(int x) {\n    return -x + a[2] * Math.max(1, x);\n  }\n}").ok().unwrap();
+        let subroutine = &class.subroutines[0];
+        assert_eq!(subroutine.kind, SubroutineKind::Function);
+        assert_eq!(subroutine.return_type, Some(Type::Int));
+        assert_eq!(subroutine.parameters[0].name.name, "x");
+        let value = match &subroutine.statements[0].kind {
+            StatementKind::Return(Some(value)) => value,
+            other => panic!("{:?}", other),
+        };
+        assert!(matches!(&value.first.kind, TermKind::Unary(UnaryOperator::Negate, _)));
+        assert_eq!(value.rest.iter().map(|(op, _)| *op).collect::<Vec<BinaryOperator>>(),
+                   vec![BinaryOperator::Add, BinaryOperator::Multiply]);
+        match &value.rest[1].1.kind {
+            TermKind::Call(call) => {
+                assert_eq!(call.receiver.as_ref().map(|r| r.name.as_str()), Some("Math"));
+                assert_eq!(call.arguments.len(), 2);
+                assert_eq!((call.span.line, call.span.column), (3, 24));
+            },
+            other => panic!("{:?}", other),
+        }
+        assert_eq!((subroutine.statements[0].span.line, subroutine.statements[0].span.column), (3, 5));
+    }
+
+    #[test]
+    fn syntax_errors_test() {
+        let error = |source : &str| parse_source("Main.jack", source).err().unwrap().to_string();
+        assert_eq!(error("class Main {\n  function void f() {\n    let x = 1\n  }\n}"),
+                   "Main.jack:4:3: expected ';', found '}'");
+        assert_eq!(error("class Main {\n  var int x;\n}"),
+                   "Main.jack:2:3: expected 'static', 'field', 'constructor', 'function', 'method' or '}', found 'var'");
+        assert_eq!(error("class Main {\n  function void f() {\n    let x = ;\n  }\n}"),
+                   "Main.jack:3:13: expected integer constant, string constant, 'true', 'false', 'null', 'this', identifier, '(', '-' or '~', found ';'");
+        assert_eq!(error("class Main {\n  function 3 f() {}\n}"),
+                   "Main.jack:2:12: expected 'int', 'char', 'boolean', identifier or 'void', found integer constant 3");
+        assert_eq!(error("class Main {"),
+                   "Main.jack:1:13: expected 'static', 'field', 'constructor', 'function', 'method' or '}', found end of file");
+    }
+}
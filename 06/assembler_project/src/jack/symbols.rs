@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use crate::jack::ast::Type;
+use crate::vm::parser::Segment;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SymbolKind {
+    Static,
+    Field,
+    Argument,
+    Local,
+}
+
+impl SymbolKind {
+    //Fields live in the object `this` points at
+    pub fn segment(self) -> Segment {
+        match self {
+            SymbolKind::Static => Segment::Static,
+            SymbolKind::Field => Segment::This,
+            SymbolKind::Argument => Segment::Argument,
+            SymbolKind::Local => Segment::Local,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Symbol {
+    pub var_type : Type,
+    pub kind : SymbolKind,
+    pub index : u16,
+}
+
+//The class scope holds statics and fields, the subroutine scope arguments and locals;
+//a subroutine name shadows a class one. Indexes count up per kind in declaration order.
+pub struct SymbolTable {
+    class_scope : HashMap<String, Symbol>,
+    subroutine_scope : HashMap<String, Symbol>,
+    counts : [u16; 4],
+}
+
+fn kind_number(kind : SymbolKind) -> usize {
+    match kind {
+        SymbolKind::Static => 0,
+        SymbolKind::Field => 1,
+        SymbolKind::Argument => 2,
+        SymbolKind::Local => 3,
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable { class_scope : HashMap::new(), subroutine_scope : HashMap::new(), counts : [0; 4] }
+    }
+
+    pub fn start_subroutine(&mut self) {
+        self.subroutine_scope.clear();
+        self.counts[kind_number(SymbolKind::Argument)] = 0;
+        self.counts[kind_number(SymbolKind::Local)] = 0;
+    }
+
+    pub fn define(&mut self, name : &str, var_type : &Type, kind : SymbolKind) {
+        let index = self.counts[kind_number(kind)];
+        self.counts[kind_number(kind)] += 1;
+        let symbol = Symbol { var_type : var_type.clone(), kind, index };
+        match kind {
+            SymbolKind::Static | SymbolKind::Field => self.class_scope.insert(name.to_string(), symbol),
+            SymbolKind::Argument | SymbolKind::Local => self.subroutine_scope.insert(name.to_string(), symbol),
+        };
+    }
+
+    pub fn var_count(&self, kind : SymbolKind) -> u16 {
+        self.counts[kind_number(kind)]
+    }
+
+    pub fn lookup(&self, name : &str) -> Option<&Symbol> {
+        self.subroutine_scope.get(name).or_else(|| self.class_scope.get(name))
+    }
+}
+
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::jack::ast::Type;
+    use crate::jack::symbols::{SymbolTable, SymbolKind};
+
+    #[test]
+    fn scopes_and_indexes_test() {
+        let mut table = SymbolTable::new();
+        table.define("x", &Type::Int, SymbolKind::Field);
+        table.define("y", &Type::Int, SymbolKind::Field);
+        table.define("count", &Type::Int, SymbolKind::Static);
+        table.start_subroutine();
+        table.define("this", &Type::Class(String::from("Point")), SymbolKind::Argument);
+        table.define("x", &Type::Boolean, SymbolKind::Argument);
+        table.define("i", &Type::Int, SymbolKind::Local);
+        assert_eq!(table.lookup("x").map(|s| (s.kind, s.index)), Some((SymbolKind::Argument, 1)));
+        assert_eq!(table.lookup("y").map(|s| (s.kind, s.index)), Some((SymbolKind::Field, 1)));
+        assert_eq!(table.var_count(SymbolKind::Field), 2);
+        assert_eq!(table.var_count(SymbolKind::Local), 1);
+        table.start_subroutine();
+        assert_eq!(table.lookup("x").map(|s| s.kind), Some(SymbolKind::Field));
+        assert_eq!(table.lookup("i"), None);
+        assert_eq!(table.var_count(SymbolKind::Argument), 0);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::jack::ast::*;
+use crate::jack::lexer::escape_xml;
+
+//Writes the project 10 parse tree format: non-terminals as nested elements indented by two
+//spaces, terminals as "<kind> text </kind>" lines. The AST holds no punctuation, so the
+//writer puts back every keyword and symbol the grammar requires.
+struct XmlWriter {
+    xml : String,
+    depth : usize,
+}
+
+impl XmlWriter {
+    fn line(&mut self, text : &str) {
+        for _ in 0..self.depth {
+            self.xml.push_str("  ");
+        }
+        self.xml.push_str(text);
+        self.xml.push('\n');
+    }
+
+    fn open(&mut self, tag : &str) {
+        self.line(&format!("<{}>", tag));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag : &str) {
+        self.depth -= 1;
+        self.line(&format!("</{}>", tag));
+    }
+
+    fn terminal(&mut self, kind : &str, text : &str) {
+        self.line(&format!("<{0}> {1} </{0}>", kind, escape_xml(text)));
+    }
+
+    fn keyword(&mut self, text : &str) {
+        self.terminal("keyword", text);
+    }
+
+    fn symbol(&mut self, symbol : char) {
+        self.terminal("symbol", &symbol.to_string());
+    }
+
+    fn identifier(&mut self, identifier : &Identifier) {
+        self.terminal("identifier", &identifier.name);
+    }
+
+    fn var_type(&mut self, var_type : &Type) {
+        match var_type {
+            Type::Int => self.keyword("int"),
+            Type::Char => self.keyword("char"),
+            Type::Boolean => self.keyword("boolean"),
+            Type::Class(name) => self.terminal("identifier", name),
+        }
+    }
+
+    fn names(&mut self, names : &[Identifier]) {
+        for (index, name) in names.iter().enumerate() {
+            if index > 0 {
+                self.symbol(',');
+            }
+            self.identifier(name);
+        }
+        self.symbol(';');
+    }
+
+    fn class(&mut self, class : &Class) {
+        self.open("class");
+        self.keyword("class");
+        self.identifier(&class.name);
+        self.symbol('{');
+        for variable in &class.variables {
+            self.open("classVarDec");
+            self.keyword(if variable.kind == ClassVarKind::Static { "static" } else { "field" });
+            self.var_type(&variable.var_type);
+            self.names(&variable.names);
+            self.close("classVarDec");
+        }
+        for subroutine in &class.subroutines {
+            self.subroutine(subroutine);
+        }
+        self.symbol('}');
+        self.close("class");
+    }
+
+    fn subroutine(&mut self, subroutine : &SubroutineDec) {
+        self.open("subroutineDec");
+        self.keyword(match subroutine.kind {
+            SubroutineKind::Constructor => "constructor",
+            SubroutineKind::Function => "function",
+            SubroutineKind::Method => "method",
+        });
+        match &subroutine.return_type {
+            Some(var_type) => self.var_type(var_type),
+            None => self.keyword("void"),
+        }
+        self.identifier(&subroutine.name);
+        self.symbol('(');
+        self.open("parameterList");
+        for (index, parameter) in subroutine.parameters.iter().enumerate() {
+            if index > 0 {
+                self.symbol(',');
+            }
+            self.var_type(&parameter.var_type);
+            self.identifier(&parameter.name);
+        }
+        self.close("parameterList");
+        self.symbol(')');
+        self.open("subroutineBody");
+        self.symbol('{');
+        for local in &subroutine.locals {
+            self.open("varDec");
+            self.keyword("var");
+            self.var_type(&local.var_type);
+            self.names(&local.names);
+            self.close("varDec");
+        }
+        self.statements(&subroutine.statements);
+        self.symbol('}');
+        self.close("subroutineBody");
+        self.close("subroutineDec");
+    }
+
+    fn statements(&mut self, statements : &[Statement]) {
+        self.open("statements");
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.close("statements");
+    }
+
+    fn block(&mut self, statements : &[Statement]) {
+        self.symbol('{');
+        self.statements(statements);
+        self.symbol('}');
+    }
+
+    fn condition(&mut self, condition : &Expression) {
+        self.symbol('(');
+        self.expression(condition);
+        self.symbol(')');
+    }
+
+    fn statement(&mut self, statement : &Statement) {
+        match &statement.kind {
+            StatementKind::Let(target, index, value) => {
+                self.open("letStatement");
+                self.keyword("let");
+                self.identifier(target);
+                if let Some(index) = index {
+                    self.symbol('[');
+                    self.expression(index);
+                    self.symbol(']');
+                }
+                self.symbol('=');
+                self.expression(value);
+                self.symbol(';');
+                self.close("letStatement");
+            },
+            StatementKind::If(condition, then_branch, else_branch) => {
+                self.open("ifStatement");
+                self.keyword("if");
+                self.condition(condition);
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.keyword("else");
+                    self.block(else_branch);
+                }
+                self.close("ifStatement");
+            },
+            StatementKind::While(condition, body) => {
+                self.open("whileStatement");
+                self.keyword("while");
+                self.condition(condition);
+                self.block(body);
+                self.close("whileStatement");
+            },
+            StatementKind::Do(call) => {
+                self.open("doStatement");
+                self.keyword("do");
+                self.call(call);
+                self.symbol(';');
+                self.close("doStatement");
+            },
+            StatementKind::Return(value) => {
+                self.open("returnStatement");
+                self.keyword("return");
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                self.symbol(';');
+                self.close("returnStatement");
+            },
+        }
+    }
+
+    fn call(&mut self, call : &SubroutineCall) {
+        if let Some(receiver) = &call.receiver {
+            self.identifier(receiver);
+            self.symbol('.');
+        }
+        self.identifier(&call.name);
+        self.symbol('(');
+        self.open("expressionList");
+        for (index, argument) in call.arguments.iter().enumerate() {
+            if index > 0 {
+                self.symbol(',');
+            }
+            self.expression(argument);
+        }
+        self.close("expressionList");
+        self.symbol(')');
+    }
+
+    fn expression(&mut self, expression : &Expression) {
+        self.open("expression");
+        self.term(&expression.first);
+        for (operator, term) in &expression.rest {
+            self.symbol(operator.symbol());
+            self.term(term);
+        }
+        self.close("expression");
+    }
+
+    fn term(&mut self, term : &Term) {
+        self.open("term");
+        match &term.kind {
+            TermKind::IntegerConstant(value) => self.terminal("integerConstant", &value.to_string()),
+            TermKind::StringConstant(text) => self.terminal("stringConstant", text),
+            TermKind::KeywordConstant(constant) => self.keyword(match constant {
+                KeywordConstant::True => "true",
+                KeywordConstant::False => "false",
+                KeywordConstant::Null => "null",
+                KeywordConstant::This => "this",
+            }),
+            TermKind::Variable(name) => self.identifier(name),
+            TermKind::ArrayElement(name, index) => {
+                self.identifier(name);
+                self.symbol('[');
+                self.expression(index);
+                self.symbol(']');
+            },
+            TermKind::Call(call) => self.call(call),
+            TermKind::Parenthesized(expression) => {
+                self.symbol('(');
+                self.expression(expression);
+                self.symbol(')');
+            },
+            TermKind::Unary(operator, operand) => {
+                self.symbol(operator.symbol());
+                self.term(operand);
+            },
+        }
+        self.close("term");
+    }
+}
+
+pub fn class_xml(class : &Class) -> String {
+    let mut writer = XmlWriter { xml : String::new(), depth : 0 };
+    writer.class(class);
+    writer.xml
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::jack::parser::parse_source;
+    use crate::jack::xml::class_xml;
+    use crate::test_support::PROJECTS;
+
+    #[test]
+    fn project_10_parse_tree_files_test() {
+        let mut checked = 0;
+        for directory in ["ArrayTest", "ExpressionLessSquare", "Square"].iter() {
+            let directory = Path::new(PROJECTS).join("10").join(directory);
+            for entry in fs::read_dir(&directory).unwrap().flatten() {
+                let path = entry.path();
+                if path.extension().map(|e| e != "jack").unwrap_or(true) {
+                    continue;
+                }
+                let source = fs::read_to_string(&path).unwrap();
+                let class = match parse_source(&path.display().to_string(), &source) {
+                    Ok(class) => class,
+                    Err(e) => panic!("{}", e),
+                };
+                let expected = fs::read_to_string(path.with_extension("xml")).unwrap();
+                assert_eq!(class_xml(&class), expected, "{}", path.display());
+                checked += 1;
+            }
+        }
+        assert_eq!(checked, 7);
+    }
+}
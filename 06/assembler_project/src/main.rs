@@ -1,79 +1,663 @@
-
 mod parser;
 mod symbol_table;
 mod code_generator;
+mod assembler;
+mod disassembler;
+mod cpu_emulator;
+mod test_script;
+mod vm;
+mod jack;
+mod pipeline;
+mod hdl;
+mod vcd;
+mod cli;
+#[cfg(test)]
+mod test_support;
 
+use std::convert::TryFrom;
 use std::env;
-use std::fs::File;
-use std::io::{Read, Write};
-use symbol_table::SymbolTable;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use cli::{Args, CliError, Console, Verbosity, derived_output, input_error, read_input, write_output};
+use cpu_emulator::Machine;
+use cpu_emulator::debugger::{Debugger, DEFAULT_HISTORY_CAPACITY, run_repl};
+use cpu_emulator::simulator::CpuSimulator;
+use cpu_emulator::snapshot::load_snapshot;
+use vm::simulator::VmSimulator;
+use hdl::script::HardwareSimulator;
+use hdl::simulator::HdlSimulator;
+
+fn load_program(file_name : &str) -> Result<Vec<u16>, CliError> {
+    let program = if file_name.ends_with(".asm") {
+        assembler::assemble_file(file_name).map_err(|e| e.to_string())
+    }
+    else {
+        assembler::read_machine_lines_from_file(file_name).map_err(|e| e.to_string())
+    };
+    program.map_err(|e| CliError::error(format!("Error loading program {:?}: {}", file_name, e)))
+}
 
-fn read_lines_from_file(file_name : &String) -> Vec<String>{
-    let mut file = File::open(file_name).unwrap_or_else(|_e| {
-        panic!("Error opening file {:?}\n", file_name);
-    });
+fn load_machine(file_name : &str) -> Result<Machine, CliError> {
+    if file_name.ends_with(".snap") {
+        return load_snapshot(file_name).map_err(|e| CliError::error(format!("Error loading snapshot {:?}: {}", file_name, e)));
+    }
+    let mut machine = Machine::new();
+    machine.load_program(&load_program(file_name)?);
+    Ok(machine)
+}
 
-    let mut raw_string = String::new();
+//Assembles Foo.asm into Foo.hack, or what -o names
+fn assemble(mut args : Args, console : Console) -> Result<(), CliError> {
+    let output = args.value(&["-o"])?;
+    let input = args.positional(1, 1)?.remove(0);
+    let source = read_input(&input)?;
+    let program = assembler::assemble_source(&input, &source).map_err(input_error)?;
+    console.detail(&format!("{}: {} instructions", input, program.len()));
+    write_output(&output.unwrap_or_else(|| derived_output(&input, "hack")), &assembler::machine_text(&program), console)
+}
 
-    file.read_to_string(&mut raw_string).unwrap_or_else(|e| {
-        panic!("Error reading file {:?}\n", e);
-    });
+//Checks the instruction literals of test scripts against the mnemonics in their comments, printing
+//every literal that differs; a script that cannot be read is an error
+fn verify_scripts(args : Args, console : Console) -> Result<(), CliError> {
+    let mut failures = 0;
+    for script in args.positional(1, usize::MAX)? {
+        match assembler::verify_tst(&script) {
+            Ok(checked) => console.info(&format!("{}: {} instructions match their comments", script, checked)),
+            Err(assembler::VerifyError::Mismatches(mismatches)) => {
+                for mismatch in &mismatches {
+                    println!("{}", mismatch);
+                }
+                failures += mismatches.len();
+            },
+            Err(e) => return Err(input_error(e)),
+        }
+    }
+    if failures > 0 {
+        return Err(CliError::failed(format!("{} {} from their comments", failures, if failures == 1 { "instruction differs" } else { "instructions differ" })));
+    }
+    Ok(())
+}
 
-    raw_string = raw_string.replace("\r\n\r\n", "\r\n");
+fn asm_command(mut args : Args, console : Console) -> Result<(), CliError> {
+    match args.action(&["verify"]).as_deref() {
+        Some("verify") => verify_scripts(args, console),
+        _ => assemble(args, console),
+    }
+}
 
-    return raw_string.lines().map(|s| s.trim().to_string()).collect::<Vec<String>>();
+//Turns Foo.hack back into assembly in Foo.dis.asm, or what -o names
+fn disassemble(mut args : Args, console : Console) -> Result<(), CliError> {
+    let output = args.value(&["-o"])?;
+    let input = args.positional(1, 1)?.remove(0);
+    let program = assembler::parse_machine_lines(&input, &read_input(&input)?).map_err(input_error)?;
+    let mut text = disassembler::disassemble_lines(&program).join("\r\n");
+    text.push_str("\r\n");
+    write_output(&output.unwrap_or_else(|| derived_output(&input, "dis.asm")), &text, console)
 }
 
-fn remove_comments_from_lines(lines : &Vec<String>) -> Vec<String> {
-    let mut ret_vec = Vec::new();
-    for line in lines {
-        if line.starts_with("//") {
-            continue;
+//Runs a .hack, .asm or .snap program on the CPU emulator, in the debugger or for --cycles instructions,
+//and anything else on the VM emulator
+fn run_program(mut args : Args, console : Console) -> Result<(), CliError> {
+    let cycles = args.number(&["--cycles"])?;
+    let debug = args.flag(&["--debug"]);
+    let vcd = args.value(&["--vcd"])?;
+    let from = args.number(&["--from"])?;
+    let to = args.number(&["--to"])?;
+    let steps = args.number(&["--steps"])?;
+    let keys = args.value(&["--keys"])?;
+    let jack_os = args.value(&["--jack-os"])?;
+    let program = args.positional(1, 1)?.remove(0);
+    if ![".hack", ".asm", ".snap"].iter().any(|extension| program.ends_with(extension)) {
+        if cycles.is_some() || debug || vcd.is_some() || from.is_some() || to.is_some() {
+            return Err(CliError::usage(String::from("--cycles, --debug, --vcd, --from and --to are for CPU programs")));
         }
-        if let Some(comment_index) = line.find("//") {
-            let string : String = String::from(&line[..comment_index]).trim().to_string();
-            ret_vec.push(string);
+        return run_headless(&program, steps.unwrap_or(10_000_000), keys, jack_os, console);
+    }
+    if steps.is_some() || keys.is_some() || jack_os.is_some() {
+        return Err(CliError::usage(String::from("--steps, --keys and --jack-os are for VM programs")));
+    }
+    let mut machine = load_machine(&program)?;
+    if debug {
+        let mut debugger = Debugger::new(machine, DEFAULT_HISTORY_CAPACITY);
+        run_repl(&mut debugger, &mut io::stdin().lock(), &mut io::stdout());
+        return Ok(());
+    }
+    let cycles = cycles.unwrap_or(1000);
+    if let Some(vcd) = vcd {
+        let from = from.unwrap_or(0);
+        write_output(&vcd, &cpu_emulator::trace::trace(&machine, from, to.unwrap_or(cycles).max(from)), console)?;
+    }
+    while machine.cycles < cycles {
+        machine.step();
+    }
+    println!("A={} D={} PC={}", machine.a as i16, machine.d as i16, machine.pc);
+    for (address, value) in machine.ram[..16].iter().enumerate() {
+        println!("RAM[{}]={}", address, *value as i16);
+    }
+    Ok(())
+}
+
+//Runs a Jack program (or .vm files) in the VM emulator with the built-in OS and no display: keys are
+//typed as the program asks for them, and the text on the screen is printed at the end. The OS runs
+//natively except for the classes named by jack_os (a comma separated list, or all).
+fn run_headless(path : &str, max_steps : u64, keys : Option<String>, jack_os : Option<String>, console : Console) -> Result<(), CliError> {
+    let keys = vm::headless::parse_keys(keys.as_deref().unwrap_or("")).map_err(|e| CliError::usage(format!("Bad key script: {}", e)))?;
+    let jack_os = jack_os.map(|s| s.split(',').map(String::from).collect::<Vec<String>>()).unwrap_or_default();
+    let (mut files, os_files) = pipeline::load_vm_program(Path::new(path)).map_err(|e| CliError::error(format!("Error loading {}", e)))?;
+    let native = os_files.iter().map(|file| file.name.clone())
+        .filter(|name| !jack_os.iter().any(|class| class == "all" || class == name))
+        .collect::<Vec<String>>();
+    files.extend(os_files);
+    let program = vm::emulator::link(&files).map_err(|e| CliError::error(format!("Error linking {}", e)))?;
+    let mut machine = vm::emulator::VmMachine::new(program);
+    machine.use_native_os(&native);
+    machine.bootstrap().map_err(|e| CliError::error(format!("Error starting {}", e)))?;
+    let outcome = vm::headless::run_headless(&mut machine, &keys, max_steps);
+    let outcome = outcome.map_err(|e| CliError::failed(format!("Error running {}: {}", machine.current_function(), e)))?;
+    for line in jack::os::screen_text(&machine.ram) {
+        println!("{}", line);
+    }
+    match outcome {
+        vm::headless::Outcome::Halted => console.info(&format!("Halted after {} commands", machine.steps)),
+        vm::headless::Outcome::StepLimit => console.info(&format!("Stopped after {} commands", machine.steps)),
+        vm::headless::Outcome::Error(code) => return Err(CliError::failed(format!("Sys.error {} after {} commands", code, machine.steps))),
+    }
+    Ok(())
+}
+
+fn run_test_script(script_file_name : &str, simulator : &mut dyn test_script::Simulator) -> Result<test_script::ScriptResult, CliError> {
+    test_script::run_script_file(script_file_name, simulator).map_err(|e| CliError::error(format!("Error running script {}", e)))
+}
+
+fn report_test_result(result : &test_script::ScriptResult, console : Console) -> Result<(), CliError> {
+    result.write_output_file().map_err(|e| CliError::error(format!("Error writing output file {}", e)))?;
+    for echo in &result.echoes {
+        println!("{}", echo);
+    }
+    if !result.passed() {
+        return Err(CliError::failed(format!("Comparison failure at line {}", result.comparison_failure.unwrap())));
+    }
+    if result.compared {
+        console.info("End of script - Comparison ended successfully");
+    }
+    else {
+        console.info("End of script");
+    }
+    Ok(())
+}
+
+//Picks the simulator from what the script loads: a .hdl file means the hardware simulator, a .vm file
+//or a directory the VM emulator, anything else the CPU emulator. --coverage and --lcov report which
+//instructions of a CPU program ran; --vcd dumps the chip of a hardware script, and is written before
+//the result is reported so that a failing chip can be looked at. --keys holds keys down for a hardware script.
+fn run_test(mut args : Args, console : Console) -> Result<(), CliError> {
+    let coverage = args.flag(&["--coverage"]);
+    let lcov = args.value(&["--lcov"])?;
+    let vcd = args.value(&["--vcd"])?;
+    let from = args.number(&["--from"])?;
+    let to = args.number(&["--to"])?;
+    let keys = args.value(&["--keys"])?;
+    let script = args.positional(1, 1)?.remove(0);
+    let source = fs::read_to_string(&script).map_err(|e| CliError::error(format!("Error reading {:?}: {}", script, e)))?;
+    let program = test_script::loaded_program(&source).map_err(|(line, message)| {
+        CliError::error(format!("Error running script {}:{}: {}", script, line, message))
+    })?;
+    let hardware = matches!(&program, Some(Some(name)) if name.ends_with(".hdl"));
+    let vm = matches!(&program, Some(Some(name)) if name.ends_with(".vm")) || program == Some(None);
+    if !hardware && (vcd.is_some() || from.is_some() || to.is_some() || keys.is_some()) {
+        return Err(CliError::usage(String::from("--vcd, --from, --to and --keys are for hardware test scripts")));
+    }
+    if (hardware || vm) && (coverage || lcov.is_some()) {
+        return Err(CliError::usage(String::from("--coverage and --lcov are for CPU test scripts")));
+    }
+    if hardware {
+        let mut simulator = if vcd.is_some() { HardwareSimulator::with_trace(from.unwrap_or(0), to) } else { HardwareSimulator::new() };
+        simulator.hold_keys(&vm::headless::parse_keys(keys.as_deref().unwrap_or("")).map_err(|e| CliError::usage(format!("Bad key script: {}", e)))?);
+        let result = run_test_script(&script, &mut simulator)?;
+        if let (Some(vcd), Some(trace)) = (vcd, simulator.trace) {
+            write_output(&vcd, &trace.finish(), console)?;
+        }
+        return report_test_result(&result, console);
+    }
+    if vm {
+        return report_test_result(&run_test_script(&script, &mut VmSimulator::new())?, console);
+    }
+    if !coverage && lcov.is_none() {
+        return report_test_result(&run_test_script(&script, &mut CpuSimulator::new())?, console);
+    }
+    let mut simulator = CpuSimulator::with_coverage();
+    let outcome = report_test_result(&run_test_script(&script, &mut simulator)?, console);
+    let (text, lcov_text) = simulator.coverage_reports().ok_or_else(|| CliError::error(format!("Script {:?} did not load a program", script)))?;
+    if coverage {
+        print!("{}", text);
+    }
+    if let Some(lcov) = lcov {
+        write_output(&lcov, &lcov_text, console)?;
+    }
+    outcome
+}
+
+fn vm_command(mut args : Args, console : Console) -> Result<(), CliError> {
+    match args.action(&["translate", "size", "check"]).as_deref() {
+        Some("size") => report_vm_sizes(args),
+        Some("check") => check_vm_translation(args, console),
+        _ => translate_vm(args, console),
+    }
+}
+
+//Translates a .vm file or a directory of them to .asm, or straight to .hack when the output name says so.
+//Bootstrap code is emitted when the program defines Sys.init unless --bootstrap/--no-bootstrap says otherwise,
+//and --optimize selects the smaller code with shared call/return/compare routines. A program read from
+//stdin is the one file Main.vm.
+fn translate_vm(mut args : Args, console : Console) -> Result<(), CliError> {
+    let output = args.value(&["-o"])?;
+    let optimize = args.flag(&["--optimize"]);
+    let no_bootstrap = args.flag(&["--no-bootstrap"]);
+    let bootstrap = args.flag(&["--bootstrap"]);
+    let input = args.positional(1, 1)?.remove(0);
+    let files = if input == "-" {
+        let commands = vm::parser::parse_source("Main.vm", &read_input(&input)?).map_err(|e| CliError::error(format!("Error translating {}", e)))?;
+        vec![vm::VmFile { name : String::from("Main"), commands }]
+    }
+    else {
+        vm::read_vm_files(Path::new(&input)).map_err(|e| CliError::error(format!("Error translating {}", e)))?
+    };
+    let bootstrap = !no_bootstrap && (bootstrap || vm::defines_sys_init(&files));
+    console.detail(&format!("{} file{}, {}", files.len(), if files.len() == 1 { "" } else { "s" },
+                            if bootstrap { "with bootstrap code" } else { "without bootstrap code" }));
+    let output = output.unwrap_or_else(|| derived_output(&input, "asm"));
+    let text = if output.ends_with(".hack") {
+        assembler::machine_text(&vm::translate_to_machine_lines(&files, bootstrap, optimize).map_err(input_error)?)
+    }
+    else {
+        let mut text = vm::translate(&files, bootstrap, optimize).join("\r\n");
+        text.push_str("\r\n");
+        text
+    };
+    write_output(&output, &text, console)
+}
+
+//Prints how many ROM words the naive and the optimizing translation of each input take
+fn report_vm_sizes(args : Args) -> Result<(), CliError> {
+    for path in args.positional(1, usize::MAX)? {
+        let files = vm::read_vm_files(Path::new(&path)).map_err(|e| CliError::error(format!("Error translating {}", e)))?;
+        let (naive, optimized) = vm::translation_sizes(&files, vm::defines_sys_init(&files)).map_err(input_error)?;
+        let saved = 100.0 * (naive as f64 - optimized as f64) / naive.max(1) as f64;
+        println!("{}: {} words naive, {} words optimized ({:.1}% smaller)", path, naive, optimized, saved);
+    }
+    Ok(())
+}
+
+//Runs a .vm file or directory in the VM emulator and its translation on the CPU emulator side by side
+fn check_vm_translation(mut args : Args, console : Console) -> Result<(), CliError> {
+    let max_steps = args.number(&["--steps"])?.unwrap_or(100_000);
+    let input = args.positional(1, 1)?.remove(0);
+    let files = vm::read_vm_files(Path::new(&input)).map_err(|e| CliError::error(format!("Error translating {}", e)))?;
+    let bootstrap = vm::defines_sys_init(&files);
+    let initial_ram = if bootstrap { Vec::new() } else { vec![(0, 256)] };
+    match vm::cross_check::cross_check(&files, bootstrap, &initial_ram, max_steps) {
+        Ok(steps) => {
+            console.info(&format!("Translation matches the VM emulator for {} commands", steps));
+            Ok(())
+        },
+        Err(divergence) => Err(CliError::failed(format!("Translation diverges {}", divergence))),
+    }
+}
+
+fn jack_command(mut args : Args, console : Console) -> Result<(), CliError> {
+    match args.action(&["compile", "check", "xml", "build"]).as_deref() {
+        Some("check") => check_jack(args, console),
+        Some("xml") => write_jack_xml(args, console),
+        Some("build") => build_program(args, console),
+        _ => compile_jack(args, console),
+    }
+}
+
+//Writes FooT.xml (tokens, with --tokens) or Foo.xml (parse tree) next to each Foo.jack the path names,
+//like the project 10 reference analyzer
+fn write_jack_xml(mut args : Args, console : Console) -> Result<(), CliError> {
+    let tokens_only = args.flag(&["--tokens"]);
+    let path = args.positional(1, 1)?.remove(0);
+    let jack_files = jack::jack_file_paths(Path::new(&path)).map_err(|e| CliError::error(format!("Error opening {:?}: {}", path, e)))?;
+    for jack_file in jack_files {
+        let file_name = jack_file.display().to_string();
+        let source = read_input(&file_name)?;
+        let name = jack_file.file_stem().unwrap().to_string_lossy().to_string();
+        let (output_path, xml) = if tokens_only {
+            let tokens = jack::lexer::tokenize(&file_name, &source).map_err(|e| CliError::error(format!("Error tokenizing {}", e)))?;
+            (jack_file.with_file_name(format!("{}T.xml", name)), jack::lexer::tokens_xml(&tokens))
         }
         else {
-            ret_vec.push(line.to_string());
+            let class = jack::parser::parse_source(&file_name, &source).map_err(|e| CliError::error(format!("Error parsing {}", e)))?;
+            (jack_file.with_extension("xml"), jack::xml::class_xml(&class))
+        };
+        write_output(&output_path.display().to_string(), &xml, console)?;
+    }
+    Ok(())
+}
+
+//Checks and compiles each Foo.jack the path names to Foo.vm next to it
+fn compile_jack(args : Args, console : Console) -> Result<(), CliError> {
+    let path = args.positional(1, 1)?.remove(0);
+    let (classes, _) = pipeline::compile(Path::new(&path), None).map_err(|e| CliError::error(format!("Error compiling {}", e)))?;
+    let directory = if Path::new(&path).is_dir() { Path::new(&path) } else { Path::new(&path).parent().unwrap_or_else(|| Path::new(".")) };
+    for class in classes {
+        let output_path = directory.join(format!("{}.vm", class.name));
+        write_output(&output_path.display().to_string(), &jack::codegen::vm_text(&class), console)?;
+    }
+    Ok(())
+}
+
+//Runs the semantic checks on a .jack file or directory; the OS signatures come from --os <dir> or the built-in OS
+fn check_jack(mut args : Args, console : Console) -> Result<(), CliError> {
+    let os_directory = args.value(&["--os"])?.map(PathBuf::from);
+    let path = args.positional(1, 1)?.remove(0);
+    let count = pipeline::check(Path::new(&path), os_directory.as_deref()).map_err(input_error)?;
+    console.info(&format!("{} classes checked, no errors", count));
+    Ok(())
+}
+
+//Builds a directory of .jack files into Dir/Dir.hack (or what -o names), linking the OS from --os <dir>
+//or the built-in OS. --keep leaves the .vm files and the .asm next to it.
+fn build_program(mut args : Args, console : Console) -> Result<(), CliError> {
+    let output = args.value(&["-o"])?;
+    let os_directory = args.value(&["--os"])?.map(PathBuf::from);
+    let keep = args.flag(&["--keep"]);
+    let optimize = args.flag(&["--optimize"]);
+    let path = args.positional(1, 1)?.remove(0);
+    let directory = Path::new(&path);
+    let output_name = output.unwrap_or_else(|| derived_output(&path, "hack"));
+    let output = pipeline::build(directory, &pipeline::BuildOptions { os_directory, optimize }).map_err(|e| {
+        CliError::error(format!("Error building {}", e))
+    })?;
+    if keep {
+        let asm_path = if output_name == "-" { vm::output_path(directory, "asm") } else { Path::new(&output_name).with_extension("asm") };
+        pipeline::write_intermediates(&output, directory, &asm_path).map_err(|e| {
+            CliError::error(format!("Error writing intermediate files {}", e))
+        })?;
+    }
+    console.detail(&format!("{} words", output.machine_lines.len()));
+    write_output(&output_name, &assembler::machine_text(&output.machine_lines), console)
+}
+
+//Where the parts of a chip come from: its own directory, then each --lib directory, then the builtin
+//chips, of which --builtin Name,... takes the named ones even where an .hdl file exists
+struct HdlLibrary {
+    directories : Vec<PathBuf>,
+    builtin : Vec<String>,
+}
+
+impl HdlLibrary {
+    fn load(&self, path : &Path) -> Result<HdlSimulator, CliError> {
+        HdlSimulator::load(path, &self.directories, &self.builtin).map_err(|e| CliError::error(format!("Error loading {}", e)))
+    }
+
+    fn for_chip(&self, path : &Path) -> (hdl::elaborate::Library, String) {
+        hdl::elaborate::Library::for_chip(path, &self.directories, &self.builtin)
+    }
+}
+
+fn hdl_command(mut args : Args, console : Console) -> Result<(), CliError> {
+    let actions = ["check", "lint", "eval", "run", "verify", "stats", "verilog", "dot"];
+    let action = args.action(&actions).ok_or_else(|| CliError::usage(format!("hdl needs one of the actions {}", actions.join(", "))))?;
+    let library = HdlLibrary {
+        directories : args.values("--lib")?.into_iter().map(PathBuf::from).collect(),
+        builtin : args.value(&["--builtin"])?.map(|s| s.split(',').map(String::from).collect()).unwrap_or_default(),
+    };
+    match action.as_str() {
+        "check" => check_hdl(args, console),
+        "lint" => lint_hdl(args, &library, console),
+        "eval" => eval_hdl(args, &library),
+        "run" => run_hdl(args, &library),
+        "verify" => verify_hdl(args, &library),
+        "stats" => stats_hdl(args, &library),
+        "verilog" => export_hdl(args, &library, console),
+        _ => draw_hdl(args, &library, console),
+    }
+}
+
+//The .hdl file a path names, or every one in the directory it names
+fn hdl_paths(path : &str) -> Result<Vec<PathBuf>, CliError> {
+    hdl::hdl_file_paths(Path::new(path)).map_err(|e| CliError::error(format!("Error reading {:?}: {}", path, e)))
+}
+
+//Parses an .hdl file, or every .hdl file in a directory, and reports the first syntax error of each
+fn check_hdl(args : Args, console : Console) -> Result<(), CliError> {
+    let paths = hdl_paths(&args.positional(1, 1)?[0])?;
+    let errors = paths.iter().filter_map(|path| hdl::read_chip(path).err()).collect::<Vec<hdl::HdlError>>();
+    for error in &errors {
+        println!("{}", error);
+    }
+    if !errors.is_empty() {
+        return Err(CliError::error(format!("{} of {} chips have errors", errors.len(), paths.len())));
+    }
+    console.info(&format!("{} chips parsed, no errors", paths.len()));
+    Ok(())
+}
+
+//Prints the lint warnings of a chip or of each chip in a directory. Syntax errors are errors; warnings are not.
+fn lint_hdl(args : Args, library : &HdlLibrary, console : Console) -> Result<(), CliError> {
+    let paths = hdl_paths(&args.positional(1, 1)?[0])?;
+    let mut warnings = 0;
+    for path in &paths {
+        let (mut chips, _) = library.for_chip(path);
+        let found = hdl::read_chip(path).and_then(|chip| hdl::lint::lint(&chip, &path.display().to_string(), &mut chips)).map_err(input_error)?;
+        for warning in &found {
+            println!("{}:{}:{}: warning: {}", warning.file_name, warning.line, warning.column, warning.message);
         }
+        warnings += found.len();
+    }
+    console.info(&format!("{} chips checked, {} warning{}", paths.len(), warnings, if warnings == 1 { "" } else { "s" }));
+    Ok(())
+}
+
+fn parse_assignment(assignment : &str) -> Result<(&str, u16), CliError> {
+    let bad = || CliError::usage(format!("expected name=value, not {:?}", assignment));
+    let equals = assignment.find('=').ok_or_else(bad)?;
+    let value = assignment[equals + 1..].parse::<i32>().map_err(|_e| bad())?;
+    Ok((&assignment[..equals], value as u16))
+}
+
+//Evaluates a chip for one set of inputs given as pin=value and prints its outputs
+fn eval_hdl(args : Args, library : &HdlLibrary) -> Result<(), CliError> {
+    let names = args.positional(1, usize::MAX)?;
+    let mut simulator = library.load(Path::new(&names[0]))?;
+    for assignment in &names[1..] {
+        let (name, value) = parse_assignment(assignment)?;
+        simulator.set(name, value).map_err(CliError::usage)?;
+    }
+    simulator.eval();
+    let netlist = &simulator.netlist;
+    for (name, _) in &netlist.instances[0].pins[netlist.inputs..netlist.inputs + netlist.outputs] {
+        println!("{}={}", name, simulator.get(name).unwrap());
     }
-    ret_vec
+    Ok(())
 }
 
-fn write_lines_to_file(file_name : &str, lines : &Vec<u16>) {
-    let mut file = File::create(file_name).unwrap_or_else(|_e| {
-        panic!("Error creating file {:?}\n", file_name);
-    });
-    for line in lines {
-        for index in (0..16).rev() {
-            if line & (1 << index) > 0 {
-                file.write("1".as_ref()).unwrap();
+//Runs a computer chip: loads a .hack or .asm program into its ROM32K, sets RAM words given as
+//address=value, clocks it --cycles times (1000 by default) and prints the first 16 words of its RAM16K
+fn run_hdl(mut args : Args, library : &HdlLibrary) -> Result<(), CliError> {
+    let cycles = args.number(&["--cycles"])?.unwrap_or(1000);
+    let names = args.positional(1, usize::MAX)?;
+    let mut simulator = library.load(Path::new(&names[0]))?;
+    let missing = |memory : &str| CliError::error(format!("{} has no {}", names[0], memory));
+    for name in &names[1..] {
+        if name.contains('=') {
+            let (address, value) = parse_assignment(name)?;
+            let ram = simulator.memory_mut("RAM16K").ok_or_else(|| missing("RAM16K"))?;
+            let address = address.parse::<usize>().ok().filter(|address| *address < ram.len());
+            ram[address.ok_or_else(|| CliError::usage(format!("Bad address {:?}", name)))?] = value;
+        }
+        else {
+            let program = load_program(name)?;
+            let rom = simulator.memory_mut("ROM32K").ok_or_else(|| missing("ROM32K"))?;
+            if program.len() > rom.len() {
+                return Err(CliError::error(format!("{} does not fit in the ROM32K", name)));
             }
-            else {
-                file.write("0".as_ref()).unwrap();
+            rom[..program.len()].copy_from_slice(&program);
+        }
+    }
+    simulator.eval();
+    for _ in 0..cycles {
+        simulator.tick();
+        simulator.tock();
+    }
+    let ram = simulator.memory("RAM16K").ok_or_else(|| missing("RAM16K"))?;
+    for (address, value) in ram[..16].iter().enumerate() {
+        println!("RAM[{}]={}", address, *value as i16);
+    }
+    Ok(())
+}
+
+//Checks a chip, or each chip in a directory that has one, against the builtin chip's Rust model: on
+//every input for small chips, else on --samples random ones (10000 by default) drawn from --seed.
+//A difference is printed as the test script lines that show it, and makes the command fail.
+fn verify_hdl(mut args : Args, library : &HdlLibrary) -> Result<(), CliError> {
+    let samples = args.number(&["--samples"])?.unwrap_or(10000);
+    let seed = args.number(&["--seed"])?.unwrap_or(2463534242);
+    let seed = u32::try_from(seed).map_err(|_e| CliError::usage(format!("--seed needs a number up to {}, not {}", u32::MAX, seed)))?;
+    let paths = hdl_paths(&args.positional(1, 1)?[0])?;
+    let mut differences = 0;
+    for path in &paths {
+        match hdl::verify::verify(&mut library.load(path)?, samples, seed) {
+            Ok(verification) => {
+                print!("{}", verification);
+                differences += verification.mismatch.is_some() as usize;
+            },
+            //In a directory, chips without a model are passed over
+            Err(_) if paths.len() > 1 => continue,
+            Err(e) => return Err(CliError::error(format!("Error verifying {}", e))),
+        }
+    }
+    if differences > 0 {
+        return Err(CliError::failed(format!("{} {} from the builtin models", differences, if differences == 1 { "chip differs" } else { "chips differ" })));
+    }
+    Ok(())
+}
+
+//Reports the Nand gates and depth of a chip, or a line for each chip in a directory, and fails if any
+//chip is over the budget that --budget file gives it
+fn stats_hdl(mut args : Args, library : &HdlLibrary) -> Result<(), CliError> {
+    let budget_file = args.value(&["--budget"])?;
+    let paths = hdl_paths(&args.positional(1, 1)?[0])?;
+    let budgets = match budget_file {
+        Some(file_name) => hdl::stats::parse_budgets(&file_name, &read_input(&file_name)?).map_err(|e| {
+            CliError::error(format!("Error in budget {}", e))
+        })?,
+        None => Vec::new(),
+    };
+    let mut excesses = 0;
+    for path in &paths {
+        let (mut chips, name) = library.for_chip(path);
+        let stats = hdl::elaborate::elaborate(&mut chips, &name).and_then(|netlist| hdl::stats::analyze(&netlist)).map_err(|e| {
+            CliError::error(format!("Error elaborating {}", e))
+        })?;
+        if paths.len() == 1 {
+            print!("{}", stats);
+        }
+        else {
+            println!("{}: {}, depth {}", stats.chip, stats.contents, stats.depth);
+        }
+        for budget in budgets.iter().filter(|budget| budget.chip == stats.chip) {
+            for excess in budget.excesses(&stats) {
+                println!("Over budget: {}", excess);
+                excesses += 1;
             }
         }
-        file.write_all(b"\r\n").unwrap();
+    }
+    if excesses > 0 {
+        return Err(CliError::failed(format!("{} budget{} exceeded", excesses, if excesses == 1 { "" } else { "s" })));
+    }
+    Ok(())
+}
+
+//Writes a chip and its parts as Verilog to Chip.v or what -o names, with the ROM32K initialized from
+//--rom Prog.hack if given
+fn export_hdl(mut args : Args, library : &HdlLibrary, console : Console) -> Result<(), CliError> {
+    let output = args.value(&["-o"])?;
+    let rom_file = args.value(&["--rom"])?;
+    let path = args.positional(1, 1)?.remove(0);
+    let verilog = hdl::verilog::export_file(Path::new(&path), &library.directories, &library.builtin, rom_file.as_deref()).map_err(|e| {
+        CliError::error(format!("Error exporting {}", e))
+    })?;
+    write_output(&output.unwrap_or_else(|| derived_output(&path, "v")), &verilog, console)
+}
+
+//Writes one level of a chip as a DOT graph to Chip.dot or what -o names, or with --flat the whole chip
+//down to Nand gates, whose count is reported too
+fn draw_hdl(mut args : Args, library : &HdlLibrary, console : Console) -> Result<(), CliError> {
+    let output = args.value(&["-o"])?;
+    let flat = args.flag(&["--flat"]);
+    let path = args.positional(1, 1)?.remove(0);
+    let (mut chips, name) = library.for_chip(Path::new(&path));
+    let graph = if flat {
+        hdl::elaborate::elaborate(&mut chips, &name).map(|netlist| {
+            console.info(&format!("{}: {}", name, hdl::stats::gate_count(&netlist.components)));
+            hdl::dot::flat_graph(&netlist)
+        })
+    }
+    else {
+        hdl::dot::chip_graph(&mut chips, &name)
+    };
+    let graph = graph.map_err(|e| CliError::error(format!("Error drawing {}", e)))?;
+    write_output(&output.unwrap_or_else(|| derived_output(&path, "dot")), &graph, console)
+}
+
+fn run_command(command : &str, args : Args, console : Console) -> Result<(), CliError> {
+    match command {
+        "asm" => asm_command(args, console),
+        "disasm" => disassemble(args, console),
+        "run" => run_program(args, console),
+        "test" => run_test(args, console),
+        "vm" => vm_command(args, console),
+        "jack" => jack_command(args, console),
+        _ => hdl_command(args, console),
     }
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let assembly_file_name = &args[1];
-    let output_file_name = &args[2];
-    let lines = remove_comments_from_lines(&read_lines_from_file(assembly_file_name));
-
-    let mut a = parser::Parser::new(&lines).unwrap_or_else(|e| {
-        panic!("Error creating parser {}\n", e);
-    });
-
-    let mut symbol_table = SymbolTable::new(0);
-    symbol_table.pass_1(&mut a);
-    let lines = symbol_table.pass_2(&lines);
-    let mut a = parser::Parser::new(&lines).ok().unwrap();
-    let machine_lines = code_generator::generate_machine_lines(&mut a);
-
-    write_lines_to_file(output_file_name, &machine_lines);
-    println!("Successfully wrote file {}", output_file_name);
+    let mut args = Args::new(&env::args().skip(1).collect::<Vec<String>>());
+    let help = args.leading_flag(&["-h", "--help"]);
+    let verbosity = if args.leading_flag(&["-q", "--quiet"]) {
+        Verbosity::Quiet
+    }
+    else if args.leading_flag(&["-v", "--verbose"]) {
+        Verbosity::Verbose
+    }
+    else {
+        Verbosity::Normal
+    };
+    args.action(&["--"]);
+    let command = match args.action(&["asm", "disasm", "run", "test", "vm", "jack", "hdl", "help"]) {
+        Some(command) if command == "help" => {
+            let topic = args.positional(0, 1).ok().and_then(|mut topics| topics.pop());
+            print!("{}", topic.as_deref().and_then(cli::command_help).unwrap_or(cli::USAGE_TEXT));
+            return;
+        },
+        //n2t <command> --help, where --help comes right after the command
+        Some(command) if help || args.action(&["-h", "--help"]).is_some() => {
+            print!("{}", cli::command_help(&command).unwrap_or(cli::USAGE_TEXT));
+            return;
+        },
+        Some(command) => command,
+        None if help => {
+            print!("{}", cli::USAGE_TEXT);
+            return;
+        },
+        None => {
+            if let Some(unknown) = args.positional(0, usize::MAX).ok().and_then(|names| names.into_iter().next()) {
+                eprintln!("Unknown command {}\n", unknown);
+            }
+            eprint!("{}", cli::USAGE_TEXT);
+            std::process::exit(cli::USAGE);
+        },
+    };
+    if let Err(e) = run_command(&command, args, Console { verbosity }) {
+        eprintln!("{}", e);
+        if e.status == cli::USAGE {
+            eprintln!("See n2t {} --help", command);
+        }
+        std::process::exit(e.status);
+    }
 }
@@ -15,13 +15,21 @@ pub enum NewParserError {
 
 impl fmt::Display for NewParserError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self)
+        match self {
+            NewParserError::LinesEmpty => write!(f, "no lines to parse"),
+        }
     }
 }
 pub enum AdvanceError {
     NoMoreCommands,
 }
 
+//A line the assembler cannot translate: its index among the lines it was given and what is wrong with it
+pub struct SyntaxError {
+    pub index : usize,
+    pub message : String,
+}
+
 pub struct Parser {
     lines : Vec<String>,
     line_index : usize,
@@ -29,7 +37,7 @@ pub struct Parser {
 
 impl Parser {
     pub fn new(lines : &Vec<String>) -> Result<Parser, NewParserError> {
-        if lines.len() > 0 {
+        if !lines.is_empty() {
             let mut p = Parser {
                 lines : Vec::new(),
                 line_index : 0,
@@ -46,14 +54,36 @@ impl Parser {
         self.line_index < self.lines.len() - 1
     }
 
-    fn get_command_type_from_line(line : &str) -> Result<CommandType, ()> {
-        if line.starts_with('@') {
+    //Symbols are made of letters, digits, '_', '.', '$' and ':' and do not start with a digit
+    fn is_symbol(name : &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with(|c : char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c))
+    }
+
+    fn get_command_type_from_line(line : &str) -> Result<CommandType, String> {
+        if let Some(value) = line.strip_prefix('@') {
+            if value.is_empty() {
+                return Err(String::from("'@' is missing its value"));
+            }
+            if !value.chars().all(|c| c.is_ascii_digit()) && !Parser::is_symbol(value) {
+                return Err(format!("'{}' is not a number or a symbol", value));
+            }
             return Ok(CommandType::A);
         }
         if line.contains('=') || line.contains(';') {
             return Ok(CommandType::C);
         }
-        return Ok(CommandType::L);
+        if line.starts_with('(') {
+            if !line.ends_with(')') {
+                return Err(format!("label '{}' is missing its ')'", line));
+            }
+            if !Parser::is_symbol(&line[1..line.len() - 1]) {
+                return Err(format!("'{}' is not a valid label", line));
+            }
+            return Ok(CommandType::L);
+        }
+        Err(format!("'{}' is not an instruction or a label", line))
     }
 
     fn get_symbol_from_line(command_type : &CommandType, line : &str) -> Result<String, ()> {
@@ -65,7 +95,7 @@ impl Parser {
     }
 
     fn get_dest_from_line(command_type : &CommandType, line : &str) -> Result<String, ()> {
-        return match command_type {
+        match command_type {
             CommandType::A => Err(()),
             CommandType::L => Err(()),
             CommandType::C => {
@@ -80,23 +110,19 @@ impl Parser {
     }
 
     fn get_comp_from_line(command_type : &CommandType, line : &str) -> Result<String, ()> {
-        return match command_type {
+        match command_type {
             CommandType::A => Err(()),
             CommandType::L => Err(()),
             CommandType::C => {
-                if let Some(semicolon_index) = line.find(';') {
-                    return Ok(String::from(&line[..semicolon_index]));
-                }
-                if let Some(equal_index) = line.find('=') {
-                    return Ok(String::from(&line[equal_index + 1..]));
-                }
-                Err(())
+                let start = line.find('=').map(|equal_index| equal_index + 1).unwrap_or(0);
+                let end = line.find(';').unwrap_or(line.len());
+                Ok(String::from(line.get(start..end).unwrap_or_default()))
             },
         }
     }
 
     fn get_jump_from_line(command_type : &CommandType, line : &str) -> Result<String, ()> {
-        return match command_type {
+        match command_type {
             CommandType::A => Err(()),
             CommandType::L => Err(()),
             CommandType::C => {
@@ -116,6 +142,16 @@ impl Parser {
         Ok(())
     }
 
+    pub fn line_index(&self) -> usize {
+        self.line_index
+    }
+
+    //The type of the current line, or why it is not an instruction or a label
+    pub fn check_command(&self) -> Result<CommandType, SyntaxError> {
+        let line = self.lines.get(self.line_index).map(|line| line.as_str()).unwrap_or_default();
+        Parser::get_command_type_from_line(line).map_err(|message| SyntaxError { index : self.line_index, message })
+    }
+
     pub fn command_type(&self) -> Option<CommandType> {
         if let Some(line) = self.lines.get(self.line_index) {
             if let Ok(command_type) = Parser::get_command_type_from_line(line.as_ref()) {
@@ -167,6 +203,7 @@ impl Parser {
 }
 
 #[cfg(test)]
+#[allow(clippy::assertions_on_constants)]
 mod tests {
     use crate::parser::{Parser, CommandType};
 
@@ -0,0 +1,292 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::assembler::{AssemblyError, assemble_source_lines, source_line_numbers};
+use crate::cpu_emulator::MEMORY_SIZE;
+use crate::jack::{JackError, jack_file_paths};
+use crate::jack::ast::Class;
+use crate::jack::checker::{ClassIndex, check_class};
+use crate::jack::codegen::{compile_vm_file, vm_text};
+use crate::jack::os::os_classes;
+use crate::jack::parser::parse_source;
+use crate::vm::{VmFile, read_vm_files, translate};
+use crate::vm::parser::{VmCommand, VmError};
+
+pub enum BuildError {
+    Io(PathBuf, io::Error),
+    Jack(JackError),
+    Check(Vec<JackError>),
+    Vm(VmError),
+    Assembly(AssemblyError),
+    NoSources(PathBuf),
+    RomOverflow(usize),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            BuildError::Jack(e) => write!(f, "{}", e),
+            BuildError::Check(errors) => {
+                let lines = errors.iter().map(|e| e.to_string()).collect::<Vec<String>>();
+                write!(f, "{}", lines.join("\n"))
+            },
+            BuildError::Vm(e) => write!(f, "{}", e),
+            BuildError::Assembly(e) => write!(f, "{}", e),
+            BuildError::NoSources(path) => write!(f, "{}: no .jack files found", path.display()),
+            BuildError::RomOverflow(size) => write!(f, "the program needs {} words of ROM but there are only {}", size, MEMORY_SIZE),
+        }
+    }
+}
+
+impl From<JackError> for BuildError {
+    fn from(e : JackError) -> BuildError {
+        BuildError::Jack(e)
+    }
+}
+
+impl From<VmError> for BuildError {
+    fn from(e : VmError) -> BuildError {
+        BuildError::Vm(e)
+    }
+}
+
+pub struct BuildOptions {
+    pub os_directory : Option<PathBuf>,
+    pub optimize : bool,
+}
+
+//Every stage of a build: the program's classes, the OS classes linked in, the assembly and the machine code
+pub struct BuildOutput {
+    pub classes : Vec<VmFile>,
+    pub os_classes : Vec<VmFile>,
+    pub assembly : Vec<String>,
+    pub machine_lines : Vec<u16>,
+}
+
+//A parsed class and the file it came from
+struct SourceClass {
+    file_name : String,
+    class : Class,
+}
+
+fn parse_directory(directory : &Path) -> Result<Vec<SourceClass>, BuildError> {
+    let paths = jack_file_paths(directory).map_err(|e| BuildError::Io(directory.to_path_buf(), e))?;
+    let mut classes = Vec::new();
+    for path in paths {
+        let source = fs::read_to_string(&path).map_err(|e| BuildError::Io(path.clone(), e))?;
+        let file_name = path.display().to_string();
+        let class = parse_source(&file_name, &source)?;
+        classes.push(SourceClass { file_name, class });
+    }
+    Ok(classes)
+}
+
+//The OS classes from the named directory, or the built-in OS
+fn parse_os(os_directory : Option<&Path>) -> Result<Vec<SourceClass>, BuildError> {
+    match os_directory {
+        Some(directory) => parse_directory(directory),
+        None => Ok(os_classes()?.into_iter().map(|(file_name, class)| SourceClass { file_name, class }).collect()),
+    }
+}
+
+fn compile_classes(classes : &[SourceClass]) -> Result<Vec<VmFile>, BuildError> {
+    let mut files = Vec::new();
+    for source in classes {
+        files.push(compile_vm_file(&source.file_name, &source.class)?);
+    }
+    Ok(files)
+}
+
+//The program's classes and the OS classes it does not define itself, parsed and semantically checked.
+//The OS classes only contribute their signatures to the check.
+fn parse_and_check(path : &Path, os_directory : Option<&Path>) -> Result<(Vec<SourceClass>, Vec<SourceClass>), BuildError> {
+    let classes = parse_directory(path)?;
+    if classes.is_empty() {
+        return Err(BuildError::NoSources(path.to_path_buf()));
+    }
+    let os_classes = parse_os(os_directory)?.into_iter()
+        .filter(|os_class| classes.iter().all(|source| source.class.name.name != os_class.class.name.name))
+        .collect::<Vec<SourceClass>>();
+    let mut index = ClassIndex::new();
+    for source in os_classes.iter().chain(classes.iter()) {
+        index.add(&source.class);
+    }
+    let errors = classes.iter()
+        .flat_map(|source| check_class(&source.file_name, &source.class, &index))
+        .collect::<Vec<JackError>>();
+    if !errors.is_empty() {
+        return Err(BuildError::Check(errors));
+    }
+    Ok((classes, os_classes))
+}
+
+//Runs the semantic checks on a .jack file or directory without generating code
+pub fn check(path : &Path, os_directory : Option<&Path>) -> Result<usize, BuildError> {
+    Ok(parse_and_check(path, os_directory)?.0.len())
+}
+
+//Compiles a .jack file or directory after checking it; returns the program's classes and the OS classes
+pub fn compile(path : &Path, os_directory : Option<&Path>) -> Result<(Vec<VmFile>, Vec<VmFile>), BuildError> {
+    let (classes, os_classes) = parse_and_check(path, os_directory)?;
+    Ok((compile_classes(&classes)?, compile_classes(&os_classes)?))
+}
+
+fn defines_jack_program(path : &Path) -> bool {
+    if path.is_dir() {
+        jack_file_paths(path).map(|paths| !paths.is_empty()).unwrap_or(false)
+    }
+    else {
+        path.extension().map(|e| e == "jack").unwrap_or(false)
+    }
+}
+
+//The VM files a program runs as: .jack sources are compiled, otherwise the .vm files are read.
+//Either way the built-in OS classes the program does not define are linked in when it calls
+//functions it does not define itself, as the reference VM emulator does with its built-in OS.
+//Returns the program's files and the OS files.
+pub fn load_vm_program(path : &Path) -> Result<(Vec<VmFile>, Vec<VmFile>), BuildError> {
+    if defines_jack_program(path) {
+        return compile(path, None);
+    }
+    let files = read_vm_files(path)?;
+    let defined = files.iter()
+        .flat_map(|file| file.commands.iter())
+        .filter_map(|line| match &line.command {
+            VmCommand::Function(name, _) => Some(name.clone()),
+            _ => None,
+        })
+        .collect::<HashSet<String>>();
+    let calls_undefined = files.iter()
+        .flat_map(|file| file.commands.iter())
+        .any(|line| matches!(&line.command, VmCommand::Call(name, _) if !defined.contains(name)));
+    if !calls_undefined {
+        return Ok((files, Vec::new()));
+    }
+    let os_classes = parse_os(None)?.into_iter()
+        .filter(|os_class| files.iter().all(|file| file.name != os_class.class.name.name))
+        .collect::<Vec<SourceClass>>();
+    let os_files = compile_classes(&os_classes)?;
+    Ok((files, os_files))
+}
+
+//Compiles a directory of .jack files, links the OS classes the program does not define itself,
+//translates the whole program with bootstrap code and assembles it
+pub fn build(directory : &Path, options : &BuildOptions) -> Result<BuildOutput, BuildError> {
+    let (classes, os_classes) = compile(directory, options.os_directory.as_deref())?;
+    let program = classes.iter().chain(os_classes.iter()).cloned().collect::<Vec<VmFile>>();
+    let assembly = translate(&program, true, options.optimize);
+    let size = source_line_numbers(&assembly).len();
+    if size > MEMORY_SIZE {
+        return Err(BuildError::RomOverflow(size));
+    }
+    let machine_lines = assemble_source_lines("the translated program", &assembly).map_err(BuildError::Assembly)?;
+    Ok(BuildOutput { classes, os_classes, assembly, machine_lines })
+}
+
+//Writes Foo.vm for every class, program and OS alike, and the assembly next to the .hack file
+pub fn write_intermediates(output : &BuildOutput, directory : &Path, asm_path : &Path) -> io::Result<()> {
+    for class in output.classes.iter().chain(output.os_classes.iter()) {
+        fs::write(directory.join(format!("{}.vm", class.name)), vm_text(class))?;
+    }
+    let mut text = output.assembly.join("\r\n");
+    text.push_str("\r\n");
+    fs::write(asm_path, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use crate::cpu_emulator::Machine;
+    use crate::jack::os::screen_text;
+    use crate::pipeline::{build, check, write_intermediates, BuildError, BuildOptions};
+    use crate::test_support::scratch_copy;
+
+    //A stand-in OS small enough to run Seven: Sys.init calls Main.main and printInt leaves its
+    //argument at the start of the screen
+    const STUB_OS : [(&str, &str); 3] = [
+        ("Sys", "class Sys { function void init() { do Main.main(); while (true) {} return; } }"),
+        ("Math", "class Math { function int multiply(int x, int y) { var int sum; \
+                  while (y > 0) { let sum = sum + x; let y = y - 1; } return sum; } }"),
+        ("Output", "class Output { function void printInt(int i) { var Array screen; \
+                    let screen = 16384; let screen[0] = i; return; } }"),
+    ];
+
+    fn stub_os() -> PathBuf {
+        let directory = std::env::temp_dir().join("n2t_build_stub_os");
+        fs::create_dir_all(&directory).unwrap();
+        for (name, source) in STUB_OS.iter() {
+            fs::write(directory.join(format!("{}.jack", name)), source).unwrap();
+        }
+        directory
+    }
+
+    //Seven with the built-in OS, which only the optimized translation fits in ROM, prints 7 on the CPU emulator
+    #[test]
+    fn seven_runs_after_build_test() {
+        let directory = scratch_copy("11/Seven", "build");
+        let output = build(&directory, &BuildOptions { os_directory : None, optimize : true }).ok().unwrap();
+        assert_eq!(output.os_classes.len(), 8);
+        let mut machine = Machine::new();
+        machine.load_program(&output.machine_lines);
+        for _ in 0..1_000_000 {
+            machine.step();
+        }
+        assert_eq!(screen_text(&machine.ram), vec!["7"]);
+
+        write_intermediates(&output, &directory, &directory.join("Seven.asm")).unwrap();
+        assert!(fs::read_to_string(directory.join("Main.vm")).unwrap().starts_with("function Main.main 0\n"));
+        assert!(directory.join("Sys.vm").exists());
+        assert!(directory.join("Seven.asm").exists());
+    }
+
+    #[test]
+    fn os_directory_replaces_builtin_os_test() {
+        for optimize in [false, true].iter() {
+            let directory = scratch_copy("11/Seven", if *optimize { "build_stub_os_optimized" } else { "build_stub_os" });
+            let output = build(&directory, &BuildOptions { os_directory : Some(stub_os()), optimize : *optimize }).ok().unwrap();
+            assert_eq!(output.os_classes.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>(), vec!["Math", "Output", "Sys"]);
+            let mut machine = Machine::new();
+            machine.load_program(&output.machine_lines);
+            for _ in 0..5000 {
+                machine.step();
+            }
+            assert_eq!(machine.ram[0x4000], 7);
+        }
+    }
+
+    #[test]
+    fn pong_fits_in_rom_test() {
+        let directory = scratch_copy("11/Pong", "build");
+        //With the built-in OS linked in, only the optimized translation fits
+        let naive = build(&directory, &BuildOptions { os_directory : None, optimize : false });
+        assert!(matches!(naive, Err(BuildError::RomOverflow(_))));
+        let optimized = build(&directory, &BuildOptions { os_directory : None, optimize : true }).ok().unwrap();
+        assert_eq!(optimized.classes.len(), 4);
+        assert_eq!(optimized.os_classes.len(), 8);
+
+        //Pong draws its bat and prints the score on the CPU emulator
+        let mut machine = Machine::new();
+        machine.load_program(&optimized.machine_lines);
+        for _ in 0..3_000_000 {
+            machine.step();
+        }
+        assert_eq!(screen_text(&machine.ram).last().unwrap(), "Score: 0");
+    }
+
+    #[test]
+    fn semantic_errors_stop_the_build_test() {
+        let directory = std::env::temp_dir().join("n2t_build_check").join("Broken");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("Main.jack"), "class Main {\n  function void main() {\n    do Output.printInt(1, 2);\n    return;\n  }\n}").unwrap();
+        let file_name = directory.join("Main.jack").display().to_string();
+        let expected = format!("{}:3:8: Output.printInt expects 1 argument but is given 2", file_name);
+        assert_eq!(check(&directory, None).err().unwrap().to_string(), expected);
+        let options = BuildOptions { os_directory : None, optimize : false };
+        assert_eq!(build(&directory, &options).err().unwrap().to_string(), expected);
+    }
+}
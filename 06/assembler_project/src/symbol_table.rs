@@ -58,71 +58,52 @@ impl SymbolTable {
     }
 
     //Find and record all ROM addresses
-    pub fn pass_1(&mut self, parser : &mut Parser) {
+    pub fn pass_1(&mut self, parser : &mut Parser) -> Result<(), SyntaxError> {
         parser.reset();
         loop {
-            if let Some(c_type) = parser.command_type() {
-                match c_type {
-                    CommandType::A => self.rom_address += 1,
-                    CommandType::C => self.rom_address += 1,
-                    CommandType::L => {
-                        self.add_rom_entry(parser.symbol().unwrap().as_str());
+            match parser.check_command()? {
+                CommandType::A => self.rom_address += 1,
+                CommandType::C => self.rom_address += 1,
+                CommandType::L => {
+                    let symbol = parser.symbol().unwrap();
+                    if self.symbol_map.contains_key(&symbol) {
+                        return Err(SyntaxError { index : parser.line_index(), message : format!("symbol {} is already defined", symbol) });
                     }
+                    self.add_rom_entry(symbol.as_str());
                 }
             }
-            else{
-                println!("ERROR");
-            }
             if parser.advance().is_err() {
                 break;
             }
         }
         parser.reset();
         loop {
-            if let Some(c_type) = parser.command_type() {
-                match c_type {
-                    CommandType::A => {
-                        if let Some(symbol) = parser.symbol() {
-                            if symbol.parse::<u16>().is_err() {
-                                if !self.symbol_map.contains_key(&symbol) {
-                                    self.add_ram_entry(&symbol);
-                                }
-                            }
-                        }
-                    },
-                    CommandType::C => (),
-                    CommandType::L => (),
+            if parser.command_type() == Some(CommandType::A) {
+                if let Some(symbol) = parser.symbol() {
+                    if symbol.parse::<u16>().is_err() && !self.symbol_map.contains_key(&symbol) {
+                        self.add_ram_entry(&symbol);
+                    }
                 }
             }
-            else{
-                println!("ERROR");
-            }
             if parser.advance().is_err() {
                 break;
             }
         }
+        Ok(())
     }
 
+    //Drops the labels and replaces every symbol with its address
     pub fn pass_2(&mut self, lines : &Vec<String>) -> Vec<String> {
         let mut new_lines = Vec::new();
         for line in lines {
-            let mut new_line = Some(String::from(line));
-            for symbol in self.symbol_map.keys() {
-                if line.contains(symbol.as_str()) {
-                    if line.contains('@') && line.len() == symbol.len() + 1{
-                        new_line = Some(line.replace(symbol.as_str(),
-                                                self.get_address(symbol).unwrap().to_string().as_ref()));
-                        break;
-                    }
-                    else {
-                        new_line = None;
-                    }
-                }
+            if line.starts_with('(') {
+                continue;
             }
-            if let Some(line) = new_line {
-                new_lines.push(String::from(line));
+            match line.strip_prefix('@').and_then(|symbol| self.get_address(symbol)) {
+                Some(address) => new_lines.push(format!("@{}", address)),
+                None => new_lines.push(String::from(line)),
             }
         }
         new_lines
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,232 @@
+pub mod parser;
+pub mod output;
+
+use std::fmt;
+use std::fmt::Formatter;
+use std::fs;
+use std::path::{Path, PathBuf};
+use parser::{Command, Condition, Statement, parse_script};
+use output::{OutputColumn, parse_column, header_line, parse_value, compare_output};
+
+//A while loop that goes round this often is taken to wait for something that is not going to happen
+const MAX_WHILE_ROUNDS : u64 = 100_000;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Number(u16),
+    Text(String),
+}
+
+//A machine that test scripts can drive: the CPU emulator, the VM emulator or the hardware simulator
+pub trait Simulator {
+    fn load(&mut self, directory : &Path, file_name : Option<&str>) -> Result<(), String>;
+    fn get(&self, variable : &str) -> Result<Value, String>;
+    fn set(&mut self, variable : &str, value : u16) -> Result<(), String>;
+    fn execute(&mut self, command : &[String]) -> Result<(), String>;
+    //Called as each while loop starts, which usually waits for the person running the script to do something
+    fn start_waiting(&mut self) {}
+}
+
+pub struct ScriptError {
+    pub file_name : String,
+    pub line : usize,
+    pub message : String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file_name, self.line, self.message)
+    }
+}
+
+pub struct ScriptResult {
+    pub output : String,
+    pub output_file : Option<PathBuf>,
+    pub echoes : Vec<String>,
+    pub compared : bool,
+    //The first line of the output that does not match the compare file
+    pub comparison_failure : Option<usize>,
+}
+
+impl ScriptResult {
+    pub fn passed(&self) -> bool {
+        self.comparison_failure.is_none()
+    }
+
+    //Writes the output to the file named by the script's output-file command, if any
+    pub fn write_output_file(&self) -> std::io::Result<()> {
+        match &self.output_file {
+            Some(output_file) => fs::write(output_file, &self.output),
+            None => Ok(()),
+        }
+    }
+}
+
+//Splits "RAM[12]" into ("RAM", Some("12")) and "DRegister[]" into ("DRegister", Some(""))
+pub fn split_indexed(variable : &str) -> (&str, Option<&str>) {
+    match (variable.find('['), variable.strip_suffix(']')) {
+        (Some(open), Some(inner)) => (&variable[..open], Some(&inner[open + 1..])),
+        _ => (variable, None),
+    }
+}
+
+//Finds a file named by a script. Scripts are often written on case-insensitive file systems,
+//so a file that only differs in case is accepted when there is no exact match.
+pub fn resolve_path(directory : &Path, file_name : &str) -> PathBuf {
+    let path = directory.join(file_name);
+    if path.exists() {
+        return path;
+    }
+    let entries = match fs::read_dir(path.parent().unwrap_or(directory)) {
+        Ok(entries) => entries,
+        Err(_) => return path,
+    };
+    let wanted = path.file_name().map(|n| n.to_string_lossy().to_lowercase()).unwrap_or_default();
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().to_lowercase() == wanted {
+            return entry.path();
+        }
+    }
+    path
+}
+
+struct ScriptRunner<'a> {
+    directory : PathBuf,
+    simulator : &'a mut dyn Simulator,
+    output_list : Vec<OutputColumn>,
+    output_file : Option<PathBuf>,
+    compare_to : Option<PathBuf>,
+    output : String,
+    echoes : Vec<String>,
+}
+
+impl<'a> ScriptRunner<'a> {
+    fn output_line(&mut self) -> Result<(), String> {
+        let mut line = String::from("|");
+        for column in &self.output_list {
+            let value = self.simulator.get(&column.variable)?;
+            line.push_str(&column.format_value(&value));
+            line.push('|');
+        }
+        self.output.push_str(&line);
+        self.output.push_str("\r\n");
+        Ok(())
+    }
+
+    fn condition_holds(&self, variable : &str, condition : &Condition, value : &str) -> Result<bool, String> {
+        let left = match self.simulator.get(variable)? {
+            Value::Number(number) => number as i16,
+            Value::Text(text) => return Err(format!("'{}' is not a number: {}", variable, text)),
+        };
+        let right = parse_value(value).ok_or_else(|| format!("bad value '{}'", value))? as i16;
+        Ok(match condition {
+            Condition::Equal => left == right,
+            Condition::NotEqual => left != right,
+            Condition::Less => left < right,
+            Condition::Greater => left > right,
+            Condition::LessOrEqual => left <= right,
+            Condition::GreaterOrEqual => left >= right,
+        })
+    }
+
+    fn run_block(&mut self, statements : &[Statement]) -> Result<(), (usize, String)> {
+        for statement in statements {
+            self.run_statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn run_statement(&mut self, statement : &Statement) -> Result<(), (usize, String)> {
+        let line = statement.line;
+        let at_line = |message : String| (line, message);
+        match &statement.command {
+            Command::Load(file_name) => {
+                let directory = self.directory.clone();
+                self.simulator.load(&directory, file_name.as_deref()).map_err(at_line)?;
+            },
+            Command::OutputFile(file_name) => self.output_file = Some(self.directory.join(file_name)),
+            Command::CompareTo(file_name) => self.compare_to = Some(self.directory.join(file_name)),
+            Command::OutputList(specs) => {
+                self.output_list = specs.iter()
+                    .map(|spec| parse_column(spec))
+                    .collect::<Result<Vec<OutputColumn>, String>>()
+                    .map_err(at_line)?;
+                self.output.push_str(&header_line(&self.output_list));
+                self.output.push_str("\r\n");
+            },
+            Command::Set(variable, value) => {
+                let value = parse_value(value).ok_or_else(|| (line, format!("bad value '{}'", value)))?;
+                self.simulator.set(variable, value).map_err(at_line)?;
+            },
+            Command::Output => self.output_line().map_err(at_line)?,
+            Command::Echo(message) => self.echoes.push(message.clone()),
+            Command::ClearEcho => (),
+            Command::Repeat(Some(count), body) => {
+                for _ in 0..*count {
+                    self.run_block(body)?;
+                }
+            },
+            Command::Repeat(None, _) => return Err((line, String::from("repeat without a count never terminates"))),
+            Command::While(variable, condition, value, body) => {
+                self.simulator.start_waiting();
+                let mut rounds = 0;
+                while self.condition_holds(variable, condition, value).map_err(at_line)? {
+                    if rounds == MAX_WHILE_ROUNDS {
+                        return Err((line, format!("the loop is still waiting after {} rounds", MAX_WHILE_ROUNDS)));
+                    }
+                    self.run_block(body)?;
+                    rounds += 1;
+                }
+            },
+            Command::Simulator(words) => self.simulator.execute(words).map_err(at_line)?,
+        }
+        Ok(())
+    }
+}
+
+pub fn run_script(source : &str, directory : &Path, simulator : &mut dyn Simulator) -> Result<ScriptResult, (usize, String)> {
+    let statements = parse_script(source)?;
+    let mut runner = ScriptRunner {
+        directory : directory.to_path_buf(),
+        simulator,
+        output_list : Vec::new(),
+        output_file : None,
+        compare_to : None,
+        output : String::new(),
+        echoes : Vec::new(),
+    };
+    runner.run_block(&statements)?;
+
+    let last_line = statements.last().map(|s| s.line).unwrap_or(1);
+    let mut result = ScriptResult {
+        output : runner.output,
+        output_file : runner.output_file,
+        echoes : runner.echoes,
+        compared : false,
+        comparison_failure : None,
+    };
+    if let Some(compare_to) = &runner.compare_to {
+        let expected = fs::read_to_string(compare_to)
+            .map_err(|e| (last_line, format!("Error reading {}: {}", compare_to.display(), e)))?;
+        result.compared = true;
+        result.comparison_failure = compare_output(&result.output, &expected);
+    }
+    Ok(result)
+}
+
+//What the script's first load command names, so callers can pick the simulator it was written for.
+//Returns Some(None) for a bare "load", which loads the script's directory.
+pub fn loaded_program(source : &str) -> Result<Option<Option<String>>, (usize, String)> {
+    let statements = parse_script(source)?;
+    Ok(statements.into_iter().find_map(|statement| match statement.command {
+        Command::Load(program) => Some(program),
+        _ => None,
+    }))
+}
+
+pub fn run_script_file(file_name : &str, simulator : &mut dyn Simulator) -> Result<ScriptResult, ScriptError> {
+    let to_error = |(line, message) : (usize, String)| ScriptError { file_name : file_name.to_string(), line, message };
+    let source = fs::read_to_string(file_name).map_err(|e| to_error((0, e.to_string())))?;
+    let directory = Path::new(file_name).parent().unwrap_or_else(|| Path::new("."));
+    run_script(&source, directory, simulator).map_err(to_error)
+}
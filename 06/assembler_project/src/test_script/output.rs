@@ -0,0 +1,168 @@
+use crate::test_script::Value;
+
+//One column of an output-list, e.g. RAM[0]%D2.6.2
+#[derive(Clone, PartialEq, Debug)]
+pub struct OutputColumn {
+    pub variable : String,
+    pub format : char,
+    pub left : usize,
+    pub width : usize,
+    pub right : usize,
+}
+
+pub fn parse_column(spec : &str) -> Result<OutputColumn, String> {
+    let (variable, format) = match spec.find('%') {
+        Some(index) => (&spec[..index], &spec[index + 1..]),
+        None => return Ok(OutputColumn { variable : spec.to_string(), format : 'B', left : 1, width : 16, right : 1 }),
+    };
+    let mut chars = format.chars();
+    let format_char = match chars.next() {
+        Some(c) if "BDXS".contains(c) => c,
+        _ => return Err(format!("bad output format '{}'", spec)),
+    };
+    let sizes = chars.as_str().split('.').map(|s| s.parse::<usize>()).collect::<Vec<_>>();
+    match sizes.as_slice() {
+        [Ok(left), Ok(width), Ok(right)] => Ok(OutputColumn {
+            variable : variable.to_string(),
+            format : format_char,
+            left : *left,
+            width : *width,
+            right : *right,
+        }),
+        _ => Err(format!("bad output format '{}'", spec)),
+    }
+}
+
+impl OutputColumn {
+    fn total_width(&self) -> usize {
+        self.left + self.width + self.right
+    }
+
+    //The column title is centered and cut to the column width
+    pub fn header(&self) -> String {
+        let total = self.total_width();
+        let title = self.variable.chars().take(total).collect::<String>();
+        let leading = (total - title.len()) / 2;
+        let trailing = total - title.len() - leading;
+        format!("{}{}{}", " ".repeat(leading), title, " ".repeat(trailing))
+    }
+
+    pub fn format_value(&self, value : &Value) -> String {
+        let body = match (self.format, value) {
+            ('S', Value::Text(text)) => format!("{:<width$}", text, width = self.width),
+            ('S', Value::Number(number)) => format!("{:<width$}", *number as i16, width = self.width),
+            (_, Value::Text(text)) => format!("{:>width$}", text, width = self.width),
+            ('D', Value::Number(number)) => format!("{:>width$}", *number as i16, width = self.width),
+            ('X', Value::Number(number)) => {
+                let digits = format!("{:04X}", number);
+                digits[digits.len().saturating_sub(self.width)..].to_string()
+            },
+            (_, Value::Number(number)) => {
+                let digits = format!("{:016b}", number);
+                digits[digits.len().saturating_sub(self.width)..].to_string()
+            },
+        };
+        format!("{}{}{}", " ".repeat(self.left), body, " ".repeat(self.right))
+    }
+}
+
+pub fn header_line(columns : &[OutputColumn]) -> String {
+    let mut line = String::from("|");
+    for column in columns {
+        line.push_str(&column.header());
+        line.push('|');
+    }
+    line
+}
+
+//Parses a value written in a script: 12, -1, %B0101, %XFF or %D12
+pub fn parse_value(text : &str) -> Option<u16> {
+    let (radix, digits) = if let Some(rest) = text.strip_prefix("%B") {
+        (2, rest)
+    }
+    else if let Some(rest) = text.strip_prefix("%X") {
+        (16, rest)
+    }
+    else if let Some(rest) = text.strip_prefix("%D") {
+        (10, rest)
+    }
+    else {
+        (10, text)
+    };
+    if radix == 10 {
+        return digits.parse::<i32>().ok()
+            .filter(|value| *value >= -32768 && *value <= 65535)
+            .map(|value| value as u16);
+    }
+    u16::from_str_radix(digits, radix).ok()
+}
+
+//Compares produced output with a compare file, where '*' in the compare file matches anything.
+//Returns the 1-based number of the first differing line.
+pub fn compare_output(output : &str, expected : &str) -> Option<usize> {
+    let output_lines = output.lines().map(|l| l.trim_end()).collect::<Vec<&str>>();
+    let expected_lines = expected.lines().map(|l| l.trim_end()).filter(|l| !l.is_empty()).collect::<Vec<&str>>();
+    for (index, expected_line) in expected_lines.iter().enumerate() {
+        let output_line = match output_lines.get(index) {
+            Some(line) => line,
+            None => return Some(index + 1),
+        };
+        let expected_chars = expected_line.chars().collect::<Vec<char>>();
+        let output_chars = output_line.chars().collect::<Vec<char>>();
+        if expected_chars.len() != output_chars.len() {
+            return Some(index + 1);
+        }
+        if expected_chars.iter().zip(output_chars.iter()).any(|(e, o)| *e != '*' && e != o) {
+            return Some(index + 1);
+        }
+    }
+    if output_lines.len() > expected_lines.len() {
+        return Some(expected_lines.len() + 1);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_script::Value;
+    use crate::test_script::output::{parse_column, parse_value, header_line, compare_output};
+
+    #[test]
+    fn header_test() {
+        let columns = ["time%S0.4.0", "inM%D0.6.0", "instruction%B0.16.0", "DRegister[]%D1.6.1", "writeM%B3.1.3"]
+            .iter().map(|spec| parse_column(spec).ok().unwrap()).collect::<Vec<_>>();
+        assert_eq!(header_line(&columns), "|time| inM  |  instruction   |DRegiste|writeM |");
+    }
+
+    #[test]
+    fn format_value_test() {
+        let column = parse_column("RAM[0]%D2.6.2").ok().unwrap();
+        assert_eq!(column.format_value(&Value::Number(0xFFFF)), "      -1  ");
+        let column = parse_column("instruction%B0.16.0").ok().unwrap();
+        assert_eq!(column.format_value(&Value::Number(12345)), "0011000000111001");
+        let column = parse_column("reset%B2.1.2").ok().unwrap();
+        assert_eq!(column.format_value(&Value::Number(1)), "  1  ");
+        let column = parse_column("time%S1.4.1").ok().unwrap();
+        assert_eq!(column.format_value(&Value::Text(String::from("0+"))), " 0+   ");
+        let column = parse_column("out%X1.4.1").ok().unwrap();
+        assert_eq!(column.format_value(&Value::Number(0xBEEF)), " BEEF ");
+        assert!(parse_column("out%Q1.4.1").is_err());
+    }
+
+    #[test]
+    fn parse_value_test() {
+        assert_eq!(parse_value("-1"), Some(0xFFFF));
+        assert_eq!(parse_value("%B0011000000111001"), Some(12345));
+        assert_eq!(parse_value("%XFF"), Some(255));
+        assert_eq!(parse_value("%D-2"), Some(0xFFFE));
+        assert_eq!(parse_value("x"), None);
+    }
+
+    #[test]
+    fn compare_output_test() {
+        let expected = "|  a  |  b  |\n|  1  |*****|\n";
+        assert_eq!(compare_output("|  a  |  b  |\r\n|  1  |  7  |\r\n", expected), None);
+        assert_eq!(compare_output("|  a  |  b  |\n|  0  |  7  |\n", expected), Some(2));
+        assert_eq!(compare_output("|  a  |  b  |\n", expected), Some(2));
+    }
+}
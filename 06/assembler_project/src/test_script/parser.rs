@@ -0,0 +1,320 @@
+//Parses the nand2tetris test script language shared by the CPU, VM and hardware simulators
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Command {
+    Load(Option<String>),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<String>),
+    Set(String, String),
+    Output,
+    Echo(String),
+    ClearEcho,
+    Repeat(Option<u64>, Vec<Statement>),
+    While(String, Condition, String, Vec<Statement>),
+    //Anything else (tick, tock, eval, vmstep, "ROM32K load Max.hack" ...) belongs to the simulator
+    Simulator(Vec<String>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Statement {
+    pub line : usize,
+    pub command : Command,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Token {
+    text : String,
+    line : usize,
+    quoted : bool,
+}
+
+fn is_separator(c : char) -> bool {
+    c == ',' || c == ';' || c == '!' || c == '{' || c == '}'
+}
+
+fn is_terminator(token : &Token) -> bool {
+    !token.quoted && (token.text == "," || token.text == ";" || token.text == "!")
+}
+
+fn tokenize(source : &str) -> Result<Vec<Token>, (usize, String)> {
+    let chars = source.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        if c == '\n' {
+            line += 1;
+            index += 1;
+        }
+        else if c.is_whitespace() {
+            index += 1;
+        }
+        else if c == '/' && chars.get(index + 1) == Some(&'/') {
+            while index < chars.len() && chars[index] != '\n' {
+                index += 1;
+            }
+        }
+        else if c == '/' && chars.get(index + 1) == Some(&'*') {
+            let start_line = line;
+            index += 2;
+            loop {
+                if index + 1 >= chars.len() {
+                    return Err((start_line, String::from("unterminated comment")));
+                }
+                if chars[index] == '*' && chars[index + 1] == '/' {
+                    index += 2;
+                    break;
+                }
+                if chars[index] == '\n' {
+                    line += 1;
+                }
+                index += 1;
+            }
+        }
+        else if c == '"' {
+            let start = index + 1;
+            index = start;
+            while index < chars.len() && chars[index] != '"' {
+                if chars[index] == '\n' {
+                    return Err((line, String::from("unterminated string")));
+                }
+                index += 1;
+            }
+            if index >= chars.len() {
+                return Err((line, String::from("unterminated string")));
+            }
+            tokens.push(Token { text : chars[start..index].iter().collect(), line, quoted : true });
+            index += 1;
+        }
+        else if is_separator(c) {
+            tokens.push(Token { text : c.to_string(), line, quoted : false });
+            index += 1;
+        }
+        else {
+            let start = index;
+            while index < chars.len() && !chars[index].is_whitespace() && !is_separator(chars[index]) && chars[index] != '"' {
+                if chars[index] == '/' && matches!(chars.get(index + 1), Some('/') | Some('*')) {
+                    break;
+                }
+                index += 1;
+            }
+            tokens.push(Token { text : chars[start..index].iter().collect(), line, quoted : false });
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_condition(text : &str) -> Option<Condition> {
+    match text {
+        "=" => Some(Condition::Equal),
+        "<>" => Some(Condition::NotEqual),
+        "<" => Some(Condition::Less),
+        ">" => Some(Condition::Greater),
+        "<=" => Some(Condition::LessOrEqual),
+        ">=" => Some(Condition::GreaterOrEqual),
+        _ => None,
+    }
+}
+
+struct ScriptParser {
+    tokens : Vec<Token>,
+    index : usize,
+}
+
+impl ScriptParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn last_line(&self) -> usize {
+        self.tokens.last().map(|t| t.line).unwrap_or(1)
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Statement>, (usize, String)> {
+        let mut statements = Vec::new();
+        loop {
+            match self.peek() {
+                Some(token) if token.text == "}" && !token.quoted => {
+                    self.index += 1;
+                    return Ok(statements);
+                },
+                Some(_) => {
+                    if let Some(statement) = self.parse_statement()? {
+                        statements.push(statement);
+                    }
+                },
+                None => return Err((self.last_line(), String::from("missing '}'"))),
+            }
+        }
+    }
+
+    //Collects the words of one command up to its terminator or an opening brace
+    fn parse_words(&mut self) -> Vec<Token> {
+        let mut words = Vec::new();
+        while let Some(token) = self.peek() {
+            if is_terminator(token) {
+                self.index += 1;
+                break;
+            }
+            if !token.quoted && (token.text == "{" || token.text == "}") {
+                break;
+            }
+            words.push(token.clone());
+            self.index += 1;
+        }
+        words
+    }
+
+    fn expect_open_brace(&mut self, line : usize) -> Result<(), (usize, String)> {
+        match self.peek() {
+            Some(token) if token.text == "{" && !token.quoted => {
+                self.index += 1;
+                Ok(())
+            },
+            _ => Err((line, String::from("expected '{'"))),
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Option<Statement>, (usize, String)> {
+        let line = match self.peek() {
+            Some(token) => token.line,
+            None => return Ok(None),
+        };
+        let first = self.peek().unwrap().text.clone();
+        if first == "repeat" || first == "while" {
+            self.index += 1;
+            let words = self.parse_words();
+            self.expect_open_brace(line)?;
+            let body = self.parse_block()?;
+            let texts = words.iter().map(|t| t.text.as_str()).collect::<Vec<&str>>();
+            let command = if first == "repeat" {
+                match texts.as_slice() {
+                    [] => Command::Repeat(None, body),
+                    [count] => match count.parse::<u64>() {
+                        Ok(count) => Command::Repeat(Some(count), body),
+                        Err(_) => return Err((line, format!("bad repeat count '{}'", count))),
+                    },
+                    _ => return Err((line, String::from("expected 'repeat [count] {'"))),
+                }
+            }
+            else {
+                match texts.as_slice() {
+                    [variable, condition, value] => match parse_condition(condition) {
+                        Some(condition) => Command::While(variable.to_string(), condition, value.to_string(), body),
+                        None => return Err((line, format!("unknown comparison '{}'", condition))),
+                    },
+                    _ => return Err((line, String::from("expected 'while variable op value {'"))),
+                }
+            };
+            return Ok(Some(Statement { line, command }));
+        }
+
+        let words = self.parse_words();
+        if words.is_empty() {
+            match self.peek() {
+                Some(token) if token.text == "{" => return Err((line, String::from("unexpected '{'"))),
+                _ => return Ok(None),
+            }
+        }
+        let texts = words.iter().map(|t| t.text.clone()).collect::<Vec<String>>();
+        let arguments = &texts[1..];
+        let command = match texts[0].as_str() {
+            "load" => match arguments {
+                [] => Command::Load(None),
+                [file_name] => Command::Load(Some(file_name.clone())),
+                _ => return Err((line, String::from("expected 'load [file]'"))),
+            },
+            "output-file" | "compare-to" => match arguments {
+                [file_name] if texts[0] == "output-file" => Command::OutputFile(file_name.clone()),
+                [file_name] => Command::CompareTo(file_name.clone()),
+                _ => return Err((line, format!("expected '{} file'", texts[0]))),
+            },
+            "output-list" => Command::OutputList(arguments.to_vec()),
+            "set" => match arguments {
+                [variable, value] => Command::Set(variable.clone(), value.clone()),
+                _ => return Err((line, String::from("expected 'set variable value'"))),
+            },
+            "output" if arguments.is_empty() => Command::Output,
+            "echo" => Command::Echo(arguments.join(" ")),
+            "clear-echo" => Command::ClearEcho,
+            _ => Command::Simulator(texts),
+        };
+        Ok(Some(Statement { line, command }))
+    }
+}
+
+pub fn parse_script(source : &str) -> Result<Vec<Statement>, (usize, String)> {
+    let mut parser = ScriptParser {
+        tokens : tokenize(source)?,
+        index : 0,
+    };
+    let mut statements = Vec::new();
+    while let Some(token) = parser.peek() {
+        if token.text == "}" && !token.quoted {
+            return Err((token.line, String::from("unexpected '}'")));
+        }
+        if let Some(statement) = parser.parse_statement()? {
+            statements.push(statement);
+        }
+    }
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_script::parser::{parse_script, Command, Condition};
+
+    #[test]
+    fn parse_simple_script_test() {
+        let source = "load Mult.hack,\noutput-file Mult.out,\n// comment\noutput-list RAM[0]%D2.6.2 RAM[1]%D2.6.2;\n\
+                      set RAM[0] 0,   // args\nrepeat 20 {\n  ticktock;\n}\noutput;";
+        let statements = parse_script(source).ok().unwrap();
+        assert_eq!(statements.len(), 6);
+        assert_eq!(statements[0].command, Command::Load(Some(String::from("Mult.hack"))));
+        assert_eq!(statements[2].command, Command::OutputList(vec![String::from("RAM[0]%D2.6.2"), String::from("RAM[1]%D2.6.2")]));
+        assert_eq!(statements[3].line, 5);
+        match &statements[4].command {
+            Command::Repeat(Some(20), body) => assert_eq!(body[0].command, Command::Simulator(vec![String::from("ticktock")])),
+            _ => panic!(),
+        }
+        assert_eq!(statements[5].command, Command::Output);
+    }
+
+    #[test]
+    fn parse_while_and_echo_test() {
+        let source = "echo \"Hold down 'K', please\";\nwhile out <> 75 {\n    tick, tock;\n}\nclear-echo,\n/* block\n comment */ load,";
+        let statements = parse_script(source).ok().unwrap();
+        assert_eq!(statements[0].command, Command::Echo(String::from("Hold down 'K', please")));
+        match &statements[1].command {
+            Command::While(variable, Condition::NotEqual, value, body) => {
+                assert_eq!(variable, "out");
+                assert_eq!(value, "75");
+                assert_eq!(body.len(), 2);
+            },
+            _ => panic!(),
+        }
+        assert_eq!(statements[2].command, Command::ClearEcho);
+        assert_eq!(statements[3].command, Command::Load(None));
+        assert_eq!(statements[3].line, 7);
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        assert!(parse_script("repeat 3 { tick;").is_err());
+        assert!(parse_script("tick; }").is_err());
+        assert!(parse_script("set RAM[0];").is_err());
+        assert_eq!(parse_script("echo \"oops").err().unwrap().0, 1);
+    }
+}
@@ -0,0 +1,20 @@
+//Helpers the tests of every module share
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//The root of the repository, which holds the project directories 01 to 12
+pub const PROJECTS : &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../..");
+
+//Copies a test directory somewhere writable so generated files stay out of the repository
+pub fn scratch_copy(test_directory : &str, label : &str) -> PathBuf {
+    let source = Path::new(PROJECTS).join(test_directory);
+    let name = source.file_name().unwrap().to_string_lossy().to_string();
+    let target = std::env::temp_dir().join(format!("n2t_{}_{}", label, name)).join(&name);
+    fs::create_dir_all(&target).unwrap();
+    for entry in fs::read_dir(&source).unwrap().flatten() {
+        if entry.path().is_file() {
+            fs::copy(entry.path(), target.join(entry.file_name())).unwrap();
+        }
+    }
+    target
+}
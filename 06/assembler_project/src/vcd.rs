@@ -0,0 +1,108 @@
+//Writes Value Change Dump files, which waveform viewers such as GTKWave open. The variables are
+//declared first, in nested scopes; each sample then gives the value of every variable, and only the
+//ones that changed since the last sample are written.
+pub struct Vcd {
+    declarations : String,
+    widths : Vec<usize>,
+    values : Vec<Option<u16>>,
+    changes : String,
+}
+
+//The short name a variable goes by in the value changes: base 94 in the printable characters
+fn code(mut index : usize) -> String {
+    let mut code = String::new();
+    loop {
+        code.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return code;
+        }
+        index -= 1;
+    }
+}
+
+impl Vcd {
+    pub fn new() -> Vcd {
+        Vcd { declarations : String::new(), widths : Vec::new(), values : Vec::new(), changes : String::new() }
+    }
+
+    pub fn scope(&mut self, name : &str) {
+        self.declarations.push_str(&format!("$scope module {} $end\n", name));
+    }
+
+    pub fn upscope(&mut self) {
+        self.declarations.push_str("$upscope $end\n");
+    }
+
+    //Declares a variable in the current scope and returns its index in the samples
+    pub fn variable(&mut self, name : &str, width : usize) -> usize {
+        let index = self.widths.len();
+        let range = if width == 1 { String::new() } else { format!(" [{}:0]", width - 1) };
+        self.declarations.push_str(&format!("$var wire {} {} {}{} $end\n", width, code(index), name, range));
+        self.widths.push(width);
+        self.values.push(None);
+        index
+    }
+
+    //The values of all the variables at a time after the last sample's
+    pub fn sample(&mut self, time : u64, values : &[u16]) {
+        let mut changes = String::new();
+        for (index, value) in values.iter().enumerate() {
+            if self.values[index] == Some(*value) {
+                continue;
+            }
+            self.values[index] = Some(*value);
+            if self.widths[index] == 1 {
+                changes.push_str(&format!("{}{}\n", value & 1, code(index)));
+            }
+            else {
+                changes.push_str(&format!("b{:b} {}\n", value, code(index)));
+            }
+        }
+        if !changes.is_empty() {
+            self.changes.push_str(&format!("#{}\n{}", time, changes));
+        }
+    }
+
+    pub fn finish(&self) -> String {
+        format!("$timescale 1ns $end\n{}$enddefinitions $end\n{}", self.declarations, self.changes)
+    }
+}
+
+impl Default for Vcd {
+    fn default() -> Vcd {
+        Vcd::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vcd::{code, Vcd};
+
+    #[test]
+    fn code_test() {
+        assert_eq!(code(0), "!");
+        assert_eq!(code(93), "~");
+        assert_eq!(code(94), "!!");
+        assert_eq!(code(95), "\"!");
+        assert_eq!(code(94 + 94 * 94), "!!!");
+    }
+
+    #[test]
+    fn changes_test() {
+        let mut vcd = Vcd::new();
+        vcd.scope("Top");
+        let load = vcd.variable("load", 1);
+        let out = vcd.variable("out", 16);
+        vcd.upscope();
+        let mut values = vec![0; 2];
+        vcd.sample(0, &values);
+        values[out] = 5;
+        vcd.sample(1, &values);
+        vcd.sample(2, &values);
+        values[load] = 1;
+        vcd.sample(3, &values);
+        assert_eq!(vcd.finish(), "$timescale 1ns $end\n$scope module Top $end\n$var wire 1 ! load $end\n$var wire 16 \" out [15:0] $end\n$upscope $end\n\
+            $enddefinitions $end\n#0\n0!\nb0 \"\n#1\nb101 \"\n#3\n1!\n");
+    }
+}
@@ -0,0 +1,261 @@
+pub mod parser;
+pub mod code_writer;
+pub mod emulator;
+pub mod simulator;
+pub mod cross_check;
+pub mod optimizer;
+pub mod headless;
+pub mod native;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::assembler::{AssemblyError, assemble_source_lines};
+use code_writer::{CodeWriter, CommandWriter};
+use optimizer::OptimizingWriter;
+use parser::{VmCommand, VmError, VmLine, parse_source};
+
+//One parsed .vm file; its name (without extension) scopes the file's static variables
+#[derive(Clone)]
+pub struct VmFile {
+    pub name : String,
+    pub commands : Vec<VmLine>,
+}
+
+fn io_error(path : &Path, e : std::io::Error) -> VmError {
+    VmError { file_name : path.display().to_string(), line : 0, message : e.to_string() }
+}
+
+pub fn read_vm_file(path : &Path) -> Result<VmFile, VmError> {
+    let source = fs::read_to_string(path).map_err(|e| io_error(path, e))?;
+    let name = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    Ok(VmFile {
+        commands : parse_source(&path.display().to_string(), &source)?,
+        name,
+    })
+}
+
+//The .vm files a path names: the file itself, or every .vm file in a directory, sorted by name
+pub fn vm_file_paths(path : &Path) -> Result<Vec<PathBuf>, VmError> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut paths = fs::read_dir(path).map_err(|e| io_error(path, e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p.extension().map(|e| e == "vm").unwrap_or(false))
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(VmError { file_name : path.display().to_string(), line : 0, message : String::from("no .vm files found") });
+    }
+    Ok(paths)
+}
+
+pub fn read_vm_files(path : &Path) -> Result<Vec<VmFile>, VmError> {
+    vm_file_paths(path)?.iter().map(|p| read_vm_file(p)).collect()
+}
+
+pub fn defines_sys_init(files : &[VmFile]) -> bool {
+    files.iter()
+        .flat_map(|file| file.commands.iter())
+        .any(|line| matches!(&line.command, VmCommand::Function(name, _) if name == "Sys.init"))
+}
+
+fn write_program(writer : &mut dyn CommandWriter, files : &[VmFile], bootstrap : bool) {
+    if bootstrap {
+        writer.write_bootstrap();
+    }
+    for file in files {
+        writer.set_file_name(&file.name);
+        for line in &file.commands {
+            writer.write_command(&line.command);
+        }
+    }
+}
+
+//Translates all files into one program; with bootstrap the program starts by calling Sys.init.
+//The optimizing writer's code is much smaller but no longer maps command by command onto ROM.
+pub fn translate(files : &[VmFile], bootstrap : bool, optimize : bool) -> Vec<String> {
+    if optimize {
+        let mut writer = OptimizingWriter::new();
+        write_program(&mut writer, files, bootstrap);
+        writer.finish()
+    }
+    else {
+        translate_with_addresses(files, bootstrap).0
+    }
+}
+
+//Like translate, but also returns the ROM address at which each command's code starts,
+//followed by the address just past the end of the program
+pub fn translate_with_addresses(files : &[VmFile], bootstrap : bool) -> (Vec<String>, Vec<u16>) {
+    let mut writer = CodeWriter::new();
+    write_program(&mut writer, files, bootstrap);
+    let mut starts = writer.command_starts().to_vec();
+    let lines = writer.finish();
+    starts.push(lines.len());
+
+    let mut addresses = Vec::new();
+    let mut address = 0;
+    let mut line_index = 0;
+    for start in starts {
+        while line_index < start {
+            let line = &lines[line_index];
+            if !line.starts_with("//") && !line.starts_with('(') {
+                address += 1;
+            }
+            line_index += 1;
+        }
+        addresses.push(address);
+    }
+    (lines, addresses)
+}
+
+//Fails only when the program does not fit in ROM, so that its addresses no longer fit in an A-instruction
+pub fn translate_to_machine_lines(files : &[VmFile], bootstrap : bool, optimize : bool) -> Result<Vec<u16>, AssemblyError> {
+    assemble_source_lines("the translated program", &translate(files, bootstrap, optimize))
+}
+
+//The ROM words the naive and the optimizing translation need
+pub fn translation_sizes(files : &[VmFile], bootstrap : bool) -> Result<(usize, usize), AssemblyError> {
+    Ok((translate_to_machine_lines(files, bootstrap, false)?.len(), translate_to_machine_lines(files, bootstrap, true)?.len()))
+}
+
+//Foo/Bar.vm becomes Foo/Bar.<extension>; a directory Foo becomes Foo/Foo.<extension>
+pub fn output_path(path : &Path, extension : &str) -> PathBuf {
+    if path.is_dir() {
+        let name = path.canonicalize().ok()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+            .unwrap_or_else(|| String::from("out"));
+        return path.join(name).with_extension(extension);
+    }
+    path.with_extension(extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::cpu_emulator::simulator::CpuSimulator;
+    use crate::test_script::run_script_file;
+    use crate::test_support::{PROJECTS, scratch_copy};
+    use crate::cpu_emulator::Machine;
+    use crate::vm::{VmFile, read_vm_files, translate, translate_to_machine_lines, translation_sizes, output_path, defines_sys_init};
+    use crate::vm::parser::parse_source;
+
+    fn translate_and_run(test_directory : &str, bootstrap : bool) -> bool {
+        translate_and_run_with(test_directory, bootstrap, false)
+    }
+
+    fn translate_and_run_with(test_directory : &str, bootstrap : bool, optimize : bool) -> bool {
+        let directory = scratch_copy(test_directory, if optimize { "vm_optimized" } else { "vm" });
+        let lines = translate(&read_vm_files(&directory).ok().unwrap(), bootstrap, optimize);
+        let mut text = lines.join("\n");
+        text.push('\n');
+        fs::write(output_path(&directory, "asm"), text).unwrap();
+        let name = directory.file_name().unwrap().to_string_lossy().to_string();
+        let script = directory.join(format!("{}.tst", name));
+        let result = run_script_file(&script.to_string_lossy(), &mut CpuSimulator::new()).ok().unwrap();
+        result.passed()
+    }
+
+    #[test]
+    fn stack_arithmetic_test() {
+        assert!(translate_and_run("07/StackArithmetic/SimpleAdd", false));
+        assert!(translate_and_run("07/StackArithmetic/StackTest", false));
+    }
+
+    //Runs a program without bootstrap code on the CPU emulator and returns what it leaves on the stack
+    fn stack_after(source : &str, optimize : bool) -> Vec<i16> {
+        let file = VmFile { name : String::from("Main"), commands : parse_source("Main.vm", source).ok().unwrap() };
+        let program = translate_to_machine_lines(&[file], false, optimize).ok().unwrap();
+        let mut machine = Machine::new();
+        machine.load_program(&program);
+        machine.ram[0] = 256;
+        while (machine.pc as usize) < program.len() && machine.cycles < 10_000 {
+            machine.step();
+        }
+        machine.ram[256..machine.ram[0] as usize].iter().map(|value| *value as i16).collect()
+    }
+
+    #[test]
+    fn overflowing_comparison_test() {
+        //32767 gt -1, -32768 lt 1 and 1 gt -32768, whose differences overflow, then two that do not
+        let source = "push constant 32767\npush constant 1\nneg\ngt\n\
+                      push constant 32767\nneg\npush constant 1\nsub\npush constant 1\nlt\n\
+                      push constant 1\npush constant 32767\nneg\npush constant 1\nsub\ngt\n\
+                      push constant 2\npush constant 3\nlt\npush constant 3\npush constant 2\nlt\n";
+        for optimize in [false, true].iter() {
+            assert_eq!(stack_after(source, *optimize), vec![-1, -1, -1, -1, 0], "optimize {}", optimize);
+        }
+    }
+
+    #[test]
+    fn memory_access_test() {
+        assert!(translate_and_run("07/MemoryAccess/BasicTest", false));
+        assert!(translate_and_run("07/MemoryAccess/PointerTest", false));
+        assert!(translate_and_run("07/MemoryAccess/StaticTest", false));
+    }
+
+    #[test]
+    fn program_flow_test() {
+        assert!(translate_and_run("08/ProgramFlow/BasicLoop", false));
+        assert!(translate_and_run("08/ProgramFlow/FibonacciSeries", false));
+    }
+
+    #[test]
+    fn function_calls_test() {
+        assert!(translate_and_run("08/FunctionCalls/SimpleFunction", false));
+        assert!(translate_and_run("08/FunctionCalls/NestedCall", false));
+        assert!(translate_and_run("08/FunctionCalls/NestedCall", true));
+    }
+
+    #[test]
+    fn bootstrapped_programs_test() {
+        assert!(translate_and_run("08/FunctionCalls/FibonacciElement", true));
+        assert!(translate_and_run("08/FunctionCalls/StaticsTest", true));
+    }
+
+    #[test]
+    fn statics_are_scoped_per_file_test() {
+        let directory = Path::new(PROJECTS).join("08/FunctionCalls/StaticsTest");
+        let files = read_vm_files(&directory).ok().unwrap();
+        assert_eq!(files.iter().map(|f| f.name.as_str()).collect::<Vec<&str>>(), vec!["Class1", "Class2", "Sys"]);
+        assert!(defines_sys_init(&files));
+        let lines = translate(&files, false, false);
+        assert!(lines.contains(&String::from("@Class1.0")));
+        assert!(lines.contains(&String::from("@Class2.0")));
+        assert_eq!(translate(&files, true, false)[1], "@256");
+    }
+
+    const ALL_TESTS : [(&str, bool); 10] = [
+        ("07/StackArithmetic/SimpleAdd", false),
+        ("07/StackArithmetic/StackTest", false),
+        ("07/MemoryAccess/BasicTest", false),
+        ("07/MemoryAccess/PointerTest", false),
+        ("07/MemoryAccess/StaticTest", false),
+        ("08/ProgramFlow/BasicLoop", false),
+        ("08/ProgramFlow/FibonacciSeries", false),
+        ("08/FunctionCalls/SimpleFunction", false),
+        ("08/FunctionCalls/FibonacciElement", true),
+        ("08/FunctionCalls/StaticsTest", true),
+    ];
+
+    #[test]
+    fn optimized_translation_test() {
+        for (test_directory, bootstrap) in ALL_TESTS.iter() {
+            assert!(translate_and_run_with(test_directory, *bootstrap, true), "{}", test_directory);
+        }
+        assert!(translate_and_run_with("08/FunctionCalls/NestedCall", false, true));
+        assert!(translate_and_run_with("08/FunctionCalls/NestedCall", true, true));
+    }
+
+    #[test]
+    fn optimized_translation_is_smaller_test() {
+        for (test_directory, bootstrap) in ALL_TESTS.iter() {
+            let files = read_vm_files(&Path::new(PROJECTS).join(test_directory)).ok().unwrap();
+            let (naive, optimized) = translation_sizes(&files, *bootstrap).ok().unwrap();
+            assert!(optimized < naive, "{}: {} words optimized, {} naive", test_directory, optimized, naive);
+        }
+    }
+}
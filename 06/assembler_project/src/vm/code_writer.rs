@@ -0,0 +1,283 @@
+use crate::vm::parser::{VmCommand, Segment, ArithmeticCommand};
+
+//Translates VM commands into Hack assembly. The generated code relies on the predefined
+//SP, LCL, ARG, THIS, THAT and R0-R15 symbols the assembler's symbol table preloads.
+pub struct CodeWriter {
+    file_name : String,
+    function_name : String,
+    label_count : usize,
+    return_count : usize,
+    lines : Vec<String>,
+    command_starts : Vec<usize>,
+}
+
+//What the translator needs from a code writer, so the naive and optimizing writers are interchangeable
+pub trait CommandWriter {
+    fn set_file_name(&mut self, file_name : &str);
+    fn write_bootstrap(&mut self);
+    fn write_command(&mut self, command : &VmCommand);
+    fn finish(&mut self) -> Vec<String>;
+}
+
+//The generated lines and the names labels and statics are built from, which both writers keep
+pub trait AssemblyEmitter {
+    fn lines(&mut self) -> &mut Vec<String>;
+    fn file_name(&self) -> &str;
+    fn function_name(&self) -> &str;
+
+    fn emit(&mut self, lines : &[&str]) {
+        self.lines().extend(lines.iter().map(|line| line.to_string()));
+    }
+
+    fn emit_string(&mut self, line : String) {
+        self.lines().push(line);
+    }
+
+    //Labels are scoped to the function they appear in: label LOOP in Main.run is Main.run$LOOP
+    fn scoped_label(&self, label : &str) -> String {
+        if self.function_name().is_empty() {
+            label.to_string()
+        }
+        else {
+            format!("{}${}", self.function_name(), label)
+        }
+    }
+
+    //The fixed RAM address a pointer, temp or static access resolves to
+    fn direct_symbol(&self, segment : Segment, index : u16) -> Option<String> {
+        match segment {
+            Segment::Pointer if index == 0 => Some(String::from("THIS")),
+            Segment::Pointer => Some(String::from("THAT")),
+            Segment::Temp => Some(format!("R{}", 5 + index)),
+            Segment::Static => Some(format!("{}.{}", self.file_name(), index)),
+            _ => None,
+        }
+    }
+}
+
+pub fn segment_base_symbol(segment : Segment) -> Option<&'static str> {
+    match segment {
+        Segment::Local => Some("LCL"),
+        Segment::Argument => Some("ARG"),
+        Segment::This => Some("THIS"),
+        Segment::That => Some("THAT"),
+        _ => None,
+    }
+}
+
+//Code that leaves in D a value with the sign of x - y, for y in D and x on top of the stack. Subtracting
+//numbers of different signs can overflow (32767 - -1 is negative), so then x's sign decides alone.
+pub fn signed_difference(label_prefix : &str) -> Vec<String> {
+    let lines = [
+        "@R13", "M=D", "@SP", "A=M-1", "D=M", "@#.X_NEGATIVE", "D;JLT",
+        "@R13", "D=M", "@#.SUBTRACT", "D;JGE", "D=1", "@#.SIGNED", "0;JMP",
+        "(#.X_NEGATIVE)", "@R13", "D=M", "@#.SUBTRACT", "D;JLT", "D=-1", "@#.SIGNED", "0;JMP",
+        "(#.SUBTRACT)", "@SP", "A=M-1", "D=M-D",
+        "(#.SIGNED)",
+    ];
+    lines.iter().map(|line| line.replace('#', label_prefix)).collect()
+}
+
+impl CodeWriter {
+    pub fn new() -> CodeWriter {
+        CodeWriter {
+            file_name : String::new(),
+            function_name : String::new(),
+            label_count : 0,
+            return_count : 0,
+            lines : Vec::new(),
+            command_starts : Vec::new(),
+        }
+    }
+
+    //The index into the generated lines where each written command's code begins
+    pub fn command_starts(&self) -> &[usize] {
+        &self.command_starts
+    }
+
+    fn push_d(&mut self) {
+        self.emit(&["@SP", "A=M", "M=D", "@SP", "M=M+1"]);
+    }
+
+    fn pop_d(&mut self) {
+        self.emit(&["@SP", "AM=M-1", "D=M"]);
+    }
+
+    fn write_push(&mut self, segment : Segment, index : u16) {
+        if segment == Segment::Constant {
+            self.emit_string(format!("@{}", index));
+            self.emit(&["D=A"]);
+        }
+        else if let Some(base) = segment_base_symbol(segment) {
+            self.emit_string(format!("@{}", base));
+            self.emit(&["D=M"]);
+            self.emit_string(format!("@{}", index));
+            self.emit(&["A=D+A", "D=M"]);
+        }
+        else {
+            let symbol = self.direct_symbol(segment, index).unwrap();
+            self.emit_string(format!("@{}", symbol));
+            self.emit(&["D=M"]);
+        }
+        self.push_d();
+    }
+
+    fn write_pop(&mut self, segment : Segment, index : u16) {
+        if let Some(base) = segment_base_symbol(segment) {
+            self.emit_string(format!("@{}", base));
+            self.emit(&["D=M"]);
+            self.emit_string(format!("@{}", index));
+            self.emit(&["D=D+A", "@R13", "M=D"]);
+            self.pop_d();
+            self.emit(&["@R13", "A=M", "M=D"]);
+        }
+        else {
+            let symbol = self.direct_symbol(segment, index).unwrap();
+            self.pop_d();
+            self.emit_string(format!("@{}", symbol));
+            self.emit(&["M=D"]);
+        }
+    }
+
+    fn write_comparison(&mut self, jump : &str) {
+        let true_label = format!("COMPARE_TRUE.{}", self.label_count);
+        let end_label = format!("COMPARE_END.{}", self.label_count);
+        let prefix = format!("COMPARE.{}", self.label_count);
+        self.label_count += 1;
+        self.pop_d();
+        //Equal numbers are the only ones whose difference is 0, overflow or not
+        if jump == "JEQ" {
+            self.emit(&["A=A-1", "D=M-D"]);
+        }
+        else {
+            self.lines.extend(signed_difference(&prefix));
+        }
+        self.emit_string(format!("@{}", true_label));
+        self.emit_string(format!("D;{}", jump));
+        self.emit(&["@SP", "A=M-1", "M=0"]);
+        self.emit_string(format!("@{}", end_label));
+        self.emit(&["0;JMP"]);
+        self.emit_string(format!("({})", true_label));
+        self.emit(&["@SP", "A=M-1", "M=-1"]);
+        self.emit_string(format!("({})", end_label));
+    }
+
+    fn write_arithmetic(&mut self, command : ArithmeticCommand) {
+        match command {
+            ArithmeticCommand::Add => self.emit(&["@SP", "AM=M-1", "D=M", "A=A-1", "M=D+M"]),
+            ArithmeticCommand::Sub => self.emit(&["@SP", "AM=M-1", "D=M", "A=A-1", "M=M-D"]),
+            ArithmeticCommand::And => self.emit(&["@SP", "AM=M-1", "D=M", "A=A-1", "M=D&M"]),
+            ArithmeticCommand::Or => self.emit(&["@SP", "AM=M-1", "D=M", "A=A-1", "M=D|M"]),
+            ArithmeticCommand::Neg => self.emit(&["@SP", "A=M-1", "M=-M"]),
+            ArithmeticCommand::Not => self.emit(&["@SP", "A=M-1", "M=!M"]),
+            ArithmeticCommand::Eq => self.write_comparison("JEQ"),
+            ArithmeticCommand::Gt => self.write_comparison("JGT"),
+            ArithmeticCommand::Lt => self.write_comparison("JLT"),
+        }
+    }
+
+    fn write_call(&mut self, function_name : &str, arguments : u16) {
+        let return_label = format!("{}$ret.{}", function_name, self.return_count);
+        self.return_count += 1;
+        self.emit_string(format!("@{}", return_label));
+        self.emit(&["D=A"]);
+        self.push_d();
+        for symbol in ["LCL", "ARG", "THIS", "THAT"].iter() {
+            self.emit_string(format!("@{}", symbol));
+            self.emit(&["D=M"]);
+            self.push_d();
+        }
+        self.emit(&["@SP", "D=M"]);
+        self.emit_string(format!("@{}", arguments as u32 + 5));
+        self.emit(&["D=D-A", "@ARG", "M=D", "@SP", "D=M", "@LCL", "M=D"]);
+        self.emit_string(format!("@{}", function_name));
+        self.emit(&["0;JMP"]);
+        self.emit_string(format!("({})", return_label));
+    }
+
+    fn write_return(&mut self) {
+        //R13 holds the frame pointer and R14 the return address while the frame is torn down
+        self.emit(&["@LCL", "D=M", "@R13", "M=D", "@5", "A=D-A", "D=M", "@R14", "M=D"]);
+        self.pop_d();
+        self.emit(&["@ARG", "A=M", "M=D", "@ARG", "D=M+1", "@SP", "M=D"]);
+        for symbol in ["THAT", "THIS", "ARG", "LCL"].iter() {
+            self.emit(&["@R13", "AM=M-1", "D=M"]);
+            self.emit_string(format!("@{}", symbol));
+            self.emit(&["M=D"]);
+        }
+        self.emit(&["@R14", "A=M", "0;JMP"]);
+    }
+
+}
+
+impl CommandWriter for CodeWriter {
+    //Static variables are named after the file being translated: Foo.vm's static 3 is Foo.3
+    fn set_file_name(&mut self, file_name : &str) {
+        self.file_name = file_name.to_string();
+        self.function_name = String::new();
+    }
+
+    //Sets SP to 256 and calls Sys.init, as the VM specification requires of a complete program
+    fn write_bootstrap(&mut self) {
+        self.emit(&["// bootstrap", "@256", "D=A", "@SP", "M=D"]);
+        self.write_call("Sys.init", 0);
+    }
+
+    fn write_command(&mut self, command : &VmCommand) {
+        self.command_starts.push(self.lines.len());
+        self.emit_string(format!("// {}", command));
+        match command {
+            VmCommand::Arithmetic(arithmetic) => self.write_arithmetic(*arithmetic),
+            VmCommand::Push(segment, index) => self.write_push(*segment, *index),
+            VmCommand::Pop(segment, index) => self.write_pop(*segment, *index),
+            VmCommand::Label(label) => {
+                let label = self.scoped_label(label);
+                self.emit_string(format!("({})", label));
+            },
+            VmCommand::Goto(label) => {
+                let label = self.scoped_label(label);
+                self.emit_string(format!("@{}", label));
+                self.emit(&["0;JMP"]);
+            },
+            VmCommand::IfGoto(label) => {
+                let label = self.scoped_label(label);
+                self.pop_d();
+                self.emit_string(format!("@{}", label));
+                self.emit(&["D;JNE"]);
+            },
+            VmCommand::Function(name, locals) => {
+                self.function_name = name.clone();
+                self.emit_string(format!("({})", name));
+                for _ in 0..*locals {
+                    self.emit(&["@SP", "A=M", "M=0", "@SP", "M=M+1"]);
+                }
+            },
+            VmCommand::Call(name, arguments) => self.write_call(name, *arguments),
+            VmCommand::Return => self.write_return(),
+        }
+    }
+
+    fn finish(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lines)
+    }
+}
+
+impl AssemblyEmitter for CodeWriter {
+    fn lines(&mut self) -> &mut Vec<String> {
+        &mut self.lines
+    }
+
+    fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    fn function_name(&self) -> &str {
+        &self.function_name
+    }
+}
+
+impl Default for CodeWriter {
+    fn default() -> CodeWriter {
+        CodeWriter::new()
+    }
+}
@@ -0,0 +1,164 @@
+use std::fmt;
+use std::fmt::Formatter;
+use crate::assembler::assemble_source_lines;
+use crate::cpu_emulator::Machine;
+use crate::vm::{VmFile, translate_with_addresses};
+use crate::vm::emulator::{VmMachine, VmRuntimeError, link};
+
+//The longest a single VM command's translation may run before the check gives up on it
+const MAX_CYCLES_PER_COMMAND : u64 = 10_000;
+const STACK_END : usize = 2048;
+
+//The first point where the translated program and the VM emulator disagree
+pub struct Divergence {
+    pub step : u64,
+    pub command : String,
+    pub detail : String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "after step {} ({}): {}", self.step, self.command, self.detail)
+    }
+}
+
+fn describe_command(vm : &VmMachine, index : usize) -> String {
+    match vm.program.sources.get(index) {
+        Some(source) => format!("{}.vm:{}: {}", source.file_name, source.line, source.command),
+        None => String::from("bootstrap"),
+    }
+}
+
+//Compares the RAM the two machines share. R13-R15 are the translator's scratch registers, the
+//stack above SP is dead, and return addresses are compared through the command-to-ROM map.
+fn compare_ram(vm : &VmMachine, cpu : &Machine, addresses : &[u16]) -> Option<String> {
+    let sp = (vm.ram[0] as usize).min(STACK_END);
+    let live = (0..13).chain(16..sp).chain(STACK_END..vm.ram.len());
+    for address in live {
+        let mut expected = vm.ram[address];
+        if vm.return_slots.contains(&(address as u16)) {
+            match addresses.get(expected as usize) {
+                Some(rom_address) => expected = *rom_address,
+                None => return Some(format!("return address {} in RAM[{}] is not a command", expected, address)),
+            }
+        }
+        if cpu.ram[address] != expected {
+            return Some(format!("RAM[{}] is {} in the VM emulator but {} in the translated program",
+                                address, expected as i16, cpu.ram[address] as i16));
+        }
+    }
+    None
+}
+
+fn run_cpu_to(cpu : &mut Machine, target : u16) -> bool {
+    for _ in 0..MAX_CYCLES_PER_COMMAND {
+        if cpu.pc == target {
+            return true;
+        }
+        cpu.step();
+    }
+    cpu.pc == target
+}
+
+//Runs the VM emulator and the translated Hack program side by side, comparing their RAM after
+//every VM command. Returns the number of commands checked.
+pub fn cross_check(files : &[VmFile], bootstrap : bool, initial_ram : &[(usize, u16)], max_steps : u64) -> Result<u64, Divergence> {
+    let setup_error = |detail : String| Divergence { step : 0, command : String::from("setup"), detail };
+    let (lines, addresses) = translate_with_addresses(files, bootstrap);
+    let mut cpu = Machine::new();
+    cpu.load_program(&assemble_source_lines("the translated program", &lines).map_err(|e| setup_error(e.to_string()))?);
+    let mut vm = VmMachine::new(link(files).map_err(setup_error)?);
+    for (address, value) in initial_ram {
+        cpu.ram[*address] = *value;
+        vm.ram[*address] = *value;
+    }
+    if bootstrap {
+        vm.bootstrap().map_err(setup_error)?;
+    }
+    if !run_cpu_to(&mut cpu, addresses[vm.pc]) {
+        return Err(setup_error(String::from("the bootstrap code never reaches Sys.init")));
+    }
+    if let Some(detail) = compare_ram(&vm, &cpu, &addresses) {
+        return Err(Divergence { step : 0, command : String::from("bootstrap"), detail });
+    }
+
+    for step in 1..=max_steps {
+        let index = vm.pc;
+        match vm.step() {
+            Ok(()) => (),
+            Err(VmRuntimeError::ProgramEnded) => return Ok(step - 1),
+            Err(e) => return Err(Divergence { step, command : describe_command(&vm, index), detail : e.to_string() }),
+        }
+        let target = match addresses.get(vm.pc) {
+            Some(target) => *target,
+            None => return Ok(step),
+        };
+        if !run_cpu_to(&mut cpu, target) {
+            return Err(Divergence {
+                step,
+                command : describe_command(&vm, index),
+                detail : format!("the translated code never reaches ROM[{}], the code of {}", target, describe_command(&vm, vm.pc)),
+            });
+        }
+        if let Some(detail) = compare_ram(&vm, &cpu, &addresses) {
+            return Err(Divergence { step, command : describe_command(&vm, index), detail });
+        }
+    }
+    Ok(max_steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::vm::{read_vm_files, VmFile};
+    use crate::vm::parser::parse_source;
+    use crate::vm::cross_check::cross_check;
+    use crate::test_support::PROJECTS;
+
+    fn check(test_directory : &str, bootstrap : bool, initial_ram : &[(usize, u16)], steps : u64) -> u64 {
+        let files = read_vm_files(&Path::new(PROJECTS).join(test_directory)).ok().unwrap();
+        match cross_check(&files, bootstrap, initial_ram, steps) {
+            Ok(checked) => checked,
+            Err(divergence) => panic!("{}: {}", test_directory, divergence),
+        }
+    }
+
+    #[test]
+    fn project_7_cross_check_test() {
+        assert_eq!(check("07/StackArithmetic/StackTest", false, &[(0, 256)], 100), 38);
+        assert_eq!(check("07/MemoryAccess/BasicTest", false, &[(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)], 100), 25);
+        assert_eq!(check("07/MemoryAccess/StaticTest", false, &[(0, 256)], 100), 11);
+    }
+
+    #[test]
+    fn project_8_cross_check_test() {
+        assert_eq!(check("08/ProgramFlow/FibonacciSeries", false, &[(0, 256), (1, 300), (2, 400), (400, 6), (401, 3000)], 1000), 73);
+        assert_eq!(check("08/FunctionCalls/FibonacciElement", true, &[], 500), 500);
+        assert_eq!(check("08/FunctionCalls/StaticsTest", true, &[], 200), 200);
+    }
+
+    //The emulator compares the numbers themselves, so a translation that compared through an
+    //overflowing x - y would diverge here
+    #[test]
+    fn overflowing_comparison_cross_check_test() {
+        let source = "push constant 32767\npush constant 1\nneg\ngt\n\
+                      push constant 32767\nneg\npush constant 1\nsub\npush constant 1\nlt\n";
+        let file = VmFile { name : String::from("Compare"), commands : parse_source("Compare.vm", source).ok().unwrap() };
+        match cross_check(&[file], false, &[(0, 256)], 100) {
+            Ok(steps) => assert_eq!(steps, 10),
+            Err(divergence) => panic!("{}", divergence),
+        }
+    }
+
+    #[test]
+    fn divergence_is_reported_test() {
+        //Popping into pointer 5 is rejected by the parser, so corrupt the machines' shared view instead:
+        //a program that reads R13, which only the translated code uses as scratch space
+        let source = "push constant 1\npop temp 0\npush constant 3030\npop pointer 0\npush constant 7\npop this 0\n\
+                      push constant 13\npop pointer 1\npush that 0\n";
+        let file = VmFile { name : String::from("Scratch"), commands : parse_source("Scratch.vm", source).ok().unwrap() };
+        let divergence = cross_check(&[file], false, &[(0, 256)], 100).err().unwrap();
+        assert_eq!(divergence.step, 9);
+        assert!(divergence.command.contains("push that 0"));
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Formatter;
+use crate::cpu_emulator::MEMORY_SIZE;
+use crate::vm::VmFile;
+use crate::vm::native::NativeOs;
+use crate::vm::parser::{VmCommand, Segment, ArithmeticCommand};
+
+const SP : usize = 0;
+const LCL : usize = 1;
+const ARG : usize = 2;
+const THIS : usize = 3;
+const THAT : usize = 4;
+const TEMP_BASE : usize = 5;
+const STATIC_BASE : u16 = 16;
+
+//A VM command with its labels, call targets and static variables resolved
+#[derive(Clone, PartialEq, Debug)]
+pub enum Instruction {
+    Arithmetic(ArithmeticCommand),
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Label,
+    Goto(usize),
+    IfGoto(usize),
+    Function(u16),
+    Call(usize, u16),
+    Return,
+}
+
+//Where an instruction came from, for error messages and debugging
+#[derive(Clone, PartialEq, Debug)]
+pub struct InstructionSource {
+    pub file_name : String,
+    pub line : usize,
+    pub function_name : String,
+    pub command : VmCommand,
+}
+
+pub struct Program {
+    pub instructions : Vec<Instruction>,
+    pub sources : Vec<InstructionSource>,
+    pub functions : HashMap<String, usize>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum VmRuntimeError {
+    ProgramEnded,
+    StackUnderflow(usize),
+    BadAddress(usize, i32),
+    UnknownFunction(String),
+    //Sys.halt or Sys.error was reached inside a native OS call
+    Halted,
+}
+
+impl fmt::Display for VmRuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VmRuntimeError::ProgramEnded => write!(f, "the program has no more commands"),
+            VmRuntimeError::StackUnderflow(pc) => write!(f, "stack underflow at command {}", pc),
+            VmRuntimeError::BadAddress(pc, address) => write!(f, "address {} out of range at command {}", address, pc),
+            VmRuntimeError::UnknownFunction(name) => write!(f, "the OS calls {}, which the program does not define", name),
+            VmRuntimeError::Halted => write!(f, "the program halted"),
+        }
+    }
+}
+
+fn scoped_label(function_name : &str, label : &str) -> String {
+    format!("{}${}", function_name, label)
+}
+
+//Resolves labels, calls and statics the way the translator and assembler would: statics get
+//RAM addresses from 16 upwards in order of first use, so both machines agree on their location
+pub fn link(files : &[VmFile]) -> Result<Program, String> {
+    let mut sources = Vec::new();
+    let mut functions = HashMap::new();
+    let mut labels = HashMap::new();
+    for file in files {
+        let mut function_name = String::new();
+        for line in &file.commands {
+            match &line.command {
+                VmCommand::Function(name, _) => {
+                    function_name = name.clone();
+                    if functions.insert(name.clone(), sources.len()).is_some() {
+                        return Err(format!("{}.vm:{}: function {} is defined twice", file.name, line.line, name));
+                    }
+                },
+                VmCommand::Label(label) => {
+                    labels.insert(scoped_label(&function_name, label), sources.len());
+                },
+                _ => (),
+            }
+            sources.push(InstructionSource {
+                file_name : file.name.clone(),
+                line : line.line,
+                function_name : function_name.clone(),
+                command : line.command.clone(),
+            });
+        }
+    }
+
+    let mut statics = HashMap::new();
+    let mut instructions = Vec::new();
+    for source in &sources {
+        let location = || format!("{}.vm:{}", source.file_name, source.line);
+        let find_label = |label : &str| {
+            labels.get(&scoped_label(&source.function_name, label)).copied()
+                .ok_or_else(|| format!("{}: unknown label {}", location(), label))
+        };
+        let instruction = match &source.command {
+            VmCommand::Arithmetic(command) => Instruction::Arithmetic(*command),
+            VmCommand::Push(Segment::Static, index) | VmCommand::Pop(Segment::Static, index) => {
+                let symbol = format!("{}.{}", source.file_name, index);
+                let next_address = STATIC_BASE + statics.len() as u16;
+                let address = *statics.entry(symbol).or_insert(next_address);
+                if let VmCommand::Push(_, _) = source.command {
+                    Instruction::Push(Segment::Static, address)
+                }
+                else {
+                    Instruction::Pop(Segment::Static, address)
+                }
+            },
+            VmCommand::Push(segment, index) => Instruction::Push(*segment, *index),
+            VmCommand::Pop(segment, index) => Instruction::Pop(*segment, *index),
+            VmCommand::Label(_) => Instruction::Label,
+            VmCommand::Goto(label) => Instruction::Goto(find_label(label)?),
+            VmCommand::IfGoto(label) => Instruction::IfGoto(find_label(label)?),
+            VmCommand::Function(_, locals) => Instruction::Function(*locals),
+            VmCommand::Call(name, arguments) => match functions.get(name) {
+                Some(target) => Instruction::Call(*target, *arguments),
+                None => return Err(format!("{}: unknown function {}", location(), name)),
+            },
+            VmCommand::Return => Instruction::Return,
+        };
+        instructions.push(instruction);
+    }
+    Ok(Program { instructions, sources, functions })
+}
+
+//A stack machine that executes VM commands directly on a Hack sized RAM. Frames are laid out
+//exactly as the translated code lays them out, with command indices as return addresses.
+pub struct VmMachine {
+    pub program : Program,
+    pub ram : Vec<u16>,
+    pub pc : usize,
+    pub steps : u64,
+    //Stack addresses that currently hold a return address, innermost frame last
+    pub return_slots : Vec<u16>,
+    //Set once a native Sys.halt or Sys.error stops the program; stepping then does nothing
+    pub halted : bool,
+    pub native : NativeOs,
+}
+
+impl VmMachine {
+    pub fn new(program : Program) -> VmMachine {
+        VmMachine {
+            program,
+            ram : vec![0; MEMORY_SIZE],
+            pc : 0,
+            steps : 0,
+            return_slots : Vec::new(),
+            halted : false,
+            native : NativeOs::new(),
+        }
+    }
+
+    //Programs that define Sys.init start there with the stack at 256, as in the reference VM emulator;
+    //scripts that need another stack set sp themselves
+    pub fn start_at_sys_init(&mut self) {
+        if let Some(start) = self.program.functions.get("Sys.init") {
+            self.pc = *start;
+            self.ram[SP] = 256;
+        }
+    }
+
+    //Does what the translator's bootstrap code does: SP = 256, call Sys.init
+    pub fn bootstrap(&mut self) -> Result<(), String> {
+        let target = *self.program.functions.get("Sys.init").ok_or_else(|| String::from("no Sys.init to bootstrap"))?;
+        self.ram[SP] = 256;
+        //The translated bootstrap returns into the first command of the program
+        self.call(target, 0, 0).map_err(|e| e.to_string())
+    }
+
+    pub fn current_function(&self) -> &str {
+        self.program.sources.get(self.pc).map(|s| s.function_name.as_str()).unwrap_or("")
+    }
+
+    fn address(&self, address : i32) -> Result<usize, VmRuntimeError> {
+        if address < 0 || address as usize >= MEMORY_SIZE {
+            return Err(VmRuntimeError::BadAddress(self.pc, address));
+        }
+        Ok(address as usize)
+    }
+
+    pub fn push(&mut self, value : u16) -> Result<(), VmRuntimeError> {
+        let sp = self.address(self.ram[SP] as i32)?;
+        self.ram[sp] = value;
+        self.ram[SP] = self.ram[SP].wrapping_add(1);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<u16, VmRuntimeError> {
+        if self.ram[SP] == 0 {
+            return Err(VmRuntimeError::StackUnderflow(self.pc));
+        }
+        self.ram[SP] -= 1;
+        let sp = self.address(self.ram[SP] as i32)?;
+        Ok(self.ram[sp])
+    }
+
+    //The RAM address of segment[index]; constant has none
+    pub fn segment_address(&self, segment : Segment, index : u16) -> Result<usize, VmRuntimeError> {
+        let address = match segment {
+            Segment::Local => self.ram[LCL] as i16 as i32 + index as i32,
+            Segment::Argument => self.ram[ARG] as i16 as i32 + index as i32,
+            Segment::This => self.ram[THIS] as i16 as i32 + index as i32,
+            Segment::That => self.ram[THAT] as i16 as i32 + index as i32,
+            Segment::Pointer => (THIS + index as usize) as i32,
+            Segment::Temp => (TEMP_BASE + index as usize) as i32,
+            Segment::Static => index as i32,
+            Segment::Constant => -1,
+        };
+        self.address(address)
+    }
+
+    pub fn call(&mut self, target : usize, arguments : u16, return_index : usize) -> Result<(), VmRuntimeError> {
+        self.return_slots.push(self.ram[SP]);
+        self.push(return_index as u16)?;
+        for register in [LCL, ARG, THIS, THAT].iter() {
+            self.push(self.ram[*register])?;
+        }
+        self.ram[ARG] = self.ram[SP].wrapping_sub(arguments + 5);
+        self.ram[LCL] = self.ram[SP];
+        self.pc = target;
+        Ok(())
+    }
+
+    fn do_return(&mut self) -> Result<(), VmRuntimeError> {
+        let frame = self.ram[LCL] as i32;
+        let return_address = self.ram[self.address(frame - 5)?];
+        let value = self.pop()?;
+        let argument = self.address(self.ram[ARG] as i32)?;
+        self.ram[argument] = value;
+        self.ram[SP] = self.ram[ARG].wrapping_add(1);
+        for (offset, register) in [THAT, THIS, ARG, LCL].iter().enumerate() {
+            self.ram[*register] = self.ram[self.address(frame - 1 - offset as i32)?];
+        }
+        self.return_slots.pop();
+        self.pc = return_address as usize;
+        Ok(())
+    }
+
+    fn arithmetic(&mut self, command : ArithmeticCommand) -> Result<(), VmRuntimeError> {
+        let result = match command {
+            ArithmeticCommand::Neg => (self.pop()? as i16).wrapping_neg() as u16,
+            ArithmeticCommand::Not => !self.pop()?,
+            _ => {
+                let y = self.pop()?;
+                let x = self.pop()?;
+                let truth = |condition : bool| if condition { 0xFFFF } else { 0 };
+                match command {
+                    ArithmeticCommand::Add => x.wrapping_add(y),
+                    ArithmeticCommand::Sub => x.wrapping_sub(y),
+                    ArithmeticCommand::And => x & y,
+                    ArithmeticCommand::Or => x | y,
+                    ArithmeticCommand::Eq => truth(x == y),
+                    ArithmeticCommand::Gt => truth((x as i16) > (y as i16)),
+                    _ => truth((x as i16) < (y as i16)),
+                }
+            },
+        };
+        self.push(result)
+    }
+
+    //Labels are markers rather than commands, so stepping passes over them without counting them
+    fn skip_labels(&mut self) {
+        while let Some(Instruction::Label) = self.program.instructions.get(self.pc) {
+            self.pc += 1;
+        }
+    }
+
+    //Executes one VM command
+    pub fn step(&mut self) -> Result<(), VmRuntimeError> {
+        if self.halted {
+            return Ok(());
+        }
+        self.skip_labels();
+        let instruction = match self.program.instructions.get(self.pc) {
+            Some(instruction) => instruction.clone(),
+            None => return Err(VmRuntimeError::ProgramEnded),
+        };
+        self.steps += 1;
+        let mut next_pc = self.pc + 1;
+        match instruction {
+            Instruction::Arithmetic(command) => self.arithmetic(command)?,
+            Instruction::Push(Segment::Constant, value) => self.push(value)?,
+            Instruction::Push(segment, index) => {
+                let address = self.segment_address(segment, index)?;
+                self.push(self.ram[address])?;
+            },
+            Instruction::Pop(segment, index) => {
+                let address = self.segment_address(segment, index)?;
+                let value = self.pop()?;
+                self.ram[address] = value;
+            },
+            Instruction::Label => (),
+            Instruction::Goto(target) => next_pc = target,
+            Instruction::IfGoto(target) => {
+                if self.pop()? != 0 {
+                    next_pc = target;
+                }
+            },
+            Instruction::Function(locals) => {
+                for _ in 0..locals {
+                    self.push(0)?;
+                }
+            },
+            Instruction::Call(target, arguments) if self.native.intercepts(target) => {
+                self.call_native(target, arguments)?;
+                next_pc = self.pc;
+            },
+            Instruction::Call(target, arguments) => {
+                self.call(target, arguments, self.pc + 1)?;
+                next_pc = self.pc;
+            },
+            Instruction::Return => {
+                self.do_return()?;
+                next_pc = self.pc;
+            },
+        }
+        self.pc = next_pc;
+        self.skip_labels();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::{VmFile, parser::parse_source};
+    use crate::vm::emulator::{link, VmMachine, VmRuntimeError};
+
+    fn machine_for(source : &str) -> VmMachine {
+        let file = VmFile { name : String::from("Test"), commands : parse_source("Test.vm", source).ok().unwrap() };
+        VmMachine::new(link(&[file]).ok().unwrap())
+    }
+
+    #[test]
+    fn arithmetic_test() {
+        let mut machine = machine_for("push constant 7\npush constant 8\nadd\npush constant 20\nlt\n\
+                                       push constant 3\nneg\npush constant 5\ngt\n");
+        machine.ram[0] = 256;
+        for _ in 0..9 {
+            machine.step().ok().unwrap();
+        }
+        assert_eq!(machine.ram[0], 258);
+        assert_eq!(machine.ram[256], 0xFFFF);
+        assert_eq!(machine.ram[257], 0);
+        assert_eq!(machine.step(), Err(VmRuntimeError::ProgramEnded));
+    }
+
+    #[test]
+    fn call_and_return_test() {
+        let source = "function Sys.init 0\npush constant 4\ncall Test.double 1\nlabel END\ngoto END\n\
+                      function Test.double 1\npush argument 0\npush argument 0\nadd\npop local 0\npush local 0\nreturn\n";
+        let mut machine = machine_for(source);
+        machine.bootstrap().ok().unwrap();
+        assert_eq!(machine.current_function(), "Sys.init");
+        for _ in 0..11 {
+            machine.step().ok().unwrap();
+        }
+        assert_eq!(machine.ram[0], 262);
+        assert_eq!(machine.ram[261], 8);
+        assert_eq!(machine.current_function(), "Sys.init");
+        assert_eq!(machine.return_slots, vec![256]);
+    }
+
+    #[test]
+    fn link_errors_test() {
+        let file = VmFile { name : String::from("Test"), commands : parse_source("Test.vm", "goto NOWHERE\n").ok().unwrap() };
+        assert!(link(&[file]).is_err());
+        let file = VmFile { name : String::from("Test"), commands : parse_source("Test.vm", "call Foo.bar 0\n").ok().unwrap() };
+        assert!(link(&[file]).is_err());
+    }
+}
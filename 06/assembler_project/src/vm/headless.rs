@@ -0,0 +1,245 @@
+use crate::jack::os::KEYBOARD;
+use crate::vm::emulator::{Instruction, VmMachine, VmRuntimeError};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Outcome {
+    //The program reached Sys.halt
+    Halted,
+    //The program called Sys.error with this code; the OS halts after printing it
+    Error(u16),
+    StepLimit,
+}
+
+//Reads a key script: every character is typed as itself, except \n (new line, 128), \b (backspace, 129)
+//and {code} for any other key code, e.g. {137} for page down
+pub fn parse_keys(text : &str) -> Result<Vec<u16>, String> {
+    let mut keys = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\\' => match chars.next() {
+                Some('n') => 128,
+                Some('b') => 129,
+                Some('\\') => '\\' as u16,
+                other => return Err(format!("unknown escape \\{}", other.map(String::from).unwrap_or_default())),
+            },
+            '{' => {
+                let code = chars.by_ref().take_while(|c| *c != '}').collect::<String>();
+                code.parse::<u16>().map_err(|_e| format!("bad key code {{{}}}", code))?
+            },
+            _ => c as u16,
+        };
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+//Runs a program without a display or a keyboard until it halts, fails or uses up its steps.
+//The keys are typed one at a time as the program calls Keyboard.keyPressed: a key goes down when
+//the program asks for one and comes up the next time it asks, which is what readChar waits for.
+//Calls are watched rather than function entries, so native OS functions are seen as well.
+pub fn run_headless(machine : &mut VmMachine, keys : &[u16], max_steps : u64) -> Result<Outcome, VmRuntimeError> {
+    let entry = |name : &str| machine.program.functions.get(name).copied();
+    let (halt, error, key_pressed) = (entry("Sys.halt"), entry("Sys.error"), entry("Keyboard.keyPressed"));
+    let mut keys = keys.iter();
+    let mut error_code = None;
+    let mut steps = 0;
+    while steps < max_steps {
+        if machine.halted || Some(machine.pc) == halt {
+            return Ok(error_code.or(machine.native.error_code).map(Outcome::Error).unwrap_or(Outcome::Halted));
+        }
+        if let Some(Instruction::Call(target, _)) = machine.program.instructions.get(machine.pc) {
+            let target = Some(*target);
+            if target == error {
+                //The code is the argument on top of the stack, unless the program has wrecked SP
+                let top = (machine.ram[0] as usize).checked_sub(1).ok_or(VmRuntimeError::StackUnderflow(machine.pc))?;
+                error_code = Some(*machine.ram.get(top).ok_or(VmRuntimeError::BadAddress(machine.pc, top as i32))?);
+            }
+            if target == key_pressed {
+                machine.ram[KEYBOARD] = if machine.ram[KEYBOARD] != 0 { 0 } else { keys.next().copied().unwrap_or(0) };
+            }
+        }
+        match machine.step() {
+            Err(VmRuntimeError::ProgramEnded) => return Ok(Outcome::Halted),
+            result => result?,
+        }
+        steps += 1;
+    }
+    Ok(Outcome::StepLimit)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use crate::jack::os::{screen_text, SCREEN};
+    use crate::pipeline::load_vm_program;
+    use crate::vm::VmFile;
+    use crate::vm::parser::parse_source;
+    use crate::vm::emulator::{link, VmMachine};
+    use crate::vm::headless::{parse_keys, run_headless, Outcome};
+    use crate::test_support::PROJECTS;
+
+    fn run_path(path : &Path, keys : &str, max_steps : u64, native : bool) -> (Outcome, VmMachine) {
+        let (files, os_files) = load_vm_program(path).ok().unwrap();
+        let program = files.iter().chain(os_files.iter()).cloned().collect::<Vec<VmFile>>();
+        let mut machine = VmMachine::new(link(&program).ok().unwrap());
+        if native {
+            machine.use_native_os(&os_files.iter().map(|file| file.name.clone()).collect::<Vec<String>>());
+        }
+        machine.bootstrap().ok().unwrap();
+        let outcome = run_headless(&mut machine, &parse_keys(keys).ok().unwrap(), max_steps).ok().unwrap();
+        (outcome, machine)
+    }
+
+    //Runs the program on the Jack OS and on the native OS, which must leave the same screen behind
+    fn run_program(directory : &str, keys : &str) -> (Outcome, VmMachine) {
+        let path = Path::new(PROJECTS).join(directory);
+        let (outcome, machine) = run_path(&path, keys, 20_000_000, false);
+        let (native_outcome, native_machine) = run_path(&path, keys, 20_000_000, true);
+        assert_eq!(native_outcome, outcome);
+        assert!(native_machine.steps < machine.steps);
+        assert!(native_machine.ram[SCREEN..SCREEN + 8192] == machine.ram[SCREEN..SCREEN + 8192], "{}", directory);
+        (outcome, machine)
+    }
+
+    fn pixel(machine : &VmMachine, x : usize, y : usize) -> bool {
+        machine.ram[SCREEN + y * 32 + x / 16] & (1 << (x % 16)) != 0
+    }
+
+    #[test]
+    fn sys_error_with_wrecked_stack_test() {
+        //this 0 is RAM[0], so the pop leaves SP at 0 when Sys.error is called
+        let source = "function Sys.init 0\npush constant 0\npop pointer 0\npush constant 0\npop this 0\ncall Sys.error 0\n\
+                      function Sys.error 0\npush constant 0\nreturn\n";
+        let file = VmFile { name : String::from("Sys"), commands : parse_source("Sys.vm", source).ok().unwrap() };
+        let mut machine = VmMachine::new(link(&[file]).ok().unwrap());
+        machine.bootstrap().ok().unwrap();
+        assert_eq!(run_headless(&mut machine, &[], 100).err().unwrap().to_string(), "stack underflow at command 5");
+    }
+
+    #[test]
+    fn parse_keys_test() {
+        assert_eq!(parse_keys("a3\\n{137}\\b\\\\").ok().unwrap(), vec![97, 51, 128, 137, 129, 92]);
+        assert!(parse_keys("{x}").is_err());
+        assert!(parse_keys("\\q").is_err());
+    }
+
+    #[test]
+    fn string_test_runs_headless_test() {
+        let (outcome, machine) = run_program("12/StringTest", "");
+        assert_eq!(outcome, Outcome::Halted);
+        assert_eq!(screen_text(&machine.ram), vec![
+            "new,appendChar: abcde",
+            "setInt: 12345",
+            "setInt: -32767",
+            "length: 5",
+            "charAt[2]: 99",
+            "setCharAt(2,'-'): ab-de",
+            "eraseLastChar: ab-d",
+            "intValue: 456",
+            "intValue: -32123",
+            "backSpace: 129",
+            "doubleQuote: 34",
+            "newLine: 128",
+        ]);
+    }
+
+    #[test]
+    fn output_test_runs_headless_test() {
+        let (outcome, machine) = run_program("12/OutputTest", "");
+        assert_eq!(outcome, Outcome::Halted);
+        let mut expected = vec![String::new(); 23];
+        expected[0] = format!("A{}B", " ".repeat(62));
+        expected[2] = String::from("0123456789");
+        expected[3] = String::from("ABCDEFGHIJKLMNOPQRSTUVWXYZ abcdefghijklmnopqrstuvwxyz");
+        expected[4] = String::from("!#$%&'()*+,-./:;<=>?@[\\]^_`{|}~\"");
+        expected[5] = String::from("-12346789");
+        expected[22] = format!("C{}D", " ".repeat(62));
+        assert_eq!(screen_text(&machine.ram), expected);
+    }
+
+    #[test]
+    fn screen_test_runs_headless_test() {
+        let (outcome, machine) = run_program("12/ScreenTest", "");
+        assert_eq!(outcome, Outcome::Halted);
+        //Base line, house wall, door, window, door handle, roof and sun
+        assert!((0..512).all(|x| pixel(&machine, x, 220)));
+        assert!(pixel(&machine, 280, 100) && pixel(&machine, 410, 219) && pixel(&machine, 340, 200));
+        assert!(!pixel(&machine, 370, 200) && !pixel(&machine, 300, 130));
+        assert!(pixel(&machine, 360, 170));
+        assert!(pixel(&machine, 345, 35) && pixel(&machine, 312, 63) && !pixel(&machine, 345, 30));
+        assert!(pixel(&machine, 140, 60) && pixel(&machine, 140, 6) && pixel(&machine, 86, 60));
+        assert!(!pixel(&machine, 0, 0) && !pixel(&machine, 511, 255));
+    }
+
+    #[test]
+    fn keyboard_test_runs_headless_test() {
+        let (outcome, machine) = run_program("12/KeyboardTest", "{137}3JAX\\bCK\\n-32123\\n");
+        assert_eq!(outcome, Outcome::Halted);
+        let lines = screen_text(&machine.ram);
+        assert_eq!(lines.iter().filter(|line| *line == "ok").count(), 4);
+        assert!(lines.contains(&String::from("Please press the number '3': 3")));
+        assert!(lines.contains(&String::from("Please type 'JACK' and press enter: JACK")));
+        assert!(lines.contains(&String::from("Please type '-32123' and press enter: -32123")));
+        assert_eq!(lines.last().unwrap(), "Test completed successfully");
+    }
+
+    #[test]
+    fn sys_test_runs_headless_test() {
+        let (outcome, machine) = run_program("12/SysTest", "x");
+        assert_eq!(outcome, Outcome::Halted);
+        assert_eq!(screen_text(&machine.ram).last().unwrap(), "Time is up. Make sure that 2 seconds elapsed.");
+        //Without a key the program waits forever
+        assert_eq!(run_path(&Path::new(PROJECTS).join("12/SysTest"), "", 100_000, true).0, Outcome::StepLimit);
+    }
+
+    #[test]
+    fn sys_error_is_reported_test() {
+        let directory = std::env::temp_dir().join("n2t_headless").join("DivideByZero");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("Main.jack"), "class Main { function void main() { do Output.printInt(1 / 0); return; } }").unwrap();
+        for native in [false, true].iter() {
+            let (outcome, machine) = run_path(&directory, "", 1_000_000, *native);
+            assert_eq!(outcome, Outcome::Error(3));
+            assert_eq!(screen_text(&machine.ram), vec!["ERR3"]);
+        }
+    }
+
+    #[test]
+    fn native_calls_with_missing_arguments_test() {
+        //The native OS reads a missing argument as 0 rather than looking past the ones passed
+        let directory = std::env::temp_dir().join("n2t_headless").join("ShortCalls");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("Main.vm"), "function Main.main 0\ncall String.length 0\npop temp 0\n\
+                                              call Output.printInt 0\npop temp 0\npush constant 100\n\
+                                              call Screen.drawPixel 1\npop temp 0\npush constant 0\nreturn\n").unwrap();
+        let (outcome, machine) = run_path(&directory, "", 100_000, true);
+        assert_eq!(outcome, Outcome::Halted);
+        assert!(screen_text(&machine.ram)[0].starts_with("0 "));
+        assert!(pixel(&machine, 100, 0));
+    }
+
+    #[test]
+    fn program_classes_replace_native_os_classes_test() {
+        //A student's own Math runs as VM code while the rest of the OS stays native
+        let directory = std::env::temp_dir().join("n2t_headless").join("OwnMath");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("Main.jack"), "class Main { function void main() {
+            do Output.printInt(6 * 7);
+            if (Math.calls() > 0) { do Output.printString(\" by hand\"); }
+            return;
+        } }").unwrap();
+        let math = include_str!("../jack/os/Math.jack")
+            .replacen("class Math {", "class Math {\n    static int calls;", 1)
+            .replacen("function int multiply(", "function int calls() { return calls; }
+    function int multiply(int x, int y) { let calls = calls + 1; return Math.shippedMultiply(x, y); }
+    function int shippedMultiply(", 1);
+        fs::write(directory.join("Math.jack"), math).unwrap();
+        let (outcome, machine) = run_path(&directory, "", 1_000_000, true);
+        assert_eq!(outcome, Outcome::Halted);
+        assert_eq!(screen_text(&machine.ram), vec!["42 by hand"]);
+        assert!(!machine.native.intercepts(machine.program.functions["Math.multiply"]));
+        assert!(machine.native.intercepts(machine.program.functions["Output.printInt"]));
+    }
+}
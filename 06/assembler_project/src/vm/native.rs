@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use crate::cpu_emulator::MEMORY_SIZE;
+use crate::jack::os::{char_maps, GLYPH_HEIGHT, KEYBOARD, SCREEN};
+use crate::vm::emulator::{VmMachine, VmRuntimeError};
+
+//The OS functions the VM emulator can run natively. Keyboard.readChar, readLine and readInt wait
+//for the user and Sys.init calls Main.main, so they always run as VM code, calling the native
+//functions in turn; the Jack OS's private helpers are only ever called from VM code.
+const NATIVE_FUNCTIONS : [&str; 45] = [
+    "Math.init", "Math.abs", "Math.multiply", "Math.divide", "Math.sqrt", "Math.max", "Math.min",
+    "Memory.init", "Memory.peek", "Memory.poke", "Memory.alloc", "Memory.deAlloc",
+    "Array.new", "Array.dispose",
+    "String.new", "String.dispose", "String.length", "String.charAt", "String.setCharAt", "String.appendChar",
+    "String.eraseLastChar", "String.intValue", "String.setInt", "String.newLine", "String.backSpace", "String.doubleQuote",
+    "Output.init", "Output.moveCursor", "Output.printChar", "Output.printString", "Output.printInt", "Output.println",
+    "Output.backSpace",
+    "Screen.init", "Screen.clearScreen", "Screen.setColor", "Screen.drawPixel", "Screen.drawLine", "Screen.drawRectangle",
+    "Screen.drawCircle",
+    "Keyboard.init", "Keyboard.keyPressed",
+    "Sys.halt", "Sys.wait", "Sys.error",
+];
+
+const HEAP_BASE : usize = 2048;
+const HEAP_END : usize = 16384;
+const NEW_LINE : u16 = 128;
+const BACKSPACE : u16 = 129;
+const TEXT_ROWS : usize = 23;
+const TEXT_COLUMNS : usize = 64;
+
+//The state of the native OS classes; functions of classes that are not native are left to the program's VM code
+pub struct NativeOs {
+    //The command index of each intercepted function
+    functions : HashMap<usize, &'static str>,
+    //The code of the last Sys.error raised, natively or through a native call
+    pub error_code : Option<u16>,
+    free_list : usize,
+    color : bool,
+    cursor_row : usize,
+    cursor_column : usize,
+    char_maps : HashMap<u16, [u16; GLYPH_HEIGHT]>,
+}
+
+impl NativeOs {
+    pub fn new() -> NativeOs {
+        NativeOs {
+            functions : HashMap::new(),
+            error_code : None,
+            free_list : HEAP_BASE,
+            color : true,
+            cursor_row : 0,
+            cursor_column : 0,
+            char_maps : HashMap::new(),
+        }
+    }
+
+    pub fn intercepts(&self, target : usize) -> bool {
+        self.functions.contains_key(&target)
+    }
+}
+
+impl Default for NativeOs {
+    fn default() -> NativeOs {
+        NativeOs::new()
+    }
+}
+
+//Integer square root by binary search over the result's bits, as Math.sqrt does it
+fn square_root(x : u16) -> u16 {
+    let mut y = 0u32;
+    for bit in (0..8).rev() {
+        let candidate = y + (1 << bit);
+        if candidate * candidate <= x as u32 {
+            y = candidate;
+        }
+    }
+    y as u16
+}
+
+impl VmMachine {
+    //Intercepts calls to the standard functions of the given OS classes from now on
+    pub fn use_native_os(&mut self, classes : &[String]) {
+        let functions = NATIVE_FUNCTIONS.iter()
+            .filter(|name| classes.iter().any(|class| name.split('.').next() == Some(class.as_str())))
+            .filter_map(|name| self.program.functions.get(*name).map(|target| (*target, *name)))
+            .collect::<HashMap<usize, &'static str>>();
+        if !functions.is_empty() && self.native.char_maps.is_empty() {
+            self.native.char_maps = char_maps();
+        }
+        self.native.functions = functions;
+    }
+
+    //Runs an intercepted call: pops its arguments, pushes its result and moves past the call.
+    //A native Sys.halt or Sys.error leaves the machine halted instead.
+    pub fn call_native(&mut self, target : usize, arguments : u16) -> Result<(), VmRuntimeError> {
+        let name = self.native.functions[&target];
+        let mut values = vec![0; arguments as usize];
+        for value in values.iter_mut().rev() {
+            *value = self.pop()?;
+        }
+        match self.native_function(name, &values) {
+            Ok(result) => {
+                self.push(result)?;
+                self.pc += 1;
+                Ok(())
+            },
+            Err(VmRuntimeError::Halted) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    //Calls an OS function from native code: natively when its class is native, otherwise by running
+    //the program's VM code for it until it returns
+    fn invoke(&mut self, name : &str, arguments : &[u16]) -> Result<u16, VmRuntimeError> {
+        let target = *self.program.functions.get(name).ok_or_else(|| VmRuntimeError::UnknownFunction(name.to_string()))?;
+        if let Some(native_name) = self.native.functions.get(&target) {
+            return self.native_function(native_name, arguments);
+        }
+        let halt = self.program.functions.get("Sys.halt").copied();
+        for argument in arguments {
+            self.push(*argument)?;
+        }
+        let depth = self.return_slots.len();
+        let resume = self.pc;
+        self.call(target, arguments.len() as u16, resume)?;
+        while self.return_slots.len() > depth {
+            if self.halted || Some(self.pc) == halt {
+                self.halted = true;
+                return Err(VmRuntimeError::Halted);
+            }
+            self.step()?;
+        }
+        self.pop()
+    }
+
+    //Raises an OS error the way the Jack OS does, through Sys.error, which never returns
+    fn os_error(&mut self, code : u16) -> Result<u16, VmRuntimeError> {
+        self.native.error_code = Some(code);
+        self.invoke("Sys.error", &[code])?;
+        self.halted = true;
+        Err(VmRuntimeError::Halted)
+    }
+
+    fn halt(&mut self) -> Result<u16, VmRuntimeError> {
+        self.halted = true;
+        Err(VmRuntimeError::Halted)
+    }
+
+    fn ram_address(&self, address : u16) -> Result<usize, VmRuntimeError> {
+        if address as usize >= MEMORY_SIZE {
+            return Err(VmRuntimeError::BadAddress(self.pc, address as i32));
+        }
+        Ok(address as usize)
+    }
+
+    fn native_function(&mut self, name : &str, arguments : &[u16]) -> Result<u16, VmRuntimeError> {
+        let argument = |i : usize| arguments.get(i).copied().unwrap_or(0);
+        let signed = |i : usize| argument(i) as i16;
+        match name {
+            "Math.init" | "Keyboard.init" => Ok(0),
+            "Math.abs" => Ok(signed(0).wrapping_abs() as u16),
+            "Math.multiply" => Ok(signed(0).wrapping_mul(signed(1)) as u16),
+            "Math.divide" if argument(1) == 0 => self.os_error(3),
+            "Math.divide" => Ok(signed(0).wrapping_div(signed(1)) as u16),
+            "Math.sqrt" if signed(0) < 0 => self.os_error(4),
+            "Math.sqrt" => Ok(square_root(argument(0))),
+            "Math.max" => Ok(signed(0).max(signed(1)) as u16),
+            "Math.min" => Ok(signed(0).min(signed(1)) as u16),
+
+            "Memory.init" => {
+                self.native.free_list = HEAP_BASE;
+                self.ram[HEAP_BASE] = (HEAP_END - HEAP_BASE - 2) as u16;
+                self.ram[HEAP_BASE + 1] = 0;
+                Ok(0)
+            },
+            "Memory.peek" => Ok(self.ram[self.ram_address(argument(0))?]),
+            "Memory.poke" => {
+                let address = self.ram_address(argument(0))?;
+                self.ram[address] = argument(1);
+                Ok(0)
+            },
+            "Memory.alloc" if signed(0) < 1 => self.os_error(5),
+            "Memory.alloc" => self.alloc(argument(0)),
+            "Memory.deAlloc" => {
+                let segment = self.ram_address(argument(0).wrapping_sub(1))?;
+                self.ram[segment] = self.ram[segment].wrapping_sub(1);
+                self.ram[segment + 1] = self.native.free_list as u16;
+                self.native.free_list = segment;
+                Ok(0)
+            },
+
+            "Array.new" if signed(0) < 1 => self.os_error(2),
+            "Array.new" => self.invoke("Memory.alloc", &[argument(0)]),
+            "Array.dispose" => self.invoke("Memory.deAlloc", &[argument(0)]),
+
+            "String.newLine" => Ok(NEW_LINE),
+            "String.backSpace" => Ok(BACKSPACE),
+            "String.doubleQuote" => Ok(34),
+            "String.new" if signed(0) < 0 => self.os_error(14),
+            _ if name.starts_with("String.") => self.string_function(name, arguments),
+
+            "Output.init" => {
+                self.native.cursor_row = 0;
+                self.native.cursor_column = 0;
+                Ok(0)
+            },
+            _ if name.starts_with("Output.") => self.output_function(name, arguments),
+
+            "Screen.init" => {
+                self.native.color = true;
+                Ok(0)
+            },
+            _ if name.starts_with("Screen.") => self.screen_function(name, arguments),
+
+            "Keyboard.keyPressed" => Ok(self.ram[KEYBOARD]),
+
+            "Sys.halt" => self.halt(),
+            "Sys.wait" if signed(0) < 0 => self.os_error(1),
+            //There is no clock to wait for in the emulator
+            "Sys.wait" => Ok(0),
+            "Sys.error" => {
+                self.native.error_code = Some(argument(0));
+                for c in format!("ERR{}", signed(0)).chars() {
+                    self.invoke("Output.printChar", &[c as u16])?;
+                }
+                self.halt()
+            },
+            _ => Err(VmRuntimeError::UnknownFunction(name.to_string())),
+        }
+    }
+
+    //First fit over the free list, with the same layout as the Jack OS's Memory class: a free segment
+    //holds its usable size and the next segment, an allocated block is preceded by its size
+    fn alloc(&mut self, size : u16) -> Result<u16, VmRuntimeError> {
+        let mut segment = self.native.free_list;
+        while segment != 0 {
+            if self.ram[segment] > size {
+                self.ram[segment] -= size + 1;
+                let block = segment + self.ram[segment] as usize + 3;
+                self.ram[block - 1] = size;
+                return Ok(block as u16);
+            }
+            segment = self.ram[segment + 1] as usize;
+        }
+        self.os_error(6)
+    }
+
+    //Strings are laid out like the Jack OS's: the character array, the length and the capacity
+    fn string_function(&mut self, name : &str, arguments : &[u16]) -> Result<u16, VmRuntimeError> {
+        let argument = |i : usize| arguments.get(i).copied().unwrap_or(0);
+        if name == "String.new" {
+            let this = self.invoke("Memory.alloc", &[3])?;
+            let chars = if argument(0) > 0 { self.invoke("Array.new", &[argument(0)])? } else { 0 };
+            let this_address = self.ram_address(this)?;
+            self.ram[this_address] = chars;
+            self.ram[this_address + 1] = 0;
+            self.ram[this_address + 2] = argument(0);
+            return Ok(this);
+        }
+        let this = self.ram_address(argument(0))?;
+        let (chars, length, capacity) = (self.ram[this], self.ram[this + 1], self.ram[this + 2]);
+        let index = argument(1);
+        let in_range = (index as i16) >= 0 && index < length;
+        match name {
+            "String.dispose" => {
+                if capacity > 0 {
+                    self.invoke("Array.dispose", &[chars])?;
+                }
+                self.invoke("Memory.deAlloc", &[argument(0)])
+            },
+            "String.length" => Ok(length),
+            "String.charAt" if !in_range => self.os_error(15),
+            "String.charAt" => Ok(self.ram[self.ram_address(chars.wrapping_add(index))?]),
+            "String.setCharAt" if !in_range => self.os_error(16),
+            "String.setCharAt" => {
+                let address = self.ram_address(chars.wrapping_add(index))?;
+                self.ram[address] = argument(2);
+                Ok(0)
+            },
+            "String.appendChar" if length == capacity => self.os_error(17),
+            "String.appendChar" => {
+                let address = self.ram_address(chars.wrapping_add(length))?;
+                self.ram[address] = argument(1);
+                self.ram[this + 1] = length + 1;
+                Ok(argument(0))
+            },
+            "String.eraseLastChar" if length == 0 => self.os_error(18),
+            "String.eraseLastChar" => {
+                self.ram[this + 1] = length - 1;
+                Ok(0)
+            },
+            "String.intValue" => {
+                let text = (0..length)
+                    .map(|i| self.ram[(chars as usize + i as usize) % MEMORY_SIZE])
+                    .collect::<Vec<u16>>();
+                let negative = text.first() == Some(&('-' as u16));
+                let value = text.iter().skip(if negative { 1 } else { 0 })
+                    .take_while(|c| (b'0' as u16..=b'9' as u16).contains(c))
+                    .fold(0i16, |value, c| value.wrapping_mul(10).wrapping_add((c - b'0' as u16) as i16));
+                Ok(if negative { value.wrapping_neg() as u16 } else { value as u16 })
+            },
+            "String.setInt" => {
+                let text = (argument(1) as i16).to_string();
+                if text.len() > capacity as usize {
+                    return self.os_error(19);
+                }
+                for (i, c) in text.bytes().enumerate() {
+                    let address = self.ram_address(chars.wrapping_add(i as u16))?;
+                    self.ram[address] = c as u16;
+                }
+                self.ram[this + 1] = text.len() as u16;
+                Ok(0)
+            },
+            _ => Err(VmRuntimeError::UnknownFunction(name.to_string())),
+        }
+    }
+
+    //Draws a character into the cell under the cursor, as Output.drawChar does
+    fn draw_char(&mut self, c : u16) {
+        let rows = self.native.char_maps.get(&c).or_else(|| self.native.char_maps.get(&0)).copied().unwrap_or_default();
+        let mut address = SCREEN + self.native.cursor_row * GLYPH_HEIGHT * 32 + self.native.cursor_column / 2;
+        for row in rows.iter() {
+            self.ram[address] = if self.native.cursor_column.is_multiple_of(2) {
+                (self.ram[address] & 0xFF00) | row
+            }
+            else {
+                (self.ram[address] & 0x00FF) | (row << 8)
+            };
+            address += 32;
+        }
+    }
+
+    fn println(&mut self) {
+        self.native.cursor_column = 0;
+        self.native.cursor_row = (self.native.cursor_row + 1) % TEXT_ROWS;
+    }
+
+    fn print_char(&mut self, c : u16) {
+        match c {
+            NEW_LINE => self.println(),
+            BACKSPACE => {
+                if self.native.cursor_column > 0 {
+                    self.native.cursor_column -= 1;
+                }
+                else if self.native.cursor_row > 0 {
+                    self.native.cursor_row -= 1;
+                    self.native.cursor_column = TEXT_COLUMNS - 1;
+                }
+                self.draw_char(' ' as u16);
+            },
+            _ => {
+                self.draw_char(c);
+                self.native.cursor_column += 1;
+                if self.native.cursor_column == TEXT_COLUMNS {
+                    self.println();
+                }
+            },
+        }
+    }
+
+    fn output_function(&mut self, name : &str, arguments : &[u16]) -> Result<u16, VmRuntimeError> {
+        let argument = |i : usize| arguments.get(i).copied().unwrap_or(0);
+        match name {
+            "Output.moveCursor" => {
+                let (row, column) = (argument(0) as i16, argument(1) as i16);
+                if row < 0 || row >= TEXT_ROWS as i16 || column < 0 || column >= TEXT_COLUMNS as i16 {
+                    return self.os_error(20);
+                }
+                self.native.cursor_row = row as usize;
+                self.native.cursor_column = column as usize;
+                self.draw_char(' ' as u16);
+            },
+            "Output.printChar" => self.print_char(argument(0)),
+            "Output.printString" => {
+                let length = self.invoke("String.length", &[argument(0)])?;
+                for i in 0..length {
+                    let c = self.invoke("String.charAt", &[argument(0), i])?;
+                    self.print_char(c);
+                }
+            },
+            "Output.printInt" => {
+                for c in (argument(0) as i16).to_string().chars() {
+                    self.print_char(c as u16);
+                }
+            },
+            "Output.println" => self.println(),
+            "Output.backSpace" => self.print_char(BACKSPACE),
+            _ => return Err(VmRuntimeError::UnknownFunction(name.to_string())),
+        }
+        Ok(0)
+    }
+
+    fn draw_masked(&mut self, address : usize, mask : u16) {
+        if self.native.color {
+            self.ram[SCREEN + address] |= mask;
+        }
+        else {
+            self.ram[SCREEN + address] &= !mask;
+        }
+    }
+
+    //Draws the pixels x1..x2 of row y a word at a time, as Screen.drawHorizontal does
+    fn draw_horizontal(&mut self, x1 : usize, x2 : usize, y : usize) {
+        let (first, last) = (y * 32 + x1 / 16, y * 32 + x2 / 16);
+        let first_mask = !((1u16 << (x1 % 16)) - 1);
+        let last_mask = if x2 % 16 == 15 { 0xFFFF } else { (1u16 << (x2 % 16 + 1)) - 1 };
+        if first == last {
+            self.draw_masked(first, first_mask & last_mask);
+            return;
+        }
+        self.draw_masked(first, first_mask);
+        for address in first + 1..last {
+            self.ram[SCREEN + address] = if self.native.color { 0xFFFF } else { 0 };
+        }
+        self.draw_masked(last, last_mask);
+    }
+
+    fn screen_function(&mut self, name : &str, arguments : &[u16]) -> Result<u16, VmRuntimeError> {
+        let on_screen = |x : i16, y : i16| (0..512).contains(&x) && (0..256).contains(&y);
+        let value = |i : usize| arguments.get(i).copied().unwrap_or(0) as i16;
+        match name {
+            "Screen.clearScreen" => {
+                for word in &mut self.ram[SCREEN..KEYBOARD] {
+                    *word = 0;
+                }
+            },
+            "Screen.setColor" => self.native.color = value(0) != 0,
+            "Screen.drawPixel" if !on_screen(value(0), value(1)) => return self.os_error(7),
+            "Screen.drawPixel" => {
+                let (x, y) = (value(0) as usize, value(1) as usize);
+                self.draw_masked(y * 32 + x / 16, 1 << (x % 16));
+            },
+            "Screen.drawLine" if !on_screen(value(0), value(1)) || !on_screen(value(2), value(3)) => return self.os_error(8),
+            "Screen.drawLine" => {
+                let (mut x1, mut y1, mut x2, mut y2) = (value(0), value(1), value(2), value(3));
+                if x1 > x2 {
+                    std::mem::swap(&mut x1, &mut x2);
+                    std::mem::swap(&mut y1, &mut y2);
+                }
+                if y1 == y2 {
+                    self.draw_horizontal(x1 as usize, x2 as usize, y1 as usize);
+                    return Ok(0);
+                }
+                let (dx, dy, y_step) = (x2 - x1, (y2 - y1).abs(), if y2 < y1 { -1 } else { 1 });
+                let (mut a, mut b, mut diff) = (0, 0, 0);
+                //The same walk as Screen.drawLine, so both draw the same pixels
+                while a <= dx && b <= dy {
+                    let (x, y) = ((x1 + a) as usize, (y1 + b * y_step) as usize);
+                    self.draw_masked(y * 32 + x / 16, 1 << (x % 16));
+                    if diff < 0 {
+                        a += 1;
+                        diff += dy;
+                    }
+                    else {
+                        b += 1;
+                        diff -= dx;
+                    }
+                }
+            },
+            "Screen.drawRectangle" if value(0) > value(2) || value(1) > value(3) || !on_screen(value(0), value(1))
+                                      || !on_screen(value(2), value(3)) => return self.os_error(9),
+            "Screen.drawRectangle" => {
+                for y in value(1)..=value(3) {
+                    self.draw_horizontal(value(0) as usize, value(2) as usize, y as usize);
+                }
+            },
+            "Screen.drawCircle" if !on_screen(value(0), value(1)) => return self.os_error(12),
+            "Screen.drawCircle" if value(2) < 0 || value(2) > 181 => return self.os_error(13),
+            "Screen.drawCircle" => {
+                let (x, y, r) = (value(0) as i32, value(1) as i32, value(2) as i32);
+                for dy in -r..=r {
+                    if y + dy >= 0 && y + dy < 256 {
+                        let half_width = square_root((r * r - dy * dy) as u16) as i32;
+                        self.draw_horizontal((x - half_width).max(0) as usize, (x + half_width).min(511) as usize, (y + dy) as usize);
+                    }
+                }
+            },
+            _ => return Err(VmRuntimeError::UnknownFunction(name.to_string())),
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::native::square_root;
+
+    #[test]
+    fn square_root_test() {
+        assert_eq!(square_root(0), 0);
+        assert_eq!(square_root(9), 3);
+        assert_eq!(square_root(10), 3);
+        assert_eq!(square_root(32767), 181);
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::BTreeSet;
+use crate::vm::code_writer::{AssemblyEmitter, CommandWriter, segment_base_symbol, signed_difference};
+use crate::vm::parser::{VmCommand, Segment, ArithmeticCommand};
+
+//Translates VM commands into smaller Hack assembly than CodeWriter. Calls, returns and comparisons
+//jump to shared routines emitted once after the program, and the value a command would push is
+//kept in D while the next command can consume it, so push/pop and push/arithmetic pairs never
+//touch the stack.
+pub struct OptimizingWriter {
+    file_name : String,
+    function_name : String,
+    label_count : usize,
+    return_count : usize,
+    lines : Vec<String>,
+    pending : Pending,
+    routines : BTreeSet<Routine>,
+}
+
+//Where the right operand of a deferred push or binary operation lives
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Operand {
+    D,
+    Constant(u16),
+}
+
+//The top of the stack as the generated code has left it so far
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Pending {
+    //The stack is up to date
+    Nothing,
+    //One more value belongs on top of the stack
+    Push(Operand),
+    //The stack top is x; the real top is x <op> operand
+    Binary(ArithmeticCommand, Operand),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Routine {
+    Call,
+    Return,
+    Eq,
+    Gt,
+    Lt,
+}
+
+//Locals and arguments up to this index are reached by incrementing A rather than adding the index
+const SHORT_INDEX : u16 = 6;
+
+impl OptimizingWriter {
+    pub fn new() -> OptimizingWriter {
+        OptimizingWriter {
+            file_name : String::new(),
+            function_name : String::new(),
+            label_count : 0,
+            return_count : 0,
+            lines : Vec::new(),
+            pending : Pending::Nothing,
+            routines : BTreeSet::new(),
+        }
+    }
+
+    //Loads a constant operand into D; D operands are already there
+    fn load_operand(&mut self, operand : Operand) {
+        match operand {
+            Operand::D => (),
+            Operand::Constant(0) => self.emit(&["D=0"]),
+            Operand::Constant(1) => self.emit(&["D=1"]),
+            Operand::Constant(value) => {
+                self.emit_string(format!("@{}", value));
+                self.emit(&["D=A"]);
+            },
+        }
+    }
+
+    //The comp part computing x <op> operand with x in M, loading the operand first when it needs D
+    fn binary_comp(&mut self, command : ArithmeticCommand, operand : Operand) -> &'static str {
+        match (command, operand) {
+            (ArithmeticCommand::Add, Operand::Constant(0)) | (ArithmeticCommand::Sub, Operand::Constant(0)) |
+            (ArithmeticCommand::Or, Operand::Constant(0)) => "M",
+            (ArithmeticCommand::And, Operand::Constant(0)) => "0",
+            (ArithmeticCommand::Add, Operand::Constant(1)) => "M+1",
+            (ArithmeticCommand::Sub, Operand::Constant(1)) => "M-1",
+            _ => {
+                self.load_operand(operand);
+                match command {
+                    ArithmeticCommand::Add => "D+M",
+                    ArithmeticCommand::Sub => "M-D",
+                    ArithmeticCommand::And => "D&M",
+                    _ => "D|M",
+                }
+            },
+        }
+    }
+
+    //Brings the stack up to date
+    fn flush(&mut self) {
+        match self.pending {
+            Pending::Nothing => (),
+            Pending::Push(Operand::Constant(value)) if value <= 1 => {
+                self.emit(&["@SP", "A=M"]);
+                self.emit_string(format!("M={}", value));
+                self.emit(&["@SP", "M=M+1"]);
+            },
+            Pending::Push(operand) => {
+                self.load_operand(operand);
+                self.emit(&["@SP", "A=M", "M=D", "@SP", "M=M+1"]);
+            },
+            Pending::Binary(command, operand) => {
+                let comp = self.binary_comp(command, operand);
+                self.emit(&["@SP", "A=M-1"]);
+                self.emit_string(format!("M={}", comp));
+            },
+        }
+        self.pending = Pending::Nothing;
+    }
+
+    //Pops the top of the stack into D
+    fn pop_d(&mut self) {
+        match self.pending {
+            Pending::Nothing => self.emit(&["@SP", "AM=M-1", "D=M"]),
+            Pending::Push(operand) => self.load_operand(operand),
+            Pending::Binary(command, operand) => {
+                let comp = self.binary_comp(command, operand);
+                self.emit(&["@SP", "AM=M-1"]);
+                self.emit_string(format!("D={}", comp));
+            },
+        }
+        self.pending = Pending::Nothing;
+    }
+
+    //Points A at base[index] without touching D
+    fn address_short_index(&mut self, base : &str, index : u16) {
+        self.emit_string(format!("@{}", base));
+        self.emit(&["A=M"]);
+        for _ in 0..index {
+            self.emit(&["A=A+1"]);
+        }
+    }
+
+    fn write_push(&mut self, segment : Segment, index : u16) {
+        self.flush();
+        if segment == Segment::Constant {
+            self.pending = Pending::Push(Operand::Constant(index));
+            return;
+        }
+        if let Some(base) = segment_base_symbol(segment) {
+            if index <= 1 {
+                self.address_short_index(base, index);
+            }
+            else {
+                self.emit_string(format!("@{}", base));
+                self.emit(&["D=M"]);
+                self.emit_string(format!("@{}", index));
+                self.emit(&["A=D+A"]);
+            }
+        }
+        else {
+            let symbol = self.direct_symbol(segment, index).unwrap();
+            self.emit_string(format!("@{}", symbol));
+        }
+        self.emit(&["D=M"]);
+        self.pending = Pending::Push(Operand::D);
+    }
+
+    fn write_pop(&mut self, segment : Segment, index : u16) {
+        if let Some(base) = segment_base_symbol(segment) {
+            if index <= SHORT_INDEX {
+                self.pop_d();
+                self.address_short_index(base, index);
+                self.emit(&["M=D"]);
+                return;
+            }
+            //The address goes to R13 first, so D must not hold a deferred value yet
+            if let Pending::Push(Operand::D) | Pending::Binary(_, Operand::D) = self.pending {
+                self.flush();
+            }
+            self.emit_string(format!("@{}", base));
+            self.emit(&["D=M"]);
+            self.emit_string(format!("@{}", index));
+            self.emit(&["D=D+A", "@R13", "M=D"]);
+            self.pop_d();
+            self.emit(&["@R13", "A=M", "M=D"]);
+        }
+        else {
+            let symbol = self.direct_symbol(segment, index).unwrap();
+            if let Pending::Push(Operand::Constant(value)) = self.pending {
+                if value <= 1 {
+                    self.pending = Pending::Nothing;
+                    self.emit_string(format!("@{}", symbol));
+                    self.emit_string(format!("M={}", value));
+                    return;
+                }
+            }
+            self.pop_d();
+            self.emit_string(format!("@{}", symbol));
+            self.emit(&["M=D"]);
+        }
+    }
+
+    fn jump_to_routine(&mut self, routine : Routine, return_label : &str) {
+        self.routines.insert(routine);
+        self.emit_string(format!("@{}", return_label));
+        self.emit(&["D=A"]);
+        self.emit_string(format!("@{}", routine_label(routine)));
+        self.emit(&["0;JMP"]);
+        self.emit_string(format!("({})", return_label));
+    }
+
+    fn write_arithmetic(&mut self, command : ArithmeticCommand) {
+        match command {
+            ArithmeticCommand::Add | ArithmeticCommand::Sub | ArithmeticCommand::And | ArithmeticCommand::Or => {
+                match self.pending {
+                    Pending::Nothing => {
+                        let comp = match command {
+                            ArithmeticCommand::Add => "M=D+M",
+                            ArithmeticCommand::Sub => "M=M-D",
+                            ArithmeticCommand::And => "M=D&M",
+                            _ => "M=D|M",
+                        };
+                        self.emit(&["@SP", "AM=M-1", "D=M", "A=A-1", comp]);
+                    },
+                    Pending::Push(operand) => self.pending = Pending::Binary(command, operand),
+                    Pending::Binary(_, _) => {
+                        self.pop_d();
+                        self.pending = Pending::Binary(command, Operand::D);
+                    },
+                }
+            },
+            ArithmeticCommand::Neg | ArithmeticCommand::Not => {
+                let comp = if command == ArithmeticCommand::Neg { "-" } else { "!" };
+                if self.pending == Pending::Nothing {
+                    self.emit(&["@SP", "A=M-1"]);
+                    self.emit_string(format!("M={}M", comp));
+                }
+                else {
+                    self.pop_d();
+                    self.emit_string(format!("D={}D", comp));
+                    self.pending = Pending::Push(Operand::D);
+                }
+            },
+            ArithmeticCommand::Eq | ArithmeticCommand::Gt | ArithmeticCommand::Lt => {
+                self.flush();
+                let routine = match command {
+                    ArithmeticCommand::Eq => Routine::Eq,
+                    ArithmeticCommand::Gt => Routine::Gt,
+                    _ => Routine::Lt,
+                };
+                let return_label = format!("COMPARE_END.{}", self.label_count);
+                self.label_count += 1;
+                self.jump_to_routine(routine, &return_label);
+            },
+        }
+    }
+
+    //R13 holds the argument count and R14 the callee; the call routine receives the return address in D
+    fn write_call(&mut self, function_name : &str, arguments : u16) {
+        self.flush();
+        let return_label = format!("{}$ret.{}", function_name, self.return_count);
+        self.return_count += 1;
+        if arguments <= 1 {
+            self.emit_string(String::from("@R13"));
+            self.emit_string(format!("M={}", arguments));
+        }
+        else {
+            self.emit_string(format!("@{}", arguments));
+            self.emit(&["D=A", "@R13", "M=D"]);
+        }
+        self.emit_string(format!("@{}", function_name));
+        self.emit(&["D=A", "@R14", "M=D"]);
+        self.jump_to_routine(Routine::Call, &return_label);
+    }
+
+    fn write_return(&mut self) {
+        self.routines.insert(Routine::Return);
+        if self.pending == Pending::Nothing {
+            self.emit(&["@VM$RETURN", "0;JMP"]);
+        }
+        else {
+            self.pop_d();
+            self.emit(&["@VM$RETURN_VALUE", "0;JMP"]);
+        }
+    }
+
+    fn write_function(&mut self, name : &str, locals : u16) {
+        self.function_name = name.to_string();
+        self.emit_string(format!("({})", name));
+        match locals {
+            0 => (),
+            1 => self.emit(&["@SP", "A=M", "M=0", "@SP", "M=M+1"]),
+            _ => {
+                self.emit(&["@SP", "A=M"]);
+                for _ in 1..locals {
+                    self.emit(&["M=0", "A=A+1"]);
+                }
+                self.emit(&["M=0", "D=A+1", "@SP", "M=D"]);
+            },
+        }
+    }
+
+    fn write_routine(&mut self, routine : Routine) {
+        self.emit_string(format!("({})", routine_label(routine)));
+        match routine {
+            Routine::Call => {
+                self.emit(&["@SP", "A=M", "M=D"]);
+                for symbol in ["LCL", "ARG", "THIS", "THAT"].iter() {
+                    self.emit_string(format!("@{}", symbol));
+                    self.emit(&["D=M", "@SP", "AM=M+1", "M=D"]);
+                }
+                self.emit(&["@SP", "MD=M+1", "@LCL", "M=D", "@R13", "D=D-M", "@5", "D=D-A", "@ARG", "M=D",
+                            "@R14", "A=M", "0;JMP"]);
+            },
+            Routine::Return => {
+                //R15 holds the return value, R13 the frame pointer and R14 the return address,
+                //which must be read before the return value overwrites it when there are no arguments
+                self.emit(&["@SP", "AM=M-1", "D=M", "(VM$RETURN_VALUE)", "@R15", "M=D",
+                            "@LCL", "D=M", "@R13", "M=D", "@5", "A=D-A", "D=M", "@R14", "M=D",
+                            "@R15", "D=M", "@ARG", "A=M", "M=D", "@ARG", "D=M+1", "@SP", "M=D"]);
+                for symbol in ["THAT", "THIS", "ARG", "LCL"].iter() {
+                    self.emit(&["@R13", "AM=M-1", "D=M"]);
+                    self.emit_string(format!("@{}", symbol));
+                    self.emit(&["M=D"]);
+                }
+                self.emit(&["@R14", "A=M", "0;JMP"]);
+            },
+            Routine::Eq | Routine::Gt | Routine::Lt => {
+                let label = routine_label(routine);
+                let jump = match routine {
+                    Routine::Eq => "D;JEQ",
+                    Routine::Gt => "D;JGT",
+                    _ => "D;JLT",
+                };
+                self.emit(&["@R15", "M=D", "@SP", "AM=M-1", "D=M"]);
+                if routine == Routine::Eq {
+                    self.emit(&["A=A-1", "D=M-D"]);
+                }
+                else {
+                    self.lines.extend(signed_difference(label));
+                    self.emit(&["@SP", "A=M-1"]);
+                }
+                self.emit(&["M=-1"]);
+                self.emit_string(format!("@{}.TRUE", label));
+                self.emit(&[jump, "@SP", "A=M-1", "M=0"]);
+                self.emit_string(format!("({}.TRUE)", label));
+                self.emit(&["@R15", "A=M", "0;JMP"]);
+            },
+        }
+    }
+}
+
+fn routine_label(routine : Routine) -> &'static str {
+    match routine {
+        Routine::Call => "VM$CALL",
+        Routine::Return => "VM$RETURN",
+        Routine::Eq => "VM$EQ",
+        Routine::Gt => "VM$GT",
+        Routine::Lt => "VM$LT",
+    }
+}
+
+impl CommandWriter for OptimizingWriter {
+    fn set_file_name(&mut self, file_name : &str) {
+        self.flush();
+        self.file_name = file_name.to_string();
+        self.function_name = String::new();
+    }
+
+    fn write_bootstrap(&mut self) {
+        self.emit(&["// bootstrap", "@256", "D=A", "@SP", "M=D"]);
+        self.write_call("Sys.init", 0);
+    }
+
+    fn write_command(&mut self, command : &VmCommand) {
+        self.emit_string(format!("// {}", command));
+        match command {
+            VmCommand::Arithmetic(arithmetic) => self.write_arithmetic(*arithmetic),
+            VmCommand::Push(segment, index) => self.write_push(*segment, *index),
+            VmCommand::Pop(segment, index) => self.write_pop(*segment, *index),
+            VmCommand::Label(label) => {
+                self.flush();
+                let label = self.scoped_label(label);
+                self.emit_string(format!("({})", label));
+            },
+            VmCommand::Goto(label) => {
+                self.flush();
+                let label = self.scoped_label(label);
+                self.emit_string(format!("@{}", label));
+                self.emit(&["0;JMP"]);
+            },
+            VmCommand::IfGoto(label) => {
+                let label = self.scoped_label(label);
+                self.pop_d();
+                self.emit_string(format!("@{}", label));
+                self.emit(&["D;JNE"]);
+            },
+            VmCommand::Function(name, locals) => {
+                self.flush();
+                self.write_function(name, *locals);
+            },
+            VmCommand::Call(name, arguments) => self.write_call(name, *arguments),
+            VmCommand::Return => self.write_return(),
+        }
+    }
+
+    //The shared routines follow the program behind a halt loop, so running off its end stops there
+    fn finish(&mut self) -> Vec<String> {
+        self.flush();
+        if !self.routines.is_empty() {
+            self.emit(&["// shared routines", "(VM$HALT)", "@VM$HALT", "0;JMP"]);
+            for routine in self.routines.clone() {
+                self.write_routine(routine);
+            }
+        }
+        std::mem::take(&mut self.lines)
+    }
+}
+
+impl AssemblyEmitter for OptimizingWriter {
+    fn lines(&mut self) -> &mut Vec<String> {
+        &mut self.lines
+    }
+
+    fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    fn function_name(&self) -> &str {
+        &self.function_name
+    }
+}
+
+impl Default for OptimizingWriter {
+    fn default() -> OptimizingWriter {
+        OptimizingWriter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::code_writer::CommandWriter;
+    use crate::vm::optimizer::OptimizingWriter;
+    use crate::vm::parser::parse_source;
+
+    fn optimize(source : &str) -> Vec<String> {
+        let mut writer = OptimizingWriter::new();
+        writer.set_file_name("Test");
+        for line in parse_source("Test.vm", source).ok().unwrap() {
+            writer.write_command(&line.command);
+        }
+        writer.finish().into_iter().filter(|line| !line.starts_with("//")).collect()
+    }
+
+    #[test]
+    fn push_constant_folds_into_arithmetic_test() {
+        assert_eq!(optimize("push local 0\npush constant 1\nadd\npop local 0"),
+                   vec!["@LCL", "A=M", "D=M", "@SP", "A=M", "M=D", "@SP", "M=M+1",
+                        "@SP", "AM=M-1", "D=M+1", "@LCL", "A=M", "M=D"]);
+        assert_eq!(optimize("push constant 7\npop temp 2"), vec!["@7", "D=A", "@R7", "M=D"]);
+        assert_eq!(optimize("push constant 0\npop static 1"), vec!["@Test.1", "M=0"]);
+    }
+
+    #[test]
+    fn shared_routines_are_emitted_once_test() {
+        let lines = optimize("function Test.f 0\npush argument 0\npush argument 1\nlt\nreturn\n\
+                              function Test.g 0\npush constant 1\npush constant 2\ncall Test.f 2\nreturn");
+        assert_eq!(lines.iter().filter(|l| *l == "(VM$LT)").count(), 1);
+        assert_eq!(lines.iter().filter(|l| *l == "(VM$CALL)").count(), 1);
+        assert_eq!(lines.iter().filter(|l| *l == "(VM$RETURN)").count(), 1);
+        assert!(!lines.contains(&String::from("(VM$EQ)")));
+    }
+}
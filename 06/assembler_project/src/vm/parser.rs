@@ -0,0 +1,251 @@
+use std::fmt;
+use std::fmt::Formatter;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Segment {
+    Local,
+    Argument,
+    This,
+    That,
+    Pointer,
+    Temp,
+    Static,
+    Constant,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ArithmeticCommand {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum VmCommand {
+    Arithmetic(ArithmeticCommand),
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Label(String),
+    Goto(String),
+    IfGoto(String),
+    Function(String, u16),
+    Call(String, u16),
+    Return,
+}
+
+//A parsed command together with the source line it came from
+#[derive(Clone, PartialEq, Debug)]
+pub struct VmLine {
+    pub line : usize,
+    pub command : VmCommand,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct VmError {
+    pub file_name : String,
+    pub line : usize,
+    pub message : String,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file_name, self.line, self.message)
+    }
+}
+
+impl Segment {
+    pub fn from_name(name : &str) -> Option<Segment> {
+        match name {
+            "local" => Some(Segment::Local),
+            "argument" => Some(Segment::Argument),
+            "this" => Some(Segment::This),
+            "that" => Some(Segment::That),
+            "pointer" => Some(Segment::Pointer),
+            "temp" => Some(Segment::Temp),
+            "static" => Some(Segment::Static),
+            "constant" => Some(Segment::Constant),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Segment::Local => "local",
+            Segment::Argument => "argument",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Pointer => "pointer",
+            Segment::Temp => "temp",
+            Segment::Static => "static",
+            Segment::Constant => "constant",
+        }
+    }
+}
+
+impl ArithmeticCommand {
+    pub fn from_name(name : &str) -> Option<ArithmeticCommand> {
+        match name {
+            "add" => Some(ArithmeticCommand::Add),
+            "sub" => Some(ArithmeticCommand::Sub),
+            "neg" => Some(ArithmeticCommand::Neg),
+            "eq" => Some(ArithmeticCommand::Eq),
+            "gt" => Some(ArithmeticCommand::Gt),
+            "lt" => Some(ArithmeticCommand::Lt),
+            "and" => Some(ArithmeticCommand::And),
+            "or" => Some(ArithmeticCommand::Or),
+            "not" => Some(ArithmeticCommand::Not),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArithmeticCommand::Add => "add",
+            ArithmeticCommand::Sub => "sub",
+            ArithmeticCommand::Neg => "neg",
+            ArithmeticCommand::Eq => "eq",
+            ArithmeticCommand::Gt => "gt",
+            ArithmeticCommand::Lt => "lt",
+            ArithmeticCommand::And => "and",
+            ArithmeticCommand::Or => "or",
+            ArithmeticCommand::Not => "not",
+        }
+    }
+}
+
+impl fmt::Display for VmCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VmCommand::Arithmetic(command) => write!(f, "{}", command.name()),
+            VmCommand::Push(segment, index) => write!(f, "push {} {}", segment.name(), index),
+            VmCommand::Pop(segment, index) => write!(f, "pop {} {}", segment.name(), index),
+            VmCommand::Label(label) => write!(f, "label {}", label),
+            VmCommand::Goto(label) => write!(f, "goto {}", label),
+            VmCommand::IfGoto(label) => write!(f, "if-goto {}", label),
+            VmCommand::Function(name, locals) => write!(f, "function {} {}", name, locals),
+            VmCommand::Call(name, arguments) => write!(f, "call {} {}", name, arguments),
+            VmCommand::Return => write!(f, "return"),
+        }
+    }
+}
+
+fn is_symbol(text : &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if !c.is_ascii_digit() => (),
+        _ => return false,
+    }
+    text.chars().all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c))
+}
+
+fn parse_symbol(text : &str) -> Result<String, String> {
+    if is_symbol(text) {
+        Ok(text.to_string())
+    }
+    else {
+        Err(format!("'{}' is not a valid symbol", text))
+    }
+}
+
+fn parse_number(text : &str) -> Result<u16, String> {
+    match text.parse::<u16>() {
+        Ok(number) if number <= 0x7FFF => Ok(number),
+        _ => Err(format!("'{}' is not a number between 0 and 32767", text)),
+    }
+}
+
+fn parse_segment_access(segment : &str, index : &str, is_pop : bool) -> Result<(Segment, u16), String> {
+    let segment = Segment::from_name(segment).ok_or_else(|| format!("unknown segment '{}'", segment))?;
+    let index = parse_number(index)?;
+    match segment {
+        Segment::Constant if is_pop => Err(String::from("cannot pop to the constant segment")),
+        Segment::Pointer if index > 1 => Err(format!("pointer index {} is out of range 0..1", index)),
+        Segment::Temp if index > 7 => Err(format!("temp index {} is out of range 0..7", index)),
+        _ => Ok((segment, index)),
+    }
+}
+
+pub fn parse_line(line : &str) -> Result<Option<VmCommand>, String> {
+    let line = match line.find("//") {
+        Some(comment_index) => &line[..comment_index],
+        None => line,
+    };
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    let command = match words.as_slice() {
+        [] => return Ok(None),
+        [name] if ArithmeticCommand::from_name(name).is_some() => {
+            VmCommand::Arithmetic(ArithmeticCommand::from_name(name).unwrap())
+        },
+        ["return"] => VmCommand::Return,
+        ["push", segment, index] => {
+            let (segment, index) = parse_segment_access(segment, index, false)?;
+            VmCommand::Push(segment, index)
+        },
+        ["pop", segment, index] => {
+            let (segment, index) = parse_segment_access(segment, index, true)?;
+            VmCommand::Pop(segment, index)
+        },
+        ["label", label] => VmCommand::Label(parse_symbol(label)?),
+        ["goto", label] => VmCommand::Goto(parse_symbol(label)?),
+        ["if-goto", label] => VmCommand::IfGoto(parse_symbol(label)?),
+        ["function", name, locals] => VmCommand::Function(parse_symbol(name)?, parse_number(locals)?),
+        ["call", name, arguments] => VmCommand::Call(parse_symbol(name)?, parse_number(arguments)?),
+        _ => return Err(format!("unknown command '{}'", words.join(" "))),
+    };
+    Ok(Some(command))
+}
+
+pub fn parse_source(file_name : &str, source : &str) -> Result<Vec<VmLine>, VmError> {
+    let mut commands = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        match parse_line(line) {
+            Ok(Some(command)) => commands.push(VmLine { line : index + 1, command }),
+            Ok(None) => (),
+            Err(message) => return Err(VmError { file_name : file_name.to_string(), line : index + 1, message }),
+        }
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::parser::{parse_line, parse_source, VmCommand, Segment, ArithmeticCommand};
+
+    #[test]
+    fn parse_line_test() {
+        assert_eq!(parse_line("push constant 7").unwrap(), Some(VmCommand::Push(Segment::Constant, 7)));
+        assert_eq!(parse_line("pop local 0         // sum = 0").unwrap(), Some(VmCommand::Pop(Segment::Local, 0)));
+        assert_eq!(parse_line("\tadd").unwrap(), Some(VmCommand::Arithmetic(ArithmeticCommand::Add)));
+        assert_eq!(parse_line("if-goto LOOP_START").unwrap(), Some(VmCommand::IfGoto(String::from("LOOP_START"))));
+        assert_eq!(parse_line("function Sys.init 0").unwrap(), Some(VmCommand::Function(String::from("Sys.init"), 0)));
+        assert_eq!(parse_line("call Main.fibonacci 1").unwrap(), Some(VmCommand::Call(String::from("Main.fibonacci"), 1)));
+        assert_eq!(parse_line("return").unwrap(), Some(VmCommand::Return));
+        assert_eq!(parse_line("// comment").unwrap(), None);
+    }
+
+    #[test]
+    fn parse_errors_test() {
+        assert!(parse_line("pop constant 1").is_err());
+        assert!(parse_line("push pointer 2").is_err());
+        assert!(parse_line("push temp 8").is_err());
+        assert!(parse_line("push heap 1").is_err());
+        assert!(parse_line("label 1ABC").is_err());
+        assert!(parse_line("add 1").is_err());
+        let error = parse_source("Bad.vm", "push constant 1\n\nfoo\n").err().unwrap();
+        assert_eq!(error.to_string(), "Bad.vm:3: unknown command 'foo'");
+    }
+
+    #[test]
+    fn display_round_trip_test() {
+        let source = "push static 3\nlabel END\ncall Math.multiply 2\nneg\n";
+        for line in parse_source("Test.vm", source).unwrap() {
+            assert_eq!(parse_line(&line.command.to_string()).unwrap(), Some(line.command));
+        }
+    }
+}
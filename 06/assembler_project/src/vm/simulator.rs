@@ -0,0 +1,147 @@
+use std::path::Path;
+use crate::pipeline::load_vm_program;
+use crate::test_script::{Simulator, Value, split_indexed, resolve_path};
+use crate::vm::VmFile;
+use crate::vm::emulator::{VmMachine, VmRuntimeError, link};
+use crate::vm::parser::Segment;
+
+//Runs VM emulator test scripts (load [Prog.vm], vmstep, sp, local, argument[i], RAM[i] ...).
+//A directory of .jack files is compiled on load, and the native built-in OS fills in the classes a program calls but lacks.
+pub struct VmSimulator {
+    pub machine : Option<VmMachine>,
+}
+
+impl VmSimulator {
+    pub fn new() -> VmSimulator {
+        VmSimulator { machine : None }
+    }
+
+    fn machine(&self) -> Result<&VmMachine, String> {
+        self.machine.as_ref().ok_or_else(|| String::from("no program loaded"))
+    }
+
+    fn machine_mut(&mut self) -> Result<&mut VmMachine, String> {
+        self.machine.as_mut().ok_or_else(|| String::from("no program loaded"))
+    }
+
+    fn index(index : Option<&str>) -> Result<u16, String> {
+        index.and_then(|i| i.parse::<u16>().ok()).ok_or_else(|| String::from("bad index"))
+    }
+
+    //Maps a script variable to the RAM address it names
+    fn variable_address(&self, variable : &str) -> Result<usize, String> {
+        let machine = self.machine()?;
+        let segment_address = |segment : Segment, index : Option<&str>| -> Result<usize, String> {
+            machine.segment_address(segment, VmSimulator::index(index)?).map_err(|e| e.to_string())
+        };
+        match split_indexed(variable) {
+            ("sp", None) => Ok(0),
+            ("local", None) => Ok(1),
+            ("argument", None) => Ok(2),
+            ("this", None) => Ok(3),
+            ("that", None) => Ok(4),
+            ("RAM", index) => {
+                let index = VmSimulator::index(index)? as usize;
+                if index < machine.ram.len() { Ok(index) } else { Err(String::from("bad memory index")) }
+            },
+            ("local", index) => segment_address(Segment::Local, index),
+            ("argument", index) => segment_address(Segment::Argument, index),
+            ("this", index) => segment_address(Segment::This, index),
+            ("that", index) => segment_address(Segment::That, index),
+            ("pointer", index) => segment_address(Segment::Pointer, index),
+            ("temp", index) => segment_address(Segment::Temp, index),
+            _ => Err(format!("unknown variable '{}'", variable)),
+        }
+    }
+}
+
+impl Default for VmSimulator {
+    fn default() -> VmSimulator {
+        VmSimulator::new()
+    }
+}
+
+impl Simulator for VmSimulator {
+    fn load(&mut self, directory : &Path, file_name : Option<&str>) -> Result<(), String> {
+        let path = match file_name {
+            Some(file_name) => resolve_path(directory, file_name),
+            None => directory.to_path_buf(),
+        };
+        let (files, os_files) = load_vm_program(&path).map_err(|e| e.to_string())?;
+        let program = files.iter().chain(os_files.iter()).cloned().collect::<Vec<VmFile>>();
+        let mut machine = VmMachine::new(link(&program)?);
+        //The OS classes the program does not bring itself run natively, as in the reference VM emulator
+        machine.use_native_os(&os_files.iter().map(|file| file.name.clone()).collect::<Vec<String>>());
+        machine.start_at_sys_init();
+        self.machine = Some(machine);
+        Ok(())
+    }
+
+    fn get(&self, variable : &str) -> Result<Value, String> {
+        if variable == "currentFunction" {
+            return Ok(Value::Text(self.machine()?.current_function().to_string()));
+        }
+        let address = self.variable_address(variable)?;
+        Ok(Value::Number(self.machine()?.ram[address]))
+    }
+
+    fn set(&mut self, variable : &str, value : u16) -> Result<(), String> {
+        let address = self.variable_address(variable)?;
+        self.machine_mut()?.ram[address] = value;
+        Ok(())
+    }
+
+    fn execute(&mut self, command : &[String]) -> Result<(), String> {
+        match command.iter().map(|s| s.as_str()).collect::<Vec<&str>>().as_slice() {
+            ["vmstep"] => match self.machine_mut()?.step() {
+                //Like the reference emulator, stepping past the last command leaves the machine halted
+                Ok(()) | Err(VmRuntimeError::ProgramEnded) => Ok(()),
+                Err(e) => Err(e.to_string()),
+            },
+            _ => Err(format!("unknown command '{}'", command.join(" "))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::test_script::run_script_file;
+    use crate::vm::simulator::VmSimulator;
+    use crate::test_support::PROJECTS;
+
+    fn run_vme_script(test_directory : &str) -> bool {
+        run_named_script(test_directory, "VME")
+    }
+
+    fn run_named_script(test_directory : &str, suffix : &str) -> bool {
+        let directory = Path::new(PROJECTS).join(test_directory);
+        let name = directory.file_name().unwrap().to_string_lossy().to_string();
+        let script = directory.join(format!("{}{}.tst", name, suffix));
+        let result = run_script_file(&script.to_string_lossy(), &mut VmSimulator::new()).ok().unwrap();
+        result.compared && result.passed()
+    }
+
+    #[test]
+    fn project_7_vme_scripts_test() {
+        for test in ["07/StackArithmetic/SimpleAdd", "07/StackArithmetic/StackTest", "07/MemoryAccess/BasicTest",
+                     "07/MemoryAccess/PointerTest", "07/MemoryAccess/StaticTest"].iter() {
+            assert!(run_vme_script(test), "{}", test);
+        }
+    }
+
+    #[test]
+    fn project_8_vme_scripts_test() {
+        for test in ["08/ProgramFlow/BasicLoop", "08/ProgramFlow/FibonacciSeries", "08/FunctionCalls/SimpleFunction",
+                     "08/FunctionCalls/NestedCall", "08/FunctionCalls/FibonacciElement", "08/FunctionCalls/StaticsTest"].iter() {
+            assert!(run_vme_script(test), "{}", test);
+        }
+    }
+
+    #[test]
+    fn project_12_scripts_test() {
+        for test in ["12/ArrayTest", "12/MathTest", "12/MemoryTest"].iter() {
+            assert!(run_named_script(test, ""), "{}", test);
+        }
+    }
+}